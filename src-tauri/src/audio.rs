@@ -1,25 +1,48 @@
+use crate::audio_output::{AudioOutput, OutputHandle};
 use crate::equalizer::{bump_settings_version, EqualizerSettings, EqualizerSource};
 use anyhow::{Context, Result};
-use rodio::{Decoder, OutputStreamBuilder, Sink};
+use rodio::source::SeekError;
+use rodio::{Decoder, Sink, Source};
 use std::fs::File;
 use std::io::BufReader;
-use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU32, AtomicU64, Ordering};
 use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
 use std::thread;
 use std::time::Duration;
 use std::time::Instant;
 use tracing::{error, info};
 
+/// A file to decode, optionally limited to a range (CUE sheet virtual tracks)
+#[derive(Debug, Clone, Default)]
+pub struct PlaybackItem {
+    /// Library track being played, reported back through `AudioState::current_track_id`
+    pub track_id: Option<i64>,
+    pub path: String,
+    pub start_ms: u64,
+    pub end_ms: Option<u64>,
+}
+
+impl PlaybackItem {
+    pub fn file(path: &str) -> Self {
+        PlaybackItem {
+            path: path.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
 // Commands sent to the audio thread
 pub enum AudioCommand {
-    Play(String),
+    Play(PlaybackItem),
+    /// Load an item paused at a position in milliseconds (session restore)
+    Cue(PlaybackItem, u64),
     Pause,
     Stop,
     SetVolume(f32),
     Seek(f64), // Seek to position in seconds
     SetSpeed(f32),
-    PreloadNext(String),
+    PreloadNext(PlaybackItem),
     SetEqBand {
         band: usize,
         gain_db: f32,
@@ -38,6 +61,14 @@ pub struct AudioState {
     is_playing: AtomicBool,
     is_paused: AtomicBool,
     position_ms: AtomicU64,
+    /// Track id of the current item, 0 when nothing is loaded
+    current_track_id: AtomicI64,
+    /// `f32` bits of the sink volume and speed
+    volume: AtomicU32,
+    speed: AtomicU32,
+    /// Library tracks that played to their end, with the position they ended at
+    /// in milliseconds, waiting for `take_finished`
+    finished: Mutex<Vec<(i64, u64)>>,
     pub eq_settings: Arc<RwLock<EqualizerSettings>>,
 }
 
@@ -47,6 +78,10 @@ impl AudioState {
             is_playing: AtomicBool::new(false),
             is_paused: AtomicBool::new(false),
             position_ms: AtomicU64::new(0),
+            current_track_id: AtomicI64::new(0),
+            volume: AtomicU32::new(1.0f32.to_bits()),
+            speed: AtomicU32::new(1.0f32.to_bits()),
+            finished: Mutex::new(Vec::new()),
             eq_settings,
         }
     }
@@ -58,6 +93,43 @@ impl AudioState {
     pub fn is_playing(&self) -> bool {
         self.is_playing.load(Ordering::Relaxed) && !self.is_paused.load(Ordering::Relaxed)
     }
+
+    /// The library track currently loaded, including after a gapless transition
+    pub fn current_track_id(&self) -> Option<i64> {
+        match self.current_track_id.load(Ordering::Relaxed) {
+            0 => None,
+            id => Some(id),
+        }
+    }
+
+    fn set_current_track_id(&self, track_id: Option<i64>) {
+        self.current_track_id
+            .store(track_id.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    pub fn volume(&self) -> f32 {
+        f32::from_bits(self.volume.load(Ordering::Relaxed))
+    }
+
+    pub fn speed(&self) -> f32 {
+        f32::from_bits(self.speed.load(Ordering::Relaxed))
+    }
+
+    /// Tracks that ended on their own since the last call, including those a
+    /// gapless transition moved on from, with their final position in milliseconds
+    pub fn take_finished(&self) -> Vec<(i64, u64)> {
+        std::mem::take(&mut *self.finished.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    fn finish_current_track(&self) {
+        if let Some(track_id) = self.current_track_id() {
+            let position_ms = self.position_ms.load(Ordering::Relaxed);
+            self.finished
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push((track_id, position_ms));
+        }
+    }
 }
 
 // The audio controller that can be sent across threads
@@ -67,7 +139,7 @@ pub struct AudioController {
 }
 
 impl AudioController {
-    pub fn new(eq_settings: Arc<RwLock<EqualizerSettings>>) -> Result<Self> {
+    pub fn new(eq_settings: Arc<RwLock<EqualizerSettings>>, output: AudioOutput) -> Result<Self> {
         let (sender, receiver) = channel::<AudioCommand>();
         let state = Arc::new(AudioState::new(Arc::clone(&eq_settings)));
         let thread_state = Arc::clone(&state);
@@ -75,71 +147,87 @@ impl AudioController {
 
         // Spawn the audio thread
         thread::spawn(move || {
-            // Create output stream in the audio thread (rodio 0.21 API)
-            let stream = match OutputStreamBuilder::open_default_stream() {
-                Ok(s) => s,
+            // Open the output backend in the audio thread (falls back to null if no device)
+            let output = match OutputHandle::open(&output) {
+                Ok(o) => o,
                 Err(e) => {
-                    error!("Failed to create audio output: {}", e);
+                    error!("Failed to open audio output: {}", e);
                     return;
                 }
             };
-            let mixer = stream.mixer();
+            let mixer = output.mixer();
 
             let mut current_sink: Option<Sink> = None;
             let mut playback_start: Option<Instant> = None;
             let mut paused_position_ms: u64 = 0;
             let mut current_volume: f32 = 1.0;
             let mut current_speed: f32 = 1.0;
-            let mut preloaded_path: Option<String> = None;
+            let mut preloaded_item: Option<PlaybackItem> = None;
 
             loop {
                 // Check for commands with timeout so we can update position regularly
                 match receiver.recv_timeout(Duration::from_millis(100)) {
                     Ok(cmd) => {
                         match cmd {
-                            AudioCommand::Play(file_path) => {
+                            AudioCommand::Play(item) => {
                                 // Stop current playback
                                 if let Some(sink) = current_sink.take() {
                                     sink.stop();
                                 }
 
-                                // Stream audio with 256KB buffered reader to prevent micro-lags
-                                match File::open(&file_path)
-                                    .map(|f| BufReader::with_capacity(256 * 1024, f))
-                                {
-                                    Ok(reader) => {
-                                        match Decoder::new(reader) {
-                                            Ok(source) => {
-                                                let sink = Sink::connect_new(mixer);
-                                                sink.set_volume(current_volume);
-                                                sink.set_speed(current_speed);
-                                                // Wrap source with EQ
-                                                let eq_source = EqualizerSource::new(
-                                                    source,
-                                                    Arc::clone(&thread_eq_settings),
-                                                );
-                                                sink.append(eq_source);
-                                                sink.play();
-
-                                                current_sink = Some(sink);
-                                                playback_start = Some(Instant::now());
-                                                paused_position_ms = 0;
-                                                preloaded_path = None;
-
-                                                thread_state
-                                                    .is_playing
-                                                    .store(true, Ordering::Relaxed);
-                                                thread_state
-                                                    .is_paused
-                                                    .store(false, Ordering::Relaxed);
-                                                thread_state
-                                                    .position_ms
-                                                    .store(0, Ordering::Relaxed);
-                                            }
-                                            Err(e) => error!("Failed to decode audio: {}", e),
+                                match open_source(&item, &thread_eq_settings) {
+                                    Ok(eq_source) => {
+                                        let sink = Sink::connect_new(mixer);
+                                        sink.set_volume(current_volume);
+                                        sink.set_speed(current_speed);
+                                        sink.append(eq_source);
+                                        sink.play();
+
+                                        current_sink = Some(sink);
+                                        playback_start = Some(Instant::now());
+                                        paused_position_ms = 0;
+                                        preloaded_item = None;
+
+                                        thread_state.is_playing.store(true, Ordering::Relaxed);
+                                        thread_state.is_paused.store(false, Ordering::Relaxed);
+                                        thread_state.position_ms.store(0, Ordering::Relaxed);
+                                        thread_state.set_current_track_id(item.track_id);
+                                    }
+                                    Err(e) => error!("Failed to play {}: {}", item.path, e),
+                                }
+                            }
+
+                            AudioCommand::Cue(item, position_ms) => {
+                                if let Some(sink) = current_sink.take() {
+                                    sink.stop();
+                                }
+
+                                match open_source(&item, &thread_eq_settings) {
+                                    Ok(eq_source) => {
+                                        let sink = Sink::connect_new(mixer);
+                                        sink.set_volume(current_volume);
+                                        sink.set_speed(current_speed);
+                                        sink.pause();
+                                        sink.append(eq_source);
+                                        if position_ms > 0 {
+                                            let _ =
+                                                sink.try_seek(Duration::from_millis(position_ms));
                                         }
+
+                                        current_sink = Some(sink);
+                                        // Resuming (Pause toggle) continues from here
+                                        playback_start = None;
+                                        paused_position_ms = position_ms;
+                                        preloaded_item = None;
+
+                                        thread_state.is_playing.store(true, Ordering::Relaxed);
+                                        thread_state.is_paused.store(true, Ordering::Relaxed);
+                                        thread_state
+                                            .position_ms
+                                            .store(position_ms, Ordering::Relaxed);
+                                        thread_state.set_current_track_id(item.track_id);
                                     }
-                                    Err(e) => error!("Failed to open file: {}", e),
+                                    Err(e) => error!("Failed to load {}: {}", item.path, e),
                                 }
                             }
 
@@ -171,10 +259,14 @@ impl AudioController {
                                 thread_state.is_playing.store(false, Ordering::Relaxed);
                                 thread_state.is_paused.store(false, Ordering::Relaxed);
                                 thread_state.position_ms.store(0, Ordering::Relaxed);
+                                thread_state.set_current_track_id(None);
                             }
 
                             AudioCommand::SetVolume(volume) => {
                                 current_volume = volume.clamp(0.0, 1.0);
+                                thread_state
+                                    .volume
+                                    .store(current_volume.to_bits(), Ordering::Relaxed);
                                 if let Some(ref sink) = current_sink {
                                     sink.set_volume(current_volume);
                                 }
@@ -195,14 +287,17 @@ impl AudioController {
 
                             AudioCommand::SetSpeed(speed) => {
                                 current_speed = speed.clamp(0.25, 4.0);
+                                thread_state
+                                    .speed
+                                    .store(current_speed.to_bits(), Ordering::Relaxed);
                                 if let Some(ref sink) = current_sink {
                                     sink.set_speed(current_speed);
                                 }
                             }
 
-                            AudioCommand::PreloadNext(file_path) => {
-                                // Store the path for gapless transition
-                                preloaded_path = Some(file_path);
+                            AudioCommand::PreloadNext(item) => {
+                                // Store the item for gapless transition
+                                preloaded_item = Some(item);
                                 info!("Preloaded next track for gapless playback");
                             }
 
@@ -275,25 +370,17 @@ impl AudioController {
                         && !sink.is_paused()
                         && thread_state.is_playing.load(Ordering::Relaxed)
                     {
-                        if let Some(next_path) = preloaded_path.take() {
+                        thread_state.finish_current_track();
+                        if let Some(next_item) = preloaded_item.take() {
                             // Gapless: immediately start preloaded track
-                            match File::open(&next_path)
-                                .map(|f| BufReader::with_capacity(256 * 1024, f))
-                            {
-                                Ok(reader) => {
-                                    if let Ok(source) = Decoder::new(reader) {
-                                        let eq_source = EqualizerSource::new(
-                                            source,
-                                            Arc::clone(&thread_eq_settings),
-                                        );
-                                        sink.append(eq_source);
-                                        playback_start = Some(Instant::now());
-                                        paused_position_ms = 0;
-                                        thread_state.position_ms.store(0, Ordering::Relaxed);
-                                        info!("Gapless transition to next track");
-                                    } else {
-                                        thread_state.is_playing.store(false, Ordering::Relaxed);
-                                    }
+                            match open_source(&next_item, &thread_eq_settings) {
+                                Ok(eq_source) => {
+                                    sink.append(eq_source);
+                                    playback_start = Some(Instant::now());
+                                    paused_position_ms = 0;
+                                    thread_state.position_ms.store(0, Ordering::Relaxed);
+                                    thread_state.set_current_track_id(next_item.track_id);
+                                    info!("Gapless transition to next track");
                                 }
                                 Err(_) => {
                                     thread_state.is_playing.store(false, Ordering::Relaxed);
@@ -311,12 +398,22 @@ impl AudioController {
     }
 
     pub fn play_file(&self, file_path: &str) -> Result<()> {
+        self.play_item(PlaybackItem::file(file_path))
+    }
+
+    pub fn play_item(&self, item: PlaybackItem) -> Result<()> {
         self.sender
-            .send(AudioCommand::Play(file_path.to_string()))
+            .send(AudioCommand::Play(item))
             .context("Failed to send play command")?;
         Ok(())
     }
 
+    /// Load an item paused at `position_secs`, ready to resume with `pause()`
+    pub fn cue_item(&self, item: PlaybackItem, position_secs: f64) {
+        let position_ms = (position_secs.max(0.0) * 1000.0) as u64;
+        let _ = self.sender.send(AudioCommand::Cue(item, position_ms));
+    }
+
     pub fn pause(&self) {
         let _ = self.sender.send(AudioCommand::Pause);
     }
@@ -341,10 +438,8 @@ impl AudioController {
         let _ = self.sender.send(AudioCommand::SetSpeed(speed));
     }
 
-    pub fn preload_next(&self, file_path: &str) {
-        let _ = self
-            .sender
-            .send(AudioCommand::PreloadNext(file_path.to_string()));
+    pub fn preload_next(&self, item: PlaybackItem) {
+        let _ = self.sender.send(AudioCommand::PreloadNext(item));
     }
 
     pub fn set_eq_band(&self, band: usize, gain_db: f32) {
@@ -375,3 +470,242 @@ impl AudioController {
             .unwrap_or_default()
     }
 }
+
+type PlaybackSource = EqualizerSource<SegmentSource<Decoder<BufReader<File>>>>;
+
+/// Open, decode and wrap a playback item with its range and the EQ
+fn open_source(
+    item: &PlaybackItem,
+    eq_settings: &Arc<RwLock<EqualizerSettings>>,
+) -> Result<PlaybackSource> {
+    // Stream audio with 256KB buffered reader to prevent micro-lags
+    let reader = File::open(&item.path)
+        .map(|f| BufReader::with_capacity(256 * 1024, f))
+        .context("Failed to open file")?;
+    let decoder = Decoder::new(reader).context("Failed to decode audio")?;
+    let segment = SegmentSource::new(
+        decoder,
+        Duration::from_millis(item.start_ms),
+        item.end_ms.map(Duration::from_millis),
+    );
+    Ok(EqualizerSource::new(segment, Arc::clone(eq_settings)))
+}
+
+/// Restricts a source to `[start, end)`. Positions seen by the sink are relative
+/// to `start`, so seeking and position tracking work as for a regular file.
+pub struct SegmentSource<S: Source<Item = f32>> {
+    source: S,
+    start: Duration,
+    end: Option<Duration>,
+    /// Samples left before `end`, recomputed after every seek
+    remaining: Option<u64>,
+    /// Samples still to discard when the inner source cannot seek to `start`
+    skip: u64,
+}
+
+impl<S: Source<Item = f32>> SegmentSource<S> {
+    pub fn new(mut source: S, start: Duration, end: Option<Duration>) -> Self {
+        let skip = if start.is_zero() || source.try_seek(start).is_ok() {
+            0
+        } else {
+            Self::samples_in(&source, start)
+        };
+        let remaining = end.map(|end| Self::samples_in(&source, end.saturating_sub(start)));
+        SegmentSource {
+            source,
+            start,
+            end,
+            remaining,
+            skip,
+        }
+    }
+
+    fn samples_in(source: &S, duration: Duration) -> u64 {
+        (duration.as_secs_f64() * source.sample_rate() as f64).round() as u64
+            * source.channels() as u64
+    }
+}
+
+impl<S: Source<Item = f32>> Iterator for SegmentSource<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        while self.skip > 0 {
+            self.source.next()?;
+            self.skip -= 1;
+        }
+        if let Some(ref mut remaining) = self.remaining {
+            if *remaining == 0 {
+                return None;
+            }
+            *remaining -= 1;
+        }
+        self.source.next()
+    }
+}
+
+impl<S: Source<Item = f32>> Source for SegmentSource<S> {
+    fn current_span_len(&self) -> Option<usize> {
+        self.source.current_span_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        match self.end {
+            Some(end) => Some(end.saturating_sub(self.start)),
+            None => self
+                .source
+                .total_duration()
+                .map(|total| total.saturating_sub(self.start)),
+        }
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(self.start + pos)?;
+        self.skip = 0;
+        self.remaining = self
+            .end
+            .map(|end| Self::samples_in(&self.source, end.saturating_sub(self.start + pos)));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_output::WavWriter;
+
+    /// Write a stereo 440 Hz tone of the given length to a WAV file
+    fn write_tone(path: &std::path::Path, secs: f32) {
+        let mut writer = WavWriter::create(path, 2, 44_100).unwrap();
+        let frames = (44_100.0 * secs) as usize;
+        for i in 0..frames {
+            let sample = (i as f32 * 440.0 * std::f32::consts::TAU / 44_100.0).sin() * 0.5;
+            writer.write_sample(sample).unwrap();
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
+
+    #[test]
+    fn test_segment_source_limits_range() {
+        // 2 seconds of stereo at 1 kHz, sample value = frame index
+        let samples: Vec<f32> = (0..2000).flat_map(|i| [i as f32, i as f32]).collect();
+        let buffer = rodio::buffer::SamplesBuffer::new(2, 1000, samples);
+
+        let mut segment = SegmentSource::new(
+            buffer,
+            Duration::from_millis(500),
+            Some(Duration::from_millis(1500)),
+        );
+        assert_eq!(segment.total_duration(), Some(Duration::from_secs(1)));
+        assert_eq!(segment.next(), Some(500.0));
+        assert_eq!(segment.next(), Some(500.0));
+
+        segment.try_seek(Duration::from_millis(500)).unwrap();
+        let rest: Vec<f32> = segment.collect();
+        assert_eq!(rest.first(), Some(&1000.0));
+        assert_eq!(rest.last(), Some(&1499.0));
+        assert_eq!(rest.len(), 1000);
+    }
+
+    #[test]
+    fn test_playback_with_null_output() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("tone.wav");
+        write_tone(&path, 2.0);
+
+        let eq_settings = Arc::new(RwLock::new(EqualizerSettings::default()));
+        let audio = AudioController::new(eq_settings, AudioOutput::Null).unwrap();
+
+        audio.play_file(path.to_str().unwrap()).unwrap();
+        thread::sleep(Duration::from_millis(300));
+        assert!(audio.state.is_playing());
+
+        audio.pause();
+        thread::sleep(Duration::from_millis(200));
+        assert!(!audio.state.is_playing());
+
+        audio.seek(1.5);
+        thread::sleep(Duration::from_millis(200));
+        assert!((audio.get_position() - 1.5).abs() < 0.01);
+
+        audio.pause();
+        audio.set_eq_enabled(false);
+        thread::sleep(Duration::from_millis(200));
+        assert!(audio.state.is_playing());
+        assert!(!audio.get_eq_settings().enabled);
+
+        audio.stop();
+        thread::sleep(Duration::from_millis(200));
+        assert!(!audio.state.is_playing());
+        assert_eq!(audio.get_position(), 0.0);
+    }
+
+    #[test]
+    fn test_cue_item_loads_paused() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("tone.wav");
+        write_tone(&path, 2.0);
+
+        let eq_settings = Arc::new(RwLock::new(EqualizerSettings::default()));
+        let audio = AudioController::new(eq_settings, AudioOutput::Null).unwrap();
+
+        audio.set_volume(0.4);
+        let item = PlaybackItem {
+            track_id: Some(7),
+            ..PlaybackItem::file(path.to_str().unwrap())
+        };
+        audio.cue_item(item, 1.0);
+        thread::sleep(Duration::from_millis(300));
+        assert!(!audio.state.is_playing());
+        assert_eq!(audio.state.current_track_id(), Some(7));
+        assert!((audio.state.volume() - 0.4).abs() < f32::EPSILON);
+        assert!((audio.get_position() - 1.0).abs() < 0.01);
+
+        // Resuming continues from the cued position
+        audio.pause();
+        thread::sleep(Duration::from_millis(300));
+        assert!(audio.state.is_playing());
+        assert!(audio.get_position() >= 1.2);
+    }
+
+    #[test]
+    fn test_finished_tracks_are_reported() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let first = temp_dir.path().join("first.wav");
+        let second = temp_dir.path().join("second.wav");
+        write_tone(&first, 0.5);
+        write_tone(&second, 0.5);
+
+        let eq_settings = Arc::new(RwLock::new(EqualizerSettings::default()));
+        let audio = AudioController::new(eq_settings, AudioOutput::Null).unwrap();
+        let item = |id: i64, path: &std::path::Path| PlaybackItem {
+            track_id: Some(id),
+            ..PlaybackItem::file(path.to_str().unwrap())
+        };
+
+        audio.play_item(item(1, &first)).unwrap();
+        audio.preload_next(item(2, &second));
+        thread::sleep(Duration::from_millis(800));
+        // The gapless transition moved on from the first track at its end
+        assert_eq!(audio.state.current_track_id(), Some(2));
+        let finished = audio.state.take_finished();
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].0, 1);
+        assert!(finished[0].1 >= 400);
+
+        thread::sleep(Duration::from_millis(600));
+        assert!(!audio.state.is_playing());
+        let finished = audio.state.take_finished();
+        assert_eq!(finished.iter().map(|f| f.0).collect::<Vec<_>>(), vec![2]);
+        assert!(audio.state.take_finished().is_empty());
+    }
+}
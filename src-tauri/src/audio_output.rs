@@ -0,0 +1,273 @@
+use anyhow::{Context, Result};
+use rodio::mixer::{Mixer, MixerSource};
+use rodio::{OutputStream, OutputStreamBuilder};
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+/// Environment variable used to select the output backend at startup.
+///
+/// Accepted values: `device` (default), `null`, or `file:<path.wav>`.
+pub const AUDIO_OUTPUT_ENV: &str = "OSMP_AUDIO_OUTPUT";
+
+/// Sample rate of the software mixer used by the null and file backends
+const SOFTWARE_SAMPLE_RATE: u32 = 44_100;
+/// Channel count of the software mixer used by the null and file backends
+const SOFTWARE_CHANNELS: u16 = 2;
+/// How often the software pump pulls samples from the mixer
+const PUMP_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Where the mixed audio ends up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AudioOutput {
+    /// The system's default sound device
+    Device,
+    /// Discard all samples (headless servers, CI)
+    Null,
+    /// Write the mixed output to a 16-bit PCM WAV file
+    File(PathBuf),
+}
+
+impl AudioOutput {
+    /// Parse a backend spec such as `null` or `file:/tmp/out.wav`.
+    pub fn parse(spec: &str) -> Option<Self> {
+        let spec = spec.trim();
+        if spec.eq_ignore_ascii_case("device") || spec.is_empty() {
+            Some(AudioOutput::Device)
+        } else if spec.eq_ignore_ascii_case("null") {
+            Some(AudioOutput::Null)
+        } else {
+            spec.strip_prefix("file:")
+                .filter(|p| !p.is_empty())
+                .map(|p| AudioOutput::File(PathBuf::from(p)))
+        }
+    }
+
+    /// Read the backend from `OSMP_AUDIO_OUTPUT`, defaulting to the sound device.
+    pub fn from_env() -> Self {
+        match std::env::var(AUDIO_OUTPUT_ENV) {
+            Ok(spec) => AudioOutput::parse(&spec).unwrap_or_else(|| {
+                warn!(
+                    "Unrecognized {} value '{}', using the default device",
+                    AUDIO_OUTPUT_ENV, spec
+                );
+                AudioOutput::Device
+            }),
+            Err(_) => AudioOutput::Device,
+        }
+    }
+}
+
+/// An opened output backend. Sinks connect to `mixer()`; dropping the handle
+/// closes the device stream or stops the software pump (finalizing any WAV file).
+pub struct OutputHandle {
+    mixer: Mixer,
+    _stream: Option<OutputStream>,
+    pump: Option<SoftwarePump>,
+}
+
+impl OutputHandle {
+    /// Open the requested backend. If the sound device cannot be opened,
+    /// fall back to the null backend so playback state keeps working.
+    pub fn open(output: &AudioOutput) -> Result<Self> {
+        match output {
+            AudioOutput::Device => match OutputStreamBuilder::open_default_stream() {
+                Ok(stream) => Ok(OutputHandle {
+                    mixer: stream.mixer().clone(),
+                    _stream: Some(stream),
+                    pump: None,
+                }),
+                Err(e) => {
+                    error!(
+                        "Failed to create audio output: {}. Falling back to null output",
+                        e
+                    );
+                    Self::open_software(None)
+                }
+            },
+            AudioOutput::Null => {
+                info!("Using null audio output");
+                Self::open_software(None)
+            }
+            AudioOutput::File(path) => {
+                info!("Writing audio output to {}", path.display());
+                let writer = WavWriter::create(path, SOFTWARE_CHANNELS, SOFTWARE_SAMPLE_RATE)?;
+                Self::open_software(Some(writer))
+            }
+        }
+    }
+
+    fn open_software(writer: Option<WavWriter>) -> Result<Self> {
+        let (mixer, source) = rodio::mixer::mixer(SOFTWARE_CHANNELS, SOFTWARE_SAMPLE_RATE);
+        let pump = SoftwarePump::spawn(source, writer)?;
+        Ok(OutputHandle {
+            mixer,
+            _stream: None,
+            pump: Some(pump),
+        })
+    }
+
+    pub fn mixer(&self) -> &Mixer {
+        &self.mixer
+    }
+}
+
+impl Drop for OutputHandle {
+    fn drop(&mut self) {
+        if let Some(pump) = self.pump.take() {
+            pump.shutdown();
+        }
+    }
+}
+
+/// Pulls samples out of a software mixer in real time, the way a sound card
+/// would, so sink state (position, end of track, gapless) behaves identically.
+struct SoftwarePump {
+    running: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+impl SoftwarePump {
+    fn spawn(mut source: MixerSource, mut writer: Option<WavWriter>) -> Result<Self> {
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = Arc::clone(&running);
+
+        let handle = thread::Builder::new()
+            .name("osmp-audio-pump".to_string())
+            .spawn(move || {
+                let samples_per_sec = SOFTWARE_SAMPLE_RATE as u64 * SOFTWARE_CHANNELS as u64;
+                let start = Instant::now();
+                let mut pulled: u64 = 0;
+
+                while thread_running.load(Ordering::Relaxed) {
+                    thread::sleep(PUMP_INTERVAL);
+
+                    // Catch up to wall-clock time so sleep jitter doesn't accumulate
+                    let due = start.elapsed().as_micros() as u64 * samples_per_sec / 1_000_000;
+                    while pulled < due {
+                        pulled += 1;
+                        let Some(sample) = source.next() else {
+                            continue;
+                        };
+                        if let Some(ref mut w) = writer {
+                            if let Err(e) = w.write_sample(sample) {
+                                error!("Failed to write audio output file: {}", e);
+                                writer = None;
+                            }
+                        }
+                    }
+                }
+
+                if let Some(w) = writer {
+                    if let Err(e) = w.finalize() {
+                        error!("Failed to finalize audio output file: {}", e);
+                    }
+                }
+            })
+            .context("Failed to spawn audio pump thread")?;
+
+        Ok(SoftwarePump { running, handle })
+    }
+
+    fn shutdown(self) {
+        self.running.store(false, Ordering::Relaxed);
+        let _ = self.handle.join();
+    }
+}
+
+/// Minimal streaming 16-bit PCM WAV writer. The RIFF sizes are patched on finalize.
+pub(crate) struct WavWriter {
+    writer: BufWriter<File>,
+    data_bytes: u32,
+}
+
+impl WavWriter {
+    pub(crate) fn create(path: &Path, channels: u16, sample_rate: u32) -> Result<Self> {
+        let file =
+            File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+
+        let block_align = channels * 2;
+        let byte_rate = sample_rate * block_align as u32;
+
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(b"WAVE")?;
+        writer.write_all(b"fmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?; // PCM
+        writer.write_all(&channels.to_le_bytes())?;
+        writer.write_all(&sample_rate.to_le_bytes())?;
+        writer.write_all(&byte_rate.to_le_bytes())?;
+        writer.write_all(&block_align.to_le_bytes())?;
+        writer.write_all(&16u16.to_le_bytes())?;
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavWriter {
+            writer,
+            data_bytes: 0,
+        })
+    }
+
+    pub(crate) fn write_sample(&mut self, sample: f32) -> std::io::Result<()> {
+        let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        self.writer.write_all(&value.to_le_bytes())?;
+        self.data_bytes = self.data_bytes.saturating_add(2);
+        Ok(())
+    }
+
+    pub(crate) fn finalize(mut self) -> std::io::Result<()> {
+        self.writer.flush()?;
+        let file = self.writer.get_mut();
+        file.seek(SeekFrom::Start(4))?;
+        file.write_all(&(36u32.saturating_add(self.data_bytes)).to_le_bytes())?;
+        file.seek(SeekFrom::Start(40))?;
+        file.write_all(&self.data_bytes.to_le_bytes())?;
+        file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::source::{SineWave, Source};
+    use rodio::Sink;
+
+    #[test]
+    fn test_parse_output_spec() {
+        assert_eq!(AudioOutput::parse("null"), Some(AudioOutput::Null));
+        assert_eq!(AudioOutput::parse("device"), Some(AudioOutput::Device));
+        assert_eq!(
+            AudioOutput::parse("file:/tmp/out.wav"),
+            Some(AudioOutput::File(PathBuf::from("/tmp/out.wav")))
+        );
+        assert_eq!(AudioOutput::parse("file:"), None);
+        assert_eq!(AudioOutput::parse("speakers"), None);
+    }
+
+    #[test]
+    fn test_file_output_writes_valid_wav() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("out.wav");
+
+        {
+            let output = OutputHandle::open(&AudioOutput::File(path.clone())).unwrap();
+            let sink = Sink::connect_new(output.mixer());
+            sink.append(SineWave::new(440.0).take_duration(Duration::from_millis(200)));
+            sink.sleep_until_end();
+        }
+
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(&bytes[8..12], b"WAVE");
+        let data_len = u32::from_le_bytes([bytes[40], bytes[41], bytes[42], bytes[43]]);
+        assert_eq!(data_len as usize, bytes.len() - 44);
+        assert!(data_len > 0);
+    }
+}
@@ -1,10 +1,17 @@
 use crate::database::Database;
 use crate::scanner::ScannerWithProgress;
+use crate::watcher::FolderWatcher;
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tauri::{AppHandle, Emitter};
 use tracing::{error, info, warn};
 
+/// How often the watched folders are matched up with the enabled scan folders,
+/// which also picks up volumes that were mounted again
+const WATCH_REFRESH: Duration = Duration::from_secs(60);
+
 pub fn start_background_scanning(
     app_handle: AppHandle,
     db: Database,
@@ -14,7 +21,7 @@ pub fn start_background_scanning(
     tauri::async_runtime::spawn(async move {
         // Read settings from DB
         let (scan_on_startup, periodic_enabled, interval_minutes) = {
-            match db.lock() {
+            match db.read() {
                 Ok(db_lock) => {
                     let startup = db_lock
                         .get_setting("scan_on_startup")
@@ -46,6 +53,17 @@ pub fn start_background_scanning(
             }
         };
 
+        // Set while every enabled folder is watched, making periodic scans
+        // unnecessary
+        let fully_watched = Arc::new(AtomicBool::new(false));
+        start_watching(
+            app_handle.clone(),
+            Arc::clone(&db),
+            Arc::clone(&scan_running),
+            Arc::clone(&scan_cancelled),
+            Arc::clone(&fully_watched),
+        );
+
         // Startup scan after a short delay so the window is ready
         if scan_on_startup {
             tokio::time::sleep(std::time::Duration::from_secs(3)).await;
@@ -61,6 +79,9 @@ pub fn start_background_scanning(
 
             loop {
                 interval.tick().await;
+                if fully_watched.load(Ordering::SeqCst) {
+                    continue;
+                }
                 run_background_scan(&app_handle, &db, &scan_running, &scan_cancelled).await;
             }
         }
@@ -82,22 +103,10 @@ async fn run_background_scan(
         return;
     }
 
-    // Get enabled folders
-    let enabled_folders: Vec<String> = match db.lock() {
-        Ok(db_lock) => match db_lock.get_scan_folders() {
-            Ok(folders) => folders
-                .into_iter()
-                .filter(|f| f.enabled)
-                .map(|f| f.path)
-                .collect(),
-            Err(e) => {
-                warn!("Background scan: failed to get scan folders: {}", e);
-                scan_running.store(false, Ordering::SeqCst);
-                return;
-            }
-        },
+    let enabled_folders = match enabled_folders(db) {
+        Ok(folders) => folders,
         Err(e) => {
-            warn!("Background scan: database lock error: {}", e);
+            warn!("Background scan: {}", e);
             scan_running.store(false, Ordering::SeqCst);
             return;
         }
@@ -117,17 +126,28 @@ async fn run_background_scan(
     let result = tokio::task::spawn_blocking(move || {
         let scanner =
             ScannerWithProgress::new_quiet(db_clone, app_handle_clone, scan_cancelled_clone);
-        let result = scanner.scan_with_progress(enabled_folders);
+        let result = scanner.scan_with_changes(&enabled_folders);
         scan_running_clone.store(false, Ordering::SeqCst);
         result
     })
     .await;
 
     match result {
-        Ok(Ok(scan_result)) => {
+        Ok(Ok((scan_result, track_ids))) => {
+            if scan_result.missing > 0 || scan_result.purged > 0 {
+                info!(
+                    "Background scan found {} missing tracks and removed {}",
+                    scan_result.missing, scan_result.purged
+                );
+            }
             if scan_result.scanned > 0 {
                 info!("Background scan found {} new tracks", scan_result.scanned);
-                let _ = app_handle.emit("library-updated", scan_result.scanned);
+            }
+            if scan_result.moved > 0 {
+                info!("Background scan followed {} moved files", scan_result.moved);
+            }
+            if !track_ids.is_empty() {
+                let _ = app_handle.emit("library-updated", track_ids);
             } else {
                 info!("Background scan complete: library is up to date");
             }
@@ -140,3 +160,136 @@ async fn run_background_scan(
         }
     }
 }
+
+/// Paths of the enabled scan folders
+fn enabled_folders(db: &Database) -> Result<Vec<String>, String> {
+    let db_lock = db
+        .read()
+        .map_err(|e| format!("database lock error: {}", e))?;
+    let folders = db_lock
+        .get_scan_folders()
+        .map_err(|e| format!("failed to get scan folders: {}", e))?;
+    Ok(folders
+        .into_iter()
+        .filter(|f| f.enabled)
+        .map(|f| f.path)
+        .collect())
+}
+
+/// Whether folder watching is turned on in the settings
+fn watch_enabled(db: &Database) -> bool {
+    match db.read() {
+        Ok(db_lock) => db_lock
+            .get_setting("watch_folders_enabled")
+            .ok()
+            .flatten()
+            .map(|v| v == "true")
+            .unwrap_or(true),
+        Err(e) => {
+            warn!("Folder watcher: database lock error: {}", e);
+            false
+        }
+    }
+}
+
+/// Watch the enabled scan folders on a thread of its own and rescan what
+/// changes in them. `fully_watched` tells the periodic scan whether it is
+/// still needed. The watch setting is read again on every refresh, so turning
+/// watching off or on takes effect without a restart.
+fn start_watching(
+    app_handle: AppHandle,
+    db: Database,
+    scan_running: Arc<AtomicBool>,
+    scan_cancelled: Arc<AtomicBool>,
+    fully_watched: Arc<AtomicBool>,
+) {
+    std::thread::spawn(move || {
+        let mut watcher = match FolderWatcher::new() {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                warn!("Folder watching unavailable, using periodic scans: {}", e);
+                return;
+            }
+        };
+
+        let mut folders: Vec<String> = Vec::new();
+        let mut next_refresh = Instant::now();
+        loop {
+            if Instant::now() >= next_refresh {
+                let enabled = watch_enabled(&db);
+                if enabled {
+                    match enabled_folders(&db) {
+                        Ok(paths) => folders = paths,
+                        Err(e) => warn!("Folder watcher: {}", e),
+                    }
+                } else {
+                    folders.clear();
+                }
+                // With watching off nothing is watched, so periodic scans
+                // have to run again
+                let all_watched = watcher.set_folders(&folders);
+                fully_watched.store(enabled && all_watched, Ordering::SeqCst);
+                next_refresh = Instant::now() + WATCH_REFRESH;
+            }
+
+            let changes = watcher.poll(Duration::from_millis(500));
+            if !changes.is_empty()
+                && !rescan_changes(
+                    &app_handle,
+                    &db,
+                    &scan_running,
+                    &scan_cancelled,
+                    &folders,
+                    &changes,
+                )
+            {
+                watcher.requeue(changes);
+            }
+        }
+    });
+}
+
+/// Rescan the changed paths inside `folders` and tell the frontend which tracks
+/// changed. Returns false when another scan is running, so the changes can be
+/// tried again later.
+fn rescan_changes(
+    app_handle: &AppHandle,
+    db: &Database,
+    scan_running: &Arc<AtomicBool>,
+    scan_cancelled: &Arc<AtomicBool>,
+    folders: &[String],
+    changes: &[PathBuf],
+) -> bool {
+    let paths: Vec<PathBuf> = changes
+        .iter()
+        .filter(|path| folders.iter().any(|folder| path.starts_with(folder)))
+        .cloned()
+        .collect();
+    if paths.is_empty() {
+        return true;
+    }
+    if scan_running
+        .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+        .is_err()
+    {
+        return false;
+    }
+
+    let scanner = ScannerWithProgress::new_quiet(
+        Arc::clone(db),
+        app_handle.clone(),
+        Arc::clone(scan_cancelled),
+    );
+    let result = scanner.scan_changes(folders, &paths);
+    scan_running.store(false, Ordering::SeqCst);
+
+    match result {
+        Ok((_, track_ids)) if !track_ids.is_empty() => {
+            info!("Folder watcher updated {} tracks", track_ids.len());
+            let _ = app_handle.emit("library-updated", track_ids);
+        }
+        Ok(_) => {}
+        Err(e) => warn!("Rescanning changed files failed: {}", e),
+    }
+    true
+}
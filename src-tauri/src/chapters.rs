@@ -0,0 +1,759 @@
+use crate::models::Chapter;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Going back within this many milliseconds of a chapter start jumps to the previous chapter
+const RESTART_THRESHOLD_MS: i64 = 3000;
+/// Refuse to buffer absurdly large `moov` atoms
+const MAX_MOOV_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Read chapter markers from an M4B/MP4 (QuickTime chapter track or Nero `chpl`)
+/// or an MP3 (ID3v2 `CHAP` frames). Other formats return no chapters; Ogg and FLAC
+/// chapters live in Vorbis comments, see `vorbis_chapters`.
+pub fn read_chapters(path: &Path, duration_ms: Option<i64>) -> io::Result<Vec<Chapter>> {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    let chapters = match extension.as_str() {
+        "m4a" | "m4b" | "mp4" => read_mp4_chapters(&mut File::open(path)?)?,
+        "mp3" | "aac" => read_id3_chapters(&mut File::open(path)?)?,
+        _ => Vec::new(),
+    };
+    Ok(finish(chapters, duration_ms))
+}
+
+/// Chapters from `CHAPTERxxx=HH:MM:SS.mmm` / `CHAPTERxxxNAME=...` Vorbis comments
+pub fn vorbis_chapters<'a>(
+    comments: impl IntoIterator<Item = (&'a str, &'a str)>,
+    duration_ms: Option<i64>,
+) -> Vec<Chapter> {
+    let mut by_number: Vec<(u32, Chapter)> = Vec::new();
+    let mut names: Vec<(u32, String)> = Vec::new();
+
+    for (key, value) in comments {
+        let key = key.to_ascii_uppercase();
+        let Some(rest) = key.strip_prefix("CHAPTER") else {
+            continue;
+        };
+        let digits: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+        let Ok(number) = digits.parse::<u32>() else {
+            continue;
+        };
+        match &rest[digits.len()..] {
+            "" => {
+                if let Some(start_ms) = parse_clock(value) {
+                    by_number.push((
+                        number,
+                        Chapter {
+                            start_ms,
+                            ..Default::default()
+                        },
+                    ));
+                }
+            }
+            "NAME" => names.push((number, value.trim().to_string())),
+            _ => {}
+        }
+    }
+
+    let chapters = by_number
+        .into_iter()
+        .map(|(number, mut chapter)| {
+            chapter.title = names
+                .iter()
+                .find(|(n, name)| *n == number && !name.is_empty())
+                .map(|(_, name)| name.clone());
+            chapter
+        })
+        .collect();
+    finish(chapters, duration_ms)
+}
+
+/// Sort, fill in end times and index the chapters. A single chapter is not worth keeping.
+fn finish(mut chapters: Vec<Chapter>, duration_ms: Option<i64>) -> Vec<Chapter> {
+    chapters.sort_by_key(|c| c.start_ms);
+    chapters.dedup_by_key(|c| c.start_ms);
+    if chapters.len() < 2 {
+        return Vec::new();
+    }
+
+    let starts: Vec<i64> = chapters.iter().map(|c| c.start_ms).collect();
+    for (i, chapter) in chapters.iter_mut().enumerate() {
+        chapter.chapter_index = i as i64;
+        chapter.end_ms = starts
+            .get(i + 1)
+            .copied()
+            .or(chapter.end_ms.filter(|&end| end > chapter.start_ms))
+            .or(duration_ms);
+    }
+    chapters
+}
+
+/// The chapter to jump to when skipping forward from `position_ms`
+pub fn next_chapter(chapters: &[Chapter], position_ms: i64) -> Option<&Chapter> {
+    chapters.iter().find(|c| c.start_ms > position_ms)
+}
+
+/// The chapter to jump to when skipping back: the start of the current chapter,
+/// or the previous one when playback is still near the current chapter's start.
+pub fn previous_chapter(chapters: &[Chapter], position_ms: i64) -> Option<&Chapter> {
+    let current = chapters.iter().rposition(|c| c.start_ms <= position_ms)?;
+    if position_ms - chapters[current].start_ms > RESTART_THRESHOLD_MS {
+        Some(&chapters[current])
+    } else {
+        current.checked_sub(1).map(|i| &chapters[i])
+    }
+}
+
+/// Parse `HH:MM:SS.mmm` (hours optional) into milliseconds
+fn parse_clock(value: &str) -> Option<i64> {
+    let mut total = 0.0;
+    for part in value.trim().split(':') {
+        total = total * 60.0 + part.parse::<f64>().ok()?;
+    }
+    (total >= 0.0).then(|| (total * 1000.0).round() as i64)
+}
+
+// ID3v2
+
+fn read_id3_chapters<R: Read>(reader: &mut R) -> io::Result<Vec<Chapter>> {
+    let mut header = [0u8; 10];
+    if reader.read_exact(&mut header).is_err() || &header[0..3] != b"ID3" {
+        return Ok(Vec::new());
+    }
+    let size = synchsafe(&header[6..10]) as usize;
+    let mut tag = Vec::with_capacity(size + 10);
+    tag.extend_from_slice(&header);
+    reader.take(size as u64).read_to_end(&mut tag)?;
+    Ok(parse_id3_chapters(&tag))
+}
+
+/// Parse `CHAP` frames from a complete ID3v2.3/2.4 tag (header included)
+fn parse_id3_chapters(tag: &[u8]) -> Vec<Chapter> {
+    if tag.len() < 10 || &tag[0..3] != b"ID3" {
+        return Vec::new();
+    }
+    let version = tag[3];
+    let flags = tag[5];
+    if version != 3 && version != 4 {
+        return Vec::new();
+    }
+
+    let end = (10 + synchsafe(&tag[6..10]) as usize).min(tag.len());
+    let mut body = tag[10..end].to_vec();
+    // ID3v2.3 unsynchronises the whole tag: FF 00 -> FF
+    if version == 3 && flags & 0x80 != 0 {
+        body = resynchronise(&body);
+    }
+
+    let mut pos = 0;
+    if flags & 0x40 != 0 {
+        // Extended header: v2.4 size includes itself, v2.3 size excludes its own 4 bytes
+        pos = match (version, body.get(0..4)) {
+            (4, Some(b)) => synchsafe(b) as usize,
+            (_, Some(b)) => u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize + 4,
+            _ => return Vec::new(),
+        };
+    }
+
+    id3_frames(body.get(pos..).unwrap_or_default(), version)
+        .filter(|(id, _)| id == b"CHAP")
+        .filter_map(|(_, data)| parse_chap_frame(data, version))
+        .collect()
+}
+
+fn parse_chap_frame(data: &[u8], version: u8) -> Option<Chapter> {
+    let id_end = data.iter().position(|&b| b == 0)?;
+    let times = data.get(id_end + 1..id_end + 17)?;
+    let start_ms = be_u32(times, 0)? as i64;
+    let end_ms = be_u32(times, 4)? as i64;
+
+    let title = id3_frames(&data[id_end + 17..], version)
+        .find(|(id, _)| id == b"TIT2")
+        .and_then(|(_, text)| decode_id3_text(text));
+
+    Some(Chapter {
+        title,
+        start_ms,
+        end_ms: Some(end_ms),
+        ..Default::default()
+    })
+}
+
+/// Iterate `(frame id, frame data)` pairs, stopping at padding or a truncated frame
+fn id3_frames(data: &[u8], version: u8) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        let header = data.get(pos..pos + 10)?;
+        if header[0] == 0 {
+            return None;
+        }
+        let size = if version == 4 {
+            synchsafe(&header[4..8])
+        } else {
+            be_u32(header, 4)?
+        } as usize;
+        let frame = data.get(pos + 10..pos + 10 + size)?;
+        pos += 10 + size;
+        Some(([header[0], header[1], header[2], header[3]], frame))
+    })
+}
+
+fn decode_id3_text(data: &[u8]) -> Option<String> {
+    let (&encoding, body) = data.split_first()?;
+    let text = match encoding {
+        0 => body.iter().map(|&b| b as char).collect(),
+        1 | 2 => decode_utf16(body, encoding == 2),
+        _ => String::from_utf8_lossy(body).into_owned(),
+    };
+    let text = text.split('\0').next().unwrap_or_default().trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/// Decode UTF-16, honouring a byte order mark when present
+fn decode_utf16(data: &[u8], big_endian: bool) -> String {
+    let (big_endian, data) = match data {
+        [0xFE, 0xFF, rest @ ..] => (true, rest),
+        [0xFF, 0xFE, rest @ ..] => (false, rest),
+        _ => (big_endian, data),
+    };
+    let units: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|c| {
+            if big_endian {
+                u16::from_be_bytes([c[0], c[1]])
+            } else {
+                u16::from_le_bytes([c[0], c[1]])
+            }
+        })
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+fn synchsafe(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .take(4)
+        .fold(0, |acc, &b| (acc << 7) | (b & 0x7F) as u32)
+}
+
+fn resynchronise(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut previous = 0u8;
+    for &b in data {
+        if !(previous == 0xFF && b == 0x00) {
+            out.push(b);
+        }
+        previous = b;
+    }
+    out
+}
+
+// MP4
+
+fn read_mp4_chapters<R: Read + Seek>(reader: &mut R) -> io::Result<Vec<Chapter>> {
+    let Some(moov) = read_top_level_box(reader, b"moov")? else {
+        return Ok(Vec::new());
+    };
+
+    let mut traks = Vec::new();
+    let mut chapter_track_ids = Vec::new();
+    let mut nero = Vec::new();
+
+    for (kind, body) in mp4_boxes(&moov) {
+        match &kind {
+            b"trak" => {
+                if let Some(tref) = child(body, b"tref") {
+                    if let Some(chap) = child(tref, b"chap") {
+                        chapter_track_ids.extend(chap.chunks_exact(4).filter_map(|c| be_u32(c, 0)));
+                    }
+                }
+                traks.push(body);
+            }
+            b"udta" => {
+                if let Some(chpl) = child(body, b"chpl") {
+                    nero = parse_chpl(chpl);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Prefer the QuickTime chapter track (iTunes, ffmpeg); fall back to Nero chapters
+    for trak in traks {
+        if track_id(trak).is_some_and(|id| chapter_track_ids.contains(&id)) {
+            let chapters = read_text_track(reader, trak)?;
+            if !chapters.is_empty() {
+                return Ok(chapters);
+            }
+        }
+    }
+    Ok(nero)
+}
+
+/// Find a top-level box by walking headers and return its payload
+fn read_top_level_box<R: Read + Seek>(
+    reader: &mut R,
+    kind: &[u8; 4],
+) -> io::Result<Option<Vec<u8>>> {
+    let mut offset = 0u64;
+    loop {
+        reader.seek(SeekFrom::Start(offset))?;
+        let mut header = [0u8; 8];
+        if reader.read_exact(&mut header).is_err() {
+            return Ok(None);
+        }
+        let mut size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+        let mut header_len = 8;
+        if size == 1 {
+            let mut large = [0u8; 8];
+            reader.read_exact(&mut large)?;
+            size = u64::from_be_bytes(large);
+            header_len = 16;
+        }
+
+        if &header[4..8] == kind {
+            let payload = if size == 0 {
+                u64::MAX
+            } else {
+                size.saturating_sub(header_len)
+            };
+            if payload > MAX_MOOV_SIZE && size != 0 {
+                return Ok(None);
+            }
+            let mut data = Vec::new();
+            reader
+                .take(payload.min(MAX_MOOV_SIZE))
+                .read_to_end(&mut data)?;
+            return Ok(Some(data));
+        }
+
+        if size < header_len {
+            return Ok(None);
+        }
+        offset = match offset.checked_add(size) {
+            Some(next) => next,
+            None => return Ok(None),
+        };
+    }
+}
+
+/// Iterate `(box type, payload)` pairs within a buffer
+fn mp4_boxes(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut pos = 0usize;
+    std::iter::from_fn(move || {
+        let header = data.get(pos..pos.checked_add(8)?)?;
+        let kind = [header[4], header[5], header[6], header[7]];
+        let (size, header_len) = match be_u32(header, 0)? {
+            0 => (data.len() - pos, 8),
+            1 => (usize::try_from(be_u64(data, pos + 8)?).ok()?, 16),
+            size => (size as usize, 8),
+        };
+        if size < header_len {
+            return None;
+        }
+        // A size past the end of memory ends the walk like one past the data
+        let end = pos.checked_add(size)?;
+        let body = data.get(pos + header_len..end)?;
+        pos = end;
+        Some((kind, body))
+    })
+}
+
+fn child<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    mp4_boxes(data)
+        .find(|(k, _)| k == kind)
+        .map(|(_, body)| body)
+}
+
+fn path<'a>(data: &'a [u8], kinds: &[&[u8; 4]]) -> Option<&'a [u8]> {
+    kinds.iter().try_fold(data, |data, kind| child(data, kind))
+}
+
+fn track_id(trak: &[u8]) -> Option<u32> {
+    let tkhd = child(trak, b"tkhd")?;
+    // Full box header, then creation/modification times (32 or 64 bit)
+    match tkhd.first()? {
+        1 => be_u32(tkhd, 20),
+        _ => be_u32(tkhd, 12),
+    }
+}
+
+/// Nero chapters: a count, then (start in 100ns units, pascal string title) entries
+fn parse_chpl(chpl: &[u8]) -> Vec<Chapter> {
+    let mut pos = if chpl.first() == Some(&1) { 8 } else { 4 };
+    let Some(&count) = chpl.get(pos) else {
+        return Vec::new();
+    };
+    pos += 1;
+
+    let mut chapters = Vec::new();
+    for _ in 0..count {
+        let Some(start) = be_u64(chpl, pos) else {
+            break;
+        };
+        let Some(&len) = chpl.get(pos + 8) else {
+            break;
+        };
+        let Some(title) = chpl.get(pos + 9..pos + 9 + len as usize) else {
+            break;
+        };
+        let title = String::from_utf8_lossy(title).trim().to_string();
+        chapters.push(Chapter {
+            title: (!title.is_empty()).then_some(title),
+            start_ms: (start / 10_000) as i64,
+            ..Default::default()
+        });
+        pos += 9 + len as usize;
+    }
+    chapters
+}
+
+/// Read the samples of a QuickTime text track: each one is a chapter title,
+/// timed by the track's `stts` table.
+fn read_text_track<R: Read + Seek>(reader: &mut R, trak: &[u8]) -> io::Result<Vec<Chapter>> {
+    let Some(mdia) = child(trak, b"mdia") else {
+        return Ok(Vec::new());
+    };
+    let timescale = child(mdia, b"mdhd")
+        .and_then(|mdhd| match mdhd.first()? {
+            1 => be_u32(mdhd, 20),
+            _ => be_u32(mdhd, 12),
+        })
+        .filter(|&t| t > 0);
+    let Some(stbl) = path(mdia, &[b"minf", b"stbl"]) else {
+        return Ok(Vec::new());
+    };
+    let Some(timescale) = timescale else {
+        return Ok(Vec::new());
+    };
+
+    let starts = sample_times(stbl);
+    let locations = sample_locations(stbl);
+
+    let mut chapters = Vec::new();
+    for (start, (offset, size)) in starts.into_iter().zip(locations) {
+        let mut sample = vec![0u8; size.min(1024) as usize];
+        reader.seek(SeekFrom::Start(offset))?;
+        reader.read_exact(&mut sample)?;
+
+        let title = be_u16(&sample, 0).and_then(|len| sample.get(2..2 + len as usize));
+        let title = title.map(|text| match text {
+            [0xFE, 0xFF, ..] | [0xFF, 0xFE, ..] => decode_utf16(text, true),
+            _ => String::from_utf8_lossy(text).into_owned(),
+        });
+        chapters.push(Chapter {
+            title: title
+                .map(|t| t.trim().to_string())
+                .filter(|t| !t.is_empty()),
+            start_ms: (start * 1000 / timescale as u64) as i64,
+            ..Default::default()
+        });
+    }
+    Ok(chapters)
+}
+
+/// Start time of each sample, in track timescale units
+fn sample_times(stbl: &[u8]) -> Vec<u64> {
+    let Some(stts) = child(stbl, b"stts") else {
+        return Vec::new();
+    };
+    let count = be_u32(stts, 4).unwrap_or(0) as usize;
+    let mut times = Vec::new();
+    let mut time = 0u64;
+    for i in 0..count {
+        let (Some(samples), Some(delta)) = (be_u32(stts, 8 + i * 8), be_u32(stts, 12 + i * 8))
+        else {
+            break;
+        };
+        for _ in 0..samples.min(10_000) {
+            times.push(time);
+            time += delta as u64;
+        }
+    }
+    times
+}
+
+/// File offset and size of each sample, from the chunk tables
+fn sample_locations(stbl: &[u8]) -> Vec<(u64, u64)> {
+    let sizes: Vec<u64> = match child(stbl, b"stsz") {
+        Some(stsz) => {
+            let fixed = be_u32(stsz, 4).unwrap_or(0) as u64;
+            let count = be_u32(stsz, 8).unwrap_or(0).min(10_000) as usize;
+            if fixed > 0 {
+                vec![fixed; count]
+            } else {
+                (0..count)
+                    .map_while(|i| be_u32(stsz, 12 + i * 4).map(u64::from))
+                    .collect()
+            }
+        }
+        None => return Vec::new(),
+    };
+
+    let chunk_offsets: Vec<u64> = if let Some(stco) = child(stbl, b"stco") {
+        let count = be_u32(stco, 4).unwrap_or(0) as usize;
+        (0..count)
+            .map_while(|i| be_u32(stco, 8 + i * 4).map(u64::from))
+            .collect()
+    } else if let Some(co64) = child(stbl, b"co64") {
+        let count = be_u32(co64, 4).unwrap_or(0) as usize;
+        (0..count).map_while(|i| be_u64(co64, 8 + i * 8)).collect()
+    } else {
+        return Vec::new();
+    };
+
+    // (first chunk, samples per chunk), chunk numbers are 1-based
+    let stsc: Vec<(u32, u32)> = child(stbl, b"stsc")
+        .map(|stsc| {
+            let count = be_u32(stsc, 4).unwrap_or(0) as usize;
+            (0..count)
+                .map_while(|i| Some((be_u32(stsc, 8 + i * 12)?, be_u32(stsc, 12 + i * 12)?)))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let mut locations = Vec::with_capacity(sizes.len());
+    let mut sizes_iter = sizes.into_iter();
+    for (i, &chunk_offset) in chunk_offsets.iter().enumerate() {
+        let chunk = i as u32 + 1;
+        let per_chunk = stsc
+            .iter()
+            .rev()
+            .find(|(first, _)| *first <= chunk)
+            .map_or(1, |&(_, n)| n);
+        let mut offset = chunk_offset;
+        for _ in 0..per_chunk {
+            let Some(size) = sizes_iter.next() else {
+                return locations;
+            };
+            locations.push((offset, size));
+            let Some(next) = offset.checked_add(size) else {
+                return locations;
+            };
+            offset = next;
+        }
+    }
+    locations
+}
+
+fn be_u16(data: &[u8], at: usize) -> Option<u16> {
+    let b = data.get(at..at + 2)?;
+    Some(u16::from_be_bytes([b[0], b[1]]))
+}
+
+fn be_u32(data: &[u8], at: usize) -> Option<u32> {
+    let b = data.get(at..at + 4)?;
+    Some(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn be_u64(data: &[u8], at: usize) -> Option<u64> {
+    let b = data.get(at..at + 8)?;
+    Some(u64::from_be_bytes([
+        b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7],
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut out = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(payload);
+        out
+    }
+
+    fn id3_frame(id: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut out = id.to_vec();
+        out.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        out.extend_from_slice(&[0, 0]);
+        out.extend_from_slice(payload);
+        out
+    }
+
+    fn chap(element: &str, start: u32, end: u32, title: &str) -> Vec<u8> {
+        let mut payload = element.as_bytes().to_vec();
+        payload.push(0);
+        for value in [start, end, u32::MAX, u32::MAX] {
+            payload.extend_from_slice(&value.to_be_bytes());
+        }
+        let mut text = vec![3];
+        text.extend_from_slice(title.as_bytes());
+        payload.extend(id3_frame(b"TIT2", &text));
+        id3_frame(b"CHAP", &payload)
+    }
+
+    #[test]
+    fn test_id3_chap_frames() {
+        let mut frames = chap("ch1", 60_000, 120_000, "Second");
+        frames.extend(chap("ch0", 0, 60_000, "First"));
+        frames.extend(vec![0; 16]); // padding
+
+        let size = frames.len() as u32;
+        let mut tag = b"ID3\x03\x00\x00".to_vec();
+        tag.extend([21, 14, 7, 0].map(|shift| ((size >> shift) & 0x7F) as u8));
+        tag.extend(frames);
+        tag.extend_from_slice(b"\xFF\xFBaudio");
+
+        let chapters = finish(
+            read_id3_chapters(&mut Cursor::new(tag)).unwrap(),
+            Some(150_000),
+        );
+        assert_eq!(chapters.len(), 2);
+        assert_eq!(chapters[0].title.as_deref(), Some("First"));
+        assert_eq!(chapters[0].end_ms, Some(60_000));
+        assert_eq!(chapters[1].chapter_index, 1);
+        assert_eq!(chapters[1].end_ms, Some(120_000));
+    }
+
+    #[test]
+    fn test_vorbis_chapters() {
+        let comments = [
+            ("CHAPTER001", "00:00:00.000"),
+            ("CHAPTER001NAME", "Intro"),
+            ("chapter002", "00:12:30.500"),
+            ("CHAPTER002NAME", "Part One"),
+            ("ARTIST", "Someone"),
+        ];
+        let chapters = vorbis_chapters(comments, Some(1_000_000));
+        assert_eq!(chapters.len(), 2);
+        assert_eq!(chapters[1].title.as_deref(), Some("Part One"));
+        assert_eq!(chapters[1].start_ms, 750_500);
+        assert_eq!(chapters[0].end_ms, Some(750_500));
+        assert_eq!(chapters[1].end_ms, Some(1_000_000));
+    }
+
+    #[test]
+    fn test_mp4_nero_chapters() {
+        let mut chpl = vec![1, 0, 0, 0, 0, 0, 0, 0, 2];
+        for (start, title) in [(0u64, "One"), (90_000_000u64, "Two")] {
+            chpl.extend_from_slice(&start.to_be_bytes());
+            chpl.push(title.len() as u8);
+            chpl.extend_from_slice(title.as_bytes());
+        }
+        let moov = mp4_box(b"moov", &mp4_box(b"udta", &mp4_box(b"chpl", &chpl)));
+        let mut file = mp4_box(b"ftyp", b"M4B \0\0\0\0");
+        file.extend(moov);
+
+        let chapters = read_mp4_chapters(&mut Cursor::new(file)).unwrap();
+        assert_eq!(chapters.len(), 2);
+        assert_eq!(chapters[1].title.as_deref(), Some("Two"));
+        assert_eq!(chapters[1].start_ms, 9_000);
+    }
+
+    #[test]
+    fn test_oversized_mp4_box_ends_the_walk() {
+        let mut data = mp4_box(b"free", b"");
+        // A 64-bit size that overflows when added to the box's offset
+        data.extend(1u32.to_be_bytes());
+        data.extend(b"moov");
+        data.extend(u64::MAX.to_be_bytes());
+        data.extend(mp4_box(b"udta", b""));
+
+        let kinds: Vec<[u8; 4]> = mp4_boxes(&data).map(|(kind, _)| kind).collect();
+        assert_eq!(kinds, vec![*b"free"]);
+        assert!(read_mp4_chapters(&mut Cursor::new(data))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_mp4_text_track_chapters() {
+        // Text samples live in mdat; their offsets are patched in below
+        let samples: Vec<Vec<u8>> = ["Opening", "Middle"]
+            .iter()
+            .map(|t| {
+                let mut s = (t.len() as u16).to_be_bytes().to_vec();
+                s.extend_from_slice(t.as_bytes());
+                s
+            })
+            .collect();
+        let ftyp = mp4_box(b"ftyp", b"M4B \0\0\0\0");
+        let mdat_payload: Vec<u8> = samples.concat();
+        let mdat = mp4_box(b"mdat", &mdat_payload);
+        let first_offset = (ftyp.len() + 8) as u32;
+
+        let audio_trak = mp4_box(b"trak", &{
+            let mut tkhd = vec![0; 12];
+            tkhd.extend_from_slice(&1u32.to_be_bytes());
+            let mut t = mp4_box(b"tkhd", &tkhd);
+            let mut chap = 2u32.to_be_bytes().to_vec();
+            chap = mp4_box(b"chap", &chap);
+            t.extend(mp4_box(b"tref", &chap));
+            t
+        });
+
+        let text_trak = mp4_box(b"trak", &{
+            let mut tkhd = vec![0; 12];
+            tkhd.extend_from_slice(&2u32.to_be_bytes());
+            let mut t = mp4_box(b"tkhd", &tkhd);
+
+            let mut mdhd = vec![0; 12];
+            mdhd.extend_from_slice(&1000u32.to_be_bytes());
+            let mut stts = vec![0, 0, 0, 0, 0, 0, 0, 1];
+            stts.extend_from_slice(&2u32.to_be_bytes());
+            stts.extend_from_slice(&5000u32.to_be_bytes());
+            let mut stsz = vec![0; 8];
+            stsz.extend_from_slice(&2u32.to_be_bytes());
+            for s in &samples {
+                stsz.extend_from_slice(&(s.len() as u32).to_be_bytes());
+            }
+            let mut stsc = vec![0, 0, 0, 0, 0, 0, 0, 1];
+            for v in [1u32, 2, 1] {
+                stsc.extend_from_slice(&v.to_be_bytes());
+            }
+            let mut stco = vec![0, 0, 0, 0, 0, 0, 0, 1];
+            stco.extend_from_slice(&first_offset.to_be_bytes());
+
+            let mut stbl = mp4_box(b"stts", &stts);
+            stbl.extend(mp4_box(b"stsz", &stsz));
+            stbl.extend(mp4_box(b"stsc", &stsc));
+            stbl.extend(mp4_box(b"stco", &stco));
+            let mut mdia = mp4_box(b"mdhd", &mdhd);
+            mdia.extend(mp4_box(b"minf", &mp4_box(b"stbl", &stbl)));
+            t.extend(mp4_box(b"mdia", &mdia));
+            t
+        });
+
+        let mut moov_payload = audio_trak;
+        moov_payload.extend(text_trak);
+        let mut file = ftyp;
+        file.extend(mdat);
+        file.extend(mp4_box(b"moov", &moov_payload));
+
+        let chapters = finish(read_mp4_chapters(&mut Cursor::new(file)).unwrap(), None);
+        assert_eq!(chapters.len(), 2);
+        assert_eq!(chapters[0].title.as_deref(), Some("Opening"));
+        assert_eq!(chapters[1].title.as_deref(), Some("Middle"));
+        assert_eq!(chapters[1].start_ms, 5000);
+    }
+
+    #[test]
+    fn test_chapter_navigation() {
+        let chapters = vorbis_chapters(
+            [
+                ("CHAPTER01", "00:00"),
+                ("CHAPTER02", "01:00"),
+                ("CHAPTER03", "02:00"),
+            ],
+            None,
+        );
+
+        assert_eq!(next_chapter(&chapters, 30_000).unwrap().start_ms, 60_000);
+        assert!(next_chapter(&chapters, 130_000).is_none());
+
+        // Well into chapter 2: restart it; just after its start: go to chapter 1
+        assert_eq!(
+            previous_chapter(&chapters, 90_000).unwrap().start_ms,
+            60_000
+        );
+        assert_eq!(previous_chapter(&chapters, 61_000).unwrap().start_ms, 0);
+        assert!(previous_chapter(&chapters, 1_000).is_none());
+    }
+}
@@ -1,28 +1,48 @@
-use crate::database::DatabaseInner;
+use crate::audio::{AudioController, PlaybackItem};
+use crate::chapters;
+use crate::database::{AlbumCoverInfo, Database, DatabaseInner, ReadConnection};
 use crate::equalizer::{get_presets, get_visualizer_levels, EqPreset, EqualizerSettings};
 use crate::media_controls::{MediaMetadata, PlaybackState};
 use crate::metadata::MetadataFetcher;
 use crate::models::{
-    AlbumInfo, MetadataResult, Playlist, ScanFolder, ScanResult, ScanSettings, Track, TrackFilters,
+    AlbumInfo, AlbumRating, ArtistCredit, ArtistInfo, ArtistRating, Chapter, Discoveries,
+    GenreInfo, ListeningClock, ListeningStreaks, ListeningTime, MetadataResult, NameStat,
+    PlaybackSession, Playlist, PlaylistKind, QualityStats, RelocationReport, ReportFormat,
+    ResumePosition, ResumeSettings, ScanFolder, ScanResult, ScanSettings, SessionContext,
+    SmartPlaylistRules, SmartRule, StatsPeriod, StatsRange, Track, TrackFilters, TrackStat,
+    YearReport,
 };
+use crate::ratings;
+use crate::reconcile;
 use crate::scanner::ScannerWithProgress;
+use crate::search_query::SearchQuery;
 use crate::AppState;
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
 use lofty::prelude::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::Ordering;
-use std::sync::{Arc, MutexGuard};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
 use tauri::{Manager, State};
 
 /// Maximum number of track IDs accepted in a single batch command.
 const MAX_BATCH_SIZE: usize = 10_000;
 
-/// Lock the database mutex. See `database.rs` for deadlock risk rationale.
-fn lock_db(state: &AppState) -> Result<MutexGuard<'_, DatabaseInner>, String> {
-    state.db.lock().map_err(|e| {
-        tracing::error!("Database lock poisoned: {}", e);
-        "Database is temporarily unavailable".to_string()
-    })
+/// Stopping this close to the end of long-form content counts as finishing it.
+const RESUME_FINISHED_MARGIN_MS: i64 = 30_000;
+
+fn db_unavailable<E: std::fmt::Display>(e: E) -> String {
+    tracing::error!("Database lock poisoned: {}", e);
+    "Database is temporarily unavailable".to_string()
+}
+
+/// Borrow a read-only connection from the pool (see `DatabasePool`)
+fn read_db(state: &AppState) -> Result<ReadConnection<'_>, String> {
+    state.db.read().map_err(db_unavailable)
+}
+
+/// Lock the pool's writer connection
+fn write_db(state: &AppState) -> Result<MutexGuard<'_, DatabaseInner>, String> {
+    state.db.write().map_err(db_unavailable)
 }
 
 /// Log the full error server-side, return a generic message to the frontend.
@@ -35,7 +55,7 @@ fn sanitize_err<E: std::fmt::Display>(context: &str) -> impl FnOnce(E) -> String
 
 #[tauri::command]
 pub async fn get_scan_folders(state: State<'_, AppState>) -> Result<Vec<ScanFolder>, String> {
-    let result = lock_db(&state)?.get_scan_folders();
+    let result = read_db(&state)?.get_scan_folders();
     result.map_err(sanitize_err("Loading scan folders"))
 }
 
@@ -95,13 +115,13 @@ pub async fn add_scan_folder(state: State<'_, AppState>, path: String) -> Result
         }
     }
 
-    let result = lock_db(&state)?.add_scan_folder(&canonical_str);
+    let result = write_db(&state)?.add_scan_folder(&canonical_str);
     result.map_err(sanitize_err("Adding scan folder"))
 }
 
 #[tauri::command]
 pub async fn remove_scan_folder(state: State<'_, AppState>, id: i64) -> Result<(), String> {
-    let result = lock_db(&state)?.remove_scan_folder(id);
+    let result = write_db(&state)?.remove_scan_folder(id);
     result.map_err(sanitize_err("Removing scan folder"))
 }
 
@@ -125,7 +145,7 @@ pub async fn scan_folders(
 
     let enabled_folders: Vec<String> = {
         let folders = db
-            .lock()
+            .read()
             .map_err(|e| {
                 scan_running.store(false, Ordering::SeqCst);
                 tracing::error!("Loading folders for scan: {}", e);
@@ -158,6 +178,64 @@ pub async fn scan_folders(
     Ok(result)
 }
 
+/// Tracks whose file was gone at the last scan, for review
+#[tauri::command]
+pub async fn get_missing_tracks(state: State<'_, AppState>) -> Result<Vec<Track>, String> {
+    read_db(&state)?
+        .get_missing_tracks()
+        .map_err(sanitize_err("Loading missing tracks"))
+}
+
+/// Remove every track flagged as missing without waiting for the grace period
+#[tauri::command]
+pub async fn purge_missing_tracks(state: State<'_, AppState>) -> Result<usize, String> {
+    write_db(&state)?
+        .purge_missing_tracks()
+        .map_err(sanitize_err("Removing missing tracks"))
+}
+
+/// Enabled scan folders whose drive is not connected. Their tracks are kept
+/// but can't be played until it is.
+#[tauri::command]
+pub async fn get_offline_folders(state: State<'_, AppState>) -> Result<Vec<String>, String> {
+    let folders = read_db(&state)?
+        .get_scan_folders()
+        .map_err(sanitize_err("Loading scan folders"))?;
+    Ok(folders
+        .into_iter()
+        .filter(|f| f.enabled && !reconcile::folder_available(Path::new(&f.path)))
+        .map(|f| f.path)
+        .collect())
+}
+
+/// Point the library at a new root folder, e.g. after moving the music to a
+/// new drive or NAS, keeping every track id. Run it as a dry run first to see
+/// what would change; the move is refused if any file is not at its new path.
+#[tauri::command]
+pub async fn relocate_library(
+    state: State<'_, AppState>,
+    old_root: String,
+    new_root: String,
+    dry_run: bool,
+) -> Result<RelocationReport, String> {
+    let (old_root, new_root) = (old_root.trim(), new_root.trim());
+    if !Path::new(old_root).is_absolute() || !Path::new(new_root).is_absolute() {
+        return Err("Both folders must be absolute paths".to_string());
+    }
+    if Path::new(old_root) == Path::new(new_root) {
+        return Err("The new folder is the same as the old one".to_string());
+    }
+    if !Path::new(new_root).is_dir() {
+        return Err("The new folder does not exist or is not accessible".to_string());
+    }
+    if state.scan_running.load(Ordering::SeqCst) {
+        return Err("A scan is in progress".to_string());
+    }
+    write_db(&state)?
+        .relocate_library(old_root, new_root, dry_run, |path| Path::new(path).exists())
+        .map_err(sanitize_err("Relocating library"))
+}
+
 #[tauri::command]
 pub async fn cancel_scan(state: State<'_, AppState>) -> Result<(), String> {
     state.scan_cancelled.store(true, Ordering::SeqCst);
@@ -169,16 +247,47 @@ pub async fn get_tracks(
     state: State<'_, AppState>,
     filters: Option<TrackFilters>,
 ) -> Result<Vec<Track>, String> {
-    let result = lock_db(&state)?.get_tracks(filters.as_ref());
+    let result = read_db(&state)?.get_tracks(filters.as_ref());
     result.map_err(sanitize_err("Loading tracks"))
 }
 
+/// Tracks returned per page when no limit is given
+const DEFAULT_PAGE_SIZE: i64 = 500;
+const MAX_PAGE_SIZE: i64 = 5_000;
+
+/// One page of the track list, filtered, searched and sorted in the database
+#[tauri::command]
+pub async fn get_track_page(
+    state: State<'_, AppState>,
+    request: TrackPageRequest,
+) -> Result<TrackPage, String> {
+    let search = match request.search.as_deref().map(str::trim) {
+        Some(query) if !query.is_empty() => {
+            Some(SearchQuery::parse(query).map_err(|e| e.to_string())?)
+        }
+        _ => None,
+    };
+    let limit = request
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    read_db(&state)?
+        .get_track_page(
+            &request.filters,
+            search.as_ref(),
+            request.cursor.as_deref(),
+            limit,
+        )
+        .map_err(sanitize_err("Loading tracks"))
+}
+
 #[tauri::command]
 pub async fn search_tracks(
     state: State<'_, AppState>,
     query: String,
 ) -> Result<Vec<Track>, String> {
-    let result = lock_db(&state)?.search_tracks(&query);
+    let query = SearchQuery::parse(&query).map_err(|e| e.to_string())?;
+    let result = read_db(&state)?.search_tracks(&query);
     result.map_err(sanitize_err("Searching tracks"))
 }
 
@@ -210,25 +319,44 @@ pub async fn fetch_metadata(
 
 #[tauri::command]
 pub async fn play_track(state: State<'_, AppState>, track_id: i64) -> Result<(), String> {
+    // Remember where the outgoing track was left off
+    persist_playback_state(&state.db, &state.audio);
+
     // Get track info
-    let (file_path, track, release_mbid) = {
-        let db = lock_db(&state)?;
+    let (file_path, track, release_mbid, resume_ms) = {
+        let db = read_db(&state)?;
         let track = db
             .get_track_by_id(track_id)
             .map_err(sanitize_err("Loading track"))?;
+        if !Path::new(track.audio_path()).exists() {
+            return Err(
+                "The file for this track is missing or its drive is not connected".to_string(),
+            );
+        }
+        let resume_ms =
+            resume_point(&db, &track).map_err(sanitize_err("Loading resume position"))?;
         (
-            track.file_path.clone(),
+            track.audio_path().to_string(),
             track.clone(),
             track.release_mbid.clone(),
+            resume_ms,
         )
     };
 
-    // Play the track
+    // Play the track (CUE sheet virtual tracks play a range of the file)
     state
         .audio
-        .play_file(&file_path)
+        .play_item(playback_item(&track))
         .map_err(sanitize_err("Playing audio"))?;
 
+    // Long-form content continues where it was left off
+    if let Some(ms) = resume_ms {
+        state.audio.seek(ms as f64 / 1000.0);
+    }
+    write_db(&state)?
+        .save_session_track(Some(track_id), resume_ms.unwrap_or(0))
+        .map_err(sanitize_err("Saving playback session"))?;
+
     // Update media controls with track metadata
     if let Some(ref media_controls) = state.media_controls {
         // Fetch cover art using the same logic as get_track_cover
@@ -268,7 +396,7 @@ pub async fn play_track(state: State<'_, AppState>, track_id: i64) -> Result<(),
 
         let _ = media_controls.update_metadata(&metadata);
         let _ = media_controls.update_playback_state(PlaybackState::Playing);
-        let _ = media_controls.update_position(0.0);
+        let _ = media_controls.update_position(resume_ms.unwrap_or(0) as f64 / 1000.0);
 
         // Determine if next/previous are available (simplified - could check queue)
         let _ = media_controls.set_available_actions(true, true);
@@ -277,12 +405,172 @@ pub async fn play_track(state: State<'_, AppState>, track_id: i64) -> Result<(),
     Ok(())
 }
 
+/// Whether a track is long enough to remember its position
+fn is_long_form(track: &Track, settings: &ResumeSettings) -> bool {
+    settings.enabled
+        && track
+            .duration
+            .is_some_and(|d| d >= settings.min_duration_minutes as i64 * 60)
+}
+
+/// Where to start a long-form track, if it was left off part way through
+fn resume_point(db: &DatabaseInner, track: &Track) -> rusqlite::Result<Option<i64>> {
+    if !is_long_form(track, &db.load_resume_settings()?) {
+        return Ok(None);
+    }
+    Ok(db
+        .get_resume_position(track.id)?
+        .filter(|p| !p.finished && p.position_ms > 0)
+        .map(|p| p.position_ms))
+}
+
+/// Save the current track and position from `AudioState` to the playback session,
+/// and the resume position of long-form content. Called on pause, stop, track change
+/// and shutdown.
+pub fn persist_playback_state(db: &Database, audio: &AudioController) {
+    let Some(track_id) = audio.state.current_track_id() else {
+        return;
+    };
+    let position_ms = (audio.get_position() * 1000.0) as i64;
+
+    let mut db = match db.write() {
+        Ok(db) => db,
+        Err(e) => {
+            tracing::error!("Database lock poisoned: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = db.save_session_track(Some(track_id), position_ms) {
+        tracing::warn!("Failed to save playback session: {}", e);
+    }
+    if let Err(e) = save_resume_position(&mut db, track_id, position_ms) {
+        tracing::warn!(
+            "Failed to save resume position for track {}: {}",
+            track_id,
+            e
+        );
+    }
+}
+
+/// Mark long-form tracks that played to their end, or that a gapless transition
+/// moved on from, as finished. Polled alongside the position updates.
+pub fn persist_finished_tracks(db: &Database, audio: &AudioController) {
+    let finished = audio.state.take_finished();
+    if finished.is_empty() {
+        return;
+    }
+    let mut db = match db.write() {
+        Ok(db) => db,
+        Err(e) => {
+            tracing::error!("Database lock poisoned: {}", e);
+            return;
+        }
+    };
+    for (track_id, position_ms) in finished {
+        if let Err(e) = save_resume_position(&mut db, track_id, position_ms as i64) {
+            tracing::warn!(
+                "Failed to save resume position for track {}: {}",
+                track_id,
+                e
+            );
+        }
+    }
+}
+
+/// Save the whole playback session: track and position, volume and speed, and
+/// the queue and modes last saved by the frontend. Called at shutdown.
+pub fn persist_playback_session(
+    db: &Database,
+    audio: &AudioController,
+    context: &Mutex<Option<SessionContext>>,
+) {
+    persist_playback_state(db, audio);
+
+    let mut db = match db.write() {
+        Ok(db) => db,
+        Err(e) => {
+            tracing::error!("Database lock poisoned: {}", e);
+            return;
+        }
+    };
+    let saved = db
+        .save_session_volume(audio.state.volume())
+        .and_then(|_| db.save_session_speed(audio.state.speed()));
+    if let Err(e) = saved {
+        tracing::warn!("Failed to save playback session: {}", e);
+    }
+    let context = context.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(context) = context.as_ref() {
+        if let Err(e) = db.save_session_context(context) {
+            tracing::warn!("Failed to save playback session: {}", e);
+        }
+    }
+}
+
+/// Remember where long-form content was left off, or mark it finished when it
+/// was stopped near the end
+fn save_resume_position(
+    db: &mut DatabaseInner,
+    track_id: i64,
+    position_ms: i64,
+) -> rusqlite::Result<()> {
+    let settings = db.load_resume_settings()?;
+    let track = db.get_track_by_id(track_id)?;
+    if !is_long_form(&track, &settings) {
+        return Ok(());
+    }
+    let duration_ms = track.duration.unwrap_or(0) * 1000;
+    if position_ms >= duration_ms - RESUME_FINISHED_MARGIN_MS {
+        db.mark_track_finished(track_id)
+    } else {
+        db.save_resume_position(track_id, position_ms)
+    }
+}
+
+/// Load the previous session's track paused at its saved position, with its volume and speed
+pub fn restore_playback_session(db: &Database, audio: &AudioController) {
+    let session = match db.read() {
+        Ok(db) => db.load_playback_session(),
+        Err(e) => {
+            tracing::error!("Database lock poisoned: {}", e);
+            return;
+        }
+    };
+
+    match session {
+        Ok(Some(session)) => {
+            audio.set_volume(session.volume);
+            audio.set_speed(session.speed);
+            if let Some(track) = session.track {
+                if std::path::Path::new(track.audio_path()).exists() {
+                    audio.cue_item(playback_item(&track), session.position_ms as f64 / 1000.0);
+                }
+            }
+        }
+        Ok(None) => {}
+        Err(e) => tracing::warn!("Failed to restore playback session: {}", e),
+    }
+}
+
+/// What the audio thread should decode for a track
+fn playback_item(track: &Track) -> PlaybackItem {
+    PlaybackItem {
+        track_id: Some(track.id),
+        path: track.audio_path().to_string(),
+        start_ms: track.start_offset_ms.unwrap_or(0).max(0) as u64,
+        end_ms: track.end_offset_ms.map(|ms| ms.max(0) as u64),
+    }
+}
+
 #[tauri::command]
 pub async fn pause_playback(state: State<'_, AppState>) -> Result<(), String> {
     // Check state BEFORE toggling (audio.pause() is async via channel, can't check after)
     let was_playing = state.audio.state.is_playing();
     let current_position = state.audio.get_position();
 
+    if was_playing {
+        persist_playback_state(&state.db, &state.audio);
+    }
     state.audio.pause();
 
     // Update media controls with the NEW state (opposite of what it was)
@@ -301,6 +589,8 @@ pub async fn pause_playback(state: State<'_, AppState>) -> Result<(), String> {
 
 #[tauri::command]
 pub async fn stop_playback(state: State<'_, AppState>) -> Result<(), String> {
+    // Save before stopping: stop clears the current track and position
+    persist_playback_state(&state.db, &state.audio);
     state.audio.stop();
 
     // Update media controls playback state
@@ -315,7 +605,9 @@ pub async fn stop_playback(state: State<'_, AppState>) -> Result<(), String> {
 pub async fn set_volume(state: State<'_, AppState>, volume: f32) -> Result<(), String> {
     let clamped = volume.clamp(0.0, 1.0);
     state.audio.set_volume(clamped);
-    Ok(())
+    write_db(&state)?
+        .save_session_volume(clamped)
+        .map_err(sanitize_err("Saving playback session"))
 }
 
 #[tauri::command]
@@ -328,6 +620,12 @@ pub async fn seek_to_position(state: State<'_, AppState>, position: f64) -> Resu
     let clamped = if position < 0.0 { 0.0 } else { position };
     state.audio.seek(clamped);
 
+    if let Some(track_id) = state.audio.state.current_track_id() {
+        write_db(&state)?
+            .save_session_track(Some(track_id), (clamped * 1000.0) as i64)
+            .map_err(sanitize_err("Saving playback session"))?;
+    }
+
     // Update media controls position
     if let Some(ref media_controls) = state.media_controls {
         let _ = media_controls.update_position(clamped);
@@ -336,9 +634,154 @@ pub async fn seek_to_position(state: State<'_, AppState>, position: f64) -> Resu
     Ok(())
 }
 
+// Playback session
+
+/// Tracks accepted in a saved queue ("play all" on a large library queues everything)
+const MAX_SESSION_QUEUE: usize = 200_000;
+
+#[tauri::command]
+pub async fn get_playback_session(
+    state: State<'_, AppState>,
+) -> Result<Option<PlaybackSession>, String> {
+    read_db(&state)?
+        .load_playback_session()
+        .map_err(sanitize_err("Loading playback session"))
+}
+
+/// Save the queue, shuffle/repeat modes and active playlist whenever they change.
+/// Track, position, volume and speed are saved by the playback commands.
+#[tauri::command]
+pub async fn save_playback_session(
+    state: State<'_, AppState>,
+    context: SessionContext,
+) -> Result<(), String> {
+    if !["off", "list", "track"].contains(&context.repeat_mode.as_str()) {
+        return Err("Repeat mode must be one of off, list or track".to_string());
+    }
+    if context.queue.len() > MAX_SESSION_QUEUE {
+        return Err(format!(
+            "Cannot save a queue of more than {} tracks",
+            MAX_SESSION_QUEUE
+        ));
+    }
+    write_db(&state)?
+        .save_session_context(&context)
+        .map_err(sanitize_err("Saving playback session"))?;
+    *state
+        .session_context
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner) = Some(context);
+    Ok(())
+}
+
+// Resume positions
+
+#[tauri::command]
+pub async fn get_resume_position(
+    state: State<'_, AppState>,
+    track_id: i64,
+) -> Result<Option<ResumePosition>, String> {
+    read_db(&state)?
+        .get_resume_position(track_id)
+        .map_err(sanitize_err("Loading resume position"))
+}
+
+#[tauri::command]
+pub async fn clear_resume_position(
+    state: State<'_, AppState>,
+    track_id: i64,
+) -> Result<(), String> {
+    write_db(&state)?
+        .clear_resume_position(track_id)
+        .map_err(sanitize_err("Clearing resume position"))
+}
+
+#[tauri::command]
+pub async fn mark_track_finished(state: State<'_, AppState>, track_id: i64) -> Result<(), String> {
+    write_db(&state)?
+        .mark_track_finished(track_id)
+        .map_err(sanitize_err("Marking track finished"))
+}
+
+#[tauri::command]
+pub async fn get_resume_settings(state: State<'_, AppState>) -> Result<ResumeSettings, String> {
+    read_db(&state)?
+        .load_resume_settings()
+        .map_err(sanitize_err("Loading resume settings"))
+}
+
+#[tauri::command]
+pub async fn set_resume_settings(
+    state: State<'_, AppState>,
+    settings: ResumeSettings,
+) -> Result<(), String> {
+    write_db(&state)?
+        .save_resume_settings(&settings)
+        .map_err(sanitize_err("Saving resume settings"))
+}
+
+// Chapters
+
+#[tauri::command]
+pub async fn get_track_chapters(
+    state: State<'_, AppState>,
+    track_id: i64,
+) -> Result<Vec<Chapter>, String> {
+    read_db(&state)?
+        .get_track_chapters(track_id)
+        .map_err(sanitize_err("Loading chapters"))
+}
+
+#[tauri::command]
+pub async fn next_chapter(state: State<'_, AppState>) -> Result<Option<Chapter>, String> {
+    skip_chapter_with_controls(&state, true)
+}
+
+#[tauri::command]
+pub async fn previous_chapter(state: State<'_, AppState>) -> Result<Option<Chapter>, String> {
+    skip_chapter_with_controls(&state, false)
+}
+
+fn skip_chapter_with_controls(state: &AppState, forward: bool) -> Result<Option<Chapter>, String> {
+    let chapter = skip_chapter(&state.db, &state.audio, forward)?;
+    if let (Some(chapter), Some(media_controls)) = (&chapter, &state.media_controls) {
+        let _ = media_controls.update_position(chapter.start_ms as f64 / 1000.0);
+    }
+    Ok(chapter)
+}
+
+/// Seek to the next or previous chapter of the current track. Returns `None` (without
+/// seeking) when the track has no chapters or there is no chapter in that direction.
+pub fn skip_chapter(
+    db: &Database,
+    audio: &AudioController,
+    forward: bool,
+) -> Result<Option<Chapter>, String> {
+    let Some(track_id) = audio.state.current_track_id() else {
+        return Ok(None);
+    };
+    let chapters = db
+        .read()
+        .map_err(db_unavailable)?
+        .get_track_chapters(track_id)
+        .map_err(sanitize_err("Loading chapters"))?;
+
+    let position_ms = (audio.get_position() * 1000.0) as i64;
+    let target = if forward {
+        chapters::next_chapter(&chapters, position_ms)
+    } else {
+        chapters::previous_chapter(&chapters, position_ms)
+    };
+
+    Ok(target.map(|chapter| {
+        audio.seek(chapter.start_ms as f64 / 1000.0);
+        chapter.clone()
+    }))
+}
+
 #[tauri::command]
 pub async fn delete_track(state: State<'_, AppState>, track_id: i64) -> Result<(), String> {
-    let result = lock_db(&state)?.delete_track(track_id);
+    let result = write_db(&state)?.delete_track(track_id);
     result.map_err(sanitize_err("Deleting track"))
 }
 
@@ -350,7 +793,7 @@ pub async fn delete_tracks(state: State<'_, AppState>, track_ids: Vec<i64>) -> R
             MAX_BATCH_SIZE
         ));
     }
-    let result = lock_db(&state)?.delete_tracks(&track_ids);
+    let result = write_db(&state)?.delete_tracks(&track_ids);
     result.map_err(sanitize_err("Deleting tracks"))
 }
 
@@ -416,10 +859,10 @@ pub async fn get_track_cover(
     track_id: i64,
 ) -> Result<Option<String>, String> {
     let (file_path, release_mbid) = {
-        let track = lock_db(&state)?
+        let track = read_db(&state)?
             .get_track_by_id(track_id)
             .map_err(sanitize_err("Loading track"))?;
-        (track.file_path, track.release_mbid)
+        (track.audio_path().to_string(), track.release_mbid)
     };
 
     // 1. Try embedded art first
@@ -465,8 +908,12 @@ pub async fn fetch_covers(
 
     for track_id in track_ids {
         let (file_path, release_mbid, title) = {
-            match lock_db(&state)?.get_track_by_id(track_id) {
-                Ok(track) => (track.file_path, track.release_mbid, track.title),
+            match read_db(&state)?.get_track_by_id(track_id) {
+                Ok(track) => (
+                    track.audio_path().to_string(),
+                    track.release_mbid,
+                    track.title,
+                ),
                 Err(_) => {
                     results.push(CoverFetchResult {
                         track_id,
@@ -534,61 +981,71 @@ pub async fn fetch_covers(
     Ok(results)
 }
 
+/// Album list entry with cover art from local sources only (embedded art and
+/// cached covers), so listing albums never waits on the network
+fn album_info(
+    (
+        id,
+        album_name,
+        artist,
+        year,
+        track_count,
+        total_duration,
+        created_at,
+        file_path,
+        release_mbid,
+        compilation,
+    ): AlbumCoverInfo,
+) -> AlbumInfo {
+    let cover_art = if let Some(ref path) = file_path {
+        if let Some(cover) = extract_embedded_cover(path) {
+            Some(cover)
+        } else if let Some(ref mbid) = release_mbid {
+            let cache_path = get_cached_cover_path(mbid);
+            if cache_path.exists() {
+                std::fs::read(&cache_path).ok().map(|data| {
+                    let base64_data = BASE64.encode(&data);
+                    format!("data:image/jpeg;base64,{}", base64_data)
+                })
+            } else {
+                None
+            }
+        } else {
+            None
+        }
+    } else {
+        None
+    };
+
+    AlbumInfo {
+        id,
+        name: album_name,
+        artist,
+        year,
+        track_count,
+        total_duration,
+        created_at,
+        cover_art,
+        compilation,
+    }
+}
+
 #[tauri::command]
 pub async fn get_albums(state: State<'_, AppState>) -> Result<Vec<AlbumInfo>, String> {
     // Single query returns album info with first track's file_path and release_mbid
-    let album_data = lock_db(&state)?
-        .get_albums_with_cover_info()
+    let album_data = read_db(&state)?
+        .get_albums_with_cover_info(false)
         .map_err(sanitize_err("Loading albums"))?;
+    Ok(album_data.into_iter().map(album_info).collect())
+}
 
-    let albums = album_data
-        .into_iter()
-        .map(
-            |(
-                album_name,
-                artist,
-                year,
-                track_count,
-                total_duration,
-                created_at,
-                file_path,
-                release_mbid,
-            )| {
-                // Only check local sources (embedded art + cached covers) - no network fetches
-                let cover_art = if let Some(ref path) = file_path {
-                    if let Some(cover) = extract_embedded_cover(path) {
-                        Some(cover)
-                    } else if let Some(ref mbid) = release_mbid {
-                        let cache_path = get_cached_cover_path(mbid);
-                        if cache_path.exists() {
-                            std::fs::read(&cache_path).ok().map(|data| {
-                                let base64_data = BASE64.encode(&data);
-                                format!("data:image/jpeg;base64,{}", base64_data)
-                            })
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                };
-
-                AlbumInfo {
-                    name: album_name,
-                    artist,
-                    year,
-                    track_count,
-                    total_duration,
-                    created_at,
-                    cover_art,
-                }
-            },
-        )
-        .collect();
-
-    Ok(albums)
+/// The "Various Artists" view: compilation albums
+#[tauri::command]
+pub async fn get_compilation_albums(state: State<'_, AppState>) -> Result<Vec<AlbumInfo>, String> {
+    let album_data = read_db(&state)?
+        .get_albums_with_cover_info(true)
+        .map_err(sanitize_err("Loading albums"))?;
+    Ok(album_data.into_iter().map(album_info).collect())
 }
 
 #[tauri::command]
@@ -597,12 +1054,106 @@ pub async fn get_album_tracks(
     album_name: String,
     artist: Option<String>,
 ) -> Result<Vec<Track>, String> {
-    let tracks = lock_db(&state)?
+    let tracks = read_db(&state)?
         .get_album_tracks(&album_name, artist.as_deref())
         .map_err(sanitize_err("Loading album tracks"))?;
     Ok(tracks)
 }
 
+#[tauri::command]
+pub async fn get_album_tracks_by_id(
+    state: State<'_, AppState>,
+    album_id: i64,
+) -> Result<Vec<Track>, String> {
+    read_db(&state)?
+        .get_album_tracks_by_id(album_id)
+        .map_err(sanitize_err("Loading album tracks"))
+}
+
+#[tauri::command]
+pub async fn get_artists(state: State<'_, AppState>) -> Result<Vec<ArtistInfo>, String> {
+    read_db(&state)?
+        .get_artists()
+        .map_err(sanitize_err("Loading artists"))
+}
+
+#[tauri::command]
+pub async fn get_genres(state: State<'_, AppState>) -> Result<Vec<GenreInfo>, String> {
+    read_db(&state)?
+        .get_genres()
+        .map_err(sanitize_err("Loading genres"))
+}
+
+/// Artists credited on a track, including featured artists
+#[tauri::command]
+pub async fn get_track_credits(
+    state: State<'_, AppState>,
+    track_id: i64,
+) -> Result<Vec<ArtistCredit>, String> {
+    read_db(&state)?
+        .get_track_credits(track_id)
+        .map_err(sanitize_err("Loading track credits"))
+}
+
+const MAX_TAG_SEPARATORS: usize = 16;
+
+#[tauri::command]
+pub async fn get_tag_separators(state: State<'_, AppState>) -> Result<Vec<String>, String> {
+    read_db(&state)?
+        .load_tag_separators()
+        .map_err(sanitize_err("Loading tag separators"))
+}
+
+/// Change how artist and genre tags are split; the whole library is re-split
+#[tauri::command]
+pub async fn set_tag_separators(
+    state: State<'_, AppState>,
+    separators: Vec<String>,
+) -> Result<(), String> {
+    if separators.len() > MAX_TAG_SEPARATORS {
+        return Err(format!(
+            "At most {} separators can be used",
+            MAX_TAG_SEPARATORS
+        ));
+    }
+    if separators.iter().any(|s| s.trim().is_empty()) {
+        return Err("Separators cannot be empty or whitespace".to_string());
+    }
+    write_db(&state)?
+        .save_tag_separators(&separators)
+        .map_err(sanitize_err("Saving tag separators"))
+}
+
+const MAX_SORT_ARTICLES: usize = 32;
+
+#[tauri::command]
+pub async fn get_sort_articles(state: State<'_, AppState>) -> Result<Vec<String>, String> {
+    read_db(&state)?
+        .load_sort_articles()
+        .map_err(sanitize_err("Loading sort articles"))
+}
+
+/// Change the articles ignored when sorting; sort keys are worked out again
+/// for the whole library
+#[tauri::command]
+pub async fn set_sort_articles(
+    state: State<'_, AppState>,
+    articles: Vec<String>,
+) -> Result<(), String> {
+    if articles.len() > MAX_SORT_ARTICLES {
+        return Err(format!(
+            "At most {} articles can be used",
+            MAX_SORT_ARTICLES
+        ));
+    }
+    if articles.iter().any(|a| a.trim().is_empty()) {
+        return Err("Articles cannot be empty or whitespace".to_string());
+    }
+    write_db(&state)?
+        .save_sort_articles(&articles)
+        .map_err(sanitize_err("Saving sort articles"))
+}
+
 #[tauri::command]
 pub async fn get_album_cover(
     state: State<'_, AppState>,
@@ -610,7 +1161,7 @@ pub async fn get_album_cover(
     artist: Option<String>,
 ) -> Result<Option<String>, String> {
     // Find first track in album (prefer one with release_mbid)
-    let track_id = lock_db(&state)?
+    let track_id = read_db(&state)?
         .get_album_first_track_id(&album_name, artist.as_deref())
         .map_err(sanitize_err("Loading album cover"))?;
 
@@ -628,7 +1179,7 @@ pub async fn play_album(
     album_name: String,
     artist: Option<String>,
 ) -> Result<(), String> {
-    let tracks = lock_db(&state)?
+    let tracks = read_db(&state)?
         .get_album_tracks(&album_name, artist.as_deref())
         .map_err(sanitize_err("Loading album"))?;
 
@@ -654,11 +1205,85 @@ pub async fn update_track_metadata_manual(
     genre: Option<String>,
     track_number: Option<i64>,
 ) -> Result<(), String> {
-    lock_db(&state)?
+    write_db(&state)?
         .update_track_metadata_manual(track_id, title, artist, album, year, genre, track_number)
         .map_err(sanitize_err("Updating metadata"))
 }
 
+/// Store a rating in a tag: an ID3v2 POPM frame, or a RATING comment for
+/// Vorbis and APE tags. `None` or 0 removes it. Returns false for other tag types.
+fn set_tag_rating(tag: &mut lofty::tag::Tag, rating: Option<i64>) -> bool {
+    use lofty::tag::{ItemKey, ItemValue, TagItem, TagType};
+
+    let rating = rating.filter(|&r| r > 0);
+    match tag.tag_type() {
+        TagType::Id3v2 => {
+            tag.retain(|item| item.key() != &ItemKey::Popularimeter);
+            rating.is_none_or(|r| {
+                tag.insert(TagItem::new(
+                    ItemKey::Popularimeter,
+                    ItemValue::Binary(ratings::popm_frame(r)),
+                ))
+            })
+        }
+        TagType::VorbisComments | TagType::Ape => {
+            tag.retain(|item| {
+                !matches!(item.key(), ItemKey::Unknown(key)
+                    if key.eq_ignore_ascii_case("RATING") || key.eq_ignore_ascii_case("FMPS_RATING"))
+            });
+            rating.is_none_or(|r| {
+                tag.insert_text(
+                    ItemKey::Unknown("RATING".to_string()),
+                    ratings::text_rating(r),
+                )
+            })
+        }
+        _ => false,
+    }
+}
+
+/// Read a file's primary tag (or its first tag), apply `edit`, and save it
+fn edit_file_tag(
+    file_path: &str,
+    edit: impl FnOnce(&mut lofty::tag::Tag) -> Result<(), String>,
+) -> Result<(), String> {
+    use lofty::config::WriteOptions;
+    use lofty::prelude::TagExt;
+
+    // Read the file
+    let mut tagged_file =
+        lofty::read_from_path(file_path).map_err(sanitize_err("Reading audio file"))?;
+
+    // Get or create primary tag
+    let has_primary = tagged_file.primary_tag().is_some();
+    let tag = if has_primary {
+        tagged_file
+            .primary_tag_mut()
+            .ok_or_else(|| "Primary tag not found in file".to_string())?
+    } else {
+        tagged_file
+            .first_tag_mut()
+            .ok_or_else(|| "No tag found in file".to_string())?
+    };
+
+    edit(tag)?;
+
+    // Save to file
+    tag.save_to_path(file_path, WriteOptions::default())
+        .map_err(sanitize_err("Writing metadata"))
+}
+
+/// Write a track's rating to its file
+fn write_rating_tag(file_path: &str, rating: Option<i64>) -> Result<(), String> {
+    edit_file_tag(file_path, |tag| {
+        if set_tag_rating(tag, rating) {
+            Ok(())
+        } else {
+            Err("This file's tags cannot store a rating".to_string())
+        }
+    })
+}
+
 #[tauri::command]
 pub async fn write_metadata_to_file(
     state: State<'_, AppState>,
@@ -670,76 +1295,252 @@ pub async fn write_metadata_to_file(
     genre: Option<String>,
     track_number: Option<i64>,
 ) -> Result<(), String> {
-    use lofty::config::WriteOptions;
-    use lofty::prelude::{Accessor, TagExt};
+    use lofty::prelude::Accessor;
 
     let file_path = {
-        let track = lock_db(&state)?
+        let track = read_db(&state)?
             .get_track_by_id(track_id)
             .map_err(sanitize_err("Reading audio file"))?;
+        // Tags describe the whole file, not a single CUE sheet entry
+        if track.source_path.is_some() {
+            return Err("Cannot write tags for a track defined by a CUE sheet".to_string());
+        }
         track.file_path
     };
 
-    // Read the file
-    let mut tagged_file =
-        lofty::read_from_path(&file_path).map_err(sanitize_err("Reading audio file"))?;
+    edit_file_tag(&file_path, |tag| {
+        // Update fields
+        if let Some(t) = title {
+            tag.set_title(t);
+        }
+        if let Some(a) = artist {
+            tag.set_artist(a);
+        }
+        if let Some(al) = album {
+            tag.set_album(al);
+        }
+        if let Some(y) = year {
+            if let Ok(valid) = u32::try_from(y) {
+                tag.set_year(valid);
+            }
+        }
+        if let Some(g) = genre {
+            tag.set_genre(g);
+        }
+        if let Some(tn) = track_number {
+            if let Ok(valid) = u32::try_from(tn) {
+                tag.set_track(valid);
+            }
+        }
+        Ok(())
+    })
+}
 
-    // Get or create primary tag
-    let has_primary = tagged_file.primary_tag().is_some();
-    let tag = if has_primary {
-        tagged_file
-            .primary_tag_mut()
-            .ok_or_else(|| "Primary tag not found in file".to_string())?
-    } else {
-        tagged_file
-            .first_tag_mut()
-            .ok_or_else(|| "No tag found in file".to_string())?
+/// Save a track's extended tags (composer, conductor, label, ISRC, BPM, ...),
+/// and optionally write them to its file. `None` fields are cleared.
+#[tauri::command]
+pub async fn update_extended_tags(
+    state: State<'_, AppState>,
+    track_id: i64,
+    tags: ExtendedTags,
+    write_to_file: bool,
+) -> Result<(), String> {
+    let track = {
+        let mut db = write_db(&state)?;
+        let track = db
+            .get_track_by_id(track_id)
+            .map_err(|_| format!("Track {} not found", track_id))?;
+        db.update_extended_tags(track_id, &tags)
+            .map_err(sanitize_err("Updating metadata"))?;
+        track
     };
 
-    // Update fields
-    if let Some(t) = title {
-        tag.set_title(t);
-    }
-    if let Some(a) = artist {
-        tag.set_artist(a);
+    if !write_to_file {
+        return Ok(());
     }
-    if let Some(al) = album {
-        tag.set_album(al);
+    // Tags describe the whole file, not a single CUE sheet entry
+    if track.source_path.is_some() {
+        return Err("Cannot write tags for a track defined by a CUE sheet".to_string());
     }
-    if let Some(y) = year {
-        if let Ok(valid) = u32::try_from(y) {
-            tag.set_year(valid);
+    edit_file_tag(&track.file_path, |tag| {
+        set_extended_tags(tag, &tags);
+        Ok(())
+    })
+}
+
+/// Write extended tags with lofty's generic keys. Fields the tag format has
+/// no place for are skipped.
+fn set_extended_tags(tag: &mut lofty::tag::Tag, tags: &ExtendedTags) {
+    use lofty::prelude::Accessor;
+    use lofty::tag::ItemKey;
+
+    let text_fields = [
+        (ItemKey::Composer, &tags.composer),
+        (ItemKey::Conductor, &tags.conductor),
+        (ItemKey::Performer, &tags.performer),
+        (ItemKey::Work, &tags.work),
+        (ItemKey::Movement, &tags.movement),
+        (ItemKey::Label, &tags.label),
+        (ItemKey::CatalogNumber, &tags.catalog_number),
+        (ItemKey::Isrc, &tags.isrc),
+        (ItemKey::TrackTitleSortOrder, &tags.title_sort),
+        (ItemKey::TrackArtistSortOrder, &tags.artist_sort),
+        (ItemKey::AlbumTitleSortOrder, &tags.album_sort),
+    ];
+    for (key, value) in text_fields {
+        tag.remove_key(&key);
+        if let Some(value) = value {
+            tag.insert_text(key, value.clone());
         }
     }
-    if let Some(g) = genre {
-        tag.set_genre(g);
-    }
-    if let Some(tn) = track_number {
-        if let Ok(valid) = u32::try_from(tn) {
-            tag.set_track(valid);
+
+    tag.remove_key(&ItemKey::IntegerBpm);
+    tag.remove_key(&ItemKey::Bpm);
+    if let Some(bpm) = tags.bpm {
+        // MP4 only has the integer `tmpo` atom
+        if !tag.insert_text(ItemKey::IntegerBpm, bpm.to_string()) {
+            tag.insert_text(ItemKey::Bpm, bpm.to_string());
         }
     }
 
-    // Save to file
-    tag.save_to_path(&file_path, WriteOptions::default())
-        .map_err(sanitize_err("Writing metadata"))?;
+    match &tags.comment {
+        Some(comment) => tag.set_comment(comment.clone()),
+        None => tag.remove_comment(),
+    }
+}
+
+// Rating and favorite commands
 
+/// Rate a track in half stars (0–10, `None` to clear). When rating tags are
+/// enabled the rating is also written to the file.
+#[tauri::command]
+pub async fn set_track_rating(
+    state: State<'_, AppState>,
+    track_id: i64,
+    rating: Option<i64>,
+) -> Result<(), String> {
+    let rating = ratings::validate_rating(rating)?;
+    let (track, write_tag) = {
+        let mut db = write_db(&state)?;
+        let track = db
+            .get_track_by_id(track_id)
+            .map_err(|_| format!("Track {} not found", track_id))?;
+        db.set_track_rating(track_id, rating)
+            .map_err(sanitize_err("Saving rating"))?;
+        let write_tag = db
+            .load_rating_tags_enabled()
+            .map_err(sanitize_err("Loading settings"))?;
+        (track, write_tag)
+    };
+
+    // Tags describe the whole file, so CUE sheet entries are only rated in the library
+    if write_tag && track.source_path.is_none() {
+        write_rating_tag(&track.file_path, rating)?;
+    }
     Ok(())
 }
 
+#[tauri::command]
+pub async fn set_track_favorite(
+    state: State<'_, AppState>,
+    track_id: i64,
+    favorite: bool,
+) -> Result<(), String> {
+    let updated = write_db(&state)?
+        .set_track_favorite(track_id, favorite)
+        .map_err(sanitize_err("Saving favorite"))?;
+    if updated {
+        Ok(())
+    } else {
+        Err(format!("Track {} not found", track_id))
+    }
+}
+
+#[tauri::command]
+pub async fn set_album_rating(
+    state: State<'_, AppState>,
+    album_name: String,
+    artist: Option<String>,
+    rating: Option<i64>,
+) -> Result<(), String> {
+    let rating = ratings::validate_rating(rating)?;
+    let result = write_db(&state)?.set_album_rating(&album_name, artist.as_deref(), rating);
+    result.map_err(sanitize_err("Saving album rating"))
+}
+
+#[tauri::command]
+pub async fn set_album_favorite(
+    state: State<'_, AppState>,
+    album_name: String,
+    artist: Option<String>,
+    favorite: bool,
+) -> Result<(), String> {
+    let result = write_db(&state)?.set_album_favorite(&album_name, artist.as_deref(), favorite);
+    result.map_err(sanitize_err("Saving album favorite"))
+}
+
+#[tauri::command]
+pub async fn set_artist_rating(
+    state: State<'_, AppState>,
+    artist: String,
+    rating: Option<i64>,
+) -> Result<(), String> {
+    let rating = ratings::validate_rating(rating)?;
+    let result = write_db(&state)?.set_artist_rating(&artist, rating);
+    result.map_err(sanitize_err("Saving artist rating"))
+}
+
+#[tauri::command]
+pub async fn set_artist_favorite(
+    state: State<'_, AppState>,
+    artist: String,
+    favorite: bool,
+) -> Result<(), String> {
+    let result = write_db(&state)?.set_artist_favorite(&artist, favorite);
+    result.map_err(sanitize_err("Saving artist favorite"))
+}
+
+#[tauri::command]
+pub async fn get_album_ratings(state: State<'_, AppState>) -> Result<Vec<AlbumRating>, String> {
+    let result = read_db(&state)?.get_album_ratings();
+    result.map_err(sanitize_err("Loading album ratings"))
+}
+
+#[tauri::command]
+pub async fn get_artist_ratings(state: State<'_, AppState>) -> Result<Vec<ArtistRating>, String> {
+    let result = read_db(&state)?.get_artist_ratings();
+    result.map_err(sanitize_err("Loading artist ratings"))
+}
+
+#[tauri::command]
+pub async fn get_rating_tags_enabled(state: State<'_, AppState>) -> Result<bool, String> {
+    let result = read_db(&state)?.load_rating_tags_enabled();
+    result.map_err(sanitize_err("Loading settings"))
+}
+
+/// Read ratings from file tags while scanning, and write them back when rating tracks
+#[tauri::command]
+pub async fn set_rating_tags_enabled(
+    state: State<'_, AppState>,
+    enabled: bool,
+) -> Result<(), String> {
+    let result = write_db(&state)?.save_rating_tags_enabled(enabled);
+    result.map_err(sanitize_err("Saving settings"))
+}
+
 // Playlist commands
 #[tauri::command]
 pub async fn create_playlist(state: State<'_, AppState>, name: String) -> Result<i64, String> {
     if name.trim().is_empty() {
         return Err("Playlist name cannot be empty".to_string());
     }
-    let result = lock_db(&state)?.create_playlist(&name);
+    let result = write_db(&state)?.create_playlist(&name);
     result.map_err(sanitize_err("Creating playlist"))
 }
 
 #[tauri::command]
 pub async fn delete_playlist(state: State<'_, AppState>, id: i64) -> Result<(), String> {
-    let result = lock_db(&state)?.delete_playlist(id);
+    let result = write_db(&state)?.delete_playlist(id);
     result.map_err(sanitize_err("Deleting playlist"))
 }
 
@@ -752,19 +1553,19 @@ pub async fn rename_playlist(
     if new_name.trim().is_empty() {
         return Err("Playlist name cannot be empty".to_string());
     }
-    let result = lock_db(&state)?.rename_playlist(id, &new_name);
+    let result = write_db(&state)?.rename_playlist(id, &new_name);
     result.map_err(sanitize_err("Renaming playlist"))
 }
 
 #[tauri::command]
 pub async fn get_playlists(state: State<'_, AppState>) -> Result<Vec<Playlist>, String> {
-    let result = lock_db(&state)?.get_playlists();
+    let result = read_db(&state)?.get_playlists();
     result.map_err(sanitize_err("Loading playlists"))
 }
 
 #[tauri::command]
 pub async fn get_playlist(state: State<'_, AppState>, id: i64) -> Result<Playlist, String> {
-    let result = lock_db(&state)?.get_playlist(id);
+    let result = read_db(&state)?.get_playlist(id);
     result.map_err(sanitize_err("Loading playlist"))
 }
 
@@ -773,7 +1574,7 @@ pub async fn get_playlist_tracks(
     state: State<'_, AppState>,
     playlist_id: i64,
 ) -> Result<Vec<Track>, String> {
-    let result = lock_db(&state)?.get_playlist_tracks(playlist_id);
+    let result = read_db(&state)?.get_playlist_tracks(playlist_id);
     result.map_err(sanitize_err("Loading playlist tracks"))
 }
 
@@ -785,9 +1586,8 @@ pub async fn add_track_to_playlist(
     position: Option<i64>,
 ) -> Result<(), String> {
     // Validate playlist and track exist
-    let mut db = lock_db(&state)?;
-    db.get_playlist(playlist_id)
-        .map_err(|_| format!("Playlist {} not found", playlist_id))?;
+    let mut db = write_db(&state)?;
+    ensure_manual_playlist(&db, playlist_id)?;
     db.get_track_by_id(track_id)
         .map_err(|_| format!("Track {} not found", track_id))?;
     db.add_track_to_playlist(playlist_id, track_id, position)
@@ -806,8 +1606,31 @@ pub async fn add_tracks_to_playlist(
             MAX_BATCH_SIZE
         ));
     }
-    lock_db(&state)?
-        .add_tracks_to_playlist_batch(playlist_id, &track_ids)
+    let mut db = write_db(&state)?;
+    ensure_manual_playlist(&db, playlist_id)?;
+    db.add_tracks_to_playlist_batch(playlist_id, &track_ids)
+        .map_err(sanitize_err("Adding to playlist"))
+}
+
+/// Append an album's tracks, in track order, to a playlist
+#[tauri::command]
+pub async fn add_album_to_playlist(
+    state: State<'_, AppState>,
+    playlist_id: i64,
+    album_id: i64,
+) -> Result<(), String> {
+    let mut db = write_db(&state)?;
+    ensure_manual_playlist(&db, playlist_id)?;
+    let track_ids: Vec<i64> = db
+        .get_album_tracks_by_id(album_id)
+        .map_err(sanitize_err("Loading album tracks"))?
+        .iter()
+        .map(|t| t.id)
+        .collect();
+    if track_ids.is_empty() {
+        return Err(format!("Album {} not found", album_id));
+    }
+    db.add_tracks_to_playlist_batch(playlist_id, &track_ids)
         .map_err(sanitize_err("Adding to playlist"))
 }
 
@@ -817,7 +1640,9 @@ pub async fn remove_track_from_playlist(
     playlist_id: i64,
     track_id: i64,
 ) -> Result<(), String> {
-    let result = lock_db(&state)?.remove_track_from_playlist(playlist_id, track_id);
+    let mut db = write_db(&state)?;
+    ensure_manual_playlist(&db, playlist_id)?;
+    let result = db.remove_track_from_playlist(playlist_id, track_id);
     result.map_err(sanitize_err("Removing from playlist"))
 }
 
@@ -827,7 +1652,9 @@ pub async fn reorder_playlist_tracks(
     playlist_id: i64,
     track_positions: Vec<(i64, i64)>,
 ) -> Result<(), String> {
-    let result = lock_db(&state)?.reorder_playlist_tracks(playlist_id, track_positions);
+    let mut db = write_db(&state)?;
+    ensure_manual_playlist(&db, playlist_id)?;
+    let result = db.reorder_playlist_tracks(playlist_id, track_positions);
     result.map_err(sanitize_err("Reordering playlist"))
 }
 
@@ -840,13 +1667,109 @@ pub async fn duplicate_playlist(
     if new_name.trim().is_empty() {
         return Err("Playlist name cannot be empty".to_string());
     }
-    let result = lock_db(&state)?.duplicate_playlist(playlist_id, &new_name);
+    let result = write_db(&state)?.duplicate_playlist(playlist_id, &new_name);
     result.map_err(sanitize_err("Duplicating playlist"))
 }
 
+/// Largest `limit` a smart playlist may set
+const MAX_SMART_PLAYLIST_LIMIT: i64 = 100_000;
+
+/// Track lists can only be edited on manual playlists; smart ones follow their rules
+fn ensure_manual_playlist(db: &DatabaseInner, playlist_id: i64) -> Result<(), String> {
+    match db.get_playlist_kind(playlist_id) {
+        Ok(Some(PlaylistKind::Manual)) => Ok(()),
+        Ok(Some(PlaylistKind::Smart)) => {
+            Err("Smart playlist tracks are chosen by its rules and cannot be edited".to_string())
+        }
+        Ok(None) => Err(format!("Playlist {} not found", playlist_id)),
+        Err(e) => Err(sanitize_err("Loading playlist")(e)),
+    }
+}
+
+fn validate_smart_rules(rules: &SmartPlaylistRules) -> Result<(), String> {
+    if let Some(limit) = rules.limit {
+        if !(1..=MAX_SMART_PLAYLIST_LIMIT).contains(&limit) {
+            return Err(format!(
+                "Smart playlist limit must be between 1 and {}",
+                MAX_SMART_PLAYLIST_LIMIT
+            ));
+        }
+    }
+
+    let mut pending = vec![&rules.rule];
+    while let Some(rule) = pending.pop() {
+        match rule {
+            SmartRule::All { rules } | SmartRule::Any { rules } => pending.extend(rules),
+            SmartRule::Not { rule } => pending.push(rule),
+            SmartRule::InLastDays { days, .. } if *days < 0 => {
+                return Err("Smart playlist day ranges cannot be negative".to_string());
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn create_smart_playlist(
+    state: State<'_, AppState>,
+    name: String,
+    rules: SmartPlaylistRules,
+) -> Result<i64, String> {
+    if name.trim().is_empty() {
+        return Err("Playlist name cannot be empty".to_string());
+    }
+    validate_smart_rules(&rules)?;
+    let result = write_db(&state)?.create_smart_playlist(&name, &rules);
+    result.map_err(sanitize_err("Creating smart playlist"))
+}
+
+#[tauri::command]
+pub async fn update_smart_playlist(
+    state: State<'_, AppState>,
+    id: i64,
+    name: String,
+    rules: SmartPlaylistRules,
+) -> Result<(), String> {
+    if name.trim().is_empty() {
+        return Err("Playlist name cannot be empty".to_string());
+    }
+    validate_smart_rules(&rules)?;
+    let updated = write_db(&state)?
+        .update_smart_playlist(id, &name, &rules)
+        .map_err(sanitize_err("Updating smart playlist"))?;
+    if updated {
+        Ok(())
+    } else {
+        Err(format!("Smart playlist {} not found", id))
+    }
+}
+
+#[tauri::command]
+pub async fn get_smart_playlist_rules(
+    state: State<'_, AppState>,
+    id: i64,
+) -> Result<SmartPlaylistRules, String> {
+    read_db(&state)?
+        .get_smart_playlist_rules(id)
+        .map_err(sanitize_err("Loading smart playlist"))?
+        .ok_or_else(|| format!("Smart playlist {} not found", id))
+}
+
+/// Evaluate rules without saving them, for the smart playlist editor
+#[tauri::command]
+pub async fn preview_smart_playlist(
+    state: State<'_, AppState>,
+    rules: SmartPlaylistRules,
+) -> Result<Vec<Track>, String> {
+    validate_smart_rules(&rules)?;
+    let result = read_db(&state)?.evaluate_smart_playlist(&rules);
+    result.map_err(sanitize_err("Previewing smart playlist"))
+}
+
 #[tauri::command]
 pub async fn play_playlist(state: State<'_, AppState>, playlist_id: i64) -> Result<(), String> {
-    let tracks = lock_db(&state)?
+    let tracks = read_db(&state)?
         .get_playlist_tracks(playlist_id)
         .map_err(sanitize_err("Loading playlist"))?;
 
@@ -920,7 +1843,7 @@ pub async fn get_eq_presets() -> Result<Vec<EqPreset>, String> {
 #[tauri::command]
 pub async fn save_eq_settings(state: State<'_, AppState>) -> Result<(), String> {
     let settings = state.audio.get_eq_settings();
-    lock_db(&state)?
+    write_db(&state)?
         .save_eq_settings(&settings)
         .map_err(sanitize_err("Saving equalizer"))
 }
@@ -937,20 +1860,18 @@ pub async fn set_playback_speed(state: State<'_, AppState>, speed: f32) -> Resul
         return Err("Speed must be a finite number between 0.25 and 4.0".to_string());
     }
     state.audio.set_speed(speed);
-    Ok(())
+    write_db(&state)?
+        .save_session_speed(speed)
+        .map_err(sanitize_err("Saving playback session"))
 }
 
 // Gapless playback: preload next track
 #[tauri::command]
 pub async fn preload_next_track(state: State<'_, AppState>, track_id: i64) -> Result<(), String> {
-    let file_path = {
-        let db = lock_db(&state)?;
-        let track = db
-            .get_track_by_id(track_id)
-            .map_err(sanitize_err("Loading track"))?;
-        track.file_path
-    };
-    state.audio.preload_next(&file_path);
+    let track = read_db(&state)?
+        .get_track_by_id(track_id)
+        .map_err(sanitize_err("Loading track"))?;
+    state.audio.preload_next(playback_item(&track));
     Ok(())
 }
 
@@ -961,7 +1882,7 @@ pub async fn record_play_history(
     track_id: i64,
     duration_listened: i64,
 ) -> Result<(), String> {
-    lock_db(&state)?
+    write_db(&state)?
         .record_play_history(track_id, duration_listened)
         .map_err(sanitize_err("Recording history"))
 }
@@ -971,15 +1892,171 @@ pub async fn get_play_history(
     state: State<'_, AppState>,
     limit: Option<i64>,
 ) -> Result<Vec<crate::models::PlayHistoryEntry>, String> {
-    lock_db(&state)?
+    read_db(&state)?
         .get_play_history(limit.unwrap_or(50))
         .map_err(sanitize_err("Loading history"))
 }
 
+// Listening statistics
+
+/// Entries returned by a top list when no limit is given
+const DEFAULT_STATS_LIMIT: i64 = 25;
+const MAX_STATS_LIMIT: i64 = 1_000;
+/// Entries in each list of a yearly report
+const YEAR_REPORT_LIMIT: i64 = 10;
+
+fn stats_limit(limit: Option<i64>) -> i64 {
+    limit
+        .unwrap_or(DEFAULT_STATS_LIMIT)
+        .clamp(1, MAX_STATS_LIMIT)
+}
+
+fn validate_year(year: i32) -> Result<(), String> {
+    if !(1970..=9999).contains(&year) {
+        return Err("Year must be between 1970 and 9999".to_string());
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_top_tracks(
+    state: State<'_, AppState>,
+    range: StatsRange,
+    limit: Option<i64>,
+) -> Result<Vec<TrackStat>, String> {
+    read_db(&state)?
+        .top_tracks(&range, stats_limit(limit))
+        .map_err(sanitize_err("Loading statistics"))
+}
+
+#[tauri::command]
+pub async fn get_top_artists(
+    state: State<'_, AppState>,
+    range: StatsRange,
+    limit: Option<i64>,
+) -> Result<Vec<NameStat>, String> {
+    read_db(&state)?
+        .top_artists(&range, stats_limit(limit))
+        .map_err(sanitize_err("Loading statistics"))
+}
+
+#[tauri::command]
+pub async fn get_top_albums(
+    state: State<'_, AppState>,
+    range: StatsRange,
+    limit: Option<i64>,
+) -> Result<Vec<NameStat>, String> {
+    read_db(&state)?
+        .top_albums(&range, stats_limit(limit))
+        .map_err(sanitize_err("Loading statistics"))
+}
+
+#[tauri::command]
+pub async fn get_top_genres(
+    state: State<'_, AppState>,
+    range: StatsRange,
+    limit: Option<i64>,
+) -> Result<Vec<NameStat>, String> {
+    read_db(&state)?
+        .top_genres(&range, stats_limit(limit))
+        .map_err(sanitize_err("Loading statistics"))
+}
+
+#[tauri::command]
+pub async fn get_listening_time(
+    state: State<'_, AppState>,
+    range: StatsRange,
+    period: StatsPeriod,
+) -> Result<Vec<ListeningTime>, String> {
+    read_db(&state)?
+        .listening_time(&range, period)
+        .map_err(sanitize_err("Loading statistics"))
+}
+
+#[tauri::command]
+pub async fn get_listening_clock(
+    state: State<'_, AppState>,
+    range: StatsRange,
+) -> Result<ListeningClock, String> {
+    read_db(&state)?
+        .listening_clock(&range)
+        .map_err(sanitize_err("Loading statistics"))
+}
+
+#[tauri::command]
+pub async fn get_listening_streaks(
+    state: State<'_, AppState>,
+    range: StatsRange,
+) -> Result<ListeningStreaks, String> {
+    read_db(&state)?
+        .listening_streaks(&range)
+        .map_err(sanitize_err("Loading statistics"))
+}
+
+#[tauri::command]
+pub async fn get_new_discoveries(
+    state: State<'_, AppState>,
+    range: StatsRange,
+    limit: Option<i64>,
+) -> Result<Discoveries, String> {
+    read_db(&state)?
+        .new_discoveries(&range, stats_limit(limit))
+        .map_err(sanitize_err("Loading statistics"))
+}
+
+#[tauri::command]
+pub async fn get_year_report(state: State<'_, AppState>, year: i32) -> Result<YearReport, String> {
+    validate_year(year)?;
+    read_db(&state)?
+        .year_report(year, YEAR_REPORT_LIMIT)
+        .map_err(sanitize_err("Building report"))
+}
+
+/// Write a yearly report to `output_path` as JSON or a standalone HTML page
+#[tauri::command]
+pub async fn export_year_report(
+    state: State<'_, AppState>,
+    year: i32,
+    format: ReportFormat,
+    output_path: String,
+) -> Result<(), String> {
+    validate_year(year)?;
+    let report = read_db(&state)?
+        .year_report(year, YEAR_REPORT_LIMIT)
+        .map_err(sanitize_err("Building report"))?;
+    let contents = match format {
+        ReportFormat::Json => {
+            serde_json::to_string_pretty(&report).map_err(sanitize_err("Exporting report"))?
+        }
+        ReportFormat::Html => crate::stats::year_report_html(&report),
+    };
+    std::fs::write(&output_path, contents).map_err(sanitize_err("Exporting report"))
+}
+
+/// Lossy tracks below this bitrate (kbps) count as low quality unless the
+/// caller picks another threshold
+const DEFAULT_LOW_BITRATE: i64 = 192;
+
+/// Codecs, sample rates and bit depths across the library, to find rips worth
+/// replacing
+#[tauri::command]
+pub async fn get_quality_stats(
+    state: State<'_, AppState>,
+    low_bitrate: Option<i64>,
+) -> Result<QualityStats, String> {
+    let low_bitrate = low_bitrate.unwrap_or(DEFAULT_LOW_BITRATE);
+    if low_bitrate <= 0 {
+        return Err("Bitrate threshold must be positive".to_string());
+    }
+    read_db(&state)?
+        .quality_stats(low_bitrate)
+        .map_err(sanitize_err("Loading quality statistics"))
+}
+
 // Duplicate detection
 #[tauri::command]
 pub async fn get_duplicates(state: State<'_, AppState>) -> Result<Vec<Vec<Track>>, String> {
-    lock_db(&state)?
+    read_db(&state)?
         .get_duplicate_tracks()
         .map_err(sanitize_err("Finding duplicates"))
 }
@@ -991,10 +2068,10 @@ pub async fn export_playlist_m3u(
     playlist_id: i64,
     output_path: String,
 ) -> Result<(), String> {
-    let tracks = lock_db(&state)?
+    let tracks = read_db(&state)?
         .get_playlist_tracks(playlist_id)
         .map_err(sanitize_err("Exporting playlist"))?;
-    let playlist = lock_db(&state)?
+    let playlist = read_db(&state)?
         .get_playlist(playlist_id)
         .map_err(sanitize_err("Exporting playlist"))?;
     crate::playlist_io::export_m3u(&playlist.name, &tracks, &output_path)
@@ -1018,7 +2095,7 @@ pub async fn import_playlist_m3u(
             .to_string()
     });
 
-    let mut db = lock_db(&state)?;
+    let mut db = write_db(&state)?;
     let playlist_id = db
         .create_playlist(&name)
         .map_err(sanitize_err("Importing playlist"))?;
@@ -1039,7 +2116,7 @@ pub async fn import_playlist_m3u(
 
 #[tauri::command]
 pub async fn get_scan_settings(state: State<'_, AppState>) -> Result<ScanSettings, String> {
-    let db = lock_db(&state)?;
+    let db = read_db(&state)?;
     let scan_on_startup = db
         .get_setting("scan_on_startup")
         .map_err(sanitize_err("Loading scan settings"))?
@@ -1055,11 +2132,21 @@ pub async fn get_scan_settings(state: State<'_, AppState>) -> Result<ScanSetting
         .map_err(sanitize_err("Loading scan settings"))?
         .and_then(|v| v.parse::<u64>().ok())
         .unwrap_or(30);
+    let missing_grace_days = db
+        .load_missing_grace_days()
+        .map_err(sanitize_err("Loading scan settings"))?;
+    let watch_folders_enabled = db
+        .get_setting("watch_folders_enabled")
+        .map_err(sanitize_err("Loading scan settings"))?
+        .map(|v| v == "true")
+        .unwrap_or(true);
 
     Ok(ScanSettings {
         scan_on_startup,
         periodic_scan_enabled,
         periodic_scan_interval_minutes,
+        missing_grace_days,
+        watch_folders_enabled,
     })
 }
 
@@ -1068,7 +2155,13 @@ pub async fn set_scan_settings(
     state: State<'_, AppState>,
     settings: ScanSettings,
 ) -> Result<(), String> {
-    let mut db = lock_db(&state)?;
+    if settings.missing_grace_days > reconcile::MAX_MISSING_GRACE_DAYS {
+        return Err(format!(
+            "Missing files can be kept for at most {} days",
+            reconcile::MAX_MISSING_GRACE_DAYS
+        ));
+    }
+    let mut db = write_db(&state)?;
     db.set_setting("scan_on_startup", &settings.scan_on_startup.to_string())
         .map_err(sanitize_err("Saving scan settings"))?;
     db.set_setting(
@@ -1081,5 +2174,15 @@ pub async fn set_scan_settings(
         &settings.periodic_scan_interval_minutes.to_string(),
     )
     .map_err(sanitize_err("Saving scan settings"))?;
+    db.set_setting(
+        "missing_grace_days",
+        &settings.missing_grace_days.to_string(),
+    )
+    .map_err(sanitize_err("Saving scan settings"))?;
+    db.set_setting(
+        "watch_folders_enabled",
+        &settings.watch_folders_enabled.to_string(),
+    )
+    .map_err(sanitize_err("Saving scan settings"))?;
     Ok(())
 }
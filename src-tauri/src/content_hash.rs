@@ -0,0 +1,395 @@
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Bytes hashed from each of the start, middle and end of the audio payload.
+/// Reading three windows instead of the whole file keeps hashing cheap on
+/// network shares; with the payload length they are enough to tell
+/// recordings apart.
+const SAMPLE_SIZE: u64 = 64 * 1024;
+
+/// Hash of the audio in a file, ignoring its tags, so the same recording
+/// keeps its hash when it is retagged, renamed or moved.
+///
+/// Leading ID3v2 and trailing ID3v1 and APEv2 tags are skipped in every
+/// format. FLAC metadata blocks, everything outside the MP4 `mdat` atom and
+/// the WAV `data` chunk are skipped, as are Ogg header pages; Ogg page
+/// sequence numbers and checksums, which change when the comment header grows
+/// onto more pages, are ignored.
+pub fn audio_hash(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let (start, end, ogg) = payload_range(&mut file, len)?;
+    let payload = end.saturating_sub(start);
+
+    let mut hasher = Sha256::new();
+    hasher.update(payload.to_le_bytes());
+    let windows = if payload <= 3 * SAMPLE_SIZE {
+        vec![(start, payload)]
+    } else {
+        vec![
+            (start, SAMPLE_SIZE),
+            (start + (payload - SAMPLE_SIZE) / 2, SAMPLE_SIZE),
+            (end - SAMPLE_SIZE, SAMPLE_SIZE),
+        ]
+    };
+    for (offset, size) in windows {
+        let mut window = read_at(&mut file, offset, size)?;
+        if ogg {
+            clear_ogg_page_numbers(&mut window);
+        }
+        hasher.update(&window);
+    }
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// Up to `size` bytes from `offset`; fewer at the end of the file
+fn read_at(file: &mut File, offset: u64, size: u64) -> io::Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buf = Vec::with_capacity(size as usize);
+    file.take(size).read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+fn u32_be(bytes: &[u8]) -> u64 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as u64
+}
+
+fn u32_le(bytes: &[u8]) -> u64 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as u64
+}
+
+/// ID3v2 sizes use seven bits per byte
+fn syncsafe(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(0, |size, &b| (size << 7) | (b & 0x7f) as u64)
+}
+
+/// Start and end of the audio payload, and whether the file is Ogg
+fn payload_range(file: &mut File, len: u64) -> io::Result<(u64, u64, bool)> {
+    let mut start = 0;
+    let mut end = len;
+
+    // Leading ID3v2 tags, with an optional footer
+    loop {
+        let header = read_at(file, start, 10)?;
+        if header.len() < 10 || &header[..3] != b"ID3" {
+            break;
+        }
+        let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+        start += 10 + syncsafe(&header[6..10]) + footer;
+    }
+
+    // Trailing ID3v1 and APEv2 tags
+    if end >= start + 128 && read_at(file, end - 128, 3)? == b"TAG" {
+        end -= 128;
+    }
+    if end >= start + 32 {
+        let footer = read_at(file, end - 32, 32)?;
+        if &footer[..8] == b"APETAGEX" {
+            let header = if u32_le(&footer[20..24]) & 0x8000_0000 != 0 {
+                32
+            } else {
+                0
+            };
+            end = end
+                .saturating_sub(u32_le(&footer[12..16]) + header)
+                .max(start);
+        }
+    }
+
+    let magic = read_at(file, start, 12)?;
+    if magic.starts_with(b"fLaC") {
+        start = flac_audio_start(file, start + 4, end)?;
+    } else if magic.len() == 12 && &magic[4..8] == b"ftyp" {
+        if let Some(range) = find_chunk(file, start, end, b"mdat", false)? {
+            (start, end) = range;
+        }
+    } else if magic.len() == 12 && &magic[..4] == b"RIFF" && &magic[8..12] == b"WAVE" {
+        if let Some(range) = find_chunk(file, start + 12, end, b"data", true)? {
+            (start, end) = range;
+        }
+    } else if magic.starts_with(b"OggS") {
+        return Ok((ogg_audio_start(file, start, end)?, end, true));
+    }
+    Ok((start, end, false))
+}
+
+/// End of the last FLAC metadata block, where the audio frames begin
+fn flac_audio_start(file: &mut File, mut offset: u64, end: u64) -> io::Result<u64> {
+    while offset + 4 <= end {
+        let header = read_at(file, offset, 4)?;
+        let size = u32_be(&[0, header[1], header[2], header[3]]);
+        offset += 4 + size;
+        if header[0] & 0x80 != 0 {
+            break;
+        }
+    }
+    Ok(offset.min(end))
+}
+
+/// Contents of the first chunk named `id`: MP4 atoms (big-endian sizes that
+/// include the header) or RIFF chunks (little-endian sizes of the contents,
+/// padded to an even length). A size that runs past the largest offset ends
+/// the search.
+fn find_chunk(
+    file: &mut File,
+    mut offset: u64,
+    end: u64,
+    id: &[u8; 4],
+    riff: bool,
+) -> io::Result<Option<(u64, u64)>> {
+    while end.checked_sub(offset).is_some_and(|left| left >= 8) {
+        let header = read_at(file, offset, 16)?;
+        let (name, contents, size) = if riff {
+            let size = u32_le(&header[4..8]);
+            (&header[..4], offset + 8, size + (size & 1))
+        } else {
+            let name = &header[4..8];
+            match u32_be(&header[..4]) {
+                // 64-bit size after the type
+                1 if header.len() == 16 => {
+                    let size = u64::from_be_bytes(header[8..16].try_into().unwrap());
+                    (name, offset + 16, size.saturating_sub(16))
+                }
+                // Extends to the end of the file
+                0 => (name, offset + 8, end - offset - 8),
+                size => (name, offset + 8, size.saturating_sub(8)),
+            }
+        };
+        let Some(next) = contents.checked_add(size) else {
+            return Ok(None);
+        };
+        if name == id {
+            return Ok(Some((contents, next.min(end))));
+        }
+        // Every chunk has a header, so this only guards against a bad size
+        if next <= offset {
+            return Ok(None);
+        }
+        offset = next;
+    }
+    Ok(None)
+}
+
+/// Start of the first Ogg page holding audio. Header pages (identification,
+/// comments, setup) have a granule position of zero.
+fn ogg_audio_start(file: &mut File, mut offset: u64, end: u64) -> io::Result<u64> {
+    while offset + 27 <= end {
+        let header = read_at(file, offset, 27)?;
+        if &header[..4] != b"OggS" {
+            break;
+        }
+        let granule = u64::from_le_bytes(header[6..14].try_into().unwrap());
+        if granule != 0 {
+            return Ok(offset);
+        }
+        let segments = read_at(file, offset + 27, header[26] as u64)?;
+        offset += 27 + header[26] as u64 + segments.iter().map(|&s| s as u64).sum::<u64>();
+    }
+    Ok(offset.min(end))
+}
+
+/// Zero the page sequence number and checksum of each Ogg page in `window`
+fn clear_ogg_page_numbers(window: &mut [u8]) {
+    let mut i = 0;
+    while i + 26 <= window.len() {
+        if &window[i..i + 4] == b"OggS" {
+            window[i + 18..i + 26].fill(0);
+            i += 27;
+        } else {
+            i += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn hash_of(dir: &tempfile::TempDir, name: &str, contents: &[u8]) -> String {
+        let path = dir.path().join(name);
+        fs::write(&path, contents).unwrap();
+        audio_hash(&path).unwrap()
+    }
+
+    fn id3v2(body: &[u8]) -> Vec<u8> {
+        let size = body.len() as u32;
+        let mut tag = b"ID3\x04\x00\x00".to_vec();
+        tag.extend((0..4).rev().map(|i| ((size >> (7 * i)) & 0x7f) as u8));
+        tag.extend(body);
+        tag
+    }
+
+    fn id3v1(title: &str) -> Vec<u8> {
+        let mut tag = b"TAG".to_vec();
+        tag.extend(title.bytes());
+        tag.resize(128, 0);
+        tag
+    }
+
+    fn audio(seed: u8, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| (i as u8).wrapping_mul(31) ^ seed)
+            .collect()
+    }
+
+    #[test]
+    fn test_tags_are_ignored() {
+        let dir = tempfile::TempDir::new().unwrap();
+        // Large enough to be sampled rather than hashed whole
+        let frames = audio(1, 300_000);
+
+        let plain = hash_of(&dir, "a.mp3", &frames);
+        let tagged = hash_of(
+            &dir,
+            "b.mp3",
+            &[id3v2(b"TIT2 Title"), frames.clone(), id3v1("Title")].concat(),
+        );
+        let retagged = hash_of(
+            &dir,
+            "c.mp3",
+            &[id3v2(&[0; 5000]), frames.clone(), id3v1("Other")].concat(),
+        );
+        assert_eq!(plain, tagged);
+        assert_eq!(plain, retagged);
+
+        let mut changed = frames.clone();
+        changed[150_000] ^= 0xff;
+        assert_ne!(plain, hash_of(&dir, "d.mp3", &changed));
+        assert_ne!(plain, hash_of(&dir, "e.mp3", &frames[..299_999]));
+    }
+
+    #[test]
+    fn test_flac_metadata_blocks_are_ignored() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let frames = audio(2, 1000);
+        let flac = |comment: &[u8]| {
+            let mut file = b"fLaC".to_vec();
+            file.extend([0x00, 0, 0, 4, 1, 2, 3, 4]);
+            file.extend([0x84, 0, 0, comment.len() as u8]);
+            file.extend(comment);
+            file.extend(&frames);
+            file
+        };
+        assert_eq!(
+            hash_of(&dir, "a.flac", &flac(b"TITLE=A")),
+            hash_of(&dir, "b.flac", &flac(b"TITLE=Longer title"))
+        );
+        assert_eq!(
+            hash_of(&dir, "a.flac", &flac(b"TITLE=A")),
+            hash_of(&dir, "raw", &frames)
+        );
+    }
+
+    #[test]
+    fn test_mp4_and_wav_payloads() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let frames = audio(3, 1000);
+        let atom = |name: &[u8], body: &[u8]| {
+            let mut atom = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+            atom.extend(name);
+            atom.extend(body);
+            atom
+        };
+        let mp4 = |meta: &[u8]| {
+            [
+                atom(b"ftyp", b"M4A \0\0\0\0"),
+                atom(b"moov", meta),
+                atom(b"mdat", &frames),
+            ]
+            .concat()
+        };
+        assert_eq!(
+            hash_of(&dir, "a.m4a", &mp4(b"short")),
+            hash_of(&dir, "b.m4a", &mp4(b"a longer metadata atom"))
+        );
+
+        let chunk = |name: &[u8], body: &[u8]| {
+            let mut chunk = name.to_vec();
+            chunk.extend((body.len() as u32).to_le_bytes());
+            chunk.extend(body);
+            if body.len() % 2 == 1 {
+                chunk.push(0);
+            }
+            chunk
+        };
+        let wav = |info: &[u8]| {
+            let body = [
+                b"WAVE".to_vec(),
+                chunk(b"LIST", info),
+                chunk(b"data", &frames),
+            ]
+            .concat();
+            [chunk(b"RIFF", &body)].concat()
+        };
+        assert_eq!(
+            hash_of(&dir, "a.wav", &wav(b"INFO")),
+            hash_of(&dir, "b.wav", &wav(b"INFOINAM title"))
+        );
+        assert_eq!(
+            hash_of(&dir, "a.wav", &wav(b"INFO")),
+            hash_of(&dir, "a.m4a", &mp4(b"short"))
+        );
+    }
+
+    #[test]
+    fn test_oversized_mp4_atom_is_not_followed() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("bad.m4a");
+        // A 64-bit size that overflows when added to the atom's offset
+        let mut bad = 16u32.to_be_bytes().to_vec();
+        bad.extend(b"ftypM4A \0\0\0\0");
+        bad.extend(1u32.to_be_bytes());
+        bad.extend(b"moov");
+        bad.extend(u64::MAX.to_be_bytes());
+        bad.extend(audio(4, 1000));
+        fs::write(&path, &bad).unwrap();
+
+        let mut file = File::open(&path).unwrap();
+        let end = bad.len() as u64;
+        assert_eq!(find_chunk(&mut file, 0, end, b"mdat", false).unwrap(), None);
+        assert!(audio_hash(&path).is_ok());
+    }
+
+    #[test]
+    fn test_ogg_header_pages_are_ignored() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let page = |granule: u64, sequence: u32, body: &[u8]| {
+            let mut page = b"OggS\0\0".to_vec();
+            page.extend(granule.to_le_bytes());
+            page.extend(1u32.to_le_bytes());
+            page.extend(sequence.to_le_bytes());
+            page.extend(sequence.wrapping_mul(7919).to_le_bytes());
+            page.push(1);
+            page.push(body.len() as u8);
+            page.extend(body);
+            page
+        };
+        let ogg = |comments: &[&[u8]]| {
+            let mut file = page(0, 0, b"\x01vorbis");
+            let mut sequence = 1;
+            for comment in comments {
+                file.extend(page(0, sequence, comment));
+                sequence += 1;
+            }
+            for i in 0..4u8 {
+                file.extend(page(1000 * (i as u64 + 1), sequence, &audio(i, 200)));
+                sequence += 1;
+            }
+            file
+        };
+        assert_eq!(
+            hash_of(&dir, "a.ogg", &ogg(&[b"\x03vorbis title"])),
+            hash_of(&dir, "b.ogg", &ogg(&[b"\x03vorbis", b"more comments"]))
+        );
+    }
+}
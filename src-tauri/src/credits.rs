@@ -0,0 +1,203 @@
+use crate::models::CreditRole;
+use rusqlite::{params, Connection, Result as SqlResult};
+
+/// Separators used until the user picks their own. Tags with several values
+/// in separate fields (multiple GENRE comments, ID3v2.4 lists) are joined with
+/// `; ` by the scanner, so `;` should stay in the list.
+pub const DEFAULT_SEPARATORS: [&str; 1] = [";"];
+
+/// Markers that introduce featured artists, matched case-insensitively with
+/// a space on either side
+const FEATURING_MARKERS: [&str; 4] = ["featuring", "feat.", "feat", "ft."];
+
+/// Split a tag value on any of `separators`, trimming each part and dropping
+/// empty and repeated ones
+pub fn split_values(value: &str, separators: &[String]) -> Vec<String> {
+    let mut parts = vec![value.to_string()];
+    for separator in separators.iter().filter(|s| !s.is_empty()) {
+        parts = parts
+            .iter()
+            .flat_map(|part| part.split(separator.as_str()))
+            .map(str::to_string)
+            .collect();
+    }
+
+    let mut values: Vec<String> = Vec::new();
+    for part in parts {
+        let part = part.trim();
+        if !part.is_empty() && !values.iter().any(|v| v.eq_ignore_ascii_case(part)) {
+            values.push(part.to_string());
+        }
+    }
+    values
+}
+
+/// Position of the first featuring marker outside the leading artist, and the
+/// length of the marker with its surrounding space
+fn find_featuring(value: &str) -> Option<(usize, usize)> {
+    let lower = value.to_lowercase();
+    // Lowercasing can change byte lengths outside ASCII; only trust positions
+    // that still fall on the same boundaries
+    if lower.len() != value.len() {
+        return None;
+    }
+    FEATURING_MARKERS
+        .iter()
+        .filter_map(|marker| {
+            [" (", " [", " "].iter().find_map(|prefix| {
+                let needle = format!("{}{} ", prefix, marker);
+                lower.find(&needle).map(|pos| (pos, needle.len()))
+            })
+        })
+        .min_by_key(|&(pos, _)| pos)
+}
+
+/// Artists credited by an artist tag, in order: the main artists, split on
+/// `separators`, then any featured artists (`A feat. B & C`, `A (ft. B)`).
+/// Featured artists are also split on `&` and commas.
+pub fn split_artist_credits(value: &str, separators: &[String]) -> Vec<(String, CreditRole)> {
+    let (main, featured) = match find_featuring(value) {
+        Some((pos, len)) => {
+            let rest = value[pos + len..].trim_end();
+            let rest = rest
+                .strip_suffix(')')
+                .or_else(|| rest.strip_suffix(']'))
+                .unwrap_or(rest);
+            (&value[..pos], Some(rest))
+        }
+        None => (value, None),
+    };
+
+    let mut credits: Vec<(String, CreditRole)> = split_values(main, separators)
+        .into_iter()
+        .map(|name| (name, CreditRole::Main))
+        .collect();
+
+    if let Some(featured) = featured {
+        let mut featured_separators = separators.to_vec();
+        featured_separators.extend([" & ".to_string(), ",".to_string()]);
+        for name in split_values(featured, &featured_separators) {
+            if !credits.iter().any(|(n, _)| n.eq_ignore_ascii_case(&name)) {
+                credits.push((name, CreditRole::Featured));
+            }
+        }
+    }
+    credits
+}
+
+/// Replace the artist and genre rows of a track with the values in its tags,
+/// creating `artists` and `genres` rows as needed, and point `tracks.artist_id`
+/// at the first credited artist.
+///
+/// Existing rows are flagged with a negative position and only the ones no
+/// longer named are deleted, so an artist or genre credited by this track alone
+/// keeps its id when the track is rescanned.
+pub fn link_track_credits(
+    conn: &Connection,
+    track_id: i64,
+    artist: Option<&str>,
+    genre: Option<&str>,
+    separators: &[String],
+) -> SqlResult<()> {
+    conn.prepare_cached("UPDATE track_artists SET position = -1 - position WHERE track_id = ?1")?
+        .execute(params![track_id])?;
+    let credits = artist.map_or_else(Vec::new, |a| split_artist_credits(a, separators));
+    for (position, (name, role)) in credits.iter().enumerate() {
+        conn.prepare_cached("INSERT OR IGNORE INTO artists (name) VALUES (?1)")?
+            .execute(params![name])?;
+        conn.prepare_cached(
+            "INSERT INTO track_artists (track_id, artist_id, position, role)
+             SELECT ?1, id, ?2, ?3 FROM artists WHERE name = ?4
+             ON CONFLICT(track_id, artist_id) DO UPDATE SET
+                position = excluded.position,
+                role = excluded.role",
+        )?
+        .execute(params![track_id, position as i64, role.as_str(), name])?;
+    }
+    conn.prepare_cached("DELETE FROM track_artists WHERE track_id = ?1 AND position < 0")?
+        .execute(params![track_id])?;
+
+    conn.prepare_cached("UPDATE track_genres SET position = -1 - position WHERE track_id = ?1")?
+        .execute(params![track_id])?;
+    let genres = genre.map_or_else(Vec::new, |g| split_values(g, separators));
+    for (position, name) in genres.iter().enumerate() {
+        conn.prepare_cached("INSERT OR IGNORE INTO genres (name) VALUES (?1)")?
+            .execute(params![name])?;
+        conn.prepare_cached(
+            "INSERT INTO track_genres (track_id, genre_id, position)
+             SELECT ?1, id, ?2 FROM genres WHERE name = ?3
+             ON CONFLICT(track_id, genre_id) DO UPDATE SET position = excluded.position",
+        )?
+        .execute(params![track_id, position as i64, name])?;
+    }
+    conn.prepare_cached("DELETE FROM track_genres WHERE track_id = ?1 AND position < 0")?
+        .execute(params![track_id])?;
+
+    conn.prepare_cached(
+        "UPDATE tracks SET artist_id = (
+            SELECT artist_id FROM track_artists WHERE track_id = ?1 ORDER BY position LIMIT 1
+         ) WHERE id = ?1",
+    )?
+    .execute(params![track_id])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn separators(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_split_values() {
+        let semicolon = separators(&[";"]);
+        assert_eq!(
+            split_values("Rock; Pop ;;rock", &semicolon),
+            vec!["Rock", "Pop"]
+        );
+        assert_eq!(split_values("AC/DC", &semicolon), vec!["AC/DC"]);
+        assert_eq!(
+            split_values("Jazz / Funk; Soul", &separators(&[";", " / "])),
+            vec!["Jazz", "Funk", "Soul"]
+        );
+        assert!(split_values("  ", &semicolon).is_empty());
+        assert_eq!(split_values("A;B", &[]), vec!["A;B"]);
+    }
+
+    #[test]
+    fn test_split_artist_credits() {
+        let semicolon = separators(&[";"]);
+        let names =
+            |value: &str| -> Vec<(String, CreditRole)> { split_artist_credits(value, &semicolon) };
+        let credit = |name: &str, role| (name.to_string(), role);
+
+        assert_eq!(
+            names("Simon & Garfunkel"),
+            vec![credit("Simon & Garfunkel", CreditRole::Main)]
+        );
+        assert_eq!(
+            names("A; B feat. C & D"),
+            vec![
+                credit("A", CreditRole::Main),
+                credit("B", CreditRole::Main),
+                credit("C", CreditRole::Featured),
+                credit("D", CreditRole::Featured),
+            ]
+        );
+        assert_eq!(
+            names("Daft Punk (Ft. Pharrell Williams, Nile Rodgers)"),
+            vec![
+                credit("Daft Punk", CreditRole::Main),
+                credit("Pharrell Williams", CreditRole::Featured),
+                credit("Nile Rodgers", CreditRole::Featured),
+            ]
+        );
+        // "Feat" inside a name is not a marker
+        assert_eq!(
+            names("Feathers"),
+            vec![credit("Feathers", CreditRole::Main)]
+        );
+    }
+}
@@ -0,0 +1,399 @@
+use crate::models::Track;
+use std::path::{Path, PathBuf};
+
+/// CUE timestamps are `mm:ss:ff` with 75 frames per second
+const FRAMES_PER_SECOND: i64 = 75;
+
+/// A parsed CUE sheet
+#[derive(Debug, Clone, Default)]
+pub struct CueSheet {
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub genre: Option<String>,
+    pub year: Option<i64>,
+    pub files: Vec<CueFile>,
+}
+
+/// A `FILE` block and the tracks it contains
+#[derive(Debug, Clone, Default)]
+pub struct CueFile {
+    pub name: String,
+    pub tracks: Vec<CueTrack>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CueTrack {
+    pub number: i64,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    /// Position of `INDEX 01` within the file, in milliseconds
+    pub start_ms: i64,
+}
+
+/// A virtual track resolved against its neighbours
+#[derive(Debug, Clone, PartialEq)]
+pub struct CueSegment {
+    pub number: i64,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub start_ms: i64,
+    /// `None` for the last track, which plays to the end of the file
+    pub end_ms: Option<i64>,
+}
+
+impl CueSheet {
+    /// Segments for a file, or for the whole sheet when it is embedded in the file.
+    /// Returns an empty list when the file holds a single track starting at zero.
+    pub fn segments(&self, file_name: Option<&str>) -> Vec<CueSegment> {
+        let tracks: Vec<&CueTrack> = match file_name {
+            Some(name) => self
+                .files
+                .iter()
+                .filter(|f| same_file_name(&f.name, name))
+                .flat_map(|f| f.tracks.iter())
+                .collect(),
+            None => self.files.iter().flat_map(|f| f.tracks.iter()).collect(),
+        };
+
+        if tracks.len() < 2 {
+            return Vec::new();
+        }
+
+        tracks
+            .iter()
+            .enumerate()
+            .map(|(i, track)| CueSegment {
+                number: track.number,
+                title: track.title.clone(),
+                performer: track.performer.clone(),
+                start_ms: track.start_ms,
+                end_ms: tracks.get(i + 1).map(|next| next.start_ms),
+            })
+            .collect()
+    }
+
+    /// Audio files referenced by the sheet, resolved relative to the sheet's directory.
+    /// Rips often reference `CDImage.wav` after being transcoded, so a missing file
+    /// falls back to any audio file with the same stem.
+    pub fn resolve_files(&self, cue_path: &Path, extensions: &[&str]) -> Vec<(String, PathBuf)> {
+        let dir = cue_path.parent().unwrap_or(Path::new("."));
+        let mut resolved = Vec::new();
+
+        for file in &self.files {
+            let candidate = dir.join(&file.name);
+            if candidate.is_file() {
+                resolved.push((file.name.clone(), candidate));
+                continue;
+            }
+            let stem = Path::new(&file.name).file_stem().map(|s| s.to_os_string());
+            if let Some(stem) = stem {
+                for ext in extensions {
+                    let alt = dir.join(&stem).with_extension(ext);
+                    if alt.is_file() {
+                        resolved.push((file.name.clone(), alt));
+                        break;
+                    }
+                }
+            }
+        }
+
+        resolved
+    }
+}
+
+/// Unique `tracks.file_path` key for a virtual track; the real file goes in `source_path`
+pub fn virtual_track_key(audio_path: &str, number: i64) -> String {
+    format!("{}#{:02}", audio_path, number)
+}
+
+/// Expand a whole-file track into one virtual track per CUE sheet entry
+pub fn virtual_tracks(file_track: &Track, sheet: &CueSheet, segments: &[CueSegment]) -> Vec<Track> {
+    let file_duration_ms = file_track.duration.map(|d| d * 1000);
+
+    segments
+        .iter()
+        .map(|segment| {
+            let end_ms = segment.end_ms.or(file_duration_ms);
+            Track {
+                file_path: virtual_track_key(&file_track.file_path, segment.number),
+                title: segment
+                    .title
+                    .clone()
+                    .or_else(|| Some(format!("Track {:02}", segment.number))),
+                artist: segment
+                    .performer
+                    .clone()
+                    .or_else(|| sheet.performer.clone())
+                    .or_else(|| file_track.artist.clone()),
+                album: sheet.title.clone().or_else(|| file_track.album.clone()),
+                // The sheet's PERFORMER credits the whole disc
+                album_artist: sheet
+                    .performer
+                    .clone()
+                    .or_else(|| file_track.album_artist.clone()),
+                duration: end_ms.map(|end| (end - segment.start_ms).max(0) / 1000),
+                year: sheet.year.or(file_track.year),
+                genre: sheet.genre.clone().or_else(|| file_track.genre.clone()),
+                track_number: Some(segment.number),
+                track_total: Some(segments.len() as i64),
+                source_path: Some(file_track.file_path.clone()),
+                start_offset_ms: Some(segment.start_ms),
+                end_offset_ms: segment.end_ms,
+                // A rating tag rates the whole file, not its CUE entries
+                rating: None,
+                ..file_track.clone()
+            }
+        })
+        .collect()
+}
+
+/// Compare `FILE` names ignoring case and extension (see `resolve_files`)
+fn same_file_name(a: &str, b: &str) -> bool {
+    let stem = |s: &str| {
+        Path::new(s)
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_lowercase())
+            .unwrap_or_default()
+    };
+    a.eq_ignore_ascii_case(b) || stem(a) == stem(b)
+}
+
+/// Read a CUE sheet from disk. Non-UTF-8 sheets (common with older rippers) are decoded lossily.
+pub fn read_cue_file(path: &Path) -> std::io::Result<CueSheet> {
+    let bytes = std::fs::read(path)?;
+    Ok(parse_cue(&String::from_utf8_lossy(&bytes)))
+}
+
+/// Parse CUE sheet text. Unknown commands are ignored.
+pub fn parse_cue(text: &str) -> CueSheet {
+    let mut sheet = CueSheet::default();
+    let mut current_track: Option<CueTrack> = None;
+    let mut has_index = false;
+
+    let flush = |sheet: &mut CueSheet, track: Option<CueTrack>, has_index: bool| {
+        if let (Some(track), true) = (track, has_index) {
+            // Embedded sheets may omit FILE; keep their tracks under an unnamed file
+            if sheet.files.is_empty() {
+                sheet.files.push(CueFile::default());
+            }
+            if let Some(file) = sheet.files.last_mut() {
+                file.tracks.push(track);
+            }
+        }
+    };
+
+    for line in text.trim_start_matches('\u{feff}').lines() {
+        let line = line.trim();
+        let (command, rest) = match line.split_once(char::is_whitespace) {
+            Some((c, r)) => (c.to_ascii_uppercase(), r.trim()),
+            None => continue,
+        };
+
+        match command.as_str() {
+            "FILE" => {
+                flush(&mut sheet, current_track.take(), has_index);
+                has_index = false;
+                // FILE "name with spaces.flac" WAVE
+                let name = match rest.strip_prefix('"') {
+                    Some(quoted) => quoted.split('"').next().unwrap_or_default().to_string(),
+                    None => rest
+                        .split_whitespace()
+                        .next()
+                        .unwrap_or_default()
+                        .to_string(),
+                };
+                sheet.files.push(CueFile {
+                    name,
+                    tracks: Vec::new(),
+                });
+            }
+            "TRACK" => {
+                flush(&mut sheet, current_track.take(), has_index);
+                has_index = false;
+                let number = rest
+                    .split_whitespace()
+                    .next()
+                    .and_then(|n| n.parse::<i64>().ok())
+                    .unwrap_or(0);
+                current_track = Some(CueTrack {
+                    number,
+                    ..Default::default()
+                });
+            }
+            "INDEX" => {
+                let mut parts = rest.split_whitespace();
+                let index = parts.next().and_then(|n| n.parse::<u32>().ok());
+                let time = parts.next().and_then(parse_timestamp);
+                if let (Some(track), Some(1), Some(ms)) = (current_track.as_mut(), index, time) {
+                    track.start_ms = ms;
+                    has_index = true;
+                }
+            }
+            "TITLE" => {
+                let value = unquote(rest);
+                match current_track.as_mut() {
+                    Some(track) => track.title = value,
+                    None => sheet.title = value,
+                }
+            }
+            "PERFORMER" => {
+                let value = unquote(rest);
+                match current_track.as_mut() {
+                    Some(track) => track.performer = value,
+                    None => sheet.performer = value,
+                }
+            }
+            "REM" if current_track.is_none() => {
+                if let Some((key, value)) = rest.split_once(char::is_whitespace) {
+                    match key.to_ascii_uppercase().as_str() {
+                        "GENRE" => sheet.genre = unquote(value),
+                        "DATE" => {
+                            sheet.year = value.trim().get(..4).and_then(|y| y.parse::<i64>().ok())
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    flush(&mut sheet, current_track.take(), has_index);
+    sheet
+}
+
+/// Parse `mm:ss:ff` into milliseconds
+fn parse_timestamp(value: &str) -> Option<i64> {
+    let mut parts = value.split(':').map(|p| p.parse::<i64>().ok());
+    let minutes = parts.next()??;
+    let seconds = parts.next()??;
+    let frames = parts.next()??;
+    Some((minutes * 60 + seconds) * 1000 + frames * 1000 / FRAMES_PER_SECOND)
+}
+
+fn unquote(value: &str) -> Option<String> {
+    let value = value.trim();
+    let value = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value)
+        .trim();
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHEET: &str = "\u{feff}REM GENRE \"Trip Hop\"
+REM DATE 1994
+PERFORMER \"Portishead\"
+TITLE \"Dummy\"
+FILE \"Portishead - Dummy.wav\" WAVE
+  TRACK 01 AUDIO
+    TITLE \"Mysterons\"
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    TITLE \"Sour Times\"
+    PERFORMER \"Portishead feat. Someone\"
+    INDEX 00 05:01:50
+    INDEX 01 05:02:00
+  TRACK 03 AUDIO
+    TITLE \"Strangers\"
+    INDEX 01 09:12:37
+";
+
+    #[test]
+    fn test_parse_cue_sheet() {
+        let sheet = parse_cue(SHEET);
+        assert_eq!(sheet.title.as_deref(), Some("Dummy"));
+        assert_eq!(sheet.performer.as_deref(), Some("Portishead"));
+        assert_eq!(sheet.genre.as_deref(), Some("Trip Hop"));
+        assert_eq!(sheet.year, Some(1994));
+        assert_eq!(sheet.files.len(), 1);
+        assert_eq!(sheet.files[0].name, "Portishead - Dummy.wav");
+        assert_eq!(sheet.files[0].tracks.len(), 3);
+        assert_eq!(sheet.files[0].tracks[1].start_ms, 302_000);
+        assert_eq!(sheet.files[0].tracks[2].start_ms, 552_493);
+    }
+
+    #[test]
+    fn test_segments_use_next_index_as_end() {
+        let sheet = parse_cue(SHEET);
+        // Matches across a transcoded extension
+        let segments = sheet.segments(Some("Portishead - Dummy.flac"));
+        assert_eq!(segments.len(), 3);
+        assert_eq!(segments[0].start_ms, 0);
+        assert_eq!(segments[0].end_ms, Some(302_000));
+        assert_eq!(
+            segments[1].performer.as_deref(),
+            Some("Portishead feat. Someone")
+        );
+        assert_eq!(segments[2].end_ms, None);
+
+        assert!(sheet.segments(Some("other.flac")).is_empty());
+    }
+
+    #[test]
+    fn test_sheet_expands_into_virtual_tracks() {
+        let sheet = parse_cue(SHEET);
+        let segments = sheet.segments(Some("Portishead - Dummy.flac"));
+        let file_track = Track {
+            file_path: "/music/Portishead - Dummy.flac".to_string(),
+            title: Some("Dummy (full album)".to_string()),
+            artist: Some("Tagged Artist".to_string()),
+            duration: Some(700),
+            file_format: "flac".to_string(),
+            rating: Some(80),
+            ..Default::default()
+        };
+
+        let tracks = virtual_tracks(&file_track, &sheet, &segments);
+        assert_eq!(tracks.len(), 3);
+        let keys: Vec<&str> = tracks.iter().map(|t| t.file_path.as_str()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                "/music/Portishead - Dummy.flac#01",
+                "/music/Portishead - Dummy.flac#02",
+                "/music/Portishead - Dummy.flac#03",
+            ]
+        );
+        for (i, track) in tracks.iter().enumerate() {
+            assert_eq!(
+                track.source_path.as_deref(),
+                Some(file_track.file_path.as_str())
+            );
+            assert_eq!(track.track_number, Some(i as i64 + 1));
+            assert_eq!(track.track_total, Some(3));
+            assert_eq!(track.album.as_deref(), Some("Dummy"));
+            assert_eq!(track.album_artist.as_deref(), Some("Portishead"));
+            assert_eq!(track.file_format, "flac");
+            assert_eq!(track.rating, None);
+        }
+        assert_eq!(tracks[0].title.as_deref(), Some("Mysterons"));
+        assert_eq!(tracks[0].artist.as_deref(), Some("Portishead"));
+        assert_eq!(
+            tracks[1].artist.as_deref(),
+            Some("Portishead feat. Someone")
+        );
+        assert_eq!(
+            (tracks[1].start_offset_ms, tracks[1].end_offset_ms),
+            (Some(302_000), Some(552_493))
+        );
+        assert_eq!(tracks[1].duration, Some(250));
+        // The last entry plays to the end of the file
+        assert_eq!(tracks[2].end_offset_ms, None);
+        assert_eq!(tracks[2].duration, Some(147));
+    }
+
+    #[test]
+    fn test_single_track_sheet_has_no_segments() {
+        let sheet = parse_cue("FILE \"a.flac\" WAVE\nTRACK 01 AUDIO\nINDEX 01 00:00:00\n");
+        assert!(sheet.segments(None).is_empty());
+    }
+}
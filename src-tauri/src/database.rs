@@ -1,14 +1,41 @@
+use crate::credits;
 use crate::equalizer::EqualizerSettings;
-use crate::models::{PlayHistoryEntry, Playlist, ScanFolder, Track, TrackFilters};
+use crate::migrations;
+use crate::models::{
+    AlbumRating, ArtistCredit, ArtistInfo, ArtistRating, AudioProperties, Chapter, CodecStat,
+    CreditRole, Discoveries, ExtendedTags, GenreInfo, ListeningClock, ListeningStreaks,
+    ListeningTime, NameStat, NumberMatch, PlayHistoryEntry, PlaybackSession, Playlist,
+    PlaylistKind, QualityStats, RelocationReport, ResumePosition, ResumeSettings, ScanFolder,
+    SessionContext, SmartDateField, SmartNumberField, SmartPlaylistRules, SmartRule, SmartSort,
+    SmartTextField, StatsPeriod, StatsRange, TextMatch, Track, TrackFilters, TrackPage,
+    TrackSortField, TrackStat, TrackSummary, ValueCount, YearReport,
+};
+use crate::reconcile::{self, StoredTrack};
+use crate::search_query::{NumberField, Range, SearchQuery, Term, TextField};
+use crate::sorting;
+use crate::stats;
 use anyhow::{Context, Result};
-use rusqlite::{params, Connection, Result as SqlResult};
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-
-/// (album, artist, year, track_count, total_duration, most_recent_created_at)
-pub type AlbumInfo = (String, Option<String>, Option<i64>, i64, Option<i64>, i64);
-/// (album, artist, year, track_count, total_duration, most_recent_created_at, file_path, release_mbid)
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension, Result as SqlResult, Row};
+use std::collections::{HashMap, HashSet};
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::{Arc, Condvar, LockResult, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+/// (album_id, album, artist, year, track_count, total_duration, most_recent_created_at)
+pub type AlbumInfo = (
+    i64,
+    String,
+    Option<String>,
+    Option<i64>,
+    i64,
+    Option<i64>,
+    i64,
+);
+/// (album_id, album, artist, year, track_count, total_duration, most_recent_created_at, file_path,
+/// release_mbid, compilation)
 pub type AlbumCoverInfo = (
+    i64,
     String,
     Option<String>,
     Option<i64>,
@@ -17,283 +44,905 @@ pub type AlbumCoverInfo = (
     i64,
     Option<String>,
     Option<String>,
+    bool,
 );
 
-/// Thread-safe handle to the database.
+/// Thread-safe handle to the database, shared by commands and background tasks.
+pub type Database = Arc<DatabasePool>;
+
+/// Read-only connections kept open next to the writer
+const READER_CONNECTIONS: usize = 4;
+/// How long a statement waits on a lock held by another connection before failing
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// One writer connection and a few read-only ones over the same WAL database.
 ///
-/// Uses `std::sync::Mutex` which blocks indefinitely on `lock()`.
-/// Deadlock risk is accepted as low because:
-/// 1. All lock acquisitions are short-lived (single DB operation then drop).
-/// 2. No code path holds the lock while acquiring another lock.
-/// 3. The `lock_db()` helper in commands.rs handles the poisoned case.
-pub type Database = Arc<Mutex<DatabaseInner>>;
+/// In WAL mode readers see the last committed state and are never blocked by
+/// the writer, so library queries from the UI keep working while a scan or a
+/// metadata fetch is writing. Writes are serialized on the writer's mutex.
+/// Read-only connections can't be used for writes: every method that changes
+/// the database takes `&mut self`, which only `write()` can provide.
+pub struct DatabasePool {
+    writer: Mutex<DatabaseInner>,
+    readers: Mutex<Vec<DatabaseInner>>,
+    reader_returned: Condvar,
+}
 
-pub struct DatabaseInner {
-    pub conn: Connection,
+/// A read-only connection borrowed from the pool, returned to it on drop
+pub struct ReadConnection<'a> {
+    pool: &'a DatabasePool,
+    db: Option<DatabaseInner>,
 }
 
-impl DatabaseInner {
-    pub fn new() -> Result<Self> {
+impl Deref for ReadConnection<'_> {
+    type Target = DatabaseInner;
+
+    fn deref(&self) -> &DatabaseInner {
+        self.db.as_ref().expect("connection is only taken on drop")
+    }
+}
+
+impl Drop for ReadConnection<'_> {
+    fn drop(&mut self) {
+        if let Some(db) = self.db.take() {
+            // The list is only pushed to and popped from, so it can't be left
+            // inconsistent by a panic
+            let mut idle = self
+                .pool
+                .readers
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            idle.push(db);
+            self.pool.reader_returned.notify_one();
+        }
+    }
+}
+
+impl DatabasePool {
+    /// Open the database in the app data directory, upgrading its schema first
+    pub fn open() -> Result<Self> {
         // Use proper app data directory instead of CWD
         let data_dir = dirs::data_dir()
             .unwrap_or_else(|| std::path::PathBuf::from("."))
             .join("osmp");
         std::fs::create_dir_all(&data_dir).context("Failed to create app data directory")?;
-        let db_path = data_dir.join("osmp.db");
+        Self::open_at(&data_dir.join("osmp.db"), Some(&data_dir))
+    }
 
-        let conn = Connection::open(&db_path).context("Failed to open database")?;
+    /// Open the database at `path`. Migrations run on the writer before any
+    /// reader connects; see `DatabaseInner::open` for `backup_dir`.
+    pub fn open_at(path: &Path, backup_dir: Option<&Path>) -> Result<Self> {
+        let writer = DatabaseInner::open(path, backup_dir)?;
+
+        let mut readers = Vec::with_capacity(READER_CONNECTIONS);
+        for _ in 0..READER_CONNECTIONS {
+            let conn = Connection::open_with_flags(
+                path,
+                OpenFlags::SQLITE_OPEN_READ_ONLY
+                    | OpenFlags::SQLITE_OPEN_NO_MUTEX
+                    | OpenFlags::SQLITE_OPEN_URI,
+            )
+            .context("Failed to open database reader")?;
+            conn.busy_timeout(BUSY_TIMEOUT)?;
+            sorting::register_collation(&conn)?;
+            readers.push(DatabaseInner { conn });
+        }
 
-        // Enable WAL mode for crash recovery and concurrent reads
-        conn.execute_batch("PRAGMA journal_mode = WAL;")
-            .context("Failed to set WAL mode")?;
-        // Enable foreign key enforcement
-        conn.execute_batch("PRAGMA foreign_keys = ON;")
-            .context("Failed to enable foreign keys")?;
+        Ok(DatabasePool {
+            writer: Mutex::new(writer),
+            readers: Mutex::new(readers),
+            reader_returned: Condvar::new(),
+        })
+    }
 
-        let mut db = DatabaseInner { conn };
-        db.init_schema()?;
-        Ok(db)
+    /// Borrow a read-only connection, waiting for one to be returned if all
+    /// are in use
+    pub fn read(&self) -> Result<ReadConnection<'_>, PoisonError<()>> {
+        let mut idle = self.readers.lock().map_err(|_| PoisonError::new(()))?;
+        loop {
+            if let Some(db) = idle.pop() {
+                return Ok(ReadConnection {
+                    pool: self,
+                    db: Some(db),
+                });
+            }
+            idle = self
+                .reader_returned
+                .wait(idle)
+                .map_err(|_| PoisonError::new(()))?;
+        }
     }
 
-    pub fn init_schema(&mut self) -> SqlResult<()> {
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS tracks (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                file_path TEXT UNIQUE NOT NULL,
-                title TEXT,
-                artist TEXT,
-                album TEXT,
-                duration INTEGER,
-                year INTEGER,
-                genre TEXT,
-                track_number INTEGER,
-                file_size INTEGER,
-                file_format TEXT,
-                last_modified INTEGER,
-                metadata_fetched BOOLEAN DEFAULT 0,
-                release_mbid TEXT,
-                created_at INTEGER DEFAULT (strftime('%s', 'now'))
-            )",
-            [],
-        )?;
+    /// Lock the writer connection. Hold it only for the writes themselves;
+    /// slow work such as tag reading or network requests belongs outside.
+    pub fn write(&self) -> LockResult<MutexGuard<'_, DatabaseInner>> {
+        self.writer.lock()
+    }
+}
 
-        // Add release_mbid column if it doesn't exist (for existing databases)
-        let _ = self
-            .conn
-            .execute("ALTER TABLE tracks ADD COLUMN release_mbid TEXT", []);
+pub struct DatabaseInner {
+    pub conn: Connection,
+}
 
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS albums (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                name TEXT NOT NULL,
-                artist TEXT,
-                year INTEGER,
-                cover_art_path TEXT
-            )",
-            [],
-        )?;
+/// Paths listed in a relocation report before the rest are only counted
+const MAX_REPORTED_PATHS: usize = 20;
 
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS artists (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                name TEXT UNIQUE NOT NULL
-            )",
-            [],
-        )?;
+/// Add `path` to a report list unless it is full
+fn note_path(list: &mut Vec<String>, path: &str) {
+    if list.len() < MAX_REPORTED_PATHS {
+        list.push(path.to_string());
+    }
+}
 
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS playlists (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                name TEXT NOT NULL,
-                created_at INTEGER DEFAULT (strftime('%s', 'now'))
-            )",
-            [],
-        )?;
+/// Column list matching `read_track`. Queries must alias `tracks` as `t`.
+const TRACK_COLUMNS: &str = "t.id, t.file_path, t.title, t.artist, t.album, t.duration, t.year,
+    t.genre, t.track_number, t.file_size, t.file_format, t.last_modified, t.metadata_fetched,
+    t.release_mbid, t.created_at, t.source_path, t.start_offset_ms, t.end_offset_ms, t.rating,
+    t.favorite, t.play_count, t.skip_count, t.first_played_at, t.last_played_at, t.artist_id,
+    t.album_id, t.album_artist, t.disc_number, t.disc_total, t.track_total, t.compilation,
+    t.composer, t.conductor, t.performer, t.work, t.movement, t.label, t.catalog_number, t.isrc,
+    t.bpm, t.comment, t.title_sort, t.artist_sort, t.album_sort, t.bitrate, t.sample_rate,
+    t.bit_depth, t.channels, t.codec, t.lossless, t.missing_since, t.content_hash";
+const TRACK_COLUMN_COUNT: usize = 52;
+
+/// Build a `Track` from `TRACK_COLUMNS` starting at column `base`
+fn read_track(row: &Row<'_>, base: usize) -> SqlResult<Track> {
+    Ok(Track {
+        id: row.get(base)?,
+        file_path: row.get(base + 1)?,
+        title: row.get(base + 2)?,
+        artist: row.get(base + 3)?,
+        album: row.get(base + 4)?,
+        duration: row.get(base + 5)?,
+        year: row.get(base + 6)?,
+        genre: row.get(base + 7)?,
+        track_number: row.get(base + 8)?,
+        file_size: row.get(base + 9)?,
+        file_format: row.get(base + 10)?,
+        last_modified: row.get(base + 11)?,
+        metadata_fetched: row.get(base + 12)?,
+        release_mbid: row.get(base + 13)?,
+        created_at: row.get(base + 14)?,
+        source_path: row.get(base + 15)?,
+        start_offset_ms: row.get(base + 16)?,
+        end_offset_ms: row.get(base + 17)?,
+        rating: row.get(base + 18)?,
+        favorite: row.get(base + 19)?,
+        play_count: row.get(base + 20)?,
+        skip_count: row.get(base + 21)?,
+        first_played_at: row.get(base + 22)?,
+        last_played_at: row.get(base + 23)?,
+        artist_id: row.get(base + 24)?,
+        album_id: row.get(base + 25)?,
+        album_artist: row.get(base + 26)?,
+        disc_number: row.get(base + 27)?,
+        disc_total: row.get(base + 28)?,
+        track_total: row.get(base + 29)?,
+        compilation: row.get(base + 30)?,
+        missing_since: row.get(base + 50)?,
+        content_hash: row.get(base + 51)?,
+        tags: ExtendedTags {
+            composer: row.get(base + 31)?,
+            conductor: row.get(base + 32)?,
+            performer: row.get(base + 33)?,
+            work: row.get(base + 34)?,
+            movement: row.get(base + 35)?,
+            label: row.get(base + 36)?,
+            catalog_number: row.get(base + 37)?,
+            isrc: row.get(base + 38)?,
+            bpm: row.get(base + 39)?,
+            comment: row.get(base + 40)?,
+            title_sort: row.get(base + 41)?,
+            artist_sort: row.get(base + 42)?,
+            album_sort: row.get(base + 43)?,
+        },
+        audio: AudioProperties {
+            bitrate: row.get(base + 44)?,
+            sample_rate: row.get(base + 45)?,
+            bit_depth: row.get(base + 46)?,
+            channels: row.get(base + 47)?,
+            codec: row.get(base + 48)?,
+            lossless: row.get(base + 49)?,
+        },
+    })
+}
 
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS playlist_tracks (
-                playlist_id INTEGER,
-                track_id INTEGER,
-                position INTEGER,
-                FOREIGN KEY (playlist_id) REFERENCES playlists(id),
-                FOREIGN KEY (track_id) REFERENCES tracks(id)
-            )",
-            [],
-        )?;
+fn track_from_row(row: &Row<'_>) -> SqlResult<Track> {
+    read_track(row, 0)
+}
 
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS scan_folders (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                path TEXT UNIQUE NOT NULL,
-                enabled BOOLEAN DEFAULT 1
-            )",
-            [],
-        )?;
+/// Insert a scanned track, or refresh the tags of the row with the same path.
+/// Ratings from the database are kept when the file has none; play statistics
+/// are never touched.
+fn upsert_track(conn: &Connection, track: &Track) -> SqlResult<usize> {
+    let tags = &track.tags;
+    let audio = &track.audio;
+    conn.prepare_cached(
+        "INSERT INTO tracks (
+            file_path, title, artist, album, duration, year, genre,
+            track_number, file_size, file_format, last_modified, metadata_fetched,
+            source_path, start_offset_ms, end_offset_ms, rating, album_artist,
+            disc_number, disc_total, track_total, compilation, composer, conductor,
+            performer, work, movement, label, catalog_number, isrc, bpm, comment,
+            title_sort, artist_sort, album_sort, bitrate, sample_rate, bit_depth, channels,
+            codec, lossless, content_hash
+        ) VALUES (
+            ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17,
+            ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27, ?28, ?29, ?30, ?31, ?32, ?33, ?34,
+            ?35, ?36, ?37, ?38, ?39, ?40, ?41
+        )
+        ON CONFLICT(file_path) DO UPDATE SET
+            title = excluded.title,
+            artist = excluded.artist,
+            album = excluded.album,
+            duration = excluded.duration,
+            year = excluded.year,
+            genre = excluded.genre,
+            track_number = excluded.track_number,
+            file_size = excluded.file_size,
+            file_format = excluded.file_format,
+            last_modified = excluded.last_modified,
+            metadata_fetched = excluded.metadata_fetched,
+            source_path = excluded.source_path,
+            start_offset_ms = excluded.start_offset_ms,
+            end_offset_ms = excluded.end_offset_ms,
+            rating = COALESCE(excluded.rating, tracks.rating),
+            album_artist = excluded.album_artist,
+            disc_number = excluded.disc_number,
+            disc_total = excluded.disc_total,
+            track_total = excluded.track_total,
+            compilation = excluded.compilation,
+            composer = excluded.composer,
+            conductor = excluded.conductor,
+            performer = excluded.performer,
+            work = excluded.work,
+            movement = excluded.movement,
+            label = excluded.label,
+            catalog_number = excluded.catalog_number,
+            isrc = excluded.isrc,
+            bpm = excluded.bpm,
+            comment = excluded.comment,
+            title_sort = excluded.title_sort,
+            artist_sort = excluded.artist_sort,
+            album_sort = excluded.album_sort,
+            bitrate = excluded.bitrate,
+            sample_rate = excluded.sample_rate,
+            bit_depth = excluded.bit_depth,
+            channels = excluded.channels,
+            codec = excluded.codec,
+            lossless = excluded.lossless,
+            content_hash = COALESCE(excluded.content_hash, tracks.content_hash),
+            missing_since = NULL,
+            tags_pending = 0",
+    )?
+    .execute(params![
+        track.file_path,
+        track.title,
+        track.artist,
+        track.album,
+        track.duration,
+        track.year,
+        track.genre,
+        track.track_number,
+        track.file_size,
+        track.file_format,
+        track.last_modified,
+        track.metadata_fetched,
+        track.source_path,
+        track.start_offset_ms,
+        track.end_offset_ms,
+        track.rating,
+        track.album_artist,
+        track.disc_number,
+        track.disc_total,
+        track.track_total,
+        track.compilation,
+        tags.composer,
+        tags.conductor,
+        tags.performer,
+        tags.work,
+        tags.movement,
+        tags.label,
+        tags.catalog_number,
+        tags.isrc,
+        tags.bpm,
+        tags.comment,
+        tags.title_sort,
+        tags.artist_sort,
+        tags.album_sort,
+        audio.bitrate,
+        audio.sample_rate,
+        audio.bit_depth,
+        audio.channels,
+        audio.codec,
+        audio.lossless,
+        track.content_hash,
+    ])
+}
 
-        // Create indexes for performance
-        self.conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_tracks_artist ON tracks(artist)",
-            [],
-        )?;
+/// Album artist given to compilation tracks that have no ALBUMARTIST tag.
+/// The album linking triggers (migration 11) use the same name.
+const VARIOUS_ARTISTS: &str = "Various Artists";
+
+/// Tracks of album `?1` credited to `?2`, the way albums are grouped: the album
+/// artist, "Various Artists" for compilations, or else the track artist
+const ALBUM_ARTIST_CONDITION: &str =
+    "t.album_id IN (SELECT id FROM albums WHERE name = ?1 AND artist = ?2)";
+
+/// Disc by disc, then by track number; untagged tracks go last
+const ALBUM_TRACK_ORDER: &str =
+    "IFNULL(t.disc_number, 1), IFNULL(t.track_number, 999999), t.title_key COLLATE UNICODE";
+
+/// Tracks by title, using the sort name and ignoring a leading article. The
+/// `UNICODE` collation is registered by `sorting::register_collation`.
+const TITLE_ORDER: &str = "t.title_key COLLATE UNICODE";
+
+/// Column list matching `read_track_summary`
+const TRACK_SUMMARY_COLUMNS: &str = "t.id, t.title, t.artist, t.album, t.duration, t.year,
+    t.genre, t.track_number, t.file_format, t.rating, t.favorite, t.play_count, t.last_played_at";
+const TRACK_SUMMARY_COLUMN_COUNT: usize = 13;
+
+fn read_track_summary(row: &Row<'_>) -> SqlResult<TrackSummary> {
+    Ok(TrackSummary {
+        id: row.get(0)?,
+        title: row.get(1)?,
+        artist: row.get(2)?,
+        album: row.get(3)?,
+        duration: row.get(4)?,
+        year: row.get(5)?,
+        genre: row.get(6)?,
+        track_number: row.get(7)?,
+        file_format: row.get(8)?,
+        rating: row.get(9)?,
+        favorite: row.get(10)?,
+        play_count: row.get(11)?,
+        last_played_at: row.get(12)?,
+    })
+}
 
-        self.conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_tracks_album ON tracks(album)",
-            [],
-        )?;
+/// `TRACK_COLUMNS` followed by plays and seconds listened
+fn read_track_stat(row: &Row<'_>) -> SqlResult<TrackStat> {
+    Ok(TrackStat {
+        track: read_track(row, 0)?,
+        plays: row.get(TRACK_COLUMN_COUNT)?,
+        seconds: row.get(TRACK_COLUMN_COUNT + 1)?,
+    })
+}
 
-        self.conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_tracks_genre ON tracks(genre)",
-            [],
-        )?;
+/// Name, artist, plays and seconds listened
+fn read_name_stat(row: &Row<'_>) -> SqlResult<NameStat> {
+    Ok(NameStat {
+        name: row.get(0)?,
+        artist: row.get(1)?,
+        plays: row.get(2)?,
+        seconds: row.get(3)?,
+    })
+}
 
-        // Create indexes for playlist_tracks
-        self.conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_playlist_tracks_playlist_id ON playlist_tracks(playlist_id)",
-            [],
-        )?;
+/// Quote free text as an FTS5 term so input such as `AC/DC` or `NEAR(` is not
+/// parsed as FTS5 syntax. Returns `None` when the text has nothing searchable.
+fn fts_term(text: &str, prefix: bool) -> Option<String> {
+    if !text.chars().any(char::is_alphanumeric) {
+        return None;
+    }
+    let quoted = format!("\"{}\"", text.replace('"', "\"\""));
+    Some(if prefix { quoted + "*" } else { quoted })
+}
 
-        self.conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_playlist_tracks_position ON playlist_tracks(playlist_id, position)",
-            [],
-        )?;
+/// Escape `%`, `_` and `\` for use in a `LIKE ... ESCAPE '\'` pattern
+fn like_escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
 
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS eq_settings (
-                id INTEGER PRIMARY KEY CHECK (id = 1),
-                enabled BOOLEAN DEFAULT 1,
-                preamp_db REAL DEFAULT 0.0,
-                band1_gain REAL DEFAULT 0.0,
-                band2_gain REAL DEFAULT 0.0,
-                band3_gain REAL DEFAULT 0.0,
-                band4_gain REAL DEFAULT 0.0,
-                band5_gain REAL DEFAULT 0.0,
-                preset_name TEXT DEFAULT 'Flat'
-            )",
-            [],
-        )?;
+fn like_contains(text: &str) -> String {
+    format!("%{}%", like_escape(text))
+}
 
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS play_history (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                track_id INTEGER NOT NULL,
-                played_at INTEGER DEFAULT (strftime('%s', 'now')),
-                duration_listened INTEGER DEFAULT 0,
-                FOREIGN KEY (track_id) REFERENCES tracks(id) ON DELETE CASCADE
-            )",
-            [],
-        )?;
+/// A search query compiled to SQL over `tracks t`
+struct CompiledSearch {
+    /// FTS5 expression for the positive free-text terms, used for ranking
+    fts_match: Option<String>,
+    conditions: Vec<String>,
+    params: Vec<Box<dyn rusqlite::ToSql>>,
+}
 
-        self.conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_play_history_track_id ON play_history(track_id)",
-            [],
-        )?;
+fn compile_search(query: &SearchQuery) -> CompiledSearch {
+    let mut fts_terms = Vec::new();
+    let mut conditions = Vec::new();
+    let mut params: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+
+    for clause in &query.clauses {
+        let condition = match &clause.term {
+            Term::Text { text, phrase } => {
+                // Negated words are excluded as whole words: `-live` keeps "Lively"
+                let Some(term) = fts_term(text, !phrase && !clause.negated) else {
+                    continue;
+                };
+                if !clause.negated {
+                    fts_terms.push(term);
+                    continue;
+                }
+                params.push(Box::new(term));
+                "t.id IN (SELECT rowid FROM tracks_fts WHERE tracks_fts MATCH ?)".to_string()
+            }
+            Term::Field(field, value) => {
+                let column = match field {
+                    TextField::Title => "t.title",
+                    TextField::Artist => "t.artist",
+                    TextField::Album => "t.album",
+                    TextField::AlbumArtist => "t.album_artist",
+                    TextField::Genre => "t.genre",
+                    TextField::Path => "t.file_path",
+                    TextField::Composer => "t.composer",
+                    TextField::Conductor => "t.conductor",
+                    TextField::Performer => "t.performer",
+                    TextField::Work => "t.work",
+                    TextField::Movement => "t.movement",
+                    TextField::Label => "t.label",
+                    TextField::CatalogNumber => "t.catalog_number",
+                    TextField::Isrc => "t.isrc",
+                    TextField::Comment => "t.comment",
+                };
+                params.push(Box::new(like_contains(value)));
+                format!("{} LIKE ? ESCAPE '\\'", column)
+            }
+            Term::Number(field, range) => {
+                let column = match field {
+                    NumberField::Year => "t.year",
+                    NumberField::Duration => "t.duration",
+                    NumberField::Rating => "t.rating",
+                    NumberField::PlayCount => "t.play_count",
+                    NumberField::SkipCount => "t.skip_count",
+                    NumberField::Disc => "t.disc_number",
+                    NumberField::Bpm => "t.bpm",
+                    NumberField::Bitrate => "t.bitrate",
+                    NumberField::SampleRate => "t.sample_rate",
+                    NumberField::BitDepth => "t.bit_depth",
+                    NumberField::Channels => "t.channels",
+                };
+                let (op, value) = match *range {
+                    Range::Eq(v) => ("= ?", v),
+                    Range::Lt(v) => ("< ?", v),
+                    Range::Le(v) => ("<= ?", v),
+                    Range::Gt(v) => ("> ?", v),
+                    Range::Ge(v) => (">= ?", v),
+                    Range::Between(low, high) => {
+                        params.push(Box::new(low));
+                        ("BETWEEN ? AND ?", high)
+                    }
+                };
+                params.push(Box::new(value));
+                format!("{} {}", column, op)
+            }
+            Term::Format(format) => {
+                params.push(Box::new(format.clone()));
+                "t.file_format = ? COLLATE NOCASE".to_string()
+            }
+            Term::Favorite(favorite) => {
+                params.push(Box::new(*favorite));
+                "t.favorite = ?".to_string()
+            }
+            Term::Codec(codec) => {
+                params.push(Box::new(codec.clone()));
+                "t.codec = ? COLLATE NOCASE".to_string()
+            }
+            Term::Lossless(lossless) => {
+                params.push(Box::new(*lossless));
+                "t.lossless = ?".to_string()
+            }
+            Term::HiRes(hi_res) => {
+                params.push(Box::new(*hi_res));
+                format!("{} = ?", HI_RES_CONDITION)
+            }
+        };
 
-        self.conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_play_history_played_at ON play_history(played_at DESC)",
-            [],
-        )?;
+        // NULL columns don't match a field, so they do match its negation
+        conditions.push(if clause.negated {
+            format!("NOT COALESCE({}, 0)", condition)
+        } else {
+            condition
+        });
+    }
 
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS app_settings (
-                key TEXT PRIMARY KEY,
-                value TEXT NOT NULL
-            )",
-            [],
-        )?;
+    CompiledSearch {
+        fts_match: (!fts_terms.is_empty()).then(|| fts_terms.join(" ")),
+        conditions,
+        params,
+    }
+}
+
+/// Conditions over `tracks t` for the fields set in `filters`
+fn filter_conditions(
+    filters: &TrackFilters,
+    conditions: &mut Vec<String>,
+    params: &mut Vec<Box<dyn rusqlite::ToSql>>,
+) {
+    // Artist and genre match any of the values a tag was split into
+    if let Some(artist) = &filters.artist {
+        conditions.push(format!("(t.artist = ? OR {})", CREDITED_ARTIST_CONDITION));
+        params.push(Box::new(artist.clone()));
+        params.push(Box::new(artist.clone()));
+    }
+    if let Some(album) = &filters.album {
+        conditions.push("t.album = ?".to_string());
+        params.push(Box::new(album.clone()));
+    }
+    if let Some(artist_id) = filters.artist_id {
+        conditions
+            .push("t.id IN (SELECT track_id FROM track_artists WHERE artist_id = ?)".to_string());
+        params.push(Box::new(artist_id));
+    }
+    if let Some(album_id) = filters.album_id {
+        conditions.push("t.album_id = ?".to_string());
+        params.push(Box::new(album_id));
+    }
+    if let Some(genre) = &filters.genre {
+        conditions.push(format!("(t.genre = ? OR {})", TAGGED_GENRE_CONDITION));
+        params.push(Box::new(genre.clone()));
+        params.push(Box::new(genre.clone()));
+    }
+    if let Some(year) = filters.year {
+        conditions.push("t.year = ?".to_string());
+        params.push(Box::new(year));
+    }
+    if let Some(format) = &filters.format {
+        conditions.push("t.file_format = ?".to_string());
+        params.push(Box::new(format.clone()));
+    }
+    if let Some(min_rating) = filters.min_rating {
+        conditions.push("t.rating >= ?".to_string());
+        params.push(Box::new(min_rating));
+    }
+    if let Some(favorite) = filters.favorite {
+        conditions.push("t.favorite = ?".to_string());
+        params.push(Box::new(favorite));
+    }
+    if let Some(codec) = &filters.codec {
+        conditions.push("t.codec = ? COLLATE NOCASE".to_string());
+        params.push(Box::new(codec.clone()));
+    }
+    if let Some(lossless) = filters.lossless {
+        conditions.push("t.lossless = ?".to_string());
+        params.push(Box::new(lossless));
+    }
+    if let Some(hi_res) = filters.hi_res {
+        conditions.push(format!("{} = ?", HI_RES_CONDITION));
+        params.push(Box::new(hi_res));
+    }
+    if let Some(channels) = filters.channels {
+        conditions.push("t.channels = ?".to_string());
+        params.push(Box::new(channels));
+    }
+    if let Some(max_bitrate) = filters.max_bitrate {
+        conditions.push("t.bitrate <= ?".to_string());
+        params.push(Box::new(max_bitrate));
+    }
+    if let Some(missing) = filters.missing {
+        conditions.push("(t.missing_since IS NOT NULL) = ?".to_string());
+        params.push(Box::new(missing));
+    }
+}
+
+/// Lossless audio with more than CD resolution (16 bits, 44.1/48 kHz). NULL
+/// when the properties are unknown.
+const HI_RES_CONDITION: &str = "(t.lossless AND (t.bit_depth > 16 OR t.sample_rate > 48000))";
+
+/// Tracks crediting the artist named by the parameter, including as a featured artist
+const CREDITED_ARTIST_CONDITION: &str = "t.id IN (
+    SELECT ta.track_id FROM track_artists ta JOIN artists ar ON ar.id = ta.artist_id
+    WHERE ar.name = ? COLLATE NOCASE
+)";
+
+/// Tracks tagged with the genre named by the parameter
+const TAGGED_GENRE_CONDITION: &str = "t.id IN (
+    SELECT tg.track_id FROM track_genres tg JOIN genres g ON g.id = tg.genre_id
+    WHERE g.name = ? COLLATE NOCASE
+)";
+
+/// `conditions` as a suffix for `WHERE 1=1`
+fn and_conditions(conditions: &[String]) -> String {
+    conditions
+        .iter()
+        .map(|condition| format!(" AND {}", condition))
+        .collect()
+}
+
+fn sort_column(field: TrackSortField) -> &'static str {
+    match field {
+        TrackSortField::Title => TITLE_ORDER,
+        TrackSortField::Artist => "t.artist_key COLLATE UNICODE",
+        TrackSortField::Album => "t.album_key COLLATE UNICODE",
+        TrackSortField::Year => "t.year",
+        TrackSortField::Duration => "t.duration",
+        TrackSortField::DateAdded => "t.created_at",
+        TrackSortField::Rating => "t.rating",
+        TrackSortField::PlayCount => "t.play_count",
+        TrackSortField::SkipCount => "t.skip_count",
+        TrackSortField::LastPlayed => "t.last_played_at",
+        TrackSortField::Bitrate => "t.bitrate",
+    }
+}
+
+/// Search relevance; bm25 weights follow the index column order: title, artist, album,
+/// album artist, composer, conductor, performer, work, movement, label, catalog number,
+/// ISRC, comment
+const SEARCH_RANK_SQL: &str =
+    "bm25(tracks_fts, 10.0, 5.0, 2.0, 2.0, 3.0, 1.0, 1.0, 2.0, 1.0, 1.0, 1.0, 1.0, 0.5)";
+
+fn invalid_cursor() -> rusqlite::Error {
+    rusqlite::Error::ToSqlConversionFailure("Invalid page cursor".into())
+}
+
+fn cursor_value(value: rusqlite::types::Value) -> serde_json::Value {
+    use rusqlite::types::Value;
+    match value {
+        Value::Integer(i) => i.into(),
+        Value::Real(f) => f.into(),
+        Value::Text(s) => s.into(),
+        Value::Null | Value::Blob(_) => serde_json::Value::Null,
+    }
+}
+
+/// Condition selecting the rows that sort after `after` under `keys`
+/// (expression, descending). SQLite sorts NULL before any value, so NULLs
+/// come first in ascending order and last in descending order.
+fn keyset_condition(
+    keys: &[(&str, bool)],
+    after: &[serde_json::Value],
+    params: &mut Vec<Box<dyn rusqlite::ToSql>>,
+) -> SqlResult<String> {
+    use rusqlite::types::Value;
+    let values = after
+        .iter()
+        .map(|value| match value {
+            serde_json::Value::Null => Ok(Value::Null),
+            serde_json::Value::String(s) => Ok(Value::Text(s.clone())),
+            serde_json::Value::Number(n) => n
+                .as_i64()
+                .map(Value::Integer)
+                .or_else(|| n.as_f64().map(Value::Real))
+                .ok_or_else(invalid_cursor),
+            _ => Err(invalid_cursor()),
+        })
+        .collect::<SqlResult<Vec<_>>>()?;
+
+    let mut alternatives = Vec::new();
+    for (i, ((expr, descending), value)) in keys.iter().zip(&values).enumerate() {
+        let after = match (value, descending) {
+            (Value::Null, false) => format!("{} IS NOT NULL", expr),
+            // Nothing sorts after NULL; ties are broken by the later keys
+            (Value::Null, true) => continue,
+            (_, false) => format!("{} > ?", expr),
+            (_, true) => format!("({expr} < ? OR {expr} IS NULL)", expr = expr),
+        };
+        let mut parts = Vec::new();
+        for ((prefix, _), prefix_value) in keys[..i].iter().zip(&values) {
+            parts.push(format!("{} IS ?", prefix));
+            params.push(Box::new(prefix_value.clone()));
+        }
+        if *value != Value::Null {
+            params.push(Box::new(value.clone()));
+        }
+        parts.push(after);
+        alternatives.push(format!("({})", parts.join(" AND ")));
+    }
+
+    if alternatives.is_empty() {
+        return Ok("0".to_string());
+    }
+    Ok(format!("({})", alternatives.join(" OR ")))
+}
+
+/// A listen counts as a play once it covers this fraction of the track...
+const PLAY_FRACTION: f64 = 0.5;
+/// ...or this many seconds, whichever comes first
+const PLAY_SECONDS: i64 = 240;
+/// Seconds needed to count as a play when the track's duration is unknown
+const PLAY_SECONDS_UNKNOWN_DURATION: i64 = 30;
+
+/// Whether a listen of `listened` seconds was a skip rather than a play
+fn is_skip(listened: i64, duration: Option<i64>) -> bool {
+    let required = match duration {
+        Some(duration) if duration > 0 => {
+            ((duration as f64 * PLAY_FRACTION).ceil() as i64).min(PLAY_SECONDS)
+        }
+        _ => PLAY_SECONDS_UNKNOWN_DURATION,
+    };
+    listened < required
+}
+
+/// Compile a smart playlist rule into a condition over `tracks t`
+fn compile_rule(rule: &SmartRule, params: &mut Vec<Box<dyn rusqlite::ToSql>>) -> String {
+    let join = |rules: &[SmartRule], separator: &str, params: &mut Vec<_>| {
+        rules
+            .iter()
+            .map(|rule| format!("({})", compile_rule(rule, params)))
+            .collect::<Vec<_>>()
+            .join(separator)
+    };
+
+    match rule {
+        SmartRule::All { rules } if rules.is_empty() => "1".to_string(),
+        SmartRule::Any { rules } if rules.is_empty() => "0".to_string(),
+        SmartRule::All { rules } => join(rules, " AND ", params),
+        SmartRule::Any { rules } => join(rules, " OR ", params),
+        // A rule on a NULL column doesn't match, so its negation does
+        SmartRule::Not { rule } => format!("NOT COALESCE(({}), 0)", compile_rule(rule, params)),
+        SmartRule::Text { field, op, value } => {
+            let column = match field {
+                SmartTextField::Title => "t.title",
+                SmartTextField::Artist => "t.artist",
+                SmartTextField::Album => "t.album",
+                SmartTextField::AlbumArtist => "t.album_artist",
+                SmartTextField::Genre => "t.genre",
+                SmartTextField::Format => "t.file_format",
+                SmartTextField::Path => "t.file_path",
+            };
+            let (comparison, pattern) = match op {
+                TextMatch::Is => ("= ? COLLATE NOCASE", value.clone()),
+                TextMatch::Contains => ("LIKE ? ESCAPE '\\'", like_contains(value)),
+                TextMatch::StartsWith => ("LIKE ? ESCAPE '\\'", format!("{}%", like_escape(value))),
+            };
+            params.push(Box::new(pattern));
+            // "Artist is" and "genre is" also match one value of a multi-value tag
+            let split_values = match (field, op) {
+                (SmartTextField::Artist, TextMatch::Is) => Some(CREDITED_ARTIST_CONDITION),
+                (SmartTextField::Genre, TextMatch::Is) => Some(TAGGED_GENRE_CONDITION),
+                _ => None,
+            };
+            match split_values {
+                Some(condition) => {
+                    params.push(Box::new(value.clone()));
+                    format!("({} {} OR {})", column, comparison, condition)
+                }
+                None => format!("{} {}", column, comparison),
+            }
+        }
+        SmartRule::Number { field, op, value } => {
+            let column = match field {
+                SmartNumberField::Year => "t.year",
+                SmartNumberField::Duration => "t.duration",
+                SmartNumberField::PlayCount => "t.play_count",
+                SmartNumberField::SkipCount => "t.skip_count",
+                SmartNumberField::Rating => "t.rating",
+            };
+            let op = match op {
+                NumberMatch::Eq => "=",
+                NumberMatch::Gt => ">",
+                NumberMatch::Gte => ">=",
+                NumberMatch::Lt => "<",
+                NumberMatch::Lte => "<=",
+            };
+            params.push(Box::new(*value));
+            format!("{} {} ?", column, op)
+        }
+        SmartRule::InLastDays { field, days } => {
+            let column = match field {
+                SmartDateField::DateAdded => "t.created_at",
+                SmartDateField::LastPlayed => "t.last_played_at",
+            };
+            params.push(Box::new(*days));
+            format!(
+                "{} >= CAST(strftime('%s', 'now') AS INTEGER) - ? * 86400",
+                column
+            )
+        }
+        SmartRule::Favorite { favorite } => {
+            params.push(Box::new(*favorite));
+            "t.favorite = ?".to_string()
+        }
+    }
+}
+
+/// `FROM ... ORDER BY ... LIMIT ?` selecting a smart playlist's tracks
+fn smart_playlist_sql(
+    rules: &SmartPlaylistRules,
+    columns: &str,
+) -> (String, Vec<Box<dyn rusqlite::ToSql>>) {
+    let mut params: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+    let condition = compile_rule(&rules.rule, &mut params);
+
+    let sort = match rules.sort {
+        SmartSort::Random => "RANDOM()",
+        SmartSort::Title => TITLE_ORDER,
+        SmartSort::Artist => "t.artist_key COLLATE UNICODE",
+        SmartSort::Album => "t.album_key COLLATE UNICODE",
+        SmartSort::Year => "t.year",
+        SmartSort::Duration => "t.duration",
+        SmartSort::DateAdded => "t.created_at",
+        SmartSort::LastPlayed => "t.last_played_at",
+        SmartSort::PlayCount => "t.play_count",
+        SmartSort::Rating => "t.rating",
+    };
+    let direction = if rules.descending { "DESC" } else { "ASC" };
+
+    // A negative LIMIT means no limit
+    params.push(Box::new(rules.limit.unwrap_or(-1)));
+    let sql = format!(
+        "SELECT {} FROM tracks t WHERE {}
+         ORDER BY {} {}, {}, t.id
+         LIMIT ?",
+        columns, condition, sort, direction, TITLE_ORDER
+    );
+    (sql, params)
+}
+
+fn smart_rules_to_json(rules: &SmartPlaylistRules) -> SqlResult<String> {
+    serde_json::to_string(rules).map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))
+}
+
+fn playlist_from_row(row: &Row<'_>) -> SqlResult<Playlist> {
+    let kind: String = row.get(5)?;
+    Ok(Playlist {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        created_at: row.get(2)?,
+        track_count: row.get(3)?,
+        total_duration: row.get(4)?,
+        kind: PlaylistKind::from_db(&kind),
+    })
+}
+
+impl DatabaseInner {
+    /// Open a read-write connection in WAL mode and upgrade the schema,
+    /// backing up an existing database to `backup_dir` first
+    pub fn open(path: &Path, backup_dir: Option<&Path>) -> Result<Self> {
+        let mut conn = Connection::open(path).context("Failed to open database")?;
+
+        // Enable WAL mode for crash recovery and concurrent reads
+        conn.execute_batch("PRAGMA journal_mode = WAL;")
+            .context("Failed to set WAL mode")?;
+        // Enable foreign key enforcement
+        conn.execute_batch("PRAGMA foreign_keys = ON;")
+            .context("Failed to enable foreign keys")?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        sorting::register_collation(&conn)?;
+
+        // Upgrade the schema, backing up an existing database first
+        migrations::migrate(&mut conn, backup_dir)?;
+
+        Ok(DatabaseInner { conn })
+    }
 
+    /// Create or upgrade the schema to the latest version (see `migrations.rs`)
+    /// and register the collation queries order by
+    pub fn init_schema(&mut self) -> Result<()> {
+        sorting::register_collation(&self.conn)?;
+        migrations::migrate(&mut self.conn, None)?;
         Ok(())
     }
 
     pub fn insert_or_update_track(&mut self, track: &Track) -> SqlResult<i64> {
-        self.conn.execute(
-            "INSERT INTO tracks (
-                file_path, title, artist, album, duration, year, genre,
-                track_number, file_size, file_format, last_modified, metadata_fetched
-            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
-            ON CONFLICT(file_path) DO UPDATE SET
-                title = excluded.title,
-                artist = excluded.artist,
-                album = excluded.album,
-                duration = excluded.duration,
-                year = excluded.year,
-                genre = excluded.genre,
-                track_number = excluded.track_number,
-                file_size = excluded.file_size,
-                file_format = excluded.file_format,
-                last_modified = excluded.last_modified,
-                metadata_fetched = excluded.metadata_fetched",
-            params![
-                track.file_path,
-                track.title,
-                track.artist,
-                track.album,
-                track.duration,
-                track.year,
-                track.genre,
-                track.track_number,
-                track.file_size,
-                track.file_format,
-                track.last_modified,
-                track.metadata_fetched,
-            ],
-        )?;
+        upsert_track(&self.conn, track)?;
         Ok(self.conn.last_insert_rowid())
     }
 
     pub fn get_tracks(&self, filters: Option<&TrackFilters>) -> SqlResult<Vec<Track>> {
-        let mut query = "SELECT id, file_path, title, artist, album, duration, year, genre,
-                        track_number, file_size, file_format, last_modified, metadata_fetched,
-                        release_mbid, created_at FROM tracks WHERE 1=1"
-            .to_string();
+        let mut conditions = Vec::new();
         let mut params_vec: Vec<Box<dyn rusqlite::ToSql>> = vec![];
-
         if let Some(filters) = filters {
-            if let Some(artist) = &filters.artist {
-                query.push_str(" AND artist = ?");
-                params_vec.push(Box::new(artist.clone()));
-            }
-            if let Some(album) = &filters.album {
-                query.push_str(" AND album = ?");
-                params_vec.push(Box::new(album.clone()));
-            }
-            if let Some(genre) = &filters.genre {
-                query.push_str(" AND genre = ?");
-                params_vec.push(Box::new(genre.clone()));
-            }
-            if let Some(year) = filters.year {
-                query.push_str(" AND year = ?");
-                params_vec.push(Box::new(year));
-            }
-            if let Some(format) = &filters.format {
-                query.push_str(" AND file_format = ?");
-                params_vec.push(Box::new(format.clone()));
-            }
+            filter_conditions(filters, &mut conditions, &mut params_vec);
         }
+        let mut query = format!(
+            "SELECT {} FROM tracks t WHERE 1=1{}",
+            TRACK_COLUMNS,
+            and_conditions(&conditions)
+        );
 
-        query.push_str(" ORDER BY title COLLATE NOCASE");
+        query.push_str(" ORDER BY ");
+        for key in filters.map(|f| f.sort.as_slice()).unwrap_or_default() {
+            let direction = if key.descending { "DESC" } else { "ASC" };
+            query.push_str(&format!("{} {}, ", sort_column(key.field), direction));
+        }
+        query.push_str(TITLE_ORDER);
+        query.push_str(", t.id");
 
         let mut stmt = self.conn.prepare(&query)?;
         let rows = stmt.query_map(
             rusqlite::params_from_iter(params_vec.iter().map(|p| p.as_ref())),
-            |row| {
-                Ok(Track {
-                    id: row.get(0)?,
-                    file_path: row.get(1)?,
-                    title: row.get(2)?,
-                    artist: row.get(3)?,
-                    album: row.get(4)?,
-                    duration: row.get(5)?,
-                    year: row.get(6)?,
-                    genre: row.get(7)?,
-                    track_number: row.get(8)?,
-                    file_size: row.get(9)?,
-                    file_format: row.get(10)?,
-                    last_modified: row.get(11)?,
-                    metadata_fetched: row.get(12)?,
-                    release_mbid: row.get(13)?,
-                    created_at: row.get(14)?,
-                })
-            },
+            track_from_row,
         )?;
 
         let mut tracks = Vec::new();
@@ -303,35 +952,142 @@ impl DatabaseInner {
         Ok(tracks)
     }
 
-    pub fn search_tracks(&self, query: &str) -> SqlResult<Vec<Track>> {
-        let search_term = format!("%{}%", query);
-        let mut stmt = self.conn.prepare(
-            "SELECT id, file_path, title, artist, album, duration, year, genre,
-             track_number, file_size, file_format, last_modified, metadata_fetched,
-             release_mbid, created_at FROM tracks
-             WHERE title LIKE ?1 OR artist LIKE ?1 OR album LIKE ?1
-             ORDER BY title COLLATE NOCASE",
+    /// One page of tracks matching `filters` and an optional search, in
+    /// `filters.sort` order (search relevance, then title, when unsorted).
+    ///
+    /// Pages are found by keyset rather than offset: `after` holds the sort
+    /// values of the last track of the previous page, as returned in
+    /// `next_cursor`, so later pages cost the same as the first.
+    pub fn get_track_page(
+        &self,
+        filters: &TrackFilters,
+        search: Option<&SearchQuery>,
+        after: Option<&[serde_json::Value]>,
+        limit: i64,
+    ) -> SqlResult<TrackPage> {
+        let mut from = "tracks t".to_string();
+        let mut conditions = Vec::new();
+        let mut params_vec: Vec<Box<dyn rusqlite::ToSql>> = vec![];
+        let mut keys: Vec<(&str, bool)> = filters
+            .sort
+            .iter()
+            .map(|key| (sort_column(key.field), key.descending))
+            .collect();
+
+        if let Some(search) = search {
+            let compiled = compile_search(search);
+            if let Some(fts_match) = compiled.fts_match {
+                from.push_str(" JOIN tracks_fts ON tracks_fts.rowid = t.id");
+                conditions.push("tracks_fts MATCH ?".to_string());
+                params_vec.push(Box::new(fts_match));
+                if keys.is_empty() {
+                    keys.push((SEARCH_RANK_SQL, false));
+                }
+            }
+            conditions.extend(compiled.conditions);
+            params_vec.extend(compiled.params);
+        }
+        filter_conditions(filters, &mut conditions, &mut params_vec);
+        // Title and then id make the order total, so every track has one place
+        keys.push((TITLE_ORDER, false));
+        keys.push(("t.id", false));
+
+        let total: i64 = self.conn.query_row(
+            &format!(
+                "SELECT COUNT(*) FROM {} WHERE 1=1{}",
+                from,
+                and_conditions(&conditions)
+            ),
+            rusqlite::params_from_iter(params_vec.iter().map(|p| p.as_ref())),
+            |row| row.get(0),
         )?;
 
-        let rows = stmt.query_map(params![search_term], |row| {
-            Ok(Track {
-                id: row.get(0)?,
-                file_path: row.get(1)?,
-                title: row.get(2)?,
-                artist: row.get(3)?,
-                album: row.get(4)?,
-                duration: row.get(5)?,
-                year: row.get(6)?,
-                genre: row.get(7)?,
-                track_number: row.get(8)?,
-                file_size: row.get(9)?,
-                file_format: row.get(10)?,
-                last_modified: row.get(11)?,
-                metadata_fetched: row.get(12)?,
-                release_mbid: row.get(13)?,
-                created_at: row.get(14)?,
+        if let Some(after) = after {
+            if after.len() != keys.len() {
+                return Err(invalid_cursor());
+            }
+            conditions.push(keyset_condition(&keys, after, &mut params_vec)?);
+        }
+        let sort_values = keys
+            .iter()
+            .map(|(expr, _)| *expr)
+            .collect::<Vec<_>>()
+            .join(", ");
+        let order = keys
+            .iter()
+            .map(|(expr, descending)| {
+                format!("{} {}", expr, if *descending { "DESC" } else { "ASC" })
             })
-        })?;
+            .collect::<Vec<_>>()
+            .join(", ");
+        // One extra row tells whether there is another page
+        params_vec.push(Box::new(limit + 1));
+        let sql = format!(
+            "SELECT {}, {} FROM {} WHERE 1=1{} ORDER BY {} LIMIT ?",
+            TRACK_SUMMARY_COLUMNS,
+            sort_values,
+            from,
+            and_conditions(&conditions),
+            order
+        );
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let mut rows = stmt.query(rusqlite::params_from_iter(
+            params_vec.iter().map(|p| p.as_ref()),
+        ))?;
+        let mut tracks = Vec::new();
+        let mut last_values = Vec::new();
+        let mut has_more = false;
+        while let Some(row) = rows.next()? {
+            if tracks.len() as i64 == limit {
+                has_more = true;
+                break;
+            }
+            tracks.push(read_track_summary(row)?);
+            last_values = (0..keys.len())
+                .map(|i| row.get::<_, rusqlite::types::Value>(TRACK_SUMMARY_COLUMN_COUNT + i))
+                .collect::<SqlResult<Vec<_>>>()?;
+        }
+
+        Ok(TrackPage {
+            tracks,
+            total,
+            next_cursor: has_more.then(|| last_values.into_iter().map(cursor_value).collect()),
+        })
+    }
+
+    /// Run a parsed search (see `search_query.rs`). Free text is matched
+    /// against the full-text index over title, artist and album, ignoring
+    /// accents, with bare words matching as prefixes; field terms filter the
+    /// results. Queries with free text return the best matches first.
+    pub fn search_tracks(&self, query: &SearchQuery) -> SqlResult<Vec<Track>> {
+        let compiled = compile_search(query);
+        let mut params_vec: Vec<Box<dyn rusqlite::ToSql>> = vec![];
+
+        let mut sql = format!("SELECT {} FROM tracks t", TRACK_COLUMNS);
+        if let Some(fts_match) = &compiled.fts_match {
+            sql.push_str(" JOIN tracks_fts ON tracks_fts.rowid = t.id WHERE tracks_fts MATCH ?");
+            params_vec.push(Box::new(fts_match.clone()));
+        } else {
+            sql.push_str(" WHERE 1=1");
+        }
+        for condition in &compiled.conditions {
+            sql.push_str(" AND ");
+            sql.push_str(condition);
+        }
+        params_vec.extend(compiled.params);
+
+        if compiled.fts_match.is_some() {
+            sql.push_str(&format!(" ORDER BY {}, {}", SEARCH_RANK_SQL, TITLE_ORDER));
+        } else {
+            sql.push_str(&format!(" ORDER BY {}", TITLE_ORDER));
+        }
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt.query_map(
+            rusqlite::params_from_iter(params_vec.iter().map(|p| p.as_ref())),
+            track_from_row,
+        )?;
 
         let mut tracks = Vec::new();
         for track in rows {
@@ -342,29 +1098,9 @@ impl DatabaseInner {
 
     pub fn get_track_by_id(&self, id: i64) -> SqlResult<Track> {
         self.conn.query_row(
-            "SELECT id, file_path, title, artist, album, duration, year, genre,
-             track_number, file_size, file_format, last_modified, metadata_fetched,
-             release_mbid, created_at FROM tracks WHERE id = ?1",
+            &format!("SELECT {} FROM tracks t WHERE id = ?1", TRACK_COLUMNS),
             params![id],
-            |row| {
-                Ok(Track {
-                    id: row.get(0)?,
-                    file_path: row.get(1)?,
-                    title: row.get(2)?,
-                    artist: row.get(3)?,
-                    album: row.get(4)?,
-                    duration: row.get(5)?,
-                    year: row.get(6)?,
-                    genre: row.get(7)?,
-                    track_number: row.get(8)?,
-                    file_size: row.get(9)?,
-                    file_format: row.get(10)?,
-                    last_modified: row.get(11)?,
-                    metadata_fetched: row.get(12)?,
-                    release_mbid: row.get(13)?,
-                    created_at: row.get(14)?,
-                })
-            },
+            track_from_row,
         )
     }
 
@@ -382,7 +1118,7 @@ impl DatabaseInner {
              WHERE id = ?6",
             params![title, artist, album, year, genre, track_id],
         )?;
-        Ok(())
+        self.relink_track(track_id)
     }
 
     /// Smart update that only updates fields with values, preserving existing data
@@ -440,6 +1176,7 @@ impl DatabaseInner {
                 &query,
                 rusqlite::params_from_iter(params.iter().map(|p| p.as_ref())),
             )?;
+            self.relink_track(track_id)?;
         }
 
         Ok(())
@@ -461,18 +1198,67 @@ impl DatabaseInner {
              WHERE id = ?7",
             params![title, artist, album, year, genre, track_number, track_id],
         )?;
-        Ok(())
+        self.relink_track(track_id)
     }
 
-    pub fn get_scan_folders(&self) -> SqlResult<Vec<ScanFolder>> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT id, path, enabled FROM scan_folders ORDER BY path")?;
-        let rows = stmt.query_map([], |row| {
-            Ok(ScanFolder {
-                id: row.get(0)?,
-                path: row.get(1)?,
-                enabled: row.get(2)?,
+    /// Replace the extended tags of a track
+    pub fn update_extended_tags(&mut self, track_id: i64, tags: &ExtendedTags) -> SqlResult<()> {
+        self.conn.execute(
+            "UPDATE tracks SET composer = ?1, conductor = ?2, performer = ?3, work = ?4,
+                movement = ?5, label = ?6, catalog_number = ?7, isrc = ?8, bpm = ?9, comment = ?10,
+                title_sort = ?11, artist_sort = ?12, album_sort = ?13
+             WHERE id = ?14",
+            params![
+                tags.composer,
+                tags.conductor,
+                tags.performer,
+                tags.work,
+                tags.movement,
+                tags.label,
+                tags.catalog_number,
+                tags.isrc,
+                tags.bpm,
+                tags.comment,
+                tags.title_sort,
+                tags.artist_sort,
+                tags.album_sort,
+                track_id
+            ],
+        )?;
+        self.relink_track(track_id)
+    }
+
+    /// Split the artist and genre of a track again and work out its sort keys
+    /// after its tags were edited
+    fn relink_track(&mut self, track_id: i64) -> SqlResult<()> {
+        let separators = self.load_tag_separators()?;
+        let articles = self.load_sort_articles()?;
+        let tx = self.conn.transaction()?;
+        let (artist, genre): (Option<String>, Option<String>) = tx.query_row(
+            "SELECT artist, genre FROM tracks WHERE id = ?1",
+            params![track_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        credits::link_track_credits(
+            &tx,
+            track_id,
+            artist.as_deref(),
+            genre.as_deref(),
+            &separators,
+        )?;
+        sorting::update_sort_keys(&tx, track_id, &articles)?;
+        tx.commit()
+    }
+
+    pub fn get_scan_folders(&self) -> SqlResult<Vec<ScanFolder>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, path, enabled FROM scan_folders ORDER BY path")?;
+        let rows = stmt.query_map([], |row| {
+            Ok(ScanFolder {
+                id: row.get(0)?,
+                path: row.get(1)?,
+                enabled: row.get(2)?,
             })
         })?;
 
@@ -516,30 +1302,31 @@ impl DatabaseInner {
     }
 
     pub fn get_albums(&self) -> SqlResult<Vec<AlbumInfo>> {
-        // Group by album and artist, calculate aggregates
-        // Returns: (album_name, artist, year, track_count, total_duration, most_recent_created_at)
+        // Returns: (id, album_name, artist, year, track_count, total_duration, most_recent_created_at)
         let mut stmt = self.conn.prepare(
-            "SELECT 
-                album,
-                artist,
-                MAX(year) as year,
+            "SELECT
+                al.id,
+                al.name,
+                al.artist,
+                MAX(t.year) as year,
                 COUNT(*) as track_count,
-                SUM(duration) as total_duration,
-                MAX(created_at) as most_recent_created_at
-            FROM tracks
-            WHERE album IS NOT NULL AND album != ''
-            GROUP BY album, artist
-            ORDER BY most_recent_created_at DESC, album COLLATE NOCASE",
+                SUM(t.duration) as total_duration,
+                MAX(t.created_at) as most_recent_created_at
+            FROM albums al
+            JOIN tracks t ON t.album_id = al.id
+            GROUP BY al.id
+            ORDER BY most_recent_created_at DESC, IFNULL(al.sort_key, al.name) COLLATE UNICODE",
         )?;
 
         let rows = stmt.query_map([], |row| {
             Ok((
-                row.get::<_, String>(0)?,
-                row.get::<_, Option<String>>(1)?,
-                row.get::<_, Option<i64>>(2)?,
-                row.get::<_, i64>(3)?,
-                row.get::<_, Option<i64>>(4)?,
-                row.get::<_, i64>(5)?,
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, Option<i64>>(3)?,
+                row.get::<_, i64>(4)?,
+                row.get::<_, Option<i64>>(5)?,
+                row.get::<_, i64>(6)?,
             ))
         })?;
 
@@ -550,52 +1337,64 @@ impl DatabaseInner {
         Ok(albums)
     }
 
-    /// Single query returning album info with first track's file_path and release_mbid for cover art lookup
-    pub fn get_albums_with_cover_info(&self) -> SqlResult<Vec<AlbumCoverInfo>> {
+    /// Single query returning album info with first track's file_path and release_mbid for cover art lookup.
+    /// `compilations_only` limits it to the "Various Artists" view.
+    pub fn get_albums_with_cover_info(
+        &self,
+        compilations_only: bool,
+    ) -> SqlResult<Vec<AlbumCoverInfo>> {
         let mut stmt = self.conn.prepare(
             "SELECT
-                a.album,
+                a.id,
+                a.name,
                 a.artist,
                 a.year,
                 a.track_count,
                 a.total_duration,
                 a.most_recent_created_at,
-                t.file_path,
-                t.release_mbid
+                COALESCE(t.source_path, t.file_path),
+                t.release_mbid,
+                a.compilation
             FROM (
                 SELECT
-                    album,
-                    artist,
-                    MAX(year) as year,
+                    al.id,
+                    al.name,
+                    al.artist,
+                    MAX(t.year) as year,
                     COUNT(*) as track_count,
-                    SUM(duration) as total_duration,
-                    MAX(created_at) as most_recent_created_at
-                FROM tracks
-                WHERE album IS NOT NULL AND album != ''
-                GROUP BY album, artist
+                    SUM(t.duration) as total_duration,
+                    MAX(t.created_at) as most_recent_created_at,
+                    MAX(t.compilation) OR al.artist IS ?1 as compilation,
+                    IFNULL(al.sort_key, al.name) as sort_key
+                FROM albums al
+                JOIN tracks t ON t.album_id = al.id
+                GROUP BY al.id
             ) a
             LEFT JOIN tracks t ON t.id = (
                 SELECT id FROM tracks t2
-                WHERE t2.album = a.album
-                    AND (t2.artist = a.artist OR (t2.artist IS NULL AND a.artist IS NULL))
+                WHERE t2.album_id = a.id
                 ORDER BY
                     CASE WHEN t2.release_mbid IS NOT NULL THEN 0 ELSE 1 END,
-                    CASE WHEN t2.track_number IS NOT NULL THEN t2.track_number ELSE 999999 END
+                    IFNULL(t2.disc_number, 1),
+                    IFNULL(t2.track_number, 999999)
                 LIMIT 1
             )
-            ORDER BY a.most_recent_created_at DESC, a.album COLLATE NOCASE",
+            WHERE a.compilation OR NOT ?2
+            ORDER BY a.most_recent_created_at DESC, a.sort_key COLLATE UNICODE",
         )?;
 
-        let rows = stmt.query_map([], |row| {
+        let rows = stmt.query_map(params![VARIOUS_ARTISTS, compilations_only], |row| {
             Ok((
-                row.get::<_, String>(0)?,
-                row.get::<_, Option<String>>(1)?,
-                row.get::<_, Option<i64>>(2)?,
-                row.get::<_, i64>(3)?,
-                row.get::<_, Option<i64>>(4)?,
-                row.get::<_, i64>(5)?,
-                row.get::<_, Option<String>>(6)?,
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, Option<i64>>(3)?,
+                row.get::<_, i64>(4)?,
+                row.get::<_, Option<i64>>(5)?,
+                row.get::<_, i64>(6)?,
                 row.get::<_, Option<String>>(7)?,
+                row.get::<_, Option<String>>(8)?,
+                row.get::<_, bool>(9)?,
             ))
         })?;
 
@@ -606,51 +1405,103 @@ impl DatabaseInner {
         Ok(albums)
     }
 
+    /// Tracks of the album with this id, in track order
+    pub fn get_album_tracks_by_id(&self, album_id: i64) -> SqlResult<Vec<Track>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM tracks t WHERE t.album_id = ?1 ORDER BY {}",
+            TRACK_COLUMNS, ALBUM_TRACK_ORDER
+        ))?;
+        let tracks = stmt
+            .query_map(params![album_id], track_from_row)?
+            .collect::<SqlResult<Vec<_>>>()?;
+        Ok(tracks)
+    }
+
+    /// Artists credited on at least one track, main or featured, with their
+    /// track and album counts. Each artist of a collaboration is counted.
+    pub fn get_artists(&self) -> SqlResult<Vec<ArtistInfo>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT ar.id, ar.name, COUNT(*), COUNT(DISTINCT t.album_id)
+             FROM artists ar
+             JOIN track_artists ta ON ta.artist_id = ar.id
+             JOIN tracks t ON t.id = ta.track_id
+             GROUP BY ar.id
+             ORDER BY IFNULL(ar.sort_key, ar.name) COLLATE UNICODE",
+        )?;
+        let artists = stmt
+            .query_map([], |row| {
+                Ok(ArtistInfo {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    track_count: row.get(2)?,
+                    album_count: row.get(3)?,
+                })
+            })?
+            .collect::<SqlResult<Vec<_>>>()?;
+        Ok(artists)
+    }
+
+    /// Genres with the number of tracks tagged with each
+    pub fn get_genres(&self) -> SqlResult<Vec<GenreInfo>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT g.id, g.name, COUNT(*)
+             FROM genres g
+             JOIN track_genres tg ON tg.genre_id = g.id
+             GROUP BY g.id
+             ORDER BY g.name COLLATE UNICODE",
+        )?;
+        let genres = stmt
+            .query_map([], |row| {
+                Ok(GenreInfo {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    track_count: row.get(2)?,
+                })
+            })?
+            .collect::<SqlResult<Vec<_>>>()?;
+        Ok(genres)
+    }
+
+    /// Artists credited on a track in tag order, main artists before featured ones
+    pub fn get_track_credits(&self, track_id: i64) -> SqlResult<Vec<ArtistCredit>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT ar.id, ar.name, ta.role
+             FROM track_artists ta
+             JOIN artists ar ON ar.id = ta.artist_id
+             WHERE ta.track_id = ?1
+             ORDER BY ta.position",
+        )?;
+        let credits = stmt
+            .query_map(params![track_id], |row| {
+                Ok(ArtistCredit {
+                    artist_id: row.get(0)?,
+                    name: row.get(1)?,
+                    role: CreditRole::from_db(&row.get::<_, String>(2)?),
+                })
+            })?
+            .collect::<SqlResult<Vec<_>>>()?;
+        Ok(credits)
+    }
+
     pub fn get_album_tracks(
         &self,
         album_name: &str,
         artist: Option<&str>,
     ) -> SqlResult<Vec<Track>> {
-        let mut query = "SELECT id, file_path, title, artist, album, duration, year, genre,
-                        track_number, file_size, file_format, last_modified, metadata_fetched,
-                        release_mbid, created_at FROM tracks WHERE album = ?1"
-            .to_string();
+        let mut query = format!("SELECT {} FROM tracks t WHERE album = ?1", TRACK_COLUMNS);
         let mut params_vec: Vec<Box<dyn rusqlite::ToSql>> = vec![Box::new(album_name.to_string())];
 
         if let Some(artist) = artist {
-            query.push_str(" AND artist = ?2");
+            query.push_str(&format!(" AND {}", ALBUM_ARTIST_CONDITION));
             params_vec.push(Box::new(artist.to_string()));
         }
 
-        // Order by track_number if available, otherwise by title
-        query.push_str(
-            " ORDER BY 
-            CASE WHEN track_number IS NOT NULL THEN track_number ELSE 999999 END,
-            title COLLATE NOCASE",
-        );
+        query.push_str(&format!(" ORDER BY {}", ALBUM_TRACK_ORDER));
 
         let mut stmt = self.conn.prepare(&query)?;
         let rows = stmt.query_map(
             rusqlite::params_from_iter(params_vec.iter().map(|p| p.as_ref())),
-            |row| {
-                Ok(Track {
-                    id: row.get(0)?,
-                    file_path: row.get(1)?,
-                    title: row.get(2)?,
-                    artist: row.get(3)?,
-                    album: row.get(4)?,
-                    duration: row.get(5)?,
-                    year: row.get(6)?,
-                    genre: row.get(7)?,
-                    track_number: row.get(8)?,
-                    file_size: row.get(9)?,
-                    file_format: row.get(10)?,
-                    last_modified: row.get(11)?,
-                    metadata_fetched: row.get(12)?,
-                    release_mbid: row.get(13)?,
-                    created_at: row.get(14)?,
-                })
-            },
+            track_from_row,
         )?;
 
         let mut tracks = Vec::new();
@@ -666,22 +1517,19 @@ impl DatabaseInner {
         artist: Option<&str>,
     ) -> SqlResult<Option<i64>> {
         // Get first track ID from album, preferring one with release_mbid
-        let mut query = "SELECT id FROM tracks WHERE album = ?1".to_string();
+        let mut query = "SELECT id FROM tracks t WHERE album = ?1".to_string();
         let mut params_vec: Vec<Box<dyn rusqlite::ToSql>> = vec![Box::new(album_name.to_string())];
 
         if let Some(artist) = artist {
-            query.push_str(" AND artist = ?2");
+            query.push_str(&format!(" AND {}", ALBUM_ARTIST_CONDITION));
             params_vec.push(Box::new(artist.to_string()));
         }
 
-        // Prefer tracks with release_mbid, then order by track_number
-        query.push_str(
-            " ORDER BY 
-            CASE WHEN release_mbid IS NOT NULL THEN 0 ELSE 1 END,
-            CASE WHEN track_number IS NOT NULL THEN track_number ELSE 999999 END,
-            title COLLATE NOCASE
-            LIMIT 1",
-        );
+        // Prefer tracks with release_mbid, then the first in album order
+        query.push_str(&format!(
+            " ORDER BY CASE WHEN release_mbid IS NOT NULL THEN 0 ELSE 1 END, {} LIMIT 1",
+            ALBUM_TRACK_ORDER
+        ));
 
         let mut stmt = self.conn.prepare(&query)?;
         let result = stmt.query_row(
@@ -696,11 +1544,13 @@ impl DatabaseInner {
         }
     }
 
-    /// Get existing file paths with their last_modified timestamps for incremental scanning
+    /// Get existing file paths with their last_modified timestamps for incremental scanning.
+    /// CUE sheet virtual tracks are reported under their underlying audio file.
     pub fn get_existing_file_info(&self) -> SqlResult<HashMap<String, i64>> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT file_path, last_modified FROM tracks")?;
+        let mut stmt = self.conn.prepare(
+            "SELECT COALESCE(source_path, file_path), MIN(last_modified) FROM tracks
+             GROUP BY COALESCE(source_path, file_path)",
+        )?;
 
         let rows = stmt.query_map([], |row| {
             Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
@@ -714,43 +1564,428 @@ impl DatabaseInner {
         Ok(map)
     }
 
-    /// Batch insert/update tracks within a single transaction for better performance
+    /// Id and file of the tracks whose tags are to be read again (see
+    /// `migrations::pending_tags`)
+    pub fn get_tracks_with_pending_tags(&self) -> SqlResult<Vec<(i64, String)>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, file_path FROM tracks WHERE tags_pending = 1")?;
+        let tracks = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<SqlResult<Vec<_>>>()?;
+        Ok(tracks)
+    }
+
+    /// Store the album artist, disc, compilation, extended and sort tags read
+    /// again for existing tracks, leaving everything else as it is. `None`
+    /// marks a track whose file could not be read as done.
+    pub fn update_pending_tags(&mut self, updates: &[(i64, Option<Track>)]) -> SqlResult<()> {
+        let articles = self.load_sort_articles()?;
+        let tx = self.conn.transaction()?;
+        for (id, tags) in updates {
+            if let Some(tags) = tags {
+                tx.prepare_cached(
+                    "UPDATE tracks SET album_artist = ?1, disc_number = ?2, disc_total = ?3,
+                        track_total = ?4, compilation = ?5, composer = ?6, conductor = ?7,
+                        performer = ?8, work = ?9, movement = ?10, label = ?11,
+                        catalog_number = ?12, isrc = ?13, bpm = ?14, comment = ?15,
+                        title_sort = ?16, artist_sort = ?17, album_sort = ?18
+                     WHERE id = ?19",
+                )?
+                .execute(params![
+                    tags.album_artist,
+                    tags.disc_number,
+                    tags.disc_total,
+                    tags.track_total,
+                    tags.compilation,
+                    tags.tags.composer,
+                    tags.tags.conductor,
+                    tags.tags.performer,
+                    tags.tags.work,
+                    tags.tags.movement,
+                    tags.tags.label,
+                    tags.tags.catalog_number,
+                    tags.tags.isrc,
+                    tags.tags.bpm,
+                    tags.tags.comment,
+                    tags.tags.title_sort,
+                    tags.tags.artist_sort,
+                    tags.tags.album_sort,
+                    id
+                ])?;
+                sorting::update_sort_keys(&tx, *id, &articles)?;
+            }
+            tx.prepare_cached("UPDATE tracks SET tags_pending = 0 WHERE id = ?1")?
+                .execute(params![id])?;
+        }
+        tx.commit()
+    }
+
+    /// Id and audio file of the tracks with no audio properties, scanned
+    /// before they were stored or from files whose headers could not be read,
+    /// that haven't been tried since
+    pub fn get_tracks_without_audio_properties(&self) -> SqlResult<Vec<(i64, String)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, COALESCE(source_path, file_path) FROM tracks
+             WHERE codec IS NULL AND NOT audio_checked",
+        )?;
+        let tracks = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<SqlResult<Vec<_>>>()?;
+        Ok(tracks)
+    }
+
+    /// Store audio properties read without rescanning the tags. `None` records
+    /// that the file's headers could not be read, so it isn't tried again.
+    pub fn update_audio_properties(
+        &mut self,
+        updates: &[(i64, Option<AudioProperties>)],
+    ) -> SqlResult<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "UPDATE tracks SET bitrate = ?1, sample_rate = ?2, bit_depth = ?3, channels = ?4,
+                    codec = ?5, lossless = ?6, audio_checked = 1
+                 WHERE id = ?7",
+            )?;
+            let mut checked = tx.prepare("UPDATE tracks SET audio_checked = 1 WHERE id = ?1")?;
+            for (id, audio) in updates {
+                let Some(audio) = audio else {
+                    checked.execute(params![id])?;
+                    continue;
+                };
+                stmt.execute(params![
+                    audio.bitrate,
+                    audio.sample_rate,
+                    audio.bit_depth,
+                    audio.channels,
+                    audio.codec,
+                    audio.lossless,
+                    id
+                ])?;
+            }
+        }
+        tx.commit()
+    }
+
+    /// Every track with its audio file and when it was found missing, for the
+    /// reconciliation pass after a scan
+    pub fn get_stored_tracks(&self) -> SqlResult<Vec<StoredTrack>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, COALESCE(source_path, file_path), missing_since FROM tracks")?;
+        let tracks = stmt
+            .query_map([], |row| {
+                Ok(StoredTrack {
+                    id: row.get(0)?,
+                    path: row.get(1)?,
+                    missing_since: row.get(2)?,
+                })
+            })?
+            .collect::<SqlResult<Vec<_>>>()?;
+        Ok(tracks)
+    }
+
+    /// Ids of the tracks read from the given audio files, including the
+    /// virtual tracks of their CUE sheets
+    pub fn get_track_ids_for_files(&self, paths: &[String]) -> SqlResult<Vec<i64>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id FROM tracks WHERE COALESCE(source_path, file_path) = ?1")?;
+        let mut ids = Vec::new();
+        for path in paths {
+            for id in stmt.query_map(params![path], |row| row.get(0))? {
+                ids.push(id?);
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Flag the `missing` tracks as gone from now on and clear the flag of the
+    /// `found` ones
+    pub fn set_tracks_missing(&mut self, missing: &[i64], found: &[i64]) -> SqlResult<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut flag = tx.prepare(
+                "UPDATE tracks SET missing_since = strftime('%s', 'now')
+                 WHERE id = ?1 AND missing_since IS NULL",
+            )?;
+            for id in missing {
+                flag.execute(params![id])?;
+            }
+            let mut clear = tx.prepare("UPDATE tracks SET missing_since = NULL WHERE id = ?1")?;
+            for id in found {
+                clear.execute(params![id])?;
+            }
+        }
+        tx.commit()
+    }
+
+    /// Tracks whose file a scan found missing, longest missing first
+    pub fn get_missing_tracks(&self) -> SqlResult<Vec<Track>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM tracks t WHERE t.missing_since IS NOT NULL
+             ORDER BY t.missing_since, COALESCE(t.source_path, t.file_path)",
+            TRACK_COLUMNS
+        ))?;
+        let tracks = stmt
+            .query_map([], track_from_row)?
+            .collect::<SqlResult<Vec<_>>>()?;
+        Ok(tracks)
+    }
+
+    /// Remove every track flagged as missing and return how many were removed
+    pub fn purge_missing_tracks(&mut self) -> SqlResult<usize> {
+        self.conn
+            .execute("DELETE FROM tracks WHERE missing_since IS NOT NULL", [])
+    }
+
+    /// Content hash of each audio file in the library, to match files that
+    /// disappeared with files found at a new path
+    pub fn get_content_hashes(&self) -> SqlResult<Vec<(String, String)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT DISTINCT COALESCE(source_path, file_path), content_hash FROM tracks
+             WHERE content_hash IS NOT NULL",
+        )?;
+        let hashes = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<SqlResult<Vec<_>>>()?;
+        Ok(hashes)
+    }
+
+    /// Audio files of the tracks that have no content hash yet
+    pub fn get_paths_without_content_hash(&self) -> SqlResult<Vec<String>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT DISTINCT COALESCE(source_path, file_path) FROM tracks
+             WHERE content_hash IS NULL AND missing_since IS NULL",
+        )?;
+        let paths = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<SqlResult<Vec<_>>>()?;
+        Ok(paths)
+    }
+
+    /// Store the content hash of each `(audio file, hash)`, for all the tracks
+    /// of the file
+    pub fn update_content_hashes(&mut self, hashes: &[(String, String)]) -> SqlResult<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "UPDATE tracks SET content_hash = ?2
+                 WHERE COALESCE(source_path, file_path) = ?1",
+            )?;
+            for (path, hash) in hashes {
+                stmt.execute(params![path, hash])?;
+            }
+        }
+        tx.commit()
+    }
+
+    /// Point the tracks of an audio file that was moved or renamed at its new
+    /// path, keeping their ids and with them their play history, playlist
+    /// entries, ratings and edits. Returns the number of tracks moved.
+    pub fn relocate_track_file(&mut self, old_path: &str, new_path: &str) -> SqlResult<usize> {
+        let tx = self.conn.transaction()?;
+        let mut moved = tx.execute(
+            "UPDATE tracks SET file_path = ?2, missing_since = NULL
+             WHERE file_path = ?1 AND source_path IS NULL",
+            params![old_path, new_path],
+        )?;
+        // CUE sheet virtual tracks are keyed by the audio path and a suffix
+        moved += tx.execute(
+            "UPDATE tracks SET
+                source_path = ?2,
+                file_path = ?2 || substr(file_path, length(?1) + 1),
+                missing_since = NULL
+             WHERE source_path = ?1",
+            params![old_path, new_path],
+        )?;
+        tx.commit()?;
+        Ok(moved)
+    }
+
+    /// Move every path under `old_root` to the same place under `new_root` in
+    /// tracks, scan folders and album covers, in one transaction.
+    ///
+    /// `exists` checks that a track's file is at its new path. Nothing is
+    /// changed for a dry run, or when a file is missing or a new path is
+    /// already in the library; the report says what would change and why not.
+    /// Tracks already flagged missing don't hold the move up, and are no
+    /// longer flagged once their file is found at the new path.
+    pub fn relocate_library(
+        &mut self,
+        old_root: &str,
+        new_root: &str,
+        dry_run: bool,
+        exists: impl Fn(&str) -> bool,
+    ) -> SqlResult<RelocationReport> {
+        let tx = self.conn.transaction()?;
+        let mut report = RelocationReport::default();
+        // Cheap filter before comparing whole path components
+        let prefix = old_root.trim_end_matches(['/', '\\']);
+
+        // Id, new path and source path, and whether a track flagged missing is
+        // found at its new path
+        let mut track_moves: Vec<(i64, String, Option<String>, bool)> = Vec::new();
+        let mut checked: HashSet<String> = HashSet::new();
+        {
+            let mut stmt = tx.prepare(
+                "SELECT id, file_path, source_path, missing_since IS NOT NULL FROM tracks
+                 WHERE substr(file_path, 1, length(?1)) = ?1",
+            )?;
+            let mut rows = stmt.query(params![prefix])?;
+            while let Some(row) = rows.next()? {
+                let file_path: String = row.get(1)?;
+                let Some(new_path) = reconcile::replace_root(&file_path, old_root, new_root) else {
+                    continue;
+                };
+                let source_path: Option<String> = row.get(2)?;
+                let new_source = source_path
+                    .as_deref()
+                    .and_then(|p| reconcile::replace_root(p, old_root, new_root));
+                let flagged_missing: bool = row.get(3)?;
+
+                let audio_path = new_source.as_deref().unwrap_or(&new_path);
+                if !flagged_missing && checked.insert(audio_path.to_string()) && !exists(audio_path)
+                {
+                    report.missing_count += 1;
+                    note_path(&mut report.missing, audio_path);
+                }
+                let found = flagged_missing && exists(audio_path);
+                track_moves.push((row.get(0)?, new_path, new_source, found));
+            }
+        }
+        for (_, new_path, _, _) in &track_moves {
+            let taken: Option<i64> = tx
+                .prepare_cached("SELECT id FROM tracks WHERE file_path = ?1")?
+                .query_row(params![new_path], |row| row.get(0))
+                .optional()?;
+            if taken.is_some() {
+                report.conflict_count += 1;
+                note_path(&mut report.conflicts, new_path);
+            }
+        }
+
+        let mut folder_moves: Vec<(i64, String)> = Vec::new();
+        let folders = tx
+            .prepare("SELECT id, path FROM scan_folders")?
+            .query_map([], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<SqlResult<Vec<_>>>()?;
+        for (id, path) in folders {
+            if let Some(new_path) = reconcile::replace_root(&path, old_root, new_root) {
+                let taken: Option<i64> = tx
+                    .query_row(
+                        "SELECT id FROM scan_folders WHERE path = ?1",
+                        params![new_path],
+                        |row| row.get(0),
+                    )
+                    .optional()?;
+                if taken.is_some() {
+                    report.conflict_count += 1;
+                    note_path(&mut report.conflicts, &new_path);
+                }
+                folder_moves.push((id, new_path));
+            }
+        }
+
+        let mut cover_moves: Vec<(i64, String)> = Vec::new();
+        {
+            let mut stmt = tx.prepare(
+                "SELECT id, cover_art_path FROM albums WHERE cover_art_path IS NOT NULL",
+            )?;
+            let mut rows = stmt.query([])?;
+            while let Some(row) = rows.next()? {
+                let path: String = row.get(1)?;
+                if let Some(new_path) = reconcile::replace_root(&path, old_root, new_root) {
+                    cover_moves.push((row.get(0)?, new_path));
+                }
+            }
+        }
+
+        report.tracks = track_moves.len();
+        report.scan_folders = folder_moves.len();
+        report.covers = cover_moves.len();
+        report.applied = !dry_run && report.missing_count == 0 && report.conflict_count == 0;
+        if !report.applied {
+            return Ok(report);
+        }
+
+        for (id, file_path, source_path, found) in &track_moves {
+            tx.prepare_cached(
+                "UPDATE tracks SET file_path = ?1, source_path = ?2,
+                    missing_since = CASE WHEN ?4 THEN NULL ELSE missing_since END
+                 WHERE id = ?3",
+            )?
+            .execute(params![file_path, source_path, id, found])?;
+        }
+        for (id, path) in &folder_moves {
+            tx.execute(
+                "UPDATE scan_folders SET path = ?1 WHERE id = ?2",
+                params![path, id],
+            )?;
+        }
+        for (id, path) in &cover_moves {
+            tx.execute(
+                "UPDATE albums SET cover_art_path = ?1 WHERE id = ?2",
+                params![path, id],
+            )?;
+        }
+        tx.commit()?;
+        Ok(report)
+    }
+
+    /// Batch insert/update tracks within a single transaction for better performance.
+    ///
+    /// All virtual tracks of a CUE sheet must be in the same batch: rows of the same
+    /// `source_path` that are not part of the batch (and the whole-file row) are removed.
     pub fn insert_tracks_batch(&mut self, tracks: &[Track]) -> SqlResult<usize> {
+        let separators = self.load_tag_separators()?;
+        let articles = self.load_sort_articles()?;
         let tx = self.conn.transaction()?;
+        let mut virtual_keys: HashMap<&str, Vec<&str>> = HashMap::new();
 
         for track in tracks {
-            tx.execute(
-                "INSERT INTO tracks (
-                    file_path, title, artist, album, duration, year, genre,
-                    track_number, file_size, file_format, last_modified, metadata_fetched
-                ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
-                ON CONFLICT(file_path) DO UPDATE SET
-                    title = excluded.title,
-                    artist = excluded.artist,
-                    album = excluded.album,
-                    duration = excluded.duration,
-                    year = excluded.year,
-                    genre = excluded.genre,
-                    track_number = excluded.track_number,
-                    file_size = excluded.file_size,
-                    file_format = excluded.file_format,
-                    last_modified = excluded.last_modified,
-                    metadata_fetched = excluded.metadata_fetched",
-                params![
-                    track.file_path,
-                    track.title,
-                    track.artist,
-                    track.album,
-                    track.duration,
-                    track.year,
-                    track.genre,
-                    track.track_number,
-                    track.file_size,
-                    track.file_format,
-                    track.last_modified,
-                    track.metadata_fetched,
-                ],
+            upsert_track(&tx, track)?;
+            let track_id: i64 = tx
+                .prepare_cached("SELECT id FROM tracks WHERE file_path = ?1")?
+                .query_row(params![track.file_path], |row| row.get(0))?;
+            credits::link_track_credits(
+                &tx,
+                track_id,
+                track.artist.as_deref(),
+                track.genre.as_deref(),
+                &separators,
             )?;
+            sorting::update_sort_keys(&tx, track_id, &articles)?;
+
+            match track.source_path {
+                Some(ref source) => virtual_keys
+                    .entry(source.as_str())
+                    .or_default()
+                    .push(track.file_path.as_str()),
+                // A plain file replaces any virtual tracks from a removed CUE sheet
+                None => {
+                    tx.execute(
+                        "DELETE FROM tracks WHERE source_path = ?1",
+                        params![track.file_path],
+                    )?;
+                }
+            }
+        }
+
+        // Remove the whole-file row and virtual tracks that no longer exist in the sheet
+        for (source, keys) in virtual_keys {
+            let placeholders = vec!["?"; keys.len()].join(", ");
+            let query = format!(
+                "DELETE FROM tracks WHERE file_path = ?1
+                    OR (source_path = ?1 AND file_path NOT IN ({}))",
+                placeholders
+            );
+            let mut params_vec: Vec<&dyn rusqlite::ToSql> = vec![&source];
+            params_vec.extend(keys.iter().map(|k| k as &dyn rusqlite::ToSql));
+            tx.execute(&query, rusqlite::params_from_iter(params_vec))?;
         }
 
         tx.commit()?;
@@ -768,6 +2003,63 @@ impl DatabaseInner {
         Ok(id)
     }
 
+    /// Replace the stored chapters of each `(file_path, chapters)` entry in one transaction.
+    /// Entries whose track does not exist are skipped.
+    pub fn replace_chapters_batch(&mut self, entries: &[(String, Vec<Chapter>)]) -> SqlResult<()> {
+        let tx = self.conn.transaction()?;
+        for (file_path, chapters) in entries {
+            let track_id: Option<i64> = tx
+                .query_row(
+                    "SELECT id FROM tracks WHERE file_path = ?1",
+                    params![file_path],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            let Some(track_id) = track_id else {
+                continue;
+            };
+
+            tx.execute(
+                "DELETE FROM chapters WHERE track_id = ?1",
+                params![track_id],
+            )?;
+            for chapter in chapters {
+                tx.execute(
+                    "INSERT INTO chapters (track_id, chapter_index, title, start_ms, end_ms)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![
+                        track_id,
+                        chapter.chapter_index,
+                        chapter.title,
+                        chapter.start_ms,
+                        chapter.end_ms
+                    ],
+                )?;
+            }
+        }
+        tx.commit()
+    }
+
+    pub fn get_track_chapters(&self, track_id: i64) -> SqlResult<Vec<Chapter>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, track_id, chapter_index, title, start_ms, end_ms
+             FROM chapters WHERE track_id = ?1 ORDER BY chapter_index",
+        )?;
+        let chapters = stmt
+            .query_map(params![track_id], |row| {
+                Ok(Chapter {
+                    id: row.get(0)?,
+                    track_id: row.get(1)?,
+                    chapter_index: row.get(2)?,
+                    title: row.get(3)?,
+                    start_ms: row.get(4)?,
+                    end_ms: row.get(5)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(chapters)
+    }
+
     // Playlist methods
     pub fn create_playlist(&mut self, name: &str) -> SqlResult<i64> {
         self.conn
@@ -782,6 +2074,10 @@ impl DatabaseInner {
             "DELETE FROM playlist_tracks WHERE playlist_id = ?1",
             params![id],
         )?;
+        tx.execute(
+            "DELETE FROM smart_playlists WHERE playlist_id = ?1",
+            params![id],
+        )?;
         // Then delete the playlist
         tx.execute("DELETE FROM playlists WHERE id = ?1", params![id])?;
         tx.commit()?;
@@ -803,87 +2099,96 @@ impl DatabaseInner {
                 p.name,
                 p.created_at,
                 COUNT(pt.track_id) as track_count,
-                SUM(t.duration) as total_duration
+                SUM(t.duration) as total_duration,
+                p.kind
             FROM playlists p
             LEFT JOIN playlist_tracks pt ON p.id = pt.playlist_id
             LEFT JOIN tracks t ON pt.track_id = t.id
             GROUP BY p.id, p.name, p.created_at
-            ORDER BY p.name COLLATE NOCASE",
+            ORDER BY p.name COLLATE UNICODE",
         )?;
 
-        let rows = stmt.query_map([], |row| {
-            Ok(Playlist {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                created_at: row.get(2)?,
-                track_count: row.get(3)?,
-                total_duration: row.get(4)?,
-            })
-        })?;
+        let rows = stmt.query_map([], playlist_from_row)?;
 
         let mut playlists = Vec::new();
         for playlist in rows {
-            playlists.push(playlist?);
+            let mut playlist = playlist?;
+            self.fill_smart_playlist_summary(&mut playlist)?;
+            playlists.push(playlist);
         }
         Ok(playlists)
     }
 
     pub fn get_playlist(&self, id: i64) -> SqlResult<Playlist> {
-        self.conn.query_row(
+        let mut playlist = self.conn.query_row(
             "SELECT 
                 p.id,
                 p.name,
                 p.created_at,
                 COUNT(pt.track_id) as track_count,
-                SUM(t.duration) as total_duration
+                SUM(t.duration) as total_duration,
+                p.kind
             FROM playlists p
             LEFT JOIN playlist_tracks pt ON p.id = pt.playlist_id
             LEFT JOIN tracks t ON pt.track_id = t.id
             WHERE p.id = ?1
             GROUP BY p.id, p.name, p.created_at",
             params![id],
-            |row| {
-                Ok(Playlist {
-                    id: row.get(0)?,
-                    name: row.get(1)?,
-                    created_at: row.get(2)?,
-                    track_count: row.get(3)?,
-                    total_duration: row.get(4)?,
-                })
-            },
-        )
+            playlist_from_row,
+        )?;
+        self.fill_smart_playlist_summary(&mut playlist)?;
+        Ok(playlist)
+    }
+
+    /// Smart playlists have no `playlist_tracks` rows; count their current matches instead
+    fn fill_smart_playlist_summary(&self, playlist: &mut Playlist) -> SqlResult<()> {
+        if playlist.kind != PlaylistKind::Smart {
+            return Ok(());
+        }
+        let Some(rules) = self.get_smart_playlist_rules(playlist.id)? else {
+            return Ok(());
+        };
+        let (sql, params_vec) = smart_playlist_sql(&rules, "t.duration");
+        let (count, duration) = self.conn.query_row(
+            &format!("SELECT COUNT(*), SUM(duration) FROM ({})", sql),
+            rusqlite::params_from_iter(params_vec.iter().map(|p| p.as_ref())),
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        playlist.track_count = count;
+        playlist.total_duration = duration;
+        Ok(())
+    }
+
+    pub fn get_playlist_kind(&self, id: i64) -> SqlResult<Option<PlaylistKind>> {
+        let kind: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT kind FROM playlists WHERE id = ?1",
+                params![id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(kind.as_deref().map(PlaylistKind::from_db))
     }
 
+    /// Tracks of a playlist. Smart playlists are re-evaluated on every call.
     pub fn get_playlist_tracks(&self, playlist_id: i64) -> SqlResult<Vec<Track>> {
-        let mut stmt = self.conn.prepare(
-            "SELECT t.id, t.file_path, t.title, t.artist, t.album, t.duration, t.year, t.genre,
-                    t.track_number, t.file_size, t.file_format, t.last_modified, t.metadata_fetched,
-                    t.release_mbid, t.created_at
-             FROM tracks t
+        if self.get_playlist_kind(playlist_id)? == Some(PlaylistKind::Smart) {
+            return match self.get_smart_playlist_rules(playlist_id)? {
+                Some(rules) => self.evaluate_smart_playlist(&rules),
+                None => Ok(Vec::new()),
+            };
+        }
+
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM tracks t
              INNER JOIN playlist_tracks pt ON t.id = pt.track_id
              WHERE pt.playlist_id = ?1
              ORDER BY pt.position ASC",
-        )?;
+            TRACK_COLUMNS
+        ))?;
 
-        let rows = stmt.query_map(params![playlist_id], |row| {
-            Ok(Track {
-                id: row.get(0)?,
-                file_path: row.get(1)?,
-                title: row.get(2)?,
-                artist: row.get(3)?,
-                album: row.get(4)?,
-                duration: row.get(5)?,
-                year: row.get(6)?,
-                genre: row.get(7)?,
-                track_number: row.get(8)?,
-                file_size: row.get(9)?,
-                file_format: row.get(10)?,
-                last_modified: row.get(11)?,
-                metadata_fetched: row.get(12)?,
-                release_mbid: row.get(13)?,
-                created_at: row.get(14)?,
-            })
-        })?;
+        let rows = stmt.query_map(params![playlist_id], track_from_row)?;
 
         let mut tracks = Vec::new();
         for track in rows {
@@ -892,19 +2197,103 @@ impl DatabaseInner {
         Ok(tracks)
     }
 
-    pub fn add_track_to_playlist(
+    pub fn create_smart_playlist(
         &mut self,
-        playlist_id: i64,
-        track_id: i64,
-        position: Option<i64>,
-    ) -> SqlResult<()> {
-        // If position is not specified, add to end
-        let final_position = if let Some(pos) = position {
-            pos
-        } else {
-            // Get max position and add 1
-            let max_pos: Option<i64> = self.conn.query_row(
-                "SELECT COALESCE(MAX(position), -1) + 1 FROM playlist_tracks WHERE playlist_id = ?1",
+        name: &str,
+        rules: &SmartPlaylistRules,
+    ) -> SqlResult<i64> {
+        let rules_json = smart_rules_to_json(rules)?;
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "INSERT INTO playlists (name, kind) VALUES (?1, 'smart')",
+            params![name],
+        )?;
+        let id = tx.last_insert_rowid();
+        tx.execute(
+            "INSERT INTO smart_playlists (playlist_id, rules) VALUES (?1, ?2)",
+            params![id, rules_json],
+        )?;
+        tx.commit()?;
+        Ok(id)
+    }
+
+    /// Replace a smart playlist's name and rules. Returns false if no smart
+    /// playlist has this id.
+    pub fn update_smart_playlist(
+        &mut self,
+        id: i64,
+        name: &str,
+        rules: &SmartPlaylistRules,
+    ) -> SqlResult<bool> {
+        let rules_json = smart_rules_to_json(rules)?;
+        let tx = self.conn.transaction()?;
+        let updated = tx.execute(
+            "UPDATE playlists SET name = ?1 WHERE id = ?2 AND kind = 'smart'",
+            params![name, id],
+        )?;
+        if updated == 0 {
+            return Ok(false);
+        }
+        tx.execute(
+            "INSERT INTO smart_playlists (playlist_id, rules) VALUES (?1, ?2)
+             ON CONFLICT(playlist_id) DO UPDATE SET rules = excluded.rules",
+            params![id, rules_json],
+        )?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    pub fn get_smart_playlist_rules(&self, id: i64) -> SqlResult<Option<SmartPlaylistRules>> {
+        let rules: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT rules FROM smart_playlists WHERE playlist_id = ?1",
+                params![id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        rules
+            .map(|json| {
+                serde_json::from_str(&json).map_err(|e| {
+                    rusqlite::Error::FromSqlConversionFailure(
+                        0,
+                        rusqlite::types::Type::Text,
+                        Box::new(e),
+                    )
+                })
+            })
+            .transpose()
+    }
+
+    /// Tracks matching a set of rules, used for smart playlists and their previews
+    pub fn evaluate_smart_playlist(&self, rules: &SmartPlaylistRules) -> SqlResult<Vec<Track>> {
+        let (sql, params_vec) = smart_playlist_sql(rules, TRACK_COLUMNS);
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt.query_map(
+            rusqlite::params_from_iter(params_vec.iter().map(|p| p.as_ref())),
+            track_from_row,
+        )?;
+
+        let mut tracks = Vec::new();
+        for track in rows {
+            tracks.push(track?);
+        }
+        Ok(tracks)
+    }
+
+    pub fn add_track_to_playlist(
+        &mut self,
+        playlist_id: i64,
+        track_id: i64,
+        position: Option<i64>,
+    ) -> SqlResult<()> {
+        // If position is not specified, add to end
+        let final_position = if let Some(pos) = position {
+            pos
+        } else {
+            // Get max position and add 1
+            let max_pos: Option<i64> = self.conn.query_row(
+                "SELECT COALESCE(MAX(position), -1) + 1 FROM playlist_tracks WHERE playlist_id = ?1",
                 params![playlist_id],
                 |row| row.get(0),
             ).unwrap_or(Some(0));
@@ -1003,13 +2392,23 @@ impl DatabaseInner {
     pub fn duplicate_playlist(&mut self, playlist_id: i64, new_name: &str) -> SqlResult<i64> {
         let tx = self.conn.transaction()?;
 
-        // Create new playlist
-        tx.execute(
-            "INSERT INTO playlists (name) VALUES (?1)",
-            params![new_name],
+        // Create new playlist of the same kind
+        let inserted = tx.execute(
+            "INSERT INTO playlists (name, kind)
+             SELECT ?1, kind FROM playlists WHERE id = ?2",
+            params![new_name, playlist_id],
         )?;
+        if inserted == 0 {
+            return Err(rusqlite::Error::QueryReturnedNoRows);
+        }
         let new_playlist_id = tx.last_insert_rowid();
 
+        tx.execute(
+            "INSERT INTO smart_playlists (playlist_id, rules)
+             SELECT ?1, rules FROM smart_playlists WHERE playlist_id = ?2",
+            params![new_playlist_id, playlist_id],
+        )?;
+
         // Copy all tracks with their positions
         tx.execute(
             "INSERT INTO playlist_tracks (playlist_id, track_id, position)
@@ -1105,46 +2504,60 @@ impl DatabaseInner {
 
     // Play History
 
-    pub fn record_play_history(&self, track_id: i64, duration_listened: i64) -> SqlResult<()> {
-        self.conn.execute(
-            "INSERT INTO play_history (track_id, duration_listened) VALUES (?1, ?2)",
-            params![track_id, duration_listened],
+    /// Record a listen and update the track's play or skip aggregates. Whether
+    /// it was a skip is decided from how much of the track was heard.
+    pub fn record_play_history(&mut self, track_id: i64, duration_listened: i64) -> SqlResult<()> {
+        let tx = self.conn.transaction()?;
+        let duration: Option<i64> = tx
+            .query_row(
+                "SELECT duration FROM tracks WHERE id = ?1",
+                params![track_id],
+                |row| row.get(0),
+            )
+            .optional()?
+            .flatten();
+        let skipped = is_skip(duration_listened, duration);
+
+        tx.execute(
+            "INSERT INTO play_history (track_id, duration_listened, skipped) VALUES (?1, ?2, ?3)",
+            params![track_id, duration_listened, skipped],
         )?;
-        Ok(())
+        let played_at: i64 = tx.query_row(
+            "SELECT played_at FROM play_history WHERE id = ?1",
+            params![tx.last_insert_rowid()],
+            |row| row.get(0),
+        )?;
+        if skipped {
+            tx.execute(
+                "UPDATE tracks SET skip_count = skip_count + 1 WHERE id = ?1",
+                params![track_id],
+            )?;
+        } else {
+            tx.execute(
+                "UPDATE tracks SET play_count = play_count + 1,
+                    first_played_at = COALESCE(first_played_at, ?2),
+                    last_played_at = MAX(COALESCE(last_played_at, ?2), ?2)
+                 WHERE id = ?1",
+                params![track_id, played_at],
+            )?;
+        }
+        tx.commit()
     }
 
     pub fn get_play_history(&self, limit: i64) -> SqlResult<Vec<PlayHistoryEntry>> {
-        let mut stmt = self.conn.prepare(
-            "SELECT ph.id, ph.track_id, ph.played_at, ph.duration_listened,
-                    t.id, t.file_path, t.title, t.artist, t.album, t.duration,
-                    t.year, t.genre, t.track_number, t.file_size, t.file_format,
-                    t.last_modified, t.metadata_fetched, t.release_mbid, t.created_at
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT ph.id, ph.track_id, ph.played_at, ph.duration_listened, ph.skipped, {}
              FROM play_history ph
              LEFT JOIN tracks t ON ph.track_id = t.id
              ORDER BY ph.played_at DESC
              LIMIT ?1",
-        )?;
+            TRACK_COLUMNS
+        ))?;
 
         let entries = stmt
             .query_map(params![limit], |row| {
-                let track = if let Ok(id) = row.get::<_, i64>(4) {
-                    Some(Track {
-                        id,
-                        file_path: row.get(5)?,
-                        title: row.get(6)?,
-                        artist: row.get(7)?,
-                        album: row.get(8)?,
-                        duration: row.get(9)?,
-                        year: row.get(10)?,
-                        genre: row.get(11)?,
-                        track_number: row.get(12)?,
-                        file_size: row.get(13)?,
-                        file_format: row.get(14)?,
-                        last_modified: row.get(15)?,
-                        metadata_fetched: row.get(16)?,
-                        release_mbid: row.get(17)?,
-                        created_at: row.get(18)?,
-                    })
+                let track = if row.get::<_, Option<i64>>(5)?.is_some() {
+                    Some(read_track(row, 5)?)
                 } else {
                     None
                 };
@@ -1154,6 +2567,7 @@ impl DatabaseInner {
                     track_id: row.get(1)?,
                     played_at: row.get(2)?,
                     duration_listened: row.get(3)?,
+                    skipped: row.get(4)?,
                     track,
                 })
             })?
@@ -1162,209 +2576,919 @@ impl DatabaseInner {
         Ok(entries)
     }
 
-    // Duplicate Detection
-
-    pub fn get_duplicate_tracks(&self) -> SqlResult<Vec<Vec<Track>>> {
-        // Find tracks with matching title + artist + similar duration (within 2 seconds)
-        let mut stmt = self.conn.prepare(
-            "SELECT t1.id, t1.file_path, t1.title, t1.artist, t1.album, t1.duration,
-                    t1.year, t1.genre, t1.track_number, t1.file_size, t1.file_format,
-                    t1.last_modified, t1.metadata_fetched, t1.release_mbid, t1.created_at
-             FROM tracks t1
-             WHERE EXISTS (
-                 SELECT 1 FROM tracks t2
-                 WHERE t2.id != t1.id
-                   AND t2.title IS NOT NULL AND t1.title IS NOT NULL
-                   AND LOWER(t2.title) = LOWER(t1.title)
-                   AND (t2.artist IS NULL OR t1.artist IS NULL OR LOWER(t2.artist) = LOWER(t1.artist))
-                   AND (t2.duration IS NULL OR t1.duration IS NULL OR ABS(t2.duration - t1.duration) <= 2)
-             )
-             ORDER BY LOWER(COALESCE(t1.title, '')), LOWER(COALESCE(t1.artist, '')), t1.id"
-        )?;
+    // Listening statistics
 
-        let tracks: Vec<Track> = stmt
-            .query_map([], |row| {
-                Ok(Track {
-                    id: row.get(0)?,
-                    file_path: row.get(1)?,
-                    title: row.get(2)?,
-                    artist: row.get(3)?,
-                    album: row.get(4)?,
-                    duration: row.get(5)?,
-                    year: row.get(6)?,
-                    genre: row.get(7)?,
-                    track_number: row.get(8)?,
-                    file_size: row.get(9)?,
-                    file_format: row.get(10)?,
-                    last_modified: row.get(11)?,
-                    metadata_fetched: row.get(12)?,
-                    release_mbid: row.get(13)?,
-                    created_at: row.get(14)?,
-                })
-            })?
+    /// Most played tracks within a range
+    pub fn top_tracks(&self, range: &StatsRange, limit: i64) -> SqlResult<Vec<TrackStat>> {
+        let (from, to) = range.bounds();
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {}, SUM(NOT ph.skipped) AS plays, SUM(ph.duration_listened) AS seconds
+             FROM play_history ph
+             JOIN tracks t ON t.id = ph.track_id
+             WHERE ph.played_at >= ?1 AND ph.played_at < ?2
+             GROUP BY t.id
+             HAVING plays > 0
+             ORDER BY plays DESC, seconds DESC, t.id
+             LIMIT ?3",
+            TRACK_COLUMNS
+        ))?;
+        let stats = stmt
+            .query_map(params![from, to, limit], read_track_stat)?
             .collect::<Result<Vec<_>, _>>()?;
+        Ok(stats)
+    }
 
-        // Group duplicates together
-        let mut groups: Vec<Vec<Track>> = Vec::new();
-        let mut current_group: Vec<Track> = Vec::new();
-
-        for track in tracks {
-            if current_group.is_empty() {
-                current_group.push(track);
-            } else {
-                let prev = &current_group[0];
-                let same_title = prev.title.as_deref().map(|s| s.to_lowercase())
-                    == track.title.as_deref().map(|s| s.to_lowercase());
-                let same_artist = prev.artist.as_deref().map(|s| s.to_lowercase())
-                    == track.artist.as_deref().map(|s| s.to_lowercase());
-                let similar_duration = match (prev.duration, track.duration) {
-                    (Some(a), Some(b)) => (a - b).abs() <= 2,
-                    _ => true,
-                };
+    pub fn top_artists(&self, range: &StatsRange, limit: i64) -> SqlResult<Vec<NameStat>> {
+        self.top_names("t.artist", "NULL", range, limit)
+    }
 
-                if same_title && same_artist && similar_duration {
-                    current_group.push(track);
-                } else {
-                    if current_group.len() > 1 {
-                        groups.push(current_group);
-                    }
-                    current_group = vec![track];
-                }
-            }
-        }
-        if current_group.len() > 1 {
-            groups.push(current_group);
-        }
+    pub fn top_albums(&self, range: &StatsRange, limit: i64) -> SqlResult<Vec<NameStat>> {
+        self.top_names("t.album", "t.artist", range, limit)
+    }
 
-        Ok(groups)
+    pub fn top_genres(&self, range: &StatsRange, limit: i64) -> SqlResult<Vec<NameStat>> {
+        self.top_names("t.genre", "NULL", range, limit)
     }
 
-    // App Settings
+    /// Most played values of a track column, optionally grouped with an artist column
+    fn top_names(
+        &self,
+        name_column: &str,
+        artist_column: &str,
+        range: &StatsRange,
+        limit: i64,
+    ) -> SqlResult<Vec<NameStat>> {
+        let (from, to) = range.bounds();
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {name} AS name, {artist} AS artist,
+                SUM(NOT ph.skipped) AS plays, SUM(ph.duration_listened) AS seconds
+             FROM play_history ph
+             JOIN tracks t ON t.id = ph.track_id
+             WHERE ph.played_at >= ?1 AND ph.played_at < ?2 AND {name} IS NOT NULL
+             GROUP BY {name}, {artist}
+             HAVING plays > 0
+             ORDER BY plays DESC, seconds DESC, name COLLATE UNICODE
+             LIMIT ?3",
+            name = name_column,
+            artist = artist_column
+        ))?;
+        let stats = stmt
+            .query_map(params![from, to, limit], read_name_stat)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(stats)
+    }
 
-    pub fn get_setting(&self, key: &str) -> SqlResult<Option<String>> {
-        let result = self.conn.query_row(
-            "SELECT value FROM app_settings WHERE key = ?1",
-            params![key],
-            |row| row.get::<_, String>(0),
-        );
-        match result {
-            Ok(value) => Ok(Some(value)),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(e),
-        }
+    /// Plays and seconds listened per local day, week or month, oldest first.
+    /// Periods without any listening are left out.
+    pub fn listening_time(
+        &self,
+        range: &StatsRange,
+        period: StatsPeriod,
+    ) -> SqlResult<Vec<ListeningTime>> {
+        let period_sql = match period {
+            StatsPeriod::Day => "date(ph.played_at, 'unixepoch', 'localtime')",
+            // Forward to Sunday, then back to that week's Monday
+            StatsPeriod::Week => {
+                "date(ph.played_at, 'unixepoch', 'localtime', 'weekday 0', '-6 days')"
+            }
+            StatsPeriod::Month => "strftime('%Y-%m', ph.played_at, 'unixepoch', 'localtime')",
+        };
+        let (from, to) = range.bounds();
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} AS period, SUM(NOT ph.skipped), SUM(ph.duration_listened)
+             FROM play_history ph
+             WHERE ph.played_at >= ?1 AND ph.played_at < ?2
+             GROUP BY period
+             ORDER BY period",
+            period_sql
+        ))?;
+        let periods = stmt
+            .query_map(params![from, to], |row| {
+                Ok(ListeningTime {
+                    period: row.get(0)?,
+                    plays: row.get(1)?,
+                    seconds: row.get(2)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(periods)
     }
 
-    pub fn set_setting(&mut self, key: &str, value: &str) -> SqlResult<()> {
-        self.conn.execute(
-            "INSERT OR REPLACE INTO app_settings (key, value) VALUES (?1, ?2)",
-            params![key, value],
+    /// Seconds listened by local weekday and hour
+    pub fn listening_clock(&self, range: &StatsRange) -> SqlResult<ListeningClock> {
+        let (from, to) = range.bounds();
+        let mut stmt = self.conn.prepare(
+            "SELECT (CAST(strftime('%w', ph.played_at, 'unixepoch', 'localtime') AS INTEGER) + 6) % 7,
+                CAST(strftime('%H', ph.played_at, 'unixepoch', 'localtime') AS INTEGER),
+                SUM(ph.duration_listened)
+             FROM play_history ph
+             WHERE ph.played_at >= ?1 AND ph.played_at < ?2
+             GROUP BY 1, 2",
         )?;
-        Ok(())
+        let mut clock = [[0; 24]; 7];
+        let mut rows = stmt.query(params![from, to])?;
+        while let Some(row) = rows.next()? {
+            let day: usize = row.get(0)?;
+            let hour: usize = row.get(1)?;
+            clock[day][hour] = row.get(2)?;
+        }
+        Ok(clock)
     }
 
-    // Find tracks by file path (for M3U import)
-    pub fn find_tracks_by_path(&self, path: &str) -> SqlResult<Vec<Track>> {
+    /// Streaks of consecutive local days with at least one play
+    pub fn listening_streaks(&self, range: &StatsRange) -> SqlResult<ListeningStreaks> {
+        let (from, to) = range.bounds();
         let mut stmt = self.conn.prepare(
-            "SELECT id, file_path, title, artist, album, duration, year, genre,
-                    track_number, file_size, file_format, last_modified, metadata_fetched,
-                    release_mbid, created_at
-             FROM tracks WHERE file_path = ?1",
+            "SELECT DISTINCT CAST(julianday(date(ph.played_at, 'unixepoch', 'localtime')) AS INTEGER) AS day,
+                date(ph.played_at, 'unixepoch', 'localtime')
+             FROM play_history ph
+             WHERE ph.played_at >= ?1 AND ph.played_at < ?2 AND NOT ph.skipped
+             ORDER BY day",
         )?;
+        let days = stmt
+            .query_map(params![from, to], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+        let today: i64 = self.conn.query_row(
+            "SELECT CAST(julianday(date('now', 'localtime')) AS INTEGER)",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(stats::streaks(&days, today))
+    }
 
+    /// Tracks and artists whose first play falls within a range, most played first
+    pub fn new_discoveries(&self, range: &StatsRange, limit: i64) -> SqlResult<Discoveries> {
+        let (from, to) = range.bounds();
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {}, SUM(NOT ph.skipped) AS plays, SUM(ph.duration_listened) AS seconds
+             FROM play_history ph
+             JOIN tracks t ON t.id = ph.track_id
+             WHERE ph.played_at >= ?1 AND ph.played_at < ?2
+                AND t.first_played_at >= ?1 AND t.first_played_at < ?2
+             GROUP BY t.id
+             ORDER BY plays DESC, seconds DESC, t.id
+             LIMIT ?3",
+            TRACK_COLUMNS
+        ))?;
         let tracks = stmt
-            .query_map(params![path], |row| {
-                Ok(Track {
-                    id: row.get(0)?,
-                    file_path: row.get(1)?,
-                    title: row.get(2)?,
-                    artist: row.get(3)?,
-                    album: row.get(4)?,
-                    duration: row.get(5)?,
-                    year: row.get(6)?,
-                    genre: row.get(7)?,
-                    track_number: row.get(8)?,
-                    file_size: row.get(9)?,
-                    file_format: row.get(10)?,
-                    last_modified: row.get(11)?,
-                    metadata_fetched: row.get(12)?,
-                    release_mbid: row.get(13)?,
-                    created_at: row.get(14)?,
-                })
-            })?
+            .query_map(params![from, to, limit], read_track_stat)?
             .collect::<Result<Vec<_>, _>>()?;
 
-        Ok(tracks)
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tempfile::TempDir;
+        let mut stmt = self.conn.prepare(
+            "SELECT t.artist AS name, NULL, SUM(NOT ph.skipped) AS plays,
+                SUM(ph.duration_listened) AS seconds
+             FROM play_history ph
+             JOIN tracks t ON t.id = ph.track_id
+             WHERE ph.played_at >= ?1 AND ph.played_at < ?2 AND t.artist IS NOT NULL
+             GROUP BY t.artist
+             HAVING (SELECT MIN(t2.first_played_at) FROM tracks t2 WHERE t2.artist = t.artist)
+                BETWEEN ?1 AND ?2 - 1
+             ORDER BY plays DESC, seconds DESC, name COLLATE UNICODE
+             LIMIT ?3",
+        )?;
+        let artists = stmt
+            .query_map(params![from, to, limit], read_name_stat)?
+            .collect::<Result<Vec<_>, _>>()?;
 
-    fn create_test_db() -> Result<DatabaseInner> {
-        let temp_dir = TempDir::new().unwrap();
-        let db_path = temp_dir.path().join("test.db");
+        Ok(Discoveries { tracks, artists })
+    }
 
-        // Create a new database connection
-        let conn = Connection::open(&db_path)?;
-        conn.execute_batch("PRAGMA journal_mode = WAL;")?;
-        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+    /// Listening summary for a calendar year in local time
+    pub fn year_report(&self, year: i32, limit: i64) -> SqlResult<YearReport> {
+        let (from, to): (i64, i64) = self.conn.query_row(
+            "SELECT CAST(strftime('%s', printf('%04d-01-01', ?1), 'utc') AS INTEGER),
+                CAST(strftime('%s', printf('%04d-01-01', ?1 + 1), 'utc') AS INTEGER)",
+            params![year],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let range = StatsRange {
+            from: Some(from),
+            to: Some(to),
+        };
 
-        let mut db = DatabaseInner { conn };
-        db.init_schema()?;
-        Ok(db)
-    }
+        let (plays, skips, seconds, distinct_tracks, distinct_artists) = self.conn.query_row(
+            "SELECT COALESCE(SUM(NOT ph.skipped), 0), COALESCE(SUM(ph.skipped), 0),
+                COALESCE(SUM(ph.duration_listened), 0),
+                COUNT(DISTINCT CASE WHEN NOT ph.skipped THEN ph.track_id END),
+                COUNT(DISTINCT CASE WHEN NOT ph.skipped THEN t.artist END)
+             FROM play_history ph
+             LEFT JOIN tracks t ON t.id = ph.track_id
+             WHERE ph.played_at >= ?1 AND ph.played_at < ?2",
+            params![from, to],
+            |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            },
+        )?;
 
-    #[test]
-    fn test_init_schema() {
-        let db = create_test_db();
-        assert!(db.is_ok());
+        Ok(YearReport {
+            year,
+            plays,
+            skips,
+            seconds,
+            distinct_tracks,
+            distinct_artists,
+            top_tracks: self.top_tracks(&range, limit)?,
+            top_artists: self.top_artists(&range, limit)?,
+            top_albums: self.top_albums(&range, limit)?,
+            top_genres: self.top_genres(&range, limit)?,
+            months: self.listening_time(&range, StatsPeriod::Month)?,
+            clock: self.listening_clock(&range)?,
+            streaks: self.listening_streaks(&range)?,
+            discoveries: self.new_discoveries(&range, limit)?,
+        })
     }
 
-    #[test]
-    fn test_insert_and_get_track() -> SqlResult<()> {
-        let mut db = create_test_db().unwrap();
+    /// Audio quality across the library. `low_bitrate` is in kbps; lossy
+    /// tracks below it are counted as low quality.
+    pub fn quality_stats(&self, low_bitrate: i64) -> SqlResult<QualityStats> {
+        let mut stats = self.conn.query_row(
+            &format!(
+                "SELECT COUNT(*), COALESCE(SUM(t.lossless = 1), 0),
+                    COALESCE(SUM(t.lossless = 0), 0), COALESCE(SUM({hi_res}), 0),
+                    COALESCE(SUM(t.codec IS NULL), 0), COALESCE(SUM(t.channels = 1), 0),
+                    COALESCE(SUM(t.lossless = 0 AND t.bitrate < ?1), 0),
+                    AVG(CASE WHEN t.lossless = 0 THEN t.bitrate END)
+                 FROM tracks t",
+                hi_res = HI_RES_CONDITION
+            ),
+            params![low_bitrate],
+            |row| {
+                Ok(QualityStats {
+                    tracks: row.get(0)?,
+                    lossless: row.get(1)?,
+                    lossy: row.get(2)?,
+                    hi_res: row.get(3)?,
+                    unknown: row.get(4)?,
+                    mono: row.get(5)?,
+                    low_bitrate: row.get(6)?,
+                    average_lossy_bitrate: row.get(7)?,
+                    ..Default::default()
+                })
+            },
+        )?;
 
-        let track = Track {
-            id: 0,
-            file_path: "/test/path.mp3".to_string(),
-            title: Some("Test Song".to_string()),
-            artist: Some("Test Artist".to_string()),
-            album: Some("Test Album".to_string()),
-            duration: Some(180i64),
-            year: Some(2023),
-            genre: Some("Rock".to_string()),
-            track_number: Some(1),
-            file_size: 5000000,
-            file_format: "mp3".to_string(),
-            last_modified: 1234567890,
-            metadata_fetched: false,
-            release_mbid: None,
-            created_at: 1234567890,
+        stats.codecs = self
+            .conn
+            .prepare(
+                "SELECT codec, MAX(lossless), COUNT(*), AVG(bitrate) FROM tracks
+                 WHERE codec IS NOT NULL
+                 GROUP BY codec
+                 ORDER BY COUNT(*) DESC, codec",
+            )?
+            .query_map([], |row| {
+                Ok(CodecStat {
+                    codec: row.get(0)?,
+                    lossless: row.get::<_, Option<bool>>(1)?.unwrap_or(false),
+                    tracks: row.get(2)?,
+                    average_bitrate: row.get(3)?,
+                })
+            })?
+            .collect::<SqlResult<Vec<_>>>()?;
+
+        let value_counts = |column: &str| -> SqlResult<Vec<ValueCount>> {
+            self.conn
+                .prepare(&format!(
+                    "SELECT {column}, COUNT(*) FROM tracks
+                     WHERE {column} IS NOT NULL
+                     GROUP BY {column}
+                     ORDER BY {column}",
+                    column = column
+                ))?
+                .query_map([], |row| {
+                    Ok(ValueCount {
+                        value: row.get(0)?,
+                        tracks: row.get(1)?,
+                    })
+                })?
+                .collect()
         };
+        stats.sample_rates = value_counts("sample_rate")?;
+        stats.bit_depths = value_counts("bit_depth")?;
+        Ok(stats)
+    }
 
-        let id = db.insert_track(&track)?;
-        let retrieved = db.get_track_by_id(id)?;
+    // Resume positions
 
-        assert_eq!(retrieved.title, track.title);
-        assert_eq!(retrieved.artist, track.artist);
-        assert_eq!(retrieved.album, track.album);
+    pub fn save_resume_position(&mut self, track_id: i64, position_ms: i64) -> SqlResult<()> {
+        self.conn.execute(
+            "INSERT INTO resume_positions (track_id, position_ms, finished, updated_at)
+             VALUES (?1, ?2, 0, strftime('%s', 'now'))
+             ON CONFLICT(track_id) DO UPDATE SET
+                position_ms = excluded.position_ms,
+                finished = 0,
+                updated_at = excluded.updated_at",
+            params![track_id, position_ms.max(0)],
+        )?;
         Ok(())
     }
 
-    #[test]
-    fn test_search_tracks() -> SqlResult<()> {
-        let mut db = create_test_db().unwrap();
+    pub fn mark_track_finished(&mut self, track_id: i64) -> SqlResult<()> {
+        self.conn.execute(
+            "INSERT INTO resume_positions (track_id, position_ms, finished, updated_at)
+             VALUES (?1, 0, 1, strftime('%s', 'now'))
+             ON CONFLICT(track_id) DO UPDATE SET
+                position_ms = 0,
+                finished = 1,
+                updated_at = excluded.updated_at",
+            params![track_id],
+        )?;
+        Ok(())
+    }
 
-        // Insert test tracks
-        let track1 = Track {
-            id: 0,
-            file_path: "/test/path1.mp3".to_string(),
-            title: Some("Rock Song".to_string()),
-            artist: Some("Rock Artist".to_string()),
-            album: Some("Rock Album".to_string()),
-            duration: Some(180i64),
+    pub fn clear_resume_position(&mut self, track_id: i64) -> SqlResult<()> {
+        self.conn.execute(
+            "DELETE FROM resume_positions WHERE track_id = ?1",
+            params![track_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_resume_position(&self, track_id: i64) -> SqlResult<Option<ResumePosition>> {
+        let result = self.conn.query_row(
+            "SELECT track_id, position_ms, finished, updated_at
+             FROM resume_positions WHERE track_id = ?1",
+            params![track_id],
+            |row| {
+                Ok(ResumePosition {
+                    track_id: row.get(0)?,
+                    position_ms: row.get(1)?,
+                    finished: row.get(2)?,
+                    updated_at: row.get(3)?,
+                })
+            },
+        );
+        match result {
+            Ok(position) => Ok(Some(position)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn load_resume_settings(&self) -> SqlResult<ResumeSettings> {
+        let defaults = ResumeSettings::default();
+        Ok(ResumeSettings {
+            enabled: self
+                .get_setting("resume_enabled")?
+                .map(|v| v == "true")
+                .unwrap_or(defaults.enabled),
+            min_duration_minutes: self
+                .get_setting("resume_min_duration_minutes")?
+                .and_then(|v| v.parse::<u64>().ok())
+                .unwrap_or(defaults.min_duration_minutes),
+        })
+    }
+
+    pub fn save_resume_settings(&mut self, settings: &ResumeSettings) -> SqlResult<()> {
+        self.set_setting("resume_enabled", &settings.enabled.to_string())?;
+        self.set_setting(
+            "resume_min_duration_minutes",
+            &settings.min_duration_minutes.to_string(),
+        )
+    }
+
+    // Ratings and favorites
+
+    /// Returns false if the track doesn't exist
+    pub fn set_track_rating(&mut self, track_id: i64, rating: Option<i64>) -> SqlResult<bool> {
+        let updated = self.conn.execute(
+            "UPDATE tracks SET rating = ?1 WHERE id = ?2",
+            params![rating, track_id],
+        )?;
+        Ok(updated > 0)
+    }
+
+    /// Returns false if the track doesn't exist
+    pub fn set_track_favorite(&mut self, track_id: i64, favorite: bool) -> SqlResult<bool> {
+        let updated = self.conn.execute(
+            "UPDATE tracks SET favorite = ?1 WHERE id = ?2",
+            params![favorite, track_id],
+        )?;
+        Ok(updated > 0)
+    }
+
+    pub fn set_album_rating(
+        &mut self,
+        album: &str,
+        artist: Option<&str>,
+        rating: Option<i64>,
+    ) -> SqlResult<()> {
+        self.upsert_album_column(album, artist, "rating", &rating)
+    }
+
+    pub fn set_album_favorite(
+        &mut self,
+        album: &str,
+        artist: Option<&str>,
+        favorite: bool,
+    ) -> SqlResult<()> {
+        self.upsert_album_column(album, artist, "favorite", &favorite)
+    }
+
+    /// Set a column on the `albums` row for this name and artist, creating the row if needed
+    fn upsert_album_column(
+        &mut self,
+        album: &str,
+        artist: Option<&str>,
+        column: &str,
+        value: &dyn rusqlite::ToSql,
+    ) -> SqlResult<()> {
+        let tx = self.conn.transaction()?;
+        let updated = tx.execute(
+            &format!(
+                "UPDATE albums SET {} = ?1 WHERE name = ?2 AND artist IS ?3",
+                column
+            ),
+            params![value, album, artist],
+        )?;
+        if updated == 0 {
+            tx.execute(
+                &format!(
+                    "INSERT INTO albums (name, artist, {}) VALUES (?1, ?2, ?3)",
+                    column
+                ),
+                params![album, artist, value],
+            )?;
+        }
+        tx.commit()
+    }
+
+    pub fn set_artist_rating(&mut self, artist: &str, rating: Option<i64>) -> SqlResult<()> {
+        self.conn.execute(
+            "INSERT INTO artists (name, rating) VALUES (?1, ?2)
+             ON CONFLICT(name) DO UPDATE SET rating = excluded.rating",
+            params![artist, rating],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_artist_favorite(&mut self, artist: &str, favorite: bool) -> SqlResult<()> {
+        self.conn.execute(
+            "INSERT INTO artists (name, favorite) VALUES (?1, ?2)
+             ON CONFLICT(name) DO UPDATE SET favorite = excluded.favorite",
+            params![artist, favorite],
+        )?;
+        Ok(())
+    }
+
+    /// Albums that are rated or marked as favorite
+    pub fn get_album_ratings(&self) -> SqlResult<Vec<AlbumRating>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT name, artist, rating, favorite FROM albums
+             WHERE rating IS NOT NULL OR favorite = 1
+             ORDER BY IFNULL(sort_key, name) COLLATE UNICODE, artist COLLATE UNICODE",
+        )?;
+        let ratings = stmt
+            .query_map([], |row| {
+                Ok(AlbumRating {
+                    album: row.get(0)?,
+                    artist: row.get(1)?,
+                    rating: row.get(2)?,
+                    favorite: row.get(3)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(ratings)
+    }
+
+    /// Artists that are rated or marked as favorite
+    pub fn get_artist_ratings(&self) -> SqlResult<Vec<ArtistRating>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT name, rating, favorite FROM artists
+             WHERE rating IS NOT NULL OR favorite = 1
+             ORDER BY IFNULL(sort_key, name) COLLATE UNICODE",
+        )?;
+        let ratings = stmt
+            .query_map([], |row| {
+                Ok(ArtistRating {
+                    artist: row.get(0)?,
+                    rating: row.get(1)?,
+                    favorite: row.get(2)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(ratings)
+    }
+
+    /// Whether ratings are read from and written to file tags (off by default)
+    pub fn load_rating_tags_enabled(&self) -> SqlResult<bool> {
+        Ok(self.get_setting("rating_tags_enabled")?.as_deref() == Some("true"))
+    }
+
+    pub fn save_rating_tags_enabled(&mut self, enabled: bool) -> SqlResult<()> {
+        self.set_setting("rating_tags_enabled", &enabled.to_string())
+    }
+
+    /// Days a missing track is kept before a scan removes it; 0 keeps it
+    pub fn load_missing_grace_days(&self) -> SqlResult<u64> {
+        Ok(self
+            .get_setting("missing_grace_days")?
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(reconcile::DEFAULT_MISSING_GRACE_DAYS))
+    }
+
+    /// Separators that split artist and genre tags into several values
+    pub fn load_tag_separators(&self) -> SqlResult<Vec<String>> {
+        match self.get_setting("tag_separators")? {
+            Some(json) => serde_json::from_str(&json).map_err(|e| {
+                rusqlite::Error::FromSqlConversionFailure(
+                    0,
+                    rusqlite::types::Type::Text,
+                    Box::new(e),
+                )
+            }),
+            None => Ok(credits::DEFAULT_SEPARATORS
+                .iter()
+                .map(|s| s.to_string())
+                .collect()),
+        }
+    }
+
+    /// Store new separators and split the tags of every track again with them
+    pub fn save_tag_separators(&mut self, separators: &[String]) -> SqlResult<()> {
+        let json = serde_json::to_string(separators)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        let articles = self.load_sort_articles()?;
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "INSERT OR REPLACE INTO app_settings (key, value) VALUES ('tag_separators', ?1)",
+            params![json],
+        )?;
+        let tracks = tx
+            .prepare("SELECT id, artist, genre FROM tracks")?
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                ))
+            })?
+            .collect::<SqlResult<Vec<_>>>()?;
+        for (id, artist, genre) in tracks {
+            credits::link_track_credits(&tx, id, artist.as_deref(), genre.as_deref(), separators)?;
+            // Artists split out by the new separators need their sort keys
+            sorting::update_sort_keys(&tx, id, &articles)?;
+        }
+        tx.commit()
+    }
+
+    /// Articles ignored at the start of titles and names when sorting
+    pub fn load_sort_articles(&self) -> SqlResult<Vec<String>> {
+        match self.get_setting("sort_articles")? {
+            Some(json) => serde_json::from_str(&json).map_err(|e| {
+                rusqlite::Error::FromSqlConversionFailure(
+                    0,
+                    rusqlite::types::Type::Text,
+                    Box::new(e),
+                )
+            }),
+            None => Ok(sorting::DEFAULT_ARTICLES
+                .iter()
+                .map(|s| s.to_string())
+                .collect()),
+        }
+    }
+
+    /// Store new articles and work out the sort keys of every track again
+    pub fn save_sort_articles(&mut self, articles: &[String]) -> SqlResult<()> {
+        let json = serde_json::to_string(articles)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "INSERT OR REPLACE INTO app_settings (key, value) VALUES ('sort_articles', ?1)",
+            params![json],
+        )?;
+        let ids = tx
+            .prepare("SELECT id FROM tracks")?
+            .query_map([], |row| row.get::<_, i64>(0))?
+            .collect::<SqlResult<Vec<_>>>()?;
+        for id in ids {
+            sorting::update_sort_keys(&tx, id, articles)?;
+        }
+        tx.commit()
+    }
+
+    // Playback session
+
+    /// Save the current track and position (a missing track is stored as none)
+    pub fn save_session_track(&mut self, track_id: Option<i64>, position_ms: i64) -> SqlResult<()> {
+        self.conn.execute(
+            "INSERT INTO playback_session (id, track_id, position_ms)
+             VALUES (1, (SELECT id FROM tracks WHERE id = ?1), ?2)
+             ON CONFLICT(id) DO UPDATE SET
+                track_id = excluded.track_id,
+                position_ms = excluded.position_ms",
+            params![track_id, position_ms.max(0)],
+        )?;
+        Ok(())
+    }
+
+    pub fn save_session_volume(&mut self, volume: f32) -> SqlResult<()> {
+        self.conn.execute(
+            "INSERT INTO playback_session (id, volume) VALUES (1, ?1)
+             ON CONFLICT(id) DO UPDATE SET volume = excluded.volume",
+            params![volume as f64],
+        )?;
+        Ok(())
+    }
+
+    pub fn save_session_speed(&mut self, speed: f32) -> SqlResult<()> {
+        self.conn.execute(
+            "INSERT INTO playback_session (id, speed) VALUES (1, ?1)
+             ON CONFLICT(id) DO UPDATE SET speed = excluded.speed",
+            params![speed as f64],
+        )?;
+        Ok(())
+    }
+
+    /// Replace the saved queue and modes. Tracks and playlists that no longer exist are dropped.
+    pub fn save_session_context(&mut self, context: &SessionContext) -> SqlResult<()> {
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "INSERT INTO playback_session (id, shuffle, repeat_mode, active_playlist_id)
+             VALUES (1, ?1, ?2, (SELECT id FROM playlists WHERE id = ?3))
+             ON CONFLICT(id) DO UPDATE SET
+                shuffle = excluded.shuffle,
+                repeat_mode = excluded.repeat_mode,
+                active_playlist_id = excluded.active_playlist_id",
+            params![
+                context.shuffle,
+                context.repeat_mode,
+                context.active_playlist_id
+            ],
+        )?;
+
+        tx.execute("DELETE FROM playback_session_queue", [])?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO playback_session_queue (position, track_id)
+                 SELECT ?1, id FROM tracks WHERE id = ?2",
+            )?;
+            for (position, track_id) in context.queue.iter().enumerate() {
+                stmt.execute(params![position as i64, track_id])?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn load_playback_session(&self) -> SqlResult<Option<PlaybackSession>> {
+        let result = self.conn.query_row(
+            "SELECT track_id, position_ms, volume, speed, shuffle, repeat_mode, active_playlist_id
+             FROM playback_session WHERE id = 1",
+            [],
+            |row| {
+                Ok((
+                    row.get::<_, Option<i64>>(0)?,
+                    PlaybackSession {
+                        track: None,
+                        position_ms: row.get(1)?,
+                        volume: row.get::<_, f64>(2)? as f32,
+                        speed: row.get::<_, f64>(3)? as f32,
+                        shuffle: row.get(4)?,
+                        repeat_mode: row.get(5)?,
+                        active_playlist_id: row.get(6)?,
+                        queue: Vec::new(),
+                    },
+                ))
+            },
+        );
+        let (track_id, mut session) = match result {
+            Ok(row) => row,
+            Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        if let Some(track_id) = track_id {
+            session.track = Some(self.get_track_by_id(track_id)?);
+        }
+
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM playback_session_queue q
+             JOIN tracks t ON q.track_id = t.id
+             ORDER BY q.position",
+            TRACK_COLUMNS
+        ))?;
+        session.queue = stmt
+            .query_map([], track_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Some(session))
+    }
+
+    // Duplicate Detection
+
+    pub fn get_duplicate_tracks(&self) -> SqlResult<Vec<Vec<Track>>> {
+        // Find tracks with matching title + artist + similar duration (within 2 seconds)
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM tracks t
+             WHERE EXISTS (
+                 SELECT 1 FROM tracks t2
+                 WHERE t2.id != t.id
+                   AND t2.title IS NOT NULL AND t.title IS NOT NULL
+                   AND LOWER(t2.title) = LOWER(t.title)
+                   AND (t2.artist IS NULL OR t.artist IS NULL OR LOWER(t2.artist) = LOWER(t.artist))
+                   AND (t2.duration IS NULL OR t.duration IS NULL OR ABS(t2.duration - t.duration) <= 2)
+             )
+             ORDER BY LOWER(COALESCE(t.title, '')), LOWER(COALESCE(t.artist, '')), t.id",
+            TRACK_COLUMNS
+        ))?;
+
+        let tracks: Vec<Track> = stmt
+            .query_map([], track_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Group duplicates together
+        let mut groups: Vec<Vec<Track>> = Vec::new();
+        let mut current_group: Vec<Track> = Vec::new();
+
+        for track in tracks {
+            if current_group.is_empty() {
+                current_group.push(track);
+            } else {
+                let prev = &current_group[0];
+                let same_title = prev.title.as_deref().map(|s| s.to_lowercase())
+                    == track.title.as_deref().map(|s| s.to_lowercase());
+                let same_artist = prev.artist.as_deref().map(|s| s.to_lowercase())
+                    == track.artist.as_deref().map(|s| s.to_lowercase());
+                let similar_duration = match (prev.duration, track.duration) {
+                    (Some(a), Some(b)) => (a - b).abs() <= 2,
+                    _ => true,
+                };
+
+                if same_title && same_artist && similar_duration {
+                    current_group.push(track);
+                } else {
+                    if current_group.len() > 1 {
+                        groups.push(current_group);
+                    }
+                    current_group = vec![track];
+                }
+            }
+        }
+        if current_group.len() > 1 {
+            groups.push(current_group);
+        }
+
+        Ok(groups)
+    }
+
+    // App Settings
+
+    pub fn get_setting(&self, key: &str) -> SqlResult<Option<String>> {
+        let result = self.conn.query_row(
+            "SELECT value FROM app_settings WHERE key = ?1",
+            params![key],
+            |row| row.get::<_, String>(0),
+        );
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn set_setting(&mut self, key: &str, value: &str) -> SqlResult<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO app_settings (key, value) VALUES (?1, ?2)",
+            params![key, value],
+        )?;
+        Ok(())
+    }
+
+    // Find tracks by file path (for M3U import)
+    pub fn find_tracks_by_path(&self, path: &str) -> SqlResult<Vec<Track>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM tracks t WHERE file_path = ?1",
+            TRACK_COLUMNS
+        ))?;
+
+        let tracks = stmt
+            .query_map(params![path], track_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(tracks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cue;
+    use crate::models::TrackSortKey;
+    use tempfile::TempDir;
+
+    fn create_test_db() -> Result<DatabaseInner> {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path().join("test.db");
+
+        // Create a new database connection
+        let conn = Connection::open(&db_path)?;
+        conn.execute_batch("PRAGMA journal_mode = WAL;")?;
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+
+        let mut db = DatabaseInner { conn };
+        db.init_schema()?;
+        Ok(db)
+    }
+
+    fn search(db: &DatabaseInner, query: &str) -> SqlResult<Vec<Track>> {
+        db.search_tracks(&SearchQuery::parse(query).unwrap())
+    }
+
+    #[test]
+    fn test_pool_reads_while_writing() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let pool = DatabasePool::open_at(&temp_dir.path().join("pool.db"), None)?;
+
+        pool.write().unwrap().insert_track(&Track {
+            file_path: "/a.mp3".to_string(),
+            title: Some("Committed".to_string()),
+            file_format: "mp3".to_string(),
+            ..Default::default()
+        })?;
+
+        // An open write transaction doesn't block readers, who see the last commit
+        let writer = pool.write().unwrap();
+        writer.conn.execute_batch(
+            "BEGIN; INSERT INTO tracks (file_path, file_size, file_format, last_modified)
+                VALUES ('/b.mp3', 0, 'mp3', 0);",
+        )?;
+        let readers: Vec<_> = (0..READER_CONNECTIONS)
+            .map(|_| pool.read().unwrap())
+            .collect();
+        for reader in &readers {
+            assert_eq!(reader.get_tracks(None)?.len(), 1);
+        }
+        writer.conn.execute_batch("COMMIT;")?;
+        drop(writer);
+        assert_eq!(readers[0].get_tracks(None)?.len(), 2);
+
+        // Readers are read-only and go back to the pool when dropped
+        assert!(readers[0].conn.execute("DELETE FROM tracks", []).is_err());
+        drop(readers);
+        assert_eq!(pool.readers.lock().unwrap().len(), READER_CONNECTIONS);
+        assert_eq!(pool.read().unwrap().get_tracks(None)?.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_init_schema() {
+        let db = create_test_db();
+        assert!(db.is_ok());
+    }
+
+    #[test]
+    fn test_insert_and_get_track() -> SqlResult<()> {
+        let mut db = create_test_db().unwrap();
+
+        let track = Track {
+            id: 0,
+            file_path: "/test/path.mp3".to_string(),
+            title: Some("Test Song".to_string()),
+            artist: Some("Test Artist".to_string()),
+            album: Some("Test Album".to_string()),
+            duration: Some(180i64),
+            year: Some(2023),
+            genre: Some("Rock".to_string()),
+            track_number: Some(1),
+            file_size: 5000000,
+            file_format: "mp3".to_string(),
+            last_modified: 1234567890,
+            metadata_fetched: false,
+            release_mbid: None,
+            created_at: 1234567890,
+            ..Default::default()
+        };
+
+        let id = db.insert_track(&track)?;
+        let retrieved = db.get_track_by_id(id)?;
+
+        assert_eq!(retrieved.title, track.title);
+        assert_eq!(retrieved.artist, track.artist);
+        assert_eq!(retrieved.album, track.album);
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_tracks() -> SqlResult<()> {
+        let mut db = create_test_db().unwrap();
+
+        // Insert test tracks
+        let track1 = Track {
+            id: 0,
+            file_path: "/test/path1.mp3".to_string(),
+            title: Some("Rock Song".to_string()),
+            artist: Some("Rock Artist".to_string()),
+            album: Some("Rock Album".to_string()),
+            duration: Some(180i64),
             year: Some(2023),
             genre: Some("Rock".to_string()),
             track_number: Some(1),
@@ -1374,50 +3498,603 @@ mod tests {
             metadata_fetched: false,
             release_mbid: None,
             created_at: 1234567890,
+            ..Default::default()
+        };
+
+        let track2 = Track {
+            id: 0,
+            file_path: "/test/path2.mp3".to_string(),
+            title: Some("Pop Song".to_string()),
+            artist: Some("Pop Artist".to_string()),
+            album: Some("Pop Album".to_string()),
+            duration: Some(200i64),
+            year: Some(2023),
+            genre: Some("Pop".to_string()),
+            track_number: Some(1),
+            file_size: 6000000,
+            file_format: "mp3".to_string(),
+            last_modified: 1234567890,
+            metadata_fetched: false,
+            release_mbid: None,
+            created_at: 1234567890,
+            ..Default::default()
+        };
+
+        db.insert_track(&track1)?;
+        db.insert_track(&track2)?;
+
+        // Search for "Rock" - matches track1 (title, artist, and album all contain "Rock")
+        let results = search(&db, "Rock")?;
+        assert_eq!(results.len(), 1);
+
+        // Search for "Pop" - matches track2
+        let results = search(&db, "Pop")?;
+        assert_eq!(results.len(), 1);
+
+        // Prefixes match, and every word must match
+        assert_eq!(search(&db, "ro")?.len(), 1);
+        assert_eq!(search(&db, "song")?.len(), 2);
+        assert!(search(&db, "rock pop")?.is_empty());
+
+        // FTS5 syntax in user input is treated as text
+        assert!(search(&db, "rock OR NEAR(")?.is_empty());
+        assert_eq!(search(&db, " ,  / ")?.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_ranking_and_diacritics() -> SqlResult<()> {
+        let mut db = create_test_db().unwrap();
+
+        let bjork = Track {
+            file_path: "/test/joga.flac".to_string(),
+            title: Some("Jóga".to_string()),
+            artist: Some("Björk".to_string()),
+            album: Some("Homogenic".to_string()),
+            file_format: "flac".to_string(),
+            ..Default::default()
+        };
+        let tribute = Track {
+            file_path: "/test/tribute.flac".to_string(),
+            title: Some("Song".to_string()),
+            artist: Some("Various".to_string()),
+            album: Some("A Tribute to Bjork".to_string()),
+            file_format: "flac".to_string(),
+            ..Default::default()
+        };
+        db.insert_track(&tribute)?;
+        let id = db.insert_track(&bjork)?;
+
+        // Accents are folded both ways, and an artist match outranks an album match
+        let results = search(&db, "bjork")?;
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].artist.as_deref(), Some("Björk"));
+        assert_eq!(search(&db, "JOGA")?.len(), 1);
+
+        // The index follows edits and deletes
+        db.update_track_metadata_manual(
+            id,
+            Some("Hunter".to_string()),
+            None,
+            None,
+            None,
+            None,
+            None,
+        )?;
+        assert!(search(&db, "joga")?.is_empty());
+        assert_eq!(search(&db, "hunt")?.len(), 1);
+        db.delete_track(id)?;
+        assert!(search(&db, "hunter")?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_structured_search() -> SqlResult<()> {
+        let mut db = create_test_db().unwrap();
+
+        let track =
+            |path: &str, title: &str, genre: &str, year: i64, duration: i64, format: &str| Track {
+                file_path: path.to_string(),
+                title: Some(title.to_string()),
+                artist: Some("Radiohead".to_string()),
+                genre: Some(genre.to_string()),
+                year: Some(year),
+                duration: Some(duration),
+                file_format: format.to_string(),
+                ..Default::default()
+            };
+        db.insert_track(&track(
+            "/r/1.flac",
+            "Paranoid Android",
+            "Alt Rock",
+            1997,
+            383,
+            "flac",
+        ))?;
+        db.insert_track(&track(
+            "/r/2.flac",
+            "Lucky (Live)",
+            "Alt Rock",
+            1997,
+            260,
+            "flac",
+        ))?;
+        db.insert_track(&track("/r/3.mp3", "Creep", "Alt Rock", 1992, 238, "mp3"))?;
+        db.insert_track(&track("/p/4.mp3", "Roads", "Trip Hop", 1994, 305, "mp3"))?;
+        db.conn.execute(
+            "UPDATE tracks SET artist = 'Portishead' WHERE file_path = '/p/4.mp3'",
+            [],
+        )?;
+
+        let titles = |query: &str| -> SqlResult<Vec<String>> {
+            Ok(search(&db, query)?
+                .into_iter()
+                .filter_map(|t| t.title)
+                .collect())
+        };
+
+        assert_eq!(
+            titles("artist:radiohead year:1995..2000 format:FLAC duration:>300 -live")?,
+            vec!["Paranoid Android"]
+        );
+        assert_eq!(titles("genre:\"trip hop\"")?, vec!["Roads"]);
+        assert_eq!(titles("-artist:radio")?, vec!["Roads"]);
+        assert_eq!(titles("year:<1995 -format:flac")?, vec!["Creep", "Roads"]);
+        assert_eq!(
+            titles("radiohead -live duration:4:00..")?,
+            vec!["Paranoid Android"]
+        );
+        assert_eq!(titles("\"paranoid android\"")?, vec!["Paranoid Android"]);
+
+        // LIKE wildcards in values are literal
+        assert!(titles("title:%")?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_and_get_playlist() -> SqlResult<()> {
+        let mut db = create_test_db().unwrap();
+
+        let playlist_id = db.create_playlist("Test Playlist")?;
+        let playlists = db.get_playlists()?;
+
+        assert_eq!(playlists.len(), 1);
+        assert_eq!(playlists[0].name, "Test Playlist");
+        assert_eq!(playlists[0].id, playlist_id);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_play_and_skip_counts() -> SqlResult<()> {
+        let mut db = create_test_db().unwrap();
+
+        let track = |path: &str, title: &str, duration: Option<i64>| Track {
+            file_path: path.to_string(),
+            title: Some(title.to_string()),
+            duration,
+            file_format: "mp3".to_string(),
+            ..Default::default()
+        };
+        let short = db.insert_track(&track("/short.mp3", "Short", Some(100)))?;
+        let long = db.insert_track(&track("/long.mp3", "Long", Some(1200)))?;
+        let unknown = db.insert_track(&track("/unknown.mp3", "Unknown", None))?;
+
+        db.record_play_history(short, 49)?; // under half: skip
+        db.record_play_history(short, 50)?;
+        db.record_play_history(short, 100)?;
+        db.record_play_history(long, 240)?; // four minutes is enough for long tracks
+        db.record_play_history(unknown, 29)?;
+
+        let short_track = db.get_track_by_id(short)?;
+        assert_eq!((short_track.play_count, short_track.skip_count), (2, 1));
+        assert!(short_track.first_played_at.is_some());
+        assert!(short_track.last_played_at >= short_track.first_played_at);
+
+        let long_track = db.get_track_by_id(long)?;
+        assert_eq!((long_track.play_count, long_track.skip_count), (1, 0));
+
+        // A skip is not a play
+        let unknown_track = db.get_track_by_id(unknown)?;
+        assert_eq!((unknown_track.play_count, unknown_track.skip_count), (0, 1));
+        assert_eq!(unknown_track.last_played_at, None);
+
+        assert_eq!(search(&db, "plays:>=1 skips:0")?.len(), 1);
+
+        let history = db.get_play_history(10)?;
+        assert_eq!(history.len(), 5);
+        assert_eq!(history.iter().filter(|e| e.skipped).count(), 2);
+
+        let most_played = db.get_tracks(Some(&TrackFilters {
+            sort: vec![TrackSortKey {
+                field: TrackSortField::PlayCount,
+                descending: true,
+            }],
+            ..Default::default()
+        }))?;
+        let titles: Vec<_> = most_played
+            .iter()
+            .map(|t| t.title.clone().unwrap())
+            .collect();
+        assert_eq!(titles, vec!["Short", "Long", "Unknown"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_listening_statistics() -> SqlResult<()> {
+        let mut db = create_test_db().unwrap();
+
+        let track = |path: &str, artist: &str, album: &str, genre: &str| Track {
+            file_path: path.to_string(),
+            title: Some(path.trim_start_matches('/').to_string()),
+            artist: Some(artist.to_string()),
+            album: Some(album.to_string()),
+            genre: Some(genre.to_string()),
+            duration: Some(300),
+            file_format: "mp3".to_string(),
+            ..Default::default()
+        };
+        let a = db.insert_track(&track("/a.mp3", "X", "P", "Rock"))?;
+        let b = db.insert_track(&track("/b.mp3", "Y", "Q", "Jazz"))?;
+
+        // Noon on Monday 4 March 2024, and the two days after
+        let day = |n: i64| 1_709_553_600 + n * 86_400;
+        for (track_id, played_at, listened, skipped) in [
+            (a, 1_685_620_800, 200, false), // June 2023
+            (a, day(0), 200, false),
+            (a, day(1), 200, false),
+            (b, day(1), 300, false),
+            (b, day(2), 10, true),
+        ] {
+            db.conn.execute(
+                "INSERT INTO play_history (track_id, played_at, duration_listened, skipped)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![track_id, played_at, listened, skipped],
+            )?;
+        }
+        db.conn.execute_batch(
+            "UPDATE tracks SET first_played_at =
+                (SELECT MIN(played_at) FROM play_history WHERE track_id = tracks.id AND NOT skipped)",
+        )?;
+
+        let range = StatsRange {
+            from: Some(day(-10)),
+            to: Some(day(10)),
+        };
+        let top = db.top_tracks(&range, 10)?;
+        assert_eq!(
+            top.iter()
+                .map(|s| (s.track.id, s.plays))
+                .collect::<Vec<_>>(),
+            vec![(a, 2), (b, 1)]
+        );
+        let albums = db.top_albums(&range, 10)?;
+        assert_eq!(albums[0].name, "P");
+        assert_eq!(albums[0].artist.as_deref(), Some("X"));
+        assert_eq!(db.top_genres(&range, 1)?.len(), 1);
+
+        let days = db.listening_time(&range, StatsPeriod::Day)?;
+        assert_eq!(days.len(), 3);
+        assert_eq!((days[2].plays, days[2].seconds), (0, 10));
+        let weeks = db.listening_time(&range, StatsPeriod::Week)?;
+        assert_eq!(weeks.len(), 1);
+        assert_eq!(weeks[0].period, "2024-03-04");
+        let all_months = db.listening_time(&StatsRange::default(), StatsPeriod::Month)?;
+        assert_eq!(
+            all_months
+                .iter()
+                .map(|m| m.period.as_str())
+                .collect::<Vec<_>>(),
+            vec!["2023-06", "2024-03"]
+        );
+
+        let clock = db.listening_clock(&range)?;
+        assert_eq!(clock.iter().flatten().sum::<i64>(), 710);
+
+        // The skip on the third day doesn't extend the streak
+        let streaks = db.listening_streaks(&range)?;
+        assert_eq!(streaks.longest, 2);
+        assert_eq!(streaks.longest_start.as_deref(), Some("2024-03-04"));
+        assert_eq!(streaks.current, 0);
+
+        let discoveries = db.new_discoveries(&range, 10)?;
+        assert_eq!(
+            discoveries
+                .tracks
+                .iter()
+                .map(|s| s.track.id)
+                .collect::<Vec<_>>(),
+            vec![b]
+        );
+        assert_eq!(discoveries.artists.len(), 1);
+        assert_eq!(discoveries.artists[0].name, "Y");
+
+        let report = db.year_report(2024, 10)?;
+        assert_eq!((report.plays, report.skips, report.seconds), (3, 1, 710));
+        assert_eq!((report.distinct_tracks, report.distinct_artists), (2, 2));
+        assert_eq!(report.top_artists[0].name, "X");
+        assert_eq!(report.months.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_track_pages() -> SqlResult<()> {
+        let mut db = create_test_db().unwrap();
+
+        for (i, (title, artist, year)) in [
+            ("Alpha", Some("Björk"), Some(1997)),
+            ("beta", Some("Bjork"), None),
+            ("Gamma", None, Some(1997)),
+            ("Delta", Some("Air"), Some(1998)),
+            ("alpha", Some("Air"), None),
+            ("Epsilon", Some("Björk"), Some(2001)),
+            ("Zeta", Some("Air"), Some(1997)),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            db.insert_track(&Track {
+                file_path: format!("/music/{}.flac", i),
+                title: Some(title.to_string()),
+                artist: artist.map(str::to_string),
+                year,
+                file_format: "flac".to_string(),
+                ..Default::default()
+            })?;
+        }
+
+        let all_pages = |filters: &TrackFilters, search: Option<&str>| -> SqlResult<Vec<i64>> {
+            let search = search.map(|q| SearchQuery::parse(q).unwrap());
+            let mut ids = Vec::new();
+            let mut cursor: Option<Vec<serde_json::Value>> = None;
+            loop {
+                let page = db.get_track_page(filters, search.as_ref(), cursor.as_deref(), 2)?;
+                assert!(page.tracks.len() <= 2);
+                ids.extend(page.tracks.iter().map(|t| t.id));
+                assert!(page.total >= ids.len() as i64);
+                match page.next_cursor {
+                    Some(next) => cursor = Some(next),
+                    None => return Ok(ids),
+                }
+            }
         };
+        let ids = |tracks: Vec<Track>| tracks.iter().map(|t| t.id).collect::<Vec<_>>();
+
+        // Pages match the unpaged order, NULLs included, in either direction
+        for descending in [false, true] {
+            let filters = TrackFilters {
+                sort: vec![
+                    TrackSortKey {
+                        field: TrackSortField::Year,
+                        descending,
+                    },
+                    TrackSortKey {
+                        field: TrackSortField::Artist,
+                        descending: !descending,
+                    },
+                ],
+                ..Default::default()
+            };
+            assert_eq!(
+                all_pages(&filters, None)?,
+                ids(db.get_tracks(Some(&filters))?)
+            );
+        }
 
-        let track2 = Track {
-            id: 0,
-            file_path: "/test/path2.mp3".to_string(),
-            title: Some("Pop Song".to_string()),
-            artist: Some("Pop Artist".to_string()),
-            album: Some("Pop Album".to_string()),
-            duration: Some(200i64),
-            year: Some(2023),
-            genre: Some("Pop".to_string()),
-            track_number: Some(1),
-            file_size: 6000000,
-            file_format: "mp3".to_string(),
-            last_modified: 1234567890,
-            metadata_fetched: false,
-            release_mbid: None,
-            created_at: 1234567890,
+        let year_1997 = TrackFilters {
+            year: Some(1997),
+            ..Default::default()
         };
+        assert_eq!(
+            all_pages(&year_1997, None)?,
+            ids(db.get_tracks(Some(&year_1997))?)
+        );
 
-        db.insert_track(&track1)?;
-        db.insert_track(&track2)?;
+        // Search relevance order, narrowed by filters
+        let searched = all_pages(&TrackFilters::default(), Some("bjork"))?;
+        assert_eq!(searched, ids(search(&db, "bjork")?));
+        assert_eq!(all_pages(&year_1997, Some("bjork"))?.len(), 1);
+
+        let page = db.get_track_page(&TrackFilters::default(), None, None, 3)?;
+        assert_eq!(page.total, 7);
+        assert_eq!(page.tracks.len(), 3);
+        assert!(db
+            .get_track_page(
+                &TrackFilters::default(),
+                None,
+                Some(&[serde_json::Value::Null]),
+                3
+            )
+            .is_err());
 
-        // Search for "Rock" - matches track1 (title, artist, and album all contain "Rock")
-        let results = db.search_tracks("Rock")?;
-        assert_eq!(results.len(), 1);
+        Ok(())
+    }
 
-        // Search for "Pop" - matches track2
-        let results = db.search_tracks("Pop")?;
-        assert_eq!(results.len(), 1);
+    #[test]
+    fn test_ratings_and_favorites() -> SqlResult<()> {
+        let mut db = create_test_db().unwrap();
+
+        let track = |path: &str, title: &str| Track {
+            file_path: path.to_string(),
+            title: Some(title.to_string()),
+            artist: Some("Artist".to_string()),
+            album: Some("Album".to_string()),
+            file_format: "mp3".to_string(),
+            ..Default::default()
+        };
+        let a = db.insert_track(&track("/a.mp3", "A"))?;
+        let b = db.insert_track(&track("/b.mp3", "B"))?;
+        db.insert_track(&track("/c.mp3", "C"))?;
+
+        assert!(db.set_track_rating(a, Some(6))?);
+        assert!(db.set_track_rating(b, Some(9))?);
+        assert!(db.set_track_favorite(a, true)?);
+        assert!(!db.set_track_rating(9999, Some(1))?);
+
+        // A rescan without a rating tag keeps the stored rating
+        db.insert_track(&track("/a.mp3", "A"))?;
+        let stored = db.get_track_by_id(a)?;
+        assert_eq!(stored.rating, Some(6));
+        assert!(stored.favorite);
+
+        let titles = |filters: TrackFilters| -> SqlResult<Vec<String>> {
+            Ok(db
+                .get_tracks(Some(&filters))?
+                .into_iter()
+                .filter_map(|t| t.title)
+                .collect())
+        };
+        let by_rating = vec![TrackSortKey {
+            field: TrackSortField::Rating,
+            descending: true,
+        }];
+        assert_eq!(
+            titles(TrackFilters {
+                sort: by_rating.clone(),
+                ..Default::default()
+            })?,
+            vec!["B", "A", "C"]
+        );
+        assert_eq!(
+            titles(TrackFilters {
+                min_rating: Some(7),
+                ..Default::default()
+            })?,
+            vec!["B"]
+        );
+        assert_eq!(
+            titles(TrackFilters {
+                favorite: Some(true),
+                ..Default::default()
+            })?,
+            vec!["A"]
+        );
+        assert_eq!(search(&db, "rating:>=3 -favorite:yes")?.len(), 1);
+
+        db.set_album_rating("Album", Some("Artist"), Some(8))?;
+        db.set_album_favorite("Album", Some("Artist"), true)?;
+        db.set_album_favorite("Album", None, true)?;
+        db.set_artist_rating("Artist", Some(10))?;
+        db.set_artist_rating("Artist", None)?;
+        db.set_artist_favorite("Artist", true)?;
+
+        assert_eq!(
+            db.get_album_ratings()?,
+            vec![
+                AlbumRating {
+                    album: "Album".to_string(),
+                    artist: None,
+                    rating: None,
+                    favorite: true,
+                },
+                AlbumRating {
+                    album: "Album".to_string(),
+                    artist: Some("Artist".to_string()),
+                    rating: Some(8),
+                    favorite: true,
+                },
+            ]
+        );
+        assert_eq!(
+            db.get_artist_ratings()?,
+            vec![ArtistRating {
+                artist: "Artist".to_string(),
+                rating: None,
+                favorite: true,
+            }]
+        );
+
+        assert!(!db.load_rating_tags_enabled()?);
+        db.save_rating_tags_enabled(true)?;
+        assert!(db.load_rating_tags_enabled()?);
 
         Ok(())
     }
 
     #[test]
-    fn test_create_and_get_playlist() -> SqlResult<()> {
+    fn test_smart_playlist() -> SqlResult<()> {
         let mut db = create_test_db().unwrap();
 
-        let playlist_id = db.create_playlist("Test Playlist")?;
-        let playlists = db.get_playlists()?;
+        let mut ids = Vec::new();
+        for (path, title, genre, year) in [
+            ("/j/1.flac", "So What", "Jazz", 1959),
+            ("/j/2.flac", "Naima", "Jazz", 1960),
+            ("/j/3.flac", "Footprints", "jazz", 1966),
+            ("/r/4.flac", "Creep", "Rock", 1992),
+        ] {
+            ids.push(db.insert_track(&Track {
+                file_path: path.to_string(),
+                title: Some(title.to_string()),
+                genre: Some(genre.to_string()),
+                year: Some(year),
+                duration: Some(300),
+                file_format: "flac".to_string(),
+                ..Default::default()
+            })?);
+        }
+        db.record_play_history(ids[1], 300)?;
+
+        // Jazz since 1959, not played in the last 90 days
+        let rules: SmartPlaylistRules = serde_json::from_str(
+            r#"{
+                "rule": {"type": "all", "rules": [
+                    {"type": "text", "field": "genre", "op": "is", "value": "JAZZ"},
+                    {"type": "number", "field": "year", "op": "gte", "value": 1959},
+                    {"type": "not", "rule": {"type": "in_last_days", "field": "last_played", "days": 90}}
+                ]},
+                "limit": 50,
+                "sort": "year",
+                "descending": true
+            }"#,
+        )
+        .unwrap();
 
-        assert_eq!(playlists.len(), 1);
-        assert_eq!(playlists[0].name, "Test Playlist");
-        assert_eq!(playlists[0].id, playlist_id);
+        let id = db.create_smart_playlist("Unplayed Jazz", &rules)?;
+        let titles = |tracks: Vec<Track>| -> Vec<String> {
+            tracks.into_iter().filter_map(|t| t.title).collect()
+        };
+        assert_eq!(
+            titles(db.get_playlist_tracks(id)?),
+            vec!["Footprints", "So What"]
+        );
+        assert_eq!(db.get_smart_playlist_rules(id)?, Some(rules.clone()));
+
+        let playlist = db.get_playlist(id)?;
+        assert_eq!(playlist.kind, PlaylistKind::Smart);
+        assert_eq!(playlist.track_count, 2);
+        assert_eq!(playlist.total_duration, Some(600));
+
+        // Re-evaluated on every call
+        db.conn.execute(
+            "UPDATE tracks SET genre = 'Jazz' WHERE id = ?1",
+            params![ids[3]],
+        )?;
+        assert_eq!(db.get_playlist_tracks(id)?.len(), 3);
+
+        // Edits replace the rules; limits apply after sorting
+        let edited = SmartPlaylistRules {
+            limit: Some(1),
+            ..rules
+        };
+        assert!(db.update_smart_playlist(id, "Newest Jazz", &edited)?);
+        assert_eq!(titles(db.get_playlist_tracks(id)?), vec!["Creep"]);
+        assert_eq!(db.get_playlist(id)?.name, "Newest Jazz");
+
+        // Manual playlists can't be edited as smart ones
+        let manual = db.create_playlist("Manual")?;
+        assert!(!db.update_smart_playlist(manual, "Manual", &edited)?);
+        assert_eq!(db.get_playlist(manual)?.kind, PlaylistKind::Manual);
+
+        let copy = db.duplicate_playlist(id, "Copy")?;
+        assert_eq!(db.get_smart_playlist_rules(copy)?, Some(edited));
+
+        db.delete_playlist(id)?;
+        assert_eq!(db.get_smart_playlist_rules(id)?, None);
 
         Ok(())
     }
@@ -1443,6 +4120,7 @@ mod tests {
             metadata_fetched: false,
             release_mbid: None,
             created_at: 1234567890,
+            ..Default::default()
         };
 
         let track_id = db.insert_track(&track)?;
@@ -1458,4 +4136,950 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_replace_chapters() -> SqlResult<()> {
+        let mut db = create_test_db().unwrap();
+        let track = Track {
+            file_path: "/test/book.m4b".to_string(),
+            file_format: "m4b".to_string(),
+            ..Default::default()
+        };
+        let track_id = db.insert_track(&track)?;
+
+        let chapter = |index: i64, start_ms: i64| Chapter {
+            chapter_index: index,
+            title: Some(format!("Chapter {}", index + 1)),
+            start_ms,
+            ..Default::default()
+        };
+        let entries = vec![(
+            track.file_path.clone(),
+            vec![chapter(0, 0), chapter(1, 60_000)],
+        )];
+        db.replace_chapters_batch(&entries)?;
+        db.replace_chapters_batch(&entries)?;
+
+        let chapters = db.get_track_chapters(track_id)?;
+        assert_eq!(chapters.len(), 2);
+        assert_eq!(chapters[1].track_id, track_id);
+        assert_eq!(chapters[1].start_ms, 60_000);
+
+        // Chapters go away with their track
+        db.delete_track(track_id)?;
+        assert!(db.get_track_chapters(track_id)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resume_positions() -> SqlResult<()> {
+        let mut db = create_test_db().unwrap();
+        let track_id = db.insert_track(&Track {
+            file_path: "/test/mix.mp3".to_string(),
+            duration: Some(3 * 3600),
+            ..Default::default()
+        })?;
+
+        assert!(db.get_resume_position(track_id)?.is_none());
+
+        db.save_resume_position(track_id, 5_000)?;
+        db.save_resume_position(track_id, 95_000)?;
+        let position = db.get_resume_position(track_id)?.unwrap();
+        assert_eq!(position.position_ms, 95_000);
+        assert!(!position.finished);
+
+        db.mark_track_finished(track_id)?;
+        let position = db.get_resume_position(track_id)?.unwrap();
+        assert_eq!(position.position_ms, 0);
+        assert!(position.finished);
+
+        db.clear_resume_position(track_id)?;
+        assert!(db.get_resume_position(track_id)?.is_none());
+
+        let settings = db.load_resume_settings()?;
+        assert_eq!(settings.min_duration_minutes, 20);
+        db.save_resume_settings(&ResumeSettings {
+            enabled: false,
+            min_duration_minutes: 45,
+        })?;
+        assert!(!db.load_resume_settings()?.enabled);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_playback_session_roundtrip() -> SqlResult<()> {
+        let mut db = create_test_db().unwrap();
+        assert!(db.load_playback_session()?.is_none());
+
+        let mut ids = Vec::new();
+        for i in 0..3 {
+            ids.push(db.insert_track(&Track {
+                file_path: format!("/test/{}.mp3", i),
+                ..Default::default()
+            })?);
+        }
+        let playlist_id = db.create_playlist("Evening")?;
+
+        db.save_session_track(Some(ids[1]), 42_000)?;
+        db.save_session_volume(0.5)?;
+        db.save_session_speed(1.25)?;
+        db.save_session_context(&SessionContext {
+            queue: vec![ids[2], 9999, ids[0]],
+            shuffle: true,
+            repeat_mode: "list".to_string(),
+            active_playlist_id: Some(playlist_id),
+        })?;
+
+        let session = db.load_playback_session()?.unwrap();
+        assert_eq!(session.track.map(|t| t.id), Some(ids[1]));
+        assert_eq!(session.position_ms, 42_000);
+        assert_eq!(session.volume, 0.5);
+        assert_eq!(session.speed, 1.25);
+        assert!(session.shuffle);
+        assert_eq!(session.repeat_mode, "list");
+        assert_eq!(session.active_playlist_id, Some(playlist_id));
+        // Unknown track ids are dropped, order is kept
+        let queue: Vec<i64> = session.queue.iter().map(|t| t.id).collect();
+        assert_eq!(queue, vec![ids[2], ids[0]]);
+
+        // Deleting the current track and playlist clears them from the session
+        db.delete_track(ids[1])?;
+        db.delete_playlist(playlist_id)?;
+        let session = db.load_playback_session()?.unwrap();
+        assert!(session.track.is_none());
+        assert!(session.active_playlist_id.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_artist_and_album_ids() -> SqlResult<()> {
+        let mut db = create_test_db().unwrap();
+        let track = |path: &str, artist: &str, album: &str| Track {
+            file_path: path.to_string(),
+            title: Some(path.to_string()),
+            artist: Some(artist.to_string()),
+            album: Some(album.to_string()),
+            ..Default::default()
+        };
+
+        db.insert_tracks_batch(&[
+            track("/a1.mp3", "Air", "Moon Safari"),
+            track("/a2.mp3", "Air", "Moon Safari"),
+            track("/b1.mp3", "Beck", "Odelay"),
+            track("/c1.mp3", "Various", "Moon Safari"),
+        ])?;
+        let tracks = db.get_tracks(None)?;
+        let by_path = |path: &str| tracks.iter().find(|t| t.file_path == path).unwrap();
+        let moon_safari = by_path("/a1.mp3").album_id.unwrap();
+        assert_eq!(by_path("/a2.mp3").album_id, Some(moon_safari));
+        assert_eq!(by_path("/a2.mp3").artist_id, by_path("/a1.mp3").artist_id);
+        // Albums are told apart by artist
+        assert_ne!(by_path("/c1.mp3").album_id, Some(moon_safari));
+
+        let albums = db.get_albums()?;
+        assert_eq!(albums.len(), 3);
+        let artists = db.get_artists()?;
+        assert_eq!(artists[0].name, "Air");
+        assert_eq!((artists[0].track_count, artists[0].album_count), (2, 1));
+
+        // Rescanning keeps the ids
+        db.insert_tracks_batch(&[track("/a1.mp3", "Air", "Moon Safari")])?;
+        assert_eq!(db.get_albums()?.len(), 3);
+        let filters = TrackFilters {
+            album_id: Some(moon_safari),
+            ..Default::default()
+        };
+        assert_eq!(db.get_tracks(Some(&filters))?.len(), 2);
+        assert_eq!(db.get_album_tracks_by_id(moon_safari)?.len(), 2);
+
+        // Retagging moves a track to another album; unused rows are removed
+        let beck = by_path("/b1.mp3").clone();
+        db.update_track_metadata_manual(
+            beck.id,
+            beck.title.clone(),
+            Some("Beck".to_string()),
+            Some("Midnite Vultures".to_string()),
+            None,
+            None,
+            None,
+        )?;
+        let retagged = db.get_track_by_id(beck.id)?;
+        assert_eq!(retagged.artist_id, beck.artist_id);
+        assert_ne!(retagged.album_id, beck.album_id);
+        let odelay_rows: i64 = db.conn.query_row(
+            "SELECT COUNT(*) FROM albums WHERE name = 'Odelay'",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(odelay_rows, 0);
+
+        // Rated albums outlive their tracks
+        db.set_album_rating("Moon Safari", Some("Air"), Some(8))?;
+        db.delete_tracks(&[by_path("/a1.mp3").id, by_path("/a2.mp3").id])?;
+        assert_eq!(db.get_album_ratings()?.len(), 1);
+        assert!(db.get_album_tracks_by_id(moon_safari)?.is_empty());
+        let id = db.insert_track(&track("/a1.mp3", "Air", "Moon Safari"))?;
+        assert_eq!(db.get_track_by_id(id)?.album_id, Some(moon_safari));
+        assert!(!db.get_artists()?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_album_artists_discs_and_compilations() -> SqlResult<()> {
+        let mut db = create_test_db().unwrap();
+        let track = |path: &str, artist: &str, album: &str, disc: i64, number: i64| Track {
+            file_path: path.to_string(),
+            title: Some(path.to_string()),
+            artist: Some(artist.to_string()),
+            album: Some(album.to_string()),
+            disc_number: Some(disc),
+            track_number: Some(number),
+            ..Default::default()
+        };
+
+        db.insert_tracks_batch(&[
+            // A compilation tagged with the flag only
+            Track {
+                compilation: true,
+                ..track("/hits/1.mp3", "A", "Hits", 1, 1)
+            },
+            Track {
+                compilation: true,
+                ..track("/hits/2.mp3", "B", "Hits", 1, 2)
+            },
+            // A two-disc set with an album artist and a guest
+            Track {
+                album_artist: Some("Band".to_string()),
+                ..track("/live/2-1.mp3", "Band", "Live", 2, 1)
+            },
+            Track {
+                album_artist: Some("Band".to_string()),
+                ..track("/live/1-2.mp3", "Band feat. Guest", "Live", 1, 2)
+            },
+            Track {
+                album_artist: Some("Band".to_string()),
+                ..track("/live/1-1.mp3", "Band", "Live", 1, 1)
+            },
+        ])?;
+
+        let albums = db.get_albums_with_cover_info(false)?;
+        assert_eq!(albums.len(), 2);
+        let compilations = db.get_albums_with_cover_info(true)?;
+        assert_eq!(compilations.len(), 1);
+        assert_eq!(compilations[0].1, "Hits");
+        assert_eq!(compilations[0].2.as_deref(), Some("Various Artists"));
+        assert_eq!(compilations[0].4, 2);
+
+        let live: Vec<String> = db
+            .get_album_tracks("Live", Some("Band"))?
+            .into_iter()
+            .map(|t| t.file_path)
+            .collect();
+        assert_eq!(
+            live,
+            vec!["/live/1-1.mp3", "/live/1-2.mp3", "/live/2-1.mp3"]
+        );
+        let hits = db.get_album_tracks("Hits", Some("Various Artists"))?;
+        assert_eq!(hits.len(), 2);
+        assert!(db.get_album_tracks("Hits", Some("A"))?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pending_tags_rekey_existing_albums() -> SqlResult<()> {
+        let mut db = create_test_db().unwrap();
+        let track = |path: &str, artist: &str| Track {
+            file_path: path.to_string(),
+            artist: Some(artist.to_string()),
+            album: Some("Live".to_string()),
+            ..Default::default()
+        };
+        db.insert_tracks_batch(&[
+            track("/live/1.mp3", "Band"),
+            track("/live/2.mp3", "Band feat. Guest"),
+            track("/other.mp3", "Other"),
+        ])?;
+        // As left by the migration for a library scanned before album artists
+        db.conn.execute("UPDATE tracks SET tags_pending = 1", [])?;
+        assert_eq!(db.get_albums_with_cover_info(false)?.len(), 3);
+
+        let pending = db.get_tracks_with_pending_tags()?;
+        assert_eq!(pending.len(), 3);
+        let album_artist = Track {
+            album_artist: Some("Band".to_string()),
+            disc_number: Some(1),
+            ..Default::default()
+        };
+        let updates: Vec<(i64, Option<Track>)> = pending
+            .iter()
+            .map(|(id, path)| match path.as_str() {
+                "/other.mp3" => (*id, None),
+                _ => (*id, Some(album_artist.clone())),
+            })
+            .collect();
+        db.update_pending_tags(&updates)?;
+
+        assert!(db.get_tracks_with_pending_tags()?.is_empty());
+        assert_eq!(db.get_albums_with_cover_info(false)?.len(), 2);
+        let live = db.get_album_tracks("Live", Some("Band"))?;
+        assert_eq!(live.len(), 2);
+        assert!(live.iter().all(|t| t.disc_number == Some(1)));
+        // An unreadable file keeps what it had
+        assert_eq!(db.get_album_tracks("Live", Some("Other"))?.len(), 1);
+
+        // A rescan reads every tag, so it clears the flag too
+        db.conn.execute("UPDATE tracks SET tags_pending = 1", [])?;
+        db.insert_tracks_batch(&[track("/other.mp3", "Other")])?;
+        assert_eq!(db.get_tracks_with_pending_tags()?.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_multi_value_artists_and_genres() -> SqlResult<()> {
+        let mut db = create_test_db().unwrap();
+        let track = |path: &str, artist: &str, genre: &str| Track {
+            file_path: path.to_string(),
+            title: Some(path.to_string()),
+            artist: Some(artist.to_string()),
+            genre: Some(genre.to_string()),
+            ..Default::default()
+        };
+        db.insert_tracks_batch(&[
+            track("/1.mp3", "A; B feat. C", "Rock; Pop"),
+            track("/2.mp3", "B", "Jazz/Funk"),
+        ])?;
+        let tracks = db.get_tracks(None)?;
+        let first = tracks.iter().find(|t| t.file_path == "/1.mp3").unwrap();
+        // The tag is kept as written for display
+        assert_eq!(first.artist.as_deref(), Some("A; B feat. C"));
+
+        let credits = db.get_track_credits(first.id)?;
+        let names: Vec<(&str, CreditRole)> =
+            credits.iter().map(|c| (c.name.as_str(), c.role)).collect();
+        assert_eq!(
+            names,
+            vec![
+                ("A", CreditRole::Main),
+                ("B", CreditRole::Main),
+                ("C", CreditRole::Featured)
+            ]
+        );
+        assert_eq!(first.artist_id, Some(credits[0].artist_id));
+
+        let filtered = |filters: TrackFilters| -> SqlResult<usize> {
+            Ok(db.get_tracks(Some(&filters))?.len())
+        };
+        let by_artist = |name: &str| TrackFilters {
+            artist: Some(name.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(filtered(by_artist("B"))?, 2);
+        assert_eq!(filtered(by_artist("c"))?, 1);
+        assert_eq!(
+            filtered(TrackFilters {
+                genre: Some("Pop".to_string()),
+                ..Default::default()
+            })?,
+            1
+        );
+
+        let artists = db.get_artists()?;
+        let counts: Vec<(&str, i64)> = artists
+            .iter()
+            .map(|a| (a.name.as_str(), a.track_count))
+            .collect();
+        assert_eq!(counts, vec![("A", 1), ("B", 2), ("C", 1)]);
+        assert_eq!(db.get_genres()?.len(), 3);
+
+        // Rescanning keeps the ids of artists credited on one track only
+        let c_id = credits[2].artist_id;
+        db.insert_tracks_batch(&[track("/1.mp3", "A; B feat. C", "Rock; Pop")])?;
+        assert_eq!(db.get_track_credits(first.id)?[2].artist_id, c_id);
+
+        // New separators apply to tracks already in the library
+        db.save_tag_separators(&[";".to_string(), "/".to_string()])?;
+        let genres: Vec<String> = db.get_genres()?.into_iter().map(|g| g.name).collect();
+        assert_eq!(genres, vec!["Funk", "Jazz", "Pop", "Rock"]);
+        assert_eq!(db.load_tag_separators()?, vec![";", "/"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_new_separators_give_split_artists_sort_keys() -> SqlResult<()> {
+        let mut db = create_test_db().unwrap();
+        db.save_tag_separators(&[";".to_string()])?;
+        db.insert_tracks_batch(&[Track {
+            file_path: "/split.mp3".to_string(),
+            artist: Some("The Beatles / The Who".to_string()),
+            ..Default::default()
+        }])?;
+
+        db.save_tag_separators(&[";".to_string(), "/".to_string()])?;
+        let sort_key = |name: &str| -> SqlResult<Option<String>> {
+            db.conn.query_row(
+                "SELECT sort_key FROM artists WHERE name = ?1",
+                params![name],
+                |row| row.get(0),
+            )
+        };
+        assert_eq!(sort_key("The Beatles")?.as_deref(), Some("Beatles"));
+        assert_eq!(sort_key("The Who")?.as_deref(), Some("Who"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_extended_tags_are_stored_and_searchable() -> SqlResult<()> {
+        let mut db = create_test_db().unwrap();
+        let id = db.insert_track(&Track {
+            file_path: "/ninth.flac".to_string(),
+            title: Some("IV. Presto".to_string()),
+            artist: Some("Berliner Philharmoniker".to_string()),
+            tags: ExtendedTags {
+                composer: Some("Ludwig van Beethoven".to_string()),
+                conductor: Some("Herbert von Karajan".to_string()),
+                work: Some("Symphony No. 9".to_string()),
+                label: Some("Deutsche Grammophon".to_string()),
+                isrc: Some("DEF056230001".to_string()),
+                bpm: Some(126),
+                ..Default::default()
+            },
+            ..Default::default()
+        })?;
+        db.insert_track(&Track {
+            file_path: "/other.mp3".to_string(),
+            title: Some("Other".to_string()),
+            ..Default::default()
+        })?;
+
+        let search = |db: &DatabaseInner, query: &str| -> Vec<i64> {
+            db.search_tracks(&SearchQuery::parse(query).unwrap())
+                .unwrap()
+                .iter()
+                .map(|t| t.id)
+                .collect()
+        };
+        assert_eq!(
+            db.get_track_by_id(id)?.tags.conductor.as_deref(),
+            Some("Herbert von Karajan")
+        );
+        assert_eq!(search(&db, "beethoven"), vec![id]);
+        assert_eq!(search(&db, "karajan grammophon"), vec![id]);
+        assert_eq!(search(&db, "composer:beethoven bpm:120..130"), vec![id]);
+        assert_eq!(search(&db, "isrc:DEF056"), vec![id]);
+        assert!(search(&db, "bpm:>130").is_empty());
+
+        // Edits replace every field and are picked up by the index
+        db.update_extended_tags(
+            id,
+            &ExtendedTags {
+                composer: Some("Gustav Mahler".to_string()),
+                comment: Some("remastered".to_string()),
+                ..Default::default()
+            },
+        )?;
+        let tags = db.get_track_by_id(id)?.tags;
+        assert_eq!(tags.conductor, None);
+        assert!(search(&db, "beethoven").is_empty());
+        assert_eq!(search(&db, "mahler remastered"), vec![id]);
+
+        // Tags read again for a track scanned before they were stored
+        let other = search(&db, "other")[0];
+        db.update_pending_tags(&[(
+            other,
+            Some(Track {
+                tags: ExtendedTags {
+                    composer: Some("Arvo Pärt".to_string()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }),
+        )])?;
+        assert_eq!(search(&db, "composer:arvo"), vec![other]);
+        assert_eq!(db.get_track_by_id(other)?.title.as_deref(), Some("Other"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sort_names_and_articles() -> SqlResult<()> {
+        let mut db = create_test_db().unwrap();
+        let track = |path: &str, title: &str, artist: &str| Track {
+            file_path: path.to_string(),
+            title: Some(title.to_string()),
+            artist: Some(artist.to_string()),
+            album: Some(format!("{} album", artist)),
+            ..Default::default()
+        };
+        db.insert_tracks_batch(&[
+            track("/1.mp3", "Track 10", "The Beatles"),
+            track("/2.mp3", "track 2", "Zappa"),
+            track("/3.mp3", "Émile", "björk"),
+            Track {
+                tags: ExtendedTags {
+                    title_sort: Some("Abc".to_string()),
+                    artist_sort: Some("Aaa, The".to_string()),
+                    ..Default::default()
+                },
+                ..track("/4.mp3", "Zulu", "The Zzz")
+            },
+        ])?;
+
+        let order = |db: &DatabaseInner, field: TrackSortField| -> SqlResult<Vec<String>> {
+            let filters = TrackFilters {
+                sort: vec![TrackSortKey {
+                    field,
+                    descending: false,
+                }],
+                ..Default::default()
+            };
+            Ok(db
+                .get_tracks(Some(&filters))?
+                .into_iter()
+                .map(|t| t.file_path)
+                .collect())
+        };
+        // Sort tags win, then accents and case are ignored and numbers are
+        // compared by value
+        assert_eq!(
+            order(&db, TrackSortField::Title)?,
+            vec!["/4.mp3", "/3.mp3", "/2.mp3", "/1.mp3"]
+        );
+        assert_eq!(
+            order(&db, TrackSortField::Artist)?,
+            vec!["/4.mp3", "/1.mp3", "/3.mp3", "/2.mp3"]
+        );
+        let artists: Vec<String> = db.get_artists()?.into_iter().map(|a| a.name).collect();
+        assert_eq!(artists, vec!["The Zzz", "The Beatles", "björk", "Zappa"]);
+
+        // Keyset pages follow the same order
+        let filters = TrackFilters {
+            sort: vec![TrackSortKey {
+                field: TrackSortField::Artist,
+                descending: false,
+            }],
+            ..Default::default()
+        };
+        let first = db.get_track_page(&filters, None, None, 2)?;
+        let rest = db.get_track_page(&filters, None, first.next_cursor.as_deref(), 2)?;
+        let paged: Vec<i64> = first
+            .tracks
+            .iter()
+            .chain(&rest.tracks)
+            .map(|t| t.id)
+            .collect();
+        let all: Vec<i64> = db
+            .get_tracks(Some(&filters))?
+            .iter()
+            .map(|t| t.id)
+            .collect();
+        assert_eq!(paged, all);
+
+        // Without articles "The Beatles" sorts under T
+        db.save_sort_articles(&[])?;
+        assert_eq!(db.load_sort_articles()?, Vec::<String>::new());
+        assert_eq!(
+            order(&db, TrackSortField::Artist)?,
+            vec!["/4.mp3", "/3.mp3", "/1.mp3", "/2.mp3"]
+        );
+
+        // A sort tag read again for a track scanned before they were stored
+        let zappa = db.get_track_ids_for_files(&["/2.mp3".to_string()])?[0];
+        db.update_pending_tags(&[(
+            zappa,
+            Some(Track {
+                tags: ExtendedTags {
+                    artist_sort: Some("Aab".to_string()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }),
+        )])?;
+        assert_eq!(
+            order(&db, TrackSortField::Artist)?,
+            vec!["/4.mp3", "/2.mp3", "/3.mp3", "/1.mp3"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_audio_properties_filters_and_quality_stats() -> SqlResult<()> {
+        let mut db = create_test_db().unwrap();
+        let track = |path: &str, audio: AudioProperties| Track {
+            file_path: path.to_string(),
+            title: Some(path.to_string()),
+            audio,
+            ..Default::default()
+        };
+        let properties =
+            |codec: &str, lossless, bitrate, sample_rate, bit_depth, channels| AudioProperties {
+                bitrate: Some(bitrate),
+                sample_rate: Some(sample_rate),
+                bit_depth,
+                channels: Some(channels),
+                codec: Some(codec.to_string()),
+                lossless: Some(lossless),
+            };
+        db.insert_tracks_batch(&[
+            track(
+                "/hires.flac",
+                properties("FLAC", true, 2900, 96000, Some(24), 2),
+            ),
+            track(
+                "/cd.flac",
+                properties("FLAC", true, 900, 44100, Some(16), 2),
+            ),
+            track("/mono.mp3", properties("MP3", false, 128, 44100, None, 1)),
+            track("/good.mp3", properties("MP3", false, 320, 44100, None, 2)),
+            track("/unknown.wma", AudioProperties::default()),
+        ])?;
+
+        let paths = |query: &str| -> Vec<String> {
+            let mut paths: Vec<String> = search(&db, query)
+                .unwrap()
+                .into_iter()
+                .map(|t| t.file_path)
+                .collect();
+            paths.sort();
+            paths
+        };
+        assert_eq!(paths("hires:yes"), vec!["/hires.flac"]);
+        assert_eq!(paths("channels:1"), vec!["/mono.mp3"]);
+        assert_eq!(paths("bitrate:<192"), vec!["/mono.mp3"]);
+        assert_eq!(paths("codec:mp3 -channels:1"), vec!["/good.mp3"]);
+        assert_eq!(paths("lossless:yes samplerate:44.1k"), vec!["/cd.flac"]);
+        // Unknown properties match the negation of a condition
+        assert_eq!(
+            paths("-lossless:yes"),
+            vec!["/good.mp3", "/mono.mp3", "/unknown.wma"]
+        );
+
+        let filtered = db.get_tracks(Some(&TrackFilters {
+            lossless: Some(false),
+            max_bitrate: Some(192),
+            ..Default::default()
+        }))?;
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered[0].audio.channels, Some(1));
+
+        let stats = db.quality_stats(192)?;
+        assert_eq!(
+            (stats.tracks, stats.lossless, stats.lossy, stats.hi_res),
+            (5, 2, 2, 1)
+        );
+        assert_eq!((stats.unknown, stats.mono, stats.low_bitrate), (1, 1, 1));
+        assert_eq!(stats.average_lossy_bitrate, Some(224.0));
+        assert_eq!(stats.codecs[0].tracks, 2);
+        assert_eq!(
+            stats.sample_rates,
+            vec![
+                ValueCount {
+                    value: 44100,
+                    tracks: 3
+                },
+                ValueCount {
+                    value: 96000,
+                    tracks: 1
+                }
+            ]
+        );
+
+        // Tracks scanned before properties were stored get them without a rescan
+        let pending = db.get_tracks_without_audio_properties()?;
+        assert_eq!(pending.len(), 1);
+        db.update_audio_properties(&[(
+            pending[0].0,
+            Some(properties("WMA", false, 192, 44100, None, 2)),
+        )])?;
+        assert!(db.get_tracks_without_audio_properties()?.is_empty());
+        assert_eq!(db.quality_stats(192)?.lossy, 3);
+
+        // A file whose headers can't be read is only tried once
+        db.insert_track(&Track {
+            file_path: "/broken.mp3".to_string(),
+            ..Default::default()
+        })?;
+        let pending = db.get_tracks_without_audio_properties()?;
+        assert_eq!(pending.len(), 1);
+        db.update_audio_properties(&[(pending[0].0, None)])?;
+        assert!(db.get_tracks_without_audio_properties()?.is_empty());
+        assert_eq!(db.quality_stats(192)?.unknown, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_tracks_are_flagged_and_purged() -> SqlResult<()> {
+        let mut db = create_test_db().unwrap();
+        let track = |path: &str| Track {
+            file_path: path.to_string(),
+            title: Some(path.to_string()),
+            ..Default::default()
+        };
+        let gone = db.insert_track(&track("/music/gone.flac"))?;
+        let back = db.insert_track(&track("/music/back.flac"))?;
+        db.insert_track(&track("/music/kept.flac"))?;
+
+        db.set_tracks_missing(&[gone, back], &[])?;
+        let stored = db.get_stored_tracks()?;
+        assert_eq!(stored.len(), 3);
+        let since = stored.iter().find(|t| t.id == gone).unwrap().missing_since;
+        assert!(since.is_some());
+
+        // Flagging again keeps the time it was first found missing
+        db.conn.execute(
+            "UPDATE tracks SET missing_since = 1 WHERE id = ?1",
+            params![gone],
+        )?;
+        db.set_tracks_missing(&[gone], &[])?;
+        assert_eq!(db.get_track_by_id(gone)?.missing_since, Some(1));
+
+        let missing = db.get_tracks(Some(&TrackFilters {
+            missing: Some(true),
+            ..Default::default()
+        }))?;
+        assert_eq!(missing.len(), 2);
+        assert_eq!(db.get_missing_tracks()?[0].id, gone);
+
+        // Found by the reconciliation pass, or rescanned
+        db.set_tracks_missing(&[], &[back])?;
+        assert_eq!(db.get_track_by_id(back)?.missing_since, None);
+        db.set_tracks_missing(&[back], &[])?;
+        db.insert_tracks_batch(&[track("/music/back.flac")])?;
+        assert_eq!(db.get_track_by_id(back)?.missing_since, None);
+
+        assert_eq!(db.purge_missing_tracks()?, 1);
+        assert!(db.get_track_by_id(gone).is_err());
+        assert_eq!(db.get_tracks(None)?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rescanned_cue_sheet_replaces_virtual_tracks() -> SqlResult<()> {
+        let mut db = create_test_db().unwrap();
+        let file_track = Track {
+            file_path: "/music/disc.flac".to_string(),
+            duration: Some(900),
+            file_format: "flac".to_string(),
+            ..Default::default()
+        };
+        let sheet = |entries: usize| {
+            let mut text = "TITLE \"Disc\"\nFILE \"disc.flac\" WAVE\n".to_string();
+            for n in 1..=entries {
+                text.push_str(&format!(
+                    "TRACK {:02} AUDIO\nTITLE \"Part {}\"\nINDEX 01 {:02}:00:00\n",
+                    n,
+                    n,
+                    (n - 1) * 5
+                ));
+            }
+            cue::parse_cue(&text)
+        };
+        let scan = |db: &mut DatabaseInner, entries: usize| -> SqlResult<Vec<(i64, String)>> {
+            let sheet = sheet(entries);
+            let tracks = match sheet.segments(Some("disc.flac")) {
+                segments if segments.is_empty() => vec![file_track.clone()],
+                segments => cue::virtual_tracks(&file_track, &sheet, &segments),
+            };
+            db.insert_tracks_batch(&tracks)?;
+            let mut rows: Vec<(i64, String)> = db
+                .get_tracks(None)?
+                .into_iter()
+                .map(|t| (t.id, t.file_path))
+                .collect();
+            rows.sort_by(|a, b| a.1.cmp(&b.1));
+            Ok(rows)
+        };
+
+        // The file was scanned before its sheet was added
+        assert_eq!(scan(&mut db, 0)?.len(), 1);
+
+        // The sheet expands it into one track per entry, replacing the file's row
+        let first = scan(&mut db, 3)?;
+        let keys: Vec<&str> = first.iter().map(|(_, path)| path.as_str()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                "/music/disc.flac#01",
+                "/music/disc.flac#02",
+                "/music/disc.flac#03"
+            ]
+        );
+
+        // Rescanning updates the same rows rather than adding more
+        assert_eq!(scan(&mut db, 3)?, first);
+
+        // An entry removed from the sheet goes, the others keep their ids
+        assert_eq!(scan(&mut db, 2)?, first[..2].to_vec());
+
+        // Without the sheet the file is a single track again
+        let rows = scan(&mut db, 0)?;
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].1, "/music/disc.flac");
+        Ok(())
+    }
+
+    #[test]
+    fn test_relocated_files_keep_track_ids() -> SqlResult<()> {
+        let mut db = create_test_db().unwrap();
+        let id = db.insert_track(&Track {
+            file_path: "/old/song.flac".to_string(),
+            title: Some("Song".to_string()),
+            ..Default::default()
+        })?;
+        let virtual_track = |number: i64| Track {
+            file_path: cue::virtual_track_key("/old/disc.flac", number),
+            source_path: Some("/old/disc.flac".to_string()),
+            title: Some(format!("Part {}", number)),
+            ..Default::default()
+        };
+        db.insert_tracks_batch(&[virtual_track(1), virtual_track(2)])?;
+        let part = db.find_tracks_by_path(&cue::virtual_track_key("/old/disc.flac", 2))?[0].id;
+        let playlist = db.create_playlist("Mix")?;
+        db.add_track_to_playlist(playlist, id, None)?;
+        db.record_play_history(id, 100)?;
+
+        assert_eq!(db.get_paths_without_content_hash()?.len(), 2);
+        db.update_content_hashes(&[("/old/disc.flac".to_string(), "abc".to_string())])?;
+        assert_eq!(
+            db.get_content_hashes()?,
+            vec![("/old/disc.flac".to_string(), "abc".to_string())]
+        );
+
+        db.set_tracks_missing(&[id], &[])?;
+        assert_eq!(
+            db.relocate_track_file("/old/song.flac", "/new/Song.flac")?,
+            1
+        );
+        let moved = db.get_track_by_id(id)?;
+        assert_eq!(moved.file_path, "/new/Song.flac");
+        assert_eq!(moved.missing_since, None);
+        assert_eq!(db.get_play_history(10)?[0].track_id, id);
+        assert_eq!(db.get_playlist_tracks(playlist)?[0].id, id);
+
+        assert_eq!(
+            db.relocate_track_file("/old/disc.flac", "/new/disc.flac")?,
+            2
+        );
+        let moved = db.get_track_by_id(part)?;
+        assert_eq!(moved.source_path.as_deref(), Some("/new/disc.flac"));
+        assert_eq!(moved.file_path, cue::virtual_track_key("/new/disc.flac", 2));
+        assert_eq!(moved.content_hash.as_deref(), Some("abc"));
+
+        let ids = db.get_track_ids_for_files(&[
+            "/new/disc.flac".to_string(),
+            "/old/song.flac".to_string(),
+        ])?;
+        assert_eq!(ids.len(), 2);
+        assert!(ids.contains(&part) && !ids.contains(&id));
+        Ok(())
+    }
+
+    #[test]
+    fn test_relocate_library() -> SqlResult<()> {
+        let mut db = create_test_db().unwrap();
+        let track = |path: &str| Track {
+            file_path: path.to_string(),
+            title: Some(path.to_string()),
+            ..Default::default()
+        };
+        let id = db.insert_track(&Track {
+            album: Some("Album".to_string()),
+            ..track("/mnt/old_nas/music/a.flac")
+        })?;
+        db.insert_track(&track("/mnt/old_nas/music/b.flac"))?;
+        db.insert_track(&track("/mnt/old_nas/musical/c.flac"))?;
+        db.add_scan_folder("/mnt/old_nas/music")?;
+        let album_id = db.get_track_by_id(id)?.album_id;
+        db.conn.execute(
+            "UPDATE albums SET cover_art_path = '/mnt/old_nas/music/cover.jpg' WHERE id = ?1",
+            params![album_id],
+        )?;
+
+        let on_disk = |missing: &'static str| move |path: &str| path != missing;
+        let report = db.relocate_library(
+            "/mnt/old_nas/music",
+            "/media/music",
+            false,
+            on_disk("/media/music/b.flac"),
+        )?;
+        assert!(!report.applied);
+        assert_eq!(report.missing, vec!["/media/music/b.flac"]);
+        assert_eq!(
+            db.get_track_by_id(id)?.file_path,
+            "/mnt/old_nas/music/a.flac"
+        );
+
+        // b.flac is flagged missing, so it no longer blocks the move
+        let b = db.find_tracks_by_path("/mnt/old_nas/music/b.flac")?[0].id;
+        db.set_tracks_missing(&[b], &[])?;
+        let report = db.relocate_library(
+            "/mnt/old_nas/music/",
+            "/media/music",
+            true,
+            on_disk("/media/music/b.flac"),
+        )?;
+        assert_eq!(
+            (report.tracks, report.scan_folders, report.covers),
+            (2, 1, 1)
+        );
+        assert!(!report.applied);
+        assert_eq!(
+            db.get_track_by_id(id)?.file_path,
+            "/mnt/old_nas/music/a.flac"
+        );
+
+        let report = db.relocate_library("/mnt/old_nas/music", "/media/music", false, |_| true)?;
+        assert!(report.applied);
+        assert_eq!(db.get_track_by_id(id)?.file_path, "/media/music/a.flac");
+        assert_eq!(db.get_scan_folders()?[0].path, "/media/music");
+        let cover: String = db.conn.query_row(
+            "SELECT cover_art_path FROM albums WHERE id = ?1",
+            params![album_id],
+            |row| row.get(0),
+        )?;
+        assert_eq!(cover, "/media/music/cover.jpg");
+        assert_eq!(
+            db.find_tracks_by_path("/mnt/old_nas/musical/c.flac")?.len(),
+            1
+        );
+
+        // Moving onto paths already in the library is refused
+        db.insert_track(&track("/mnt/old_nas/music/a.flac"))?;
+        let report = db.relocate_library("/mnt/old_nas/music", "/media/music", false, |_| true)?;
+        assert!(!report.applied);
+        assert_eq!(report.conflicts, vec!["/media/music/a.flac"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_relocated_tracks_found_again_are_no_longer_missing() -> SqlResult<()> {
+        let mut db = create_test_db().unwrap();
+        let mut ids = Vec::new();
+        for name in ["back.flac", "gone.flac"] {
+            ids.push(db.insert_track(&Track {
+                file_path: format!("/mnt/old/{}", name),
+                ..Default::default()
+            })?);
+        }
+        db.set_tracks_missing(&ids, &[])?;
+
+        let report = db.relocate_library("/mnt/old", "/media/music", false, |path| {
+            path == "/media/music/back.flac"
+        })?;
+        assert!(report.applied);
+        let back = db.get_track_by_id(ids[0])?;
+        assert_eq!(back.file_path, "/media/music/back.flac");
+        assert_eq!(back.missing_since, None);
+        // Still gone, so it stays flagged
+        let gone = db.get_track_by_id(ids[1])?;
+        assert_eq!(gone.file_path, "/media/music/gone.flac");
+        assert!(gone.missing_since.is_some());
+        Ok(())
+    }
 }
@@ -1,14 +1,27 @@
 mod audio;
+mod audio_output;
 mod background_scan;
+mod chapters;
 mod commands;
+mod content_hash;
+mod credits;
+mod cue;
 pub mod database;
 mod equalizer;
 pub mod error;
 mod media_controls;
 mod metadata;
+mod migrations;
 pub mod models;
 pub mod playlist_io;
+mod ratings;
+mod reconcile;
+mod scan_pool;
 mod scanner;
+pub mod search_query;
+mod sorting;
+mod stats;
+mod watcher;
 
 #[cfg(target_os = "macos")]
 #[allow(unused_imports)]
@@ -19,13 +32,14 @@ mod media_controls_mpris;
 mod media_controls_windows;
 
 use audio::AudioController;
+use audio_output::AudioOutput;
 use commands::*;
-use database::{Database, DatabaseInner};
+use database::{Database, DatabasePool};
 use media_controls::{MediaControlEvent, MediaControlsManager, PlaybackState};
 use std::sync::atomic::AtomicBool;
 use std::sync::{Arc, Mutex, RwLock};
 use std::time::Instant;
-use tauri::Emitter;
+use tauri::{Emitter, Manager};
 use tokio::sync::mpsc;
 use tracing::{error, info, warn};
 
@@ -38,6 +52,8 @@ pub struct AppState {
     pub media_control_event_sender:
         Option<mpsc::UnboundedSender<media_controls::MediaControlEvent>>,
     pub http_client: reqwest::Client,
+    /// The queue and modes last saved by the frontend, saved again at exit
+    pub session_context: Mutex<Option<models::SessionContext>>,
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -54,8 +70,8 @@ pub fn run() {
     info!("OSMP starting up");
 
     // Initialize database once at startup
-    let db = match DatabaseInner::new() {
-        Ok(db) => Arc::new(Mutex::new(db)),
+    let db: Database = match DatabasePool::open() {
+        Ok(pool) => Arc::new(pool),
         Err(e) => {
             error!("Failed to initialize database: {}", e);
             eprintln!("FATAL: Failed to initialize database: {}", e);
@@ -65,7 +81,7 @@ pub fn run() {
 
     // Load EQ settings from database, or use defaults
     let eq_settings = {
-        let db_lock = match db.lock() {
+        let db_lock = match db.read() {
             Ok(lock) => lock,
             Err(e) => {
                 error!("Failed to lock database during initialization: {}", e);
@@ -80,8 +96,8 @@ pub fn run() {
     };
     let eq_settings = Arc::new(RwLock::new(eq_settings));
 
-    // Initialize audio controller once at startup
-    let audio = match AudioController::new(Arc::clone(&eq_settings)) {
+    // Initialize audio controller once at startup (output backend from OSMP_AUDIO_OUTPUT)
+    let audio = match AudioController::new(Arc::clone(&eq_settings), AudioOutput::from_env()) {
         Ok(controller) => Arc::new(controller),
         Err(e) => {
             error!("Failed to initialize audio: {}", e);
@@ -90,6 +106,9 @@ pub fn run() {
         }
     };
 
+    // Pick up where the last session ended, paused
+    restore_playback_session(&db, &audio);
+
     // Initialize shared HTTP client (reuses connections)
     let http_client = match reqwest::Client::builder()
         .timeout(std::time::Duration::from_secs(15))
@@ -125,6 +144,8 @@ pub fn run() {
     // Clone references for the event handlers
     let audio_for_events = Arc::clone(&audio);
     let audio_for_position = Arc::clone(&audio);
+    let db_for_position = Arc::clone(&db);
+    let db_for_events = Arc::clone(&db);
     let media_controls_for_events = media_controls.clone();
 
     // Clone references for background scanning
@@ -138,13 +159,16 @@ pub fn run() {
         .plugin(tauri_plugin_updater::Builder::new().build())
         .plugin(tauri_plugin_process::init())
         .setup(move |app| {
-            // Push-based position updates: emit position every ~1s when playing
+            // Push-based position updates: emit position every ~1s when playing,
+            // and save where tracks that ended on their own got to
             {
                 let app_handle = app.handle().clone();
                 let audio = audio_for_position;
+                let db = db_for_position;
                 tauri::async_runtime::spawn(async move {
                     loop {
                         tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                        persist_finished_tracks(&db, &audio);
                         if audio.state.is_playing() {
                             let position = audio.get_position();
                             let _ = app_handle.emit("position-update", position);
@@ -157,6 +181,7 @@ pub fn run() {
             if let Some(mut receiver) = media_control_receiver {
                 let app_handle = app.handle().clone();
                 let audio = audio_for_events;
+                let db = db_for_events;
                 let mc = media_controls_for_events;
                 tauri::async_runtime::spawn(async move {
                     let mut last_toggle = Instant::now() - std::time::Duration::from_secs(1);
@@ -187,6 +212,9 @@ pub fn run() {
 
                                 if should_toggle {
                                     last_toggle = now;
+                                    if was_playing {
+                                        persist_playback_state(&db, &audio);
+                                    }
                                     audio.pause();
                                     let new_is_playing = !was_playing;
 
@@ -211,11 +239,27 @@ pub fn run() {
                                     );
                                 }
                             }
-                            MediaControlEvent::Next => {
-                                let _ = app_handle.emit("media-control-next", ());
-                            }
-                            MediaControlEvent::Previous => {
-                                let _ = app_handle.emit("media-control-previous", ());
+                            MediaControlEvent::Next | MediaControlEvent::Previous => {
+                                let forward = matches!(event, MediaControlEvent::Next);
+
+                                // Audiobooks: move between chapters, then on to the next track
+                                match skip_chapter(&db, &audio, forward) {
+                                    Ok(Some(chapter)) => {
+                                        if let Some(ref mc) = mc {
+                                            let _ = mc
+                                                .update_position(chapter.start_ms as f64 / 1000.0);
+                                        }
+                                        let _ = app_handle.emit("chapter-changed", &chapter);
+                                    }
+                                    Ok(None) | Err(_) => {
+                                        let event_name = if forward {
+                                            "media-control-next"
+                                        } else {
+                                            "media-control-previous"
+                                        };
+                                        let _ = app_handle.emit(event_name, ());
+                                    }
+                                }
                             }
                             MediaControlEvent::Seek(pos) => {
                                 let _ = app_handle.emit("media-control-seek", pos);
@@ -246,18 +290,34 @@ pub fn run() {
             media_controls,
             media_control_event_sender,
             http_client,
+            session_context: Mutex::new(None),
         })
         .invoke_handler(tauri::generate_handler![
             get_scan_folders,
             add_scan_folder,
             remove_scan_folder,
             scan_folders,
+            get_missing_tracks,
+            purge_missing_tracks,
+            get_offline_folders,
+            relocate_library,
             cancel_scan,
             get_tracks,
             search_tracks,
+            get_track_page,
             fetch_metadata,
             fetch_covers,
             play_track,
+            get_playback_session,
+            save_playback_session,
+            get_resume_position,
+            clear_resume_position,
+            mark_track_finished,
+            get_resume_settings,
+            set_resume_settings,
+            get_track_chapters,
+            next_chapter,
+            previous_chapter,
             pause_playback,
             stop_playback,
             set_volume,
@@ -267,11 +327,31 @@ pub fn run() {
             delete_tracks,
             get_track_cover,
             get_albums,
+            get_compilation_albums,
             get_album_tracks,
+            get_album_tracks_by_id,
+            get_artists,
+            get_genres,
+            get_track_credits,
+            get_tag_separators,
+            set_tag_separators,
+            get_sort_articles,
+            set_sort_articles,
             get_album_cover,
             play_album,
             update_track_metadata_manual,
             write_metadata_to_file,
+            update_extended_tags,
+            set_track_rating,
+            set_track_favorite,
+            set_album_rating,
+            set_album_favorite,
+            set_artist_rating,
+            set_artist_favorite,
+            get_album_ratings,
+            get_artist_ratings,
+            get_rating_tags_enabled,
+            set_rating_tags_enabled,
             create_playlist,
             delete_playlist,
             rename_playlist,
@@ -280,9 +360,14 @@ pub fn run() {
             get_playlist_tracks,
             add_track_to_playlist,
             add_tracks_to_playlist,
+            add_album_to_playlist,
             remove_track_from_playlist,
             reorder_playlist_tracks,
             duplicate_playlist,
+            create_smart_playlist,
+            update_smart_playlist,
+            get_smart_playlist_rules,
+            preview_smart_playlist,
             play_playlist,
             get_eq_settings,
             set_eq_band,
@@ -296,16 +381,34 @@ pub fn run() {
             preload_next_track,
             record_play_history,
             get_play_history,
+            get_top_tracks,
+            get_top_artists,
+            get_top_albums,
+            get_top_genres,
+            get_listening_time,
+            get_listening_clock,
+            get_listening_streaks,
+            get_new_discoveries,
+            get_year_report,
+            export_year_report,
+            get_quality_stats,
             get_duplicates,
             export_playlist_m3u,
             import_playlist_m3u,
             get_scan_settings,
             set_scan_settings
         ])
-        .run(tauri::generate_context!())
+        .build(tauri::generate_context!())
         .unwrap_or_else(|e| {
             error!("Fatal: Tauri application failed to start: {}", e);
             eprintln!("Fatal: Failed to start application: {}", e);
             std::process::exit(1);
+        })
+        .run(|app_handle, event| {
+            if let tauri::RunEvent::Exit = event {
+                // Save the listening context (and long-form resume position) for next launch
+                let state = app_handle.state::<AppState>();
+                persist_playback_session(&state.db, &state.audio, &state.session_context);
+            }
         });
 }
@@ -57,7 +57,7 @@ impl MetadataFetcher {
         let (existing_artist, existing_title, file_path) = {
             let db = self
                 .db
-                .lock()
+                .read()
                 .map_err(|e| anyhow::anyhow!("Database lock poisoned: {}", e))?;
             let track = db.get_track_by_id(track_id).context("Track not found")?;
 
@@ -355,7 +355,7 @@ impl MetadataFetcher {
                 if fetched_artist.is_some() || fetched_title.is_some() {
                     let mut db = self
                         .db
-                        .lock()
+                        .write()
                         .map_err(|e| anyhow::anyhow!("Database lock poisoned: {}", e))?;
                     db.update_track_metadata_smart(
                         track_id,
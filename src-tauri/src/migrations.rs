@@ -0,0 +1,1134 @@
+use crate::credits;
+use crate::sorting;
+use anyhow::{bail, Context, Result};
+use rusqlite::{params, Connection, Result as SqlResult, Transaction};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// A schema change. Migrations run in order, each in its own transaction, and the
+/// database's `PRAGMA user_version` records the last one applied.
+///
+/// Never edit a migration that has shipped; add a new one instead. Migrations that
+/// rebuild a table must not rely on `PRAGMA foreign_keys`, which cannot be changed
+/// inside a transaction.
+pub struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    pub up: fn(&Transaction<'_>) -> SqlResult<()>,
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Initial schema",
+        up: initial_schema,
+    },
+    Migration {
+        version: 2,
+        description: "CUE sheet virtual tracks",
+        up: cue_virtual_tracks,
+    },
+    Migration {
+        version: 3,
+        description: "Chapters",
+        up: chapters,
+    },
+    Migration {
+        version: 4,
+        description: "Resume positions",
+        up: resume_positions,
+    },
+    Migration {
+        version: 5,
+        description: "Playback session",
+        up: playback_session,
+    },
+    Migration {
+        version: 6,
+        description: "Full-text search index",
+        up: track_search_index,
+    },
+    Migration {
+        version: 7,
+        description: "Smart playlists",
+        up: smart_playlists,
+    },
+    Migration {
+        version: 8,
+        description: "Ratings and favorites",
+        up: ratings_and_favorites,
+    },
+    Migration {
+        version: 9,
+        description: "Play and skip counts",
+        up: play_statistics,
+    },
+    Migration {
+        version: 10,
+        description: "Artist and album ids",
+        up: artist_album_ids,
+    },
+    Migration {
+        version: 11,
+        description: "Album artists, discs and compilations",
+        up: album_artists,
+    },
+    Migration {
+        version: 12,
+        description: "Multi-value artists and genres",
+        up: artist_and_genre_credits,
+    },
+    Migration {
+        version: 13,
+        description: "Extended tags",
+        up: extended_tags,
+    },
+    Migration {
+        version: 14,
+        description: "Sort names",
+        up: sort_names,
+    },
+    Migration {
+        version: 15,
+        description: "Audio properties",
+        up: audio_properties,
+    },
+    Migration {
+        version: 16,
+        description: "Missing files",
+        up: missing_files,
+    },
+    Migration {
+        version: 17,
+        description: "Content hashes",
+        up: content_hashes,
+    },
+    Migration {
+        version: 18,
+        description: "Re-read tags",
+        up: pending_tags,
+    },
+    Migration {
+        version: 19,
+        description: "Audio property reads",
+        up: audio_properties_checked,
+    },
+];
+
+pub fn schema_version(conn: &Connection) -> SqlResult<u32> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+}
+
+/// Apply all pending migrations and return the resulting schema version.
+///
+/// When `backup_dir` is given and an existing database is about to change, a copy
+/// is written there first. A database from a newer build is refused rather than
+/// opened with a schema this build does not understand.
+pub fn migrate(conn: &mut Connection, backup_dir: Option<&Path>) -> Result<u32> {
+    apply(conn, MIGRATIONS, backup_dir)
+}
+
+fn apply(
+    conn: &mut Connection,
+    migrations: &[Migration],
+    backup_dir: Option<&Path>,
+) -> Result<u32> {
+    let current = schema_version(conn)?;
+    let latest = migrations.last().map_or(0, |m| m.version);
+
+    if current > latest {
+        let restore = match backup_dir {
+            Some(dir) => match newest_backup(dir, latest) {
+                Some(path) => format!("restore the pre-migration backup {}", path.display()),
+                None => format!("restore a pre-migration backup from {}", dir.display()),
+            },
+            None => "restore a pre-migration backup".to_string(),
+        };
+        bail!(
+            "Database schema version {} is newer than this build supports ({}). \
+             Update OSMP, or quit it and {} in place of the database.",
+            current,
+            latest,
+            restore
+        );
+    }
+    if current == latest {
+        return Ok(current);
+    }
+
+    if let Some(dir) = backup_dir {
+        if has_tables(conn)? {
+            let path = backup(conn, dir, current)?;
+            info!("Backed up database to {}", path.display());
+        }
+    }
+
+    for migration in migrations.iter().filter(|m| m.version > current) {
+        info!(
+            "Applying database migration {}: {}",
+            migration.version, migration.description
+        );
+        let tx = conn.transaction()?;
+        (migration.up)(&tx).with_context(|| {
+            format!(
+                "Migration {} ({}) failed",
+                migration.version, migration.description
+            )
+        })?;
+        tx.pragma_update(None, "user_version", migration.version)?;
+        tx.commit()?;
+    }
+
+    Ok(latest)
+}
+
+fn has_tables(conn: &Connection) -> SqlResult<bool> {
+    conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%')",
+        [],
+        |row| row.get(0),
+    )
+}
+
+/// Write a consistent copy of the database (WAL included) to `dir`
+fn backup(conn: &Connection, dir: &Path, version: u32) -> Result<PathBuf> {
+    std::fs::create_dir_all(dir).context("Failed to create backup directory")?;
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let path = dir.join(format!("osmp-v{}-{}.db.bak", version, timestamp));
+    conn.execute("VACUUM INTO ?1", params![path.to_string_lossy()])
+        .context("Failed to back up database before migrating")?;
+    Ok(path)
+}
+
+/// The most recent backup in `dir` (see `backup`) of a schema no newer than
+/// `version`, which this build can open
+fn newest_backup(dir: &Path, version: u32) -> Option<PathBuf> {
+    std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let name = path.file_name()?.to_str()?;
+            let (backup_version, timestamp) = name
+                .strip_prefix("osmp-v")?
+                .strip_suffix(".db.bak")?
+                .split_once('-')?;
+            let key = (
+                backup_version.parse::<u32>().ok()?,
+                timestamp.parse::<u64>().ok()?,
+            );
+            (key.0 <= version).then_some((key, path))
+        })
+        .max_by_key(|(key, _)| *key)
+        .map(|(_, path)| path)
+}
+
+/// Add a column unless it already exists. Databases created before versioned
+/// migrations may already have some of the columns a migration adds.
+fn add_column_if_missing(
+    tx: &Transaction<'_>,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> SqlResult<()> {
+    let exists: bool = tx.query_row(
+        "SELECT EXISTS (SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2)",
+        params![table, column],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        tx.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+// Migrations
+
+/// The schema as it stood before versioning. `IF NOT EXISTS` lets unversioned
+/// databases adopt it unchanged.
+fn initial_schema(tx: &Transaction<'_>) -> SqlResult<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS tracks (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            file_path TEXT UNIQUE NOT NULL,
+            title TEXT,
+            artist TEXT,
+            album TEXT,
+            duration INTEGER,
+            year INTEGER,
+            genre TEXT,
+            track_number INTEGER,
+            file_size INTEGER,
+            file_format TEXT,
+            last_modified INTEGER,
+            metadata_fetched BOOLEAN DEFAULT 0,
+            created_at INTEGER DEFAULT (strftime('%s', 'now'))
+        );
+
+        CREATE TABLE IF NOT EXISTS albums (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            artist TEXT,
+            year INTEGER,
+            cover_art_path TEXT
+        );
+
+        CREATE TABLE IF NOT EXISTS artists (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT UNIQUE NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS playlists (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            created_at INTEGER DEFAULT (strftime('%s', 'now'))
+        );
+
+        CREATE TABLE IF NOT EXISTS playlist_tracks (
+            playlist_id INTEGER,
+            track_id INTEGER,
+            position INTEGER,
+            FOREIGN KEY (playlist_id) REFERENCES playlists(id),
+            FOREIGN KEY (track_id) REFERENCES tracks(id)
+        );
+
+        CREATE TABLE IF NOT EXISTS scan_folders (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            path TEXT UNIQUE NOT NULL,
+            enabled BOOLEAN DEFAULT 1
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_tracks_artist ON tracks(artist);
+        CREATE INDEX IF NOT EXISTS idx_tracks_album ON tracks(album);
+        CREATE INDEX IF NOT EXISTS idx_tracks_genre ON tracks(genre);
+        CREATE INDEX IF NOT EXISTS idx_playlist_tracks_playlist_id ON playlist_tracks(playlist_id);
+        CREATE INDEX IF NOT EXISTS idx_playlist_tracks_position ON playlist_tracks(playlist_id, position);
+
+        CREATE TABLE IF NOT EXISTS eq_settings (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            enabled BOOLEAN DEFAULT 1,
+            preamp_db REAL DEFAULT 0.0,
+            band1_gain REAL DEFAULT 0.0,
+            band2_gain REAL DEFAULT 0.0,
+            band3_gain REAL DEFAULT 0.0,
+            band4_gain REAL DEFAULT 0.0,
+            band5_gain REAL DEFAULT 0.0,
+            preset_name TEXT DEFAULT 'Flat'
+        );
+
+        CREATE TABLE IF NOT EXISTS play_history (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            track_id INTEGER NOT NULL,
+            played_at INTEGER DEFAULT (strftime('%s', 'now')),
+            duration_listened INTEGER DEFAULT 0,
+            FOREIGN KEY (track_id) REFERENCES tracks(id) ON DELETE CASCADE
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_play_history_track_id ON play_history(track_id);
+        CREATE INDEX IF NOT EXISTS idx_play_history_played_at ON play_history(played_at DESC);
+
+        CREATE TABLE IF NOT EXISTS app_settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        );",
+    )?;
+
+    // Added to the unversioned schema after its first release
+    add_column_if_missing(tx, "tracks", "release_mbid", "TEXT")
+}
+
+fn cue_virtual_tracks(tx: &Transaction<'_>) -> SqlResult<()> {
+    add_column_if_missing(tx, "tracks", "source_path", "TEXT")?;
+    add_column_if_missing(tx, "tracks", "start_offset_ms", "INTEGER")?;
+    add_column_if_missing(tx, "tracks", "end_offset_ms", "INTEGER")?;
+    tx.execute(
+        "CREATE INDEX IF NOT EXISTS idx_tracks_source_path ON tracks(source_path)",
+        [],
+    )?;
+    Ok(())
+}
+
+fn chapters(tx: &Transaction<'_>) -> SqlResult<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS chapters (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            track_id INTEGER NOT NULL,
+            chapter_index INTEGER NOT NULL,
+            title TEXT,
+            start_ms INTEGER NOT NULL,
+            end_ms INTEGER,
+            UNIQUE(track_id, chapter_index),
+            FOREIGN KEY (track_id) REFERENCES tracks(id) ON DELETE CASCADE
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn resume_positions(tx: &Transaction<'_>) -> SqlResult<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS resume_positions (
+            track_id INTEGER PRIMARY KEY,
+            position_ms INTEGER NOT NULL DEFAULT 0,
+            finished BOOLEAN DEFAULT 0,
+            updated_at INTEGER DEFAULT (strftime('%s', 'now')),
+            FOREIGN KEY (track_id) REFERENCES tracks(id) ON DELETE CASCADE
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn playback_session(tx: &Transaction<'_>) -> SqlResult<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS playback_session (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            track_id INTEGER,
+            position_ms INTEGER DEFAULT 0,
+            volume REAL DEFAULT 1.0,
+            speed REAL DEFAULT 1.0,
+            shuffle BOOLEAN DEFAULT 0,
+            repeat_mode TEXT DEFAULT 'off',
+            active_playlist_id INTEGER,
+            FOREIGN KEY (track_id) REFERENCES tracks(id) ON DELETE SET NULL,
+            FOREIGN KEY (active_playlist_id) REFERENCES playlists(id) ON DELETE SET NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS playback_session_queue (
+            position INTEGER NOT NULL,
+            track_id INTEGER NOT NULL,
+            FOREIGN KEY (track_id) REFERENCES tracks(id) ON DELETE CASCADE
+        );",
+    )
+}
+
+/// External-content FTS5 index over `tracks`, kept in sync by triggers.
+/// `remove_diacritics 2` folds accents so "bjork" matches "Björk".
+fn track_search_index(tx: &Transaction<'_>) -> SqlResult<()> {
+    tx.execute_batch(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS tracks_fts USING fts5(
+            title, artist, album,
+            content='tracks',
+            content_rowid='id',
+            tokenize='unicode61 remove_diacritics 2'
+        );
+
+        CREATE TRIGGER IF NOT EXISTS tracks_fts_insert AFTER INSERT ON tracks BEGIN
+            INSERT INTO tracks_fts(rowid, title, artist, album)
+            VALUES (new.id, new.title, new.artist, new.album);
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS tracks_fts_delete AFTER DELETE ON tracks BEGIN
+            INSERT INTO tracks_fts(tracks_fts, rowid, title, artist, album)
+            VALUES ('delete', old.id, old.title, old.artist, old.album);
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS tracks_fts_update
+        AFTER UPDATE OF title, artist, album ON tracks BEGIN
+            INSERT INTO tracks_fts(tracks_fts, rowid, title, artist, album)
+            VALUES ('delete', old.id, old.title, old.artist, old.album);
+            INSERT INTO tracks_fts(rowid, title, artist, album)
+            VALUES (new.id, new.title, new.artist, new.album);
+        END;
+
+        INSERT INTO tracks_fts(tracks_fts) VALUES ('rebuild');",
+    )
+}
+
+fn smart_playlists(tx: &Transaction<'_>) -> SqlResult<()> {
+    tx.execute_batch(
+        "ALTER TABLE playlists ADD COLUMN kind TEXT NOT NULL DEFAULT 'manual';
+
+        CREATE TABLE IF NOT EXISTS smart_playlists (
+            playlist_id INTEGER PRIMARY KEY,
+            rules TEXT NOT NULL,
+            FOREIGN KEY (playlist_id) REFERENCES playlists(id) ON DELETE CASCADE
+        );",
+    )
+}
+
+/// Ratings are half stars (0–10). Albums and artists are rated by name until
+/// the `albums` and `artists` tables are populated by the scanner.
+fn ratings_and_favorites(tx: &Transaction<'_>) -> SqlResult<()> {
+    tx.execute_batch(
+        "ALTER TABLE tracks ADD COLUMN rating INTEGER;
+        ALTER TABLE tracks ADD COLUMN favorite BOOLEAN NOT NULL DEFAULT 0;
+        ALTER TABLE albums ADD COLUMN rating INTEGER;
+        ALTER TABLE albums ADD COLUMN favorite BOOLEAN NOT NULL DEFAULT 0;
+        ALTER TABLE artists ADD COLUMN rating INTEGER;
+        ALTER TABLE artists ADD COLUMN favorite BOOLEAN NOT NULL DEFAULT 0;
+
+        CREATE INDEX IF NOT EXISTS idx_tracks_rating ON tracks(rating);
+        CREATE INDEX IF NOT EXISTS idx_tracks_favorite ON tracks(favorite) WHERE favorite = 1;
+        CREATE INDEX IF NOT EXISTS idx_albums_name_artist ON albums(name, artist);",
+    )
+}
+
+/// Per-track play aggregates. History recorded before this version only holds
+/// listens that passed the old 30-second threshold, so it is all counted as plays.
+fn play_statistics(tx: &Transaction<'_>) -> SqlResult<()> {
+    tx.execute_batch(
+        "ALTER TABLE play_history ADD COLUMN skipped BOOLEAN NOT NULL DEFAULT 0;
+        ALTER TABLE tracks ADD COLUMN play_count INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE tracks ADD COLUMN skip_count INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE tracks ADD COLUMN first_played_at INTEGER;
+        ALTER TABLE tracks ADD COLUMN last_played_at INTEGER;
+
+        UPDATE tracks SET
+            play_count = (SELECT COUNT(*) FROM play_history ph WHERE ph.track_id = tracks.id),
+            first_played_at = (SELECT MIN(ph.played_at) FROM play_history ph WHERE ph.track_id = tracks.id),
+            last_played_at = (SELECT MAX(ph.played_at) FROM play_history ph WHERE ph.track_id = tracks.id)
+        WHERE id IN (SELECT track_id FROM play_history);
+
+        CREATE INDEX IF NOT EXISTS idx_tracks_play_count ON tracks(play_count);
+        CREATE INDEX IF NOT EXISTS idx_tracks_last_played ON tracks(last_played_at);",
+    )
+}
+
+/// Link tracks to `artists` and `albums` rows, keyed by artist name and by album
+/// name and artist. Triggers keep the links current on every insert and tag edit,
+/// so the ids survive rescans, and remove rows no track refers to unless they
+/// are rated, a favorite or have a cover.
+fn artist_album_ids(tx: &Transaction<'_>) -> SqlResult<()> {
+    tx.execute_batch(
+        "ALTER TABLE tracks ADD COLUMN artist_id INTEGER REFERENCES artists(id) ON DELETE SET NULL;
+        ALTER TABLE tracks ADD COLUMN album_id INTEGER REFERENCES albums(id) ON DELETE SET NULL;
+
+        DELETE FROM albums WHERE id NOT IN (
+            SELECT MIN(id) FROM albums GROUP BY name, IFNULL(artist, '')
+        );
+        UPDATE albums SET artist = NULL WHERE artist = '';
+        CREATE UNIQUE INDEX IF NOT EXISTS idx_albums_identity ON albums(name, IFNULL(artist, ''));
+
+        INSERT OR IGNORE INTO artists (name)
+            SELECT DISTINCT artist FROM tracks WHERE IFNULL(artist, '') != '';
+        INSERT OR IGNORE INTO albums (name, artist)
+            SELECT DISTINCT album, NULLIF(artist, '') FROM tracks WHERE IFNULL(album, '') != '';
+        UPDATE tracks SET
+            artist_id = (SELECT id FROM artists WHERE name = tracks.artist),
+            album_id = (
+                SELECT id FROM albums
+                WHERE name = tracks.album AND IFNULL(artist, '') = IFNULL(tracks.artist, '')
+            );
+
+        CREATE INDEX IF NOT EXISTS idx_tracks_artist_id ON tracks(artist_id);
+        CREATE INDEX IF NOT EXISTS idx_tracks_album_id ON tracks(album_id);
+
+        CREATE TRIGGER IF NOT EXISTS tracks_link_insert AFTER INSERT ON tracks BEGIN
+            INSERT OR IGNORE INTO artists (name)
+                SELECT new.artist WHERE IFNULL(new.artist, '') != '';
+            INSERT OR IGNORE INTO albums (name, artist)
+                SELECT new.album, NULLIF(new.artist, '') WHERE IFNULL(new.album, '') != '';
+            UPDATE tracks SET
+                artist_id = (SELECT id FROM artists WHERE name = new.artist),
+                album_id = (
+                    SELECT id FROM albums
+                    WHERE name = new.album AND IFNULL(artist, '') = IFNULL(new.artist, '')
+                )
+            WHERE id = new.id;
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS tracks_link_update AFTER UPDATE OF artist, album ON tracks
+        WHEN old.artist IS NOT new.artist OR old.album IS NOT new.album BEGIN
+            INSERT OR IGNORE INTO artists (name)
+                SELECT new.artist WHERE IFNULL(new.artist, '') != '';
+            INSERT OR IGNORE INTO albums (name, artist)
+                SELECT new.album, NULLIF(new.artist, '') WHERE IFNULL(new.album, '') != '';
+            UPDATE tracks SET
+                artist_id = (SELECT id FROM artists WHERE name = new.artist),
+                album_id = (
+                    SELECT id FROM albums
+                    WHERE name = new.album AND IFNULL(artist, '') = IFNULL(new.artist, '')
+                )
+            WHERE id = new.id;
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS tracks_unlink_delete AFTER DELETE ON tracks BEGIN
+            DELETE FROM albums
+            WHERE id = old.album_id AND rating IS NULL AND favorite = 0
+                AND cover_art_path IS NULL
+                AND NOT EXISTS (SELECT 1 FROM tracks WHERE album_id = old.album_id);
+            DELETE FROM artists
+            WHERE id = old.artist_id AND rating IS NULL AND favorite = 0
+                AND NOT EXISTS (SELECT 1 FROM tracks WHERE artist_id = old.artist_id);
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS tracks_unlink_update AFTER UPDATE OF artist_id, album_id ON tracks
+        WHEN old.artist_id IS NOT new.artist_id OR old.album_id IS NOT new.album_id BEGIN
+            DELETE FROM albums
+            WHERE id = old.album_id AND rating IS NULL AND favorite = 0
+                AND cover_art_path IS NULL
+                AND NOT EXISTS (SELECT 1 FROM tracks WHERE album_id = old.album_id);
+            DELETE FROM artists
+            WHERE id = old.artist_id AND rating IS NULL AND favorite = 0
+                AND NOT EXISTS (SELECT 1 FROM tracks WHERE artist_id = old.artist_id);
+        END;",
+    )
+}
+
+/// Albums are keyed by their album artist from here on: the ALBUMARTIST tag, then
+/// "Various Artists" for tracks flagged as part of a compilation, then the track
+/// artist. Existing rows are rekeyed once their tags are read again (see
+/// `pending_tags`).
+fn album_artists(tx: &Transaction<'_>) -> SqlResult<()> {
+    tx.execute_batch(
+        "ALTER TABLE tracks ADD COLUMN album_artist TEXT;
+        ALTER TABLE tracks ADD COLUMN disc_number INTEGER;
+        ALTER TABLE tracks ADD COLUMN disc_total INTEGER;
+        ALTER TABLE tracks ADD COLUMN track_total INTEGER;
+        ALTER TABLE tracks ADD COLUMN compilation BOOLEAN NOT NULL DEFAULT 0;
+
+        DROP TRIGGER IF EXISTS tracks_link_insert;
+        DROP TRIGGER IF EXISTS tracks_link_update;
+
+        CREATE TRIGGER IF NOT EXISTS tracks_link_insert AFTER INSERT ON tracks BEGIN
+            INSERT OR IGNORE INTO artists (name)
+                SELECT new.artist WHERE IFNULL(new.artist, '') != '';
+            INSERT OR IGNORE INTO albums (name, artist)
+                SELECT new.album, COALESCE(
+                    NULLIF(new.album_artist, ''),
+                    CASE WHEN new.compilation THEN 'Various Artists' END,
+                    NULLIF(new.artist, '')
+                )
+                WHERE IFNULL(new.album, '') != '';
+            UPDATE tracks SET
+                artist_id = (SELECT id FROM artists WHERE name = new.artist),
+                album_id = (
+                    SELECT id FROM albums
+                    WHERE name = new.album AND IFNULL(artist, '') = COALESCE(
+                        NULLIF(new.album_artist, ''),
+                        CASE WHEN new.compilation THEN 'Various Artists' END,
+                        NULLIF(new.artist, ''),
+                        ''
+                    )
+                )
+            WHERE id = new.id;
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS tracks_link_update
+        AFTER UPDATE OF artist, album, album_artist, compilation ON tracks
+        WHEN old.artist IS NOT new.artist OR old.album IS NOT new.album
+            OR old.album_artist IS NOT new.album_artist OR old.compilation IS NOT new.compilation
+        BEGIN
+            INSERT OR IGNORE INTO artists (name)
+                SELECT new.artist WHERE IFNULL(new.artist, '') != '';
+            INSERT OR IGNORE INTO albums (name, artist)
+                SELECT new.album, COALESCE(
+                    NULLIF(new.album_artist, ''),
+                    CASE WHEN new.compilation THEN 'Various Artists' END,
+                    NULLIF(new.artist, '')
+                )
+                WHERE IFNULL(new.album, '') != '';
+            UPDATE tracks SET
+                artist_id = (SELECT id FROM artists WHERE name = new.artist),
+                album_id = (
+                    SELECT id FROM albums
+                    WHERE name = new.album AND IFNULL(artist, '') = COALESCE(
+                        NULLIF(new.album_artist, ''),
+                        CASE WHEN new.compilation THEN 'Various Artists' END,
+                        NULLIF(new.artist, ''),
+                        ''
+                    )
+                )
+            WHERE id = new.id;
+        END;",
+    )
+}
+
+/// Each artist and genre named in a track's tags gets a row in `track_artists`
+/// or `track_genres`, maintained by the database layer since splitting depends
+/// on the user's separators (see `credits.rs`). `tracks.artist_id` becomes the
+/// first credited artist, so artist rows are now pruned from `track_artists`.
+fn artist_and_genre_credits(tx: &Transaction<'_>) -> SqlResult<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS genres (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT UNIQUE NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS track_artists (
+            track_id INTEGER NOT NULL REFERENCES tracks(id) ON DELETE CASCADE,
+            artist_id INTEGER NOT NULL REFERENCES artists(id) ON DELETE CASCADE,
+            position INTEGER NOT NULL,
+            role TEXT NOT NULL DEFAULT 'main',
+            PRIMARY KEY (track_id, artist_id)
+        );
+        CREATE INDEX IF NOT EXISTS idx_track_artists_artist ON track_artists(artist_id);
+
+        CREATE TABLE IF NOT EXISTS track_genres (
+            track_id INTEGER NOT NULL REFERENCES tracks(id) ON DELETE CASCADE,
+            genre_id INTEGER NOT NULL REFERENCES genres(id) ON DELETE CASCADE,
+            position INTEGER NOT NULL,
+            PRIMARY KEY (track_id, genre_id)
+        );
+        CREATE INDEX IF NOT EXISTS idx_track_genres_genre ON track_genres(genre_id);
+
+        DROP TRIGGER IF EXISTS tracks_link_insert;
+        DROP TRIGGER IF EXISTS tracks_link_update;
+        DROP TRIGGER IF EXISTS tracks_unlink_delete;
+        DROP TRIGGER IF EXISTS tracks_unlink_update;
+
+        CREATE TRIGGER IF NOT EXISTS tracks_link_insert AFTER INSERT ON tracks BEGIN
+            INSERT OR IGNORE INTO albums (name, artist)
+                SELECT new.album, COALESCE(
+                    NULLIF(new.album_artist, ''),
+                    CASE WHEN new.compilation THEN 'Various Artists' END,
+                    NULLIF(new.artist, '')
+                )
+                WHERE IFNULL(new.album, '') != '';
+            UPDATE tracks SET
+                album_id = (
+                    SELECT id FROM albums
+                    WHERE name = new.album AND IFNULL(artist, '') = COALESCE(
+                        NULLIF(new.album_artist, ''),
+                        CASE WHEN new.compilation THEN 'Various Artists' END,
+                        NULLIF(new.artist, ''),
+                        ''
+                    )
+                )
+            WHERE id = new.id;
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS tracks_link_update
+        AFTER UPDATE OF artist, album, album_artist, compilation ON tracks
+        WHEN old.artist IS NOT new.artist OR old.album IS NOT new.album
+            OR old.album_artist IS NOT new.album_artist OR old.compilation IS NOT new.compilation
+        BEGIN
+            INSERT OR IGNORE INTO albums (name, artist)
+                SELECT new.album, COALESCE(
+                    NULLIF(new.album_artist, ''),
+                    CASE WHEN new.compilation THEN 'Various Artists' END,
+                    NULLIF(new.artist, '')
+                )
+                WHERE IFNULL(new.album, '') != '';
+            UPDATE tracks SET
+                album_id = (
+                    SELECT id FROM albums
+                    WHERE name = new.album AND IFNULL(artist, '') = COALESCE(
+                        NULLIF(new.album_artist, ''),
+                        CASE WHEN new.compilation THEN 'Various Artists' END,
+                        NULLIF(new.artist, ''),
+                        ''
+                    )
+                )
+            WHERE id = new.id;
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS tracks_unlink_delete AFTER DELETE ON tracks BEGIN
+            DELETE FROM albums
+            WHERE id = old.album_id AND rating IS NULL AND favorite = 0
+                AND cover_art_path IS NULL
+                AND NOT EXISTS (SELECT 1 FROM tracks WHERE album_id = old.album_id);
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS tracks_unlink_update AFTER UPDATE OF album_id ON tracks
+        WHEN old.album_id IS NOT new.album_id BEGIN
+            DELETE FROM albums
+            WHERE id = old.album_id AND rating IS NULL AND favorite = 0
+                AND cover_art_path IS NULL
+                AND NOT EXISTS (SELECT 1 FROM tracks WHERE album_id = old.album_id);
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS track_artists_unlink AFTER DELETE ON track_artists BEGIN
+            DELETE FROM artists
+            WHERE id = old.artist_id AND rating IS NULL AND favorite = 0
+                AND NOT EXISTS (SELECT 1 FROM track_artists WHERE artist_id = old.artist_id);
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS track_genres_unlink AFTER DELETE ON track_genres BEGIN
+            DELETE FROM genres
+            WHERE id = old.genre_id
+                AND NOT EXISTS (SELECT 1 FROM track_genres WHERE genre_id = old.genre_id);
+        END;",
+    )?;
+
+    let separators: Vec<String> = credits::DEFAULT_SEPARATORS
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let tracks = {
+        let mut stmt = tx.prepare("SELECT id, artist, genre FROM tracks")?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                ))
+            })?
+            .collect::<SqlResult<Vec<_>>>()?;
+        rows
+    };
+    for (id, artist, genre) in tracks {
+        credits::link_track_credits(tx, id, artist.as_deref(), genre.as_deref(), &separators)?;
+    }
+
+    // Artist rows named after a whole multi-artist tag are no longer used
+    tx.execute(
+        "DELETE FROM artists
+         WHERE rating IS NULL AND favorite = 0
+            AND id NOT IN (SELECT artist_id FROM track_artists)",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Credits, release and catalog tags. The search index is rebuilt to cover
+/// them along with the album artist; its column order sets the bm25 weights
+/// in `database::SEARCH_RANK_SQL`. Existing tracks get them once their tags
+/// are read again (see `pending_tags`).
+fn extended_tags(tx: &Transaction<'_>) -> SqlResult<()> {
+    tx.execute_batch(
+        "ALTER TABLE tracks ADD COLUMN composer TEXT;
+        ALTER TABLE tracks ADD COLUMN conductor TEXT;
+        ALTER TABLE tracks ADD COLUMN performer TEXT;
+        ALTER TABLE tracks ADD COLUMN work TEXT;
+        ALTER TABLE tracks ADD COLUMN movement TEXT;
+        ALTER TABLE tracks ADD COLUMN label TEXT;
+        ALTER TABLE tracks ADD COLUMN catalog_number TEXT;
+        ALTER TABLE tracks ADD COLUMN isrc TEXT;
+        ALTER TABLE tracks ADD COLUMN bpm INTEGER;
+        ALTER TABLE tracks ADD COLUMN comment TEXT;
+
+        DROP TRIGGER IF EXISTS tracks_fts_insert;
+        DROP TRIGGER IF EXISTS tracks_fts_delete;
+        DROP TRIGGER IF EXISTS tracks_fts_update;
+        DROP TABLE IF EXISTS tracks_fts;
+
+        CREATE VIRTUAL TABLE tracks_fts USING fts5(
+            title, artist, album, album_artist, composer, conductor, performer, work,
+            movement, label, catalog_number, isrc, comment,
+            content='tracks',
+            content_rowid='id',
+            tokenize='unicode61 remove_diacritics 2'
+        );
+
+        CREATE TRIGGER tracks_fts_insert AFTER INSERT ON tracks BEGIN
+            INSERT INTO tracks_fts(
+                rowid, title, artist, album, album_artist, composer, conductor, performer,
+                work, movement, label, catalog_number, isrc, comment
+            ) VALUES (
+                new.id, new.title, new.artist, new.album, new.album_artist, new.composer,
+                new.conductor, new.performer, new.work, new.movement, new.label,
+                new.catalog_number, new.isrc, new.comment
+            );
+        END;
+
+        CREATE TRIGGER tracks_fts_delete AFTER DELETE ON tracks BEGIN
+            INSERT INTO tracks_fts(
+                tracks_fts, rowid, title, artist, album, album_artist, composer, conductor,
+                performer, work, movement, label, catalog_number, isrc, comment
+            ) VALUES (
+                'delete', old.id, old.title, old.artist, old.album, old.album_artist,
+                old.composer, old.conductor, old.performer, old.work, old.movement, old.label,
+                old.catalog_number, old.isrc, old.comment
+            );
+        END;
+
+        CREATE TRIGGER tracks_fts_update
+        AFTER UPDATE OF title, artist, album, album_artist, composer, conductor, performer,
+            work, movement, label, catalog_number, isrc, comment ON tracks BEGIN
+            INSERT INTO tracks_fts(
+                tracks_fts, rowid, title, artist, album, album_artist, composer, conductor,
+                performer, work, movement, label, catalog_number, isrc, comment
+            ) VALUES (
+                'delete', old.id, old.title, old.artist, old.album, old.album_artist,
+                old.composer, old.conductor, old.performer, old.work, old.movement, old.label,
+                old.catalog_number, old.isrc, old.comment
+            );
+            INSERT INTO tracks_fts(
+                rowid, title, artist, album, album_artist, composer, conductor, performer,
+                work, movement, label, catalog_number, isrc, comment
+            ) VALUES (
+                new.id, new.title, new.artist, new.album, new.album_artist, new.composer,
+                new.conductor, new.performer, new.work, new.movement, new.label,
+                new.catalog_number, new.isrc, new.comment
+            );
+        END;
+
+        INSERT INTO tracks_fts(tracks_fts) VALUES ('rebuild');",
+    )
+}
+
+/// TITLESORT, ARTISTSORT and ALBUMSORT tags, and the keys tracks, albums and
+/// artists are ordered by: the sort tag, or else the name without a leading
+/// article. Keys are filled in with the default articles, and redone once the
+/// sort tags of existing tracks are read again (see `pending_tags`).
+fn sort_names(tx: &Transaction<'_>) -> SqlResult<()> {
+    tx.execute_batch(
+        "ALTER TABLE tracks ADD COLUMN title_sort TEXT;
+        ALTER TABLE tracks ADD COLUMN artist_sort TEXT;
+        ALTER TABLE tracks ADD COLUMN album_sort TEXT;
+        ALTER TABLE tracks ADD COLUMN title_key TEXT;
+        ALTER TABLE tracks ADD COLUMN artist_key TEXT;
+        ALTER TABLE tracks ADD COLUMN album_key TEXT;
+        ALTER TABLE albums ADD COLUMN sort_key TEXT;
+        ALTER TABLE artists ADD COLUMN sort_key TEXT;",
+    )?;
+
+    let articles: Vec<String> = sorting::DEFAULT_ARTICLES
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let ids = tx
+        .prepare("SELECT id FROM tracks")?
+        .query_map([], |row| row.get::<_, i64>(0))?
+        .collect::<SqlResult<Vec<_>>>()?;
+    for id in ids {
+        sorting::update_sort_keys(tx, id, &articles)?;
+    }
+    Ok(())
+}
+
+/// Bitrate, sample rate, bit depth, channels, codec and a lossless flag.
+/// Existing tracks get them from the scanner, which reads only the audio
+/// headers of tracks without a codec.
+fn audio_properties(tx: &Transaction<'_>) -> SqlResult<()> {
+    tx.execute_batch(
+        "ALTER TABLE tracks ADD COLUMN bitrate INTEGER;
+        ALTER TABLE tracks ADD COLUMN sample_rate INTEGER;
+        ALTER TABLE tracks ADD COLUMN bit_depth INTEGER;
+        ALTER TABLE tracks ADD COLUMN channels INTEGER;
+        ALTER TABLE tracks ADD COLUMN codec TEXT;
+        ALTER TABLE tracks ADD COLUMN lossless INTEGER;",
+    )
+}
+
+/// When a track's file was first found to be gone; `NULL` while it exists
+fn missing_files(tx: &Transaction<'_>) -> SqlResult<()> {
+    tx.execute_batch(
+        "ALTER TABLE tracks ADD COLUMN missing_since INTEGER;
+        CREATE INDEX IF NOT EXISTS idx_tracks_missing_since ON tracks(missing_since)
+            WHERE missing_since IS NOT NULL;",
+    )
+}
+
+/// Hash of each track's audio without its tags (see `content_hash`), to
+/// recognise a file that was moved or renamed. Existing tracks are hashed by
+/// the next scan.
+fn content_hashes(tx: &Transaction<'_>) -> SqlResult<()> {
+    tx.execute_batch(
+        "ALTER TABLE tracks ADD COLUMN content_hash TEXT;
+        CREATE INDEX IF NOT EXISTS idx_tracks_content_hash ON tracks(content_hash);",
+    )
+}
+
+/// Tracks scanned before the album artist, extended and sort tag columns existed,
+/// which the scanner skips while their files are unchanged. `tags_pending` has
+/// the next scan read just those tags, keeping titles, artists and the rest of
+/// what may have been edited in the app. CUE sheet entries take them from the
+/// sheet, so their files are rescanned instead.
+fn pending_tags(tx: &Transaction<'_>) -> SqlResult<()> {
+    tx.execute_batch(
+        "ALTER TABLE tracks ADD COLUMN tags_pending BOOLEAN NOT NULL DEFAULT 0;
+        UPDATE tracks SET tags_pending = 1 WHERE source_path IS NULL;
+        UPDATE tracks SET last_modified = 0 WHERE source_path IS NOT NULL;
+        CREATE INDEX IF NOT EXISTS idx_tracks_tags_pending ON tracks(tags_pending)
+            WHERE tags_pending = 1;",
+    )
+}
+
+/// Tracks the scanner has tried to read audio properties for, so files whose
+/// headers can't be read aren't tried again on every scan
+fn audio_properties_checked(tx: &Transaction<'_>) -> SqlResult<()> {
+    tx.execute_batch(
+        "ALTER TABLE tracks ADD COLUMN audio_checked BOOLEAN NOT NULL DEFAULT 0;
+        UPDATE tracks SET audio_checked = 1 WHERE codec IS NOT NULL;",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn latest_version() -> u32 {
+        MIGRATIONS.last().unwrap().version
+    }
+
+    fn column_names(conn: &Connection, table: &str) -> Vec<String> {
+        let mut stmt = conn
+            .prepare("SELECT name FROM pragma_table_info(?1)")
+            .unwrap();
+        stmt.query_map(params![table], |row| row.get(0))
+            .unwrap()
+            .collect::<SqlResult<Vec<String>>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_fresh_database_migrates_without_backup() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut conn = Connection::open(dir.path().join("osmp.db")).unwrap();
+
+        assert_eq!(
+            migrate(&mut conn, Some(dir.path())).unwrap(),
+            latest_version()
+        );
+        assert_eq!(schema_version(&conn).unwrap(), latest_version());
+        assert!(column_names(&conn, "tracks").contains(&"source_path".to_string()));
+
+        // Nothing to back up on a fresh database, and a second run is a no-op
+        assert_eq!(
+            migrate(&mut conn, Some(dir.path())).unwrap(),
+            latest_version()
+        );
+        let backups = std::fs::read_dir(dir.path())
+            .unwrap()
+            .filter(|e| e.as_ref().unwrap().path().extension() == Some("bak".as_ref()))
+            .count();
+        assert_eq!(backups, 0);
+    }
+
+    #[test]
+    fn test_unversioned_database_is_adopted_and_backed_up() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut conn = Connection::open(dir.path().join("osmp.db")).unwrap();
+
+        // A database from before release_mbid and versioning
+        conn.execute_batch(
+            "CREATE TABLE tracks (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                file_path TEXT UNIQUE NOT NULL,
+                title TEXT,
+                artist TEXT,
+                album TEXT,
+                duration INTEGER,
+                year INTEGER,
+                genre TEXT,
+                track_number INTEGER,
+                file_size INTEGER,
+                file_format TEXT,
+                last_modified INTEGER,
+                metadata_fetched BOOLEAN DEFAULT 0,
+                created_at INTEGER DEFAULT (strftime('%s', 'now'))
+            );
+            INSERT INTO tracks (file_path, title) VALUES ('/music/a.flac', 'Kept');",
+        )
+        .unwrap();
+
+        migrate(&mut conn, Some(dir.path())).unwrap();
+
+        let columns = column_names(&conn, "tracks");
+        assert!(columns.contains(&"release_mbid".to_string()));
+        assert!(columns.contains(&"end_offset_ms".to_string()));
+        let title: String = conn
+            .query_row("SELECT title FROM tracks", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(title, "Kept");
+
+        let backup = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .find(|p| p.to_string_lossy().ends_with(".db.bak"))
+            .expect("pre-migration backup");
+        assert!(backup.to_string_lossy().contains("osmp-v0-"));
+        let copy = Connection::open(&backup).unwrap();
+        assert_eq!(schema_version(&copy).unwrap(), 0);
+        assert_eq!(column_names(&copy, "tracks").len(), 14);
+    }
+
+    #[test]
+    fn test_existing_tracks_have_their_tags_read_again() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        apply(&mut conn, &MIGRATIONS[..17], None).unwrap();
+        conn.execute_batch(
+            "INSERT INTO tracks (file_path, last_modified) VALUES ('/music/a.flac', 100);
+            INSERT INTO tracks (file_path, source_path, last_modified)
+                VALUES ('/music/b.flac#1', '/music/b.flac', 100);",
+        )
+        .unwrap();
+
+        migrate(&mut conn, None).unwrap();
+
+        let rows: Vec<(String, bool, i64)> = conn
+            .prepare("SELECT file_path, tags_pending, last_modified FROM tracks ORDER BY id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<SqlResult<_>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                ("/music/a.flac".to_string(), true, 100),
+                ("/music/b.flac#1".to_string(), false, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_newer_schema_is_refused() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", latest_version() + 1)
+            .unwrap();
+        let err = migrate(&mut conn, None).unwrap_err().to_string();
+        assert!(err.contains("newer than this build supports"));
+        assert!(err.contains("restore a pre-migration backup"));
+        // Nothing was changed
+        assert_eq!(schema_version(&conn).unwrap(), latest_version() + 1);
+
+        // The error names the newest backup this build can open
+        let dir = tempfile::TempDir::new().unwrap();
+        for name in [
+            "osmp-v5-100.db.bak",
+            "osmp-v7-300.db.bak",
+            "osmp-v7-200.db.bak",
+            "osmp-v999-400.db.bak",
+            "notes.txt",
+        ] {
+            std::fs::write(dir.path().join(name), "").unwrap();
+        }
+        let err = migrate(&mut conn, Some(dir.path()))
+            .unwrap_err()
+            .to_string();
+        let newest = dir.path().join("osmp-v7-300.db.bak");
+        assert!(
+            err.contains(&format!(
+                "restore the pre-migration backup {}",
+                newest.display()
+            )),
+            "{}",
+            err
+        );
+
+        // Without one it points at where backups are written
+        let empty = tempfile::TempDir::new().unwrap();
+        let err = migrate(&mut conn, Some(empty.path()))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains(&empty.path().display().to_string()));
+    }
+
+    #[test]
+    fn test_failed_migration_rolls_back() {
+        let migrations = [
+            Migration {
+                version: 1,
+                description: "Create a table",
+                up: |tx| tx.execute_batch("CREATE TABLE kept (id INTEGER)"),
+            },
+            Migration {
+                version: 2,
+                description: "Fail half way",
+                up: |tx| tx.execute_batch("CREATE TABLE dropped (id INTEGER); NOT SQL"),
+            },
+        ];
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        assert!(apply(&mut conn, &migrations, None).is_err());
+        assert_eq!(schema_version(&conn).unwrap(), 1);
+        let tables: Vec<String> = conn
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<SqlResult<_>>()
+            .unwrap();
+        assert_eq!(tables, vec!["kept".to_string()]);
+    }
+}
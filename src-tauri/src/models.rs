@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Track {
     pub id: i64,
     pub file_path: String,
@@ -17,6 +17,87 @@ pub struct Track {
     pub metadata_fetched: bool,
     pub release_mbid: Option<String>,
     pub created_at: i64,
+    /// Underlying audio file for CUE sheet virtual tracks (`file_path` is then a synthetic key)
+    pub source_path: Option<String>,
+    /// Start of a virtual track within `source_path`, in milliseconds
+    pub start_offset_ms: Option<i64>,
+    /// End of a virtual track within `source_path`; `None` plays to the end of the file
+    pub end_offset_ms: Option<i64>,
+    /// Half stars, 0–10; `None` when unrated
+    pub rating: Option<i64>,
+    pub favorite: bool,
+    /// Listens long enough to count as a play (see `database::is_skip`)
+    pub play_count: i64,
+    pub skip_count: i64,
+    /// Unix time of the first and most recent play; skips don't count
+    pub first_played_at: Option<i64>,
+    pub last_played_at: Option<i64>,
+    /// Rows in `artists` and `albums`, kept in step with the artist and album tags
+    pub artist_id: Option<i64>,
+    pub album_id: Option<i64>,
+    /// ALBUMARTIST tag; albums are grouped by it when set
+    pub album_artist: Option<String>,
+    pub disc_number: Option<i64>,
+    pub disc_total: Option<i64>,
+    pub track_total: Option<i64>,
+    /// Part of a various-artists compilation (iTunes `cpil`, ID3 `TCMP`, `COMPILATION`)
+    pub compilation: bool,
+    /// Unix time the file was first found missing by a scan; `None` while it exists
+    pub missing_since: Option<i64>,
+    /// Hash of the audio without its tags, to follow the file when it is moved
+    pub content_hash: Option<String>,
+    #[serde(flatten)]
+    pub tags: ExtendedTags,
+    #[serde(flatten)]
+    pub audio: AudioProperties,
+}
+
+/// Credits, classical, catalog and sort-name tags beyond the basic ones. Serialized inline
+/// with `Track`; also the payload for editing them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExtendedTags {
+    pub composer: Option<String>,
+    pub conductor: Option<String>,
+    pub performer: Option<String>,
+    /// Classical work the track is part of, e.g. "Symphony No. 9 in D minor"
+    pub work: Option<String>,
+    pub movement: Option<String>,
+    pub label: Option<String>,
+    pub catalog_number: Option<String>,
+    pub isrc: Option<String>,
+    /// Beats per minute, rounded
+    pub bpm: Option<i64>,
+    pub comment: Option<String>,
+    /// TITLESORT, ARTISTSORT and ALBUMSORT: the names to sort by, e.g.
+    /// "Beatles, The"
+    pub title_sort: Option<String>,
+    pub artist_sort: Option<String>,
+    pub album_sort: Option<String>,
+}
+
+/// Technical properties of the audio stream, read while scanning. All are
+/// `None` for files whose headers could not be read.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioProperties {
+    /// Audio bitrate in kbps
+    pub bitrate: Option<i64>,
+    /// Hz
+    pub sample_rate: Option<i64>,
+    /// Bits per sample; only known for uncompressed and lossless formats
+    pub bit_depth: Option<i64>,
+    pub channels: Option<i64>,
+    /// e.g. "MP3", "AAC", "ALAC", "FLAC", "Opus"
+    pub codec: Option<String>,
+    pub lossless: Option<bool>,
+}
+
+impl Track {
+    /// Path of the file to decode for this track
+    pub fn audio_path(&self) -> &str {
+        self.source_path.as_deref().unwrap_or(&self.file_path)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -31,6 +112,7 @@ pub struct Album {
 // Enhanced album struct for frontend with aggregated data
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AlbumInfo {
+    pub id: i64,
     pub name: String,
     pub artist: Option<String>,
     pub year: Option<i64>,
@@ -38,6 +120,7 @@ pub struct AlbumInfo {
     pub total_duration: Option<i64>,
     pub created_at: i64,           // most recent track's created_at
     pub cover_art: Option<String>, // base64 data URI
+    pub compilation: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -46,6 +129,53 @@ pub struct Artist {
     pub name: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArtistInfo {
+    pub id: i64,
+    pub name: String,
+    pub track_count: i64,
+    pub album_count: i64,
+}
+
+/// How an artist is credited on a track
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CreditRole {
+    Main,
+    /// Named after `feat.` or `ft.` in the artist tag
+    Featured,
+}
+
+impl CreditRole {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CreditRole::Main => "main",
+            CreditRole::Featured => "featured",
+        }
+    }
+
+    pub fn from_db(value: &str) -> Self {
+        match value {
+            "featured" => CreditRole::Featured,
+            _ => CreditRole::Main,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArtistCredit {
+    pub artist_id: i64,
+    pub name: String,
+    pub role: CreditRole,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenreInfo {
+    pub id: i64,
+    pub name: String,
+    pub track_count: i64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ScanFolder {
     pub id: i64,
@@ -53,13 +183,107 @@ pub struct ScanFolder {
     pub enabled: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct TrackFilters {
     pub artist: Option<String>,
     pub album: Option<String>,
+    pub artist_id: Option<i64>,
+    pub album_id: Option<i64>,
     pub genre: Option<String>,
     pub year: Option<i64>,
     pub format: Option<String>,
+    /// Minimum rating in half stars
+    pub min_rating: Option<i64>,
+    pub favorite: Option<bool>,
+    pub codec: Option<String>,
+    pub lossless: Option<bool>,
+    /// Lossless with more than 16 bits or 48 kHz
+    pub hi_res: Option<bool>,
+    pub channels: Option<i64>,
+    /// Highest bitrate in kbps, to find low-quality copies
+    pub max_bitrate: Option<i64>,
+    /// Only tracks whose file a scan found missing, or only those still present
+    pub missing: Option<bool>,
+    /// Sort keys in priority order; title when empty
+    #[serde(default)]
+    pub sort: Vec<TrackSortKey>,
+}
+
+/// The fields a track list shows, for paging through large libraries
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackSummary {
+    pub id: i64,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub duration: Option<i64>,
+    pub year: Option<i64>,
+    pub genre: Option<String>,
+    pub track_number: Option<i64>,
+    pub file_format: String,
+    pub rating: Option<i64>,
+    pub favorite: bool,
+    pub play_count: i64,
+    pub last_played_at: Option<i64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TrackPageRequest {
+    #[serde(default)]
+    pub filters: TrackFilters,
+    /// Search query (see `search_query.rs`), combined with `filters`
+    pub search: Option<String>,
+    /// `next_cursor` of the previous page; `None` for the first page
+    pub cursor: Option<Vec<serde_json::Value>>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackPage {
+    pub tracks: Vec<TrackSummary>,
+    /// Tracks matching the filters and search across all pages
+    pub total: i64,
+    /// Pass back to fetch the next page; `None` on the last page
+    pub next_cursor: Option<Vec<serde_json::Value>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrackSortKey {
+    pub field: TrackSortField,
+    #[serde(default)]
+    pub descending: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrackSortField {
+    Title,
+    Artist,
+    Album,
+    Year,
+    Duration,
+    DateAdded,
+    Rating,
+    PlayCount,
+    SkipCount,
+    LastPlayed,
+    Bitrate,
+}
+
+/// Rating and favorite flag of an album, identified like `AlbumInfo` by name and artist
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlbumRating {
+    pub album: String,
+    pub artist: Option<String>,
+    pub rating: Option<i64>,
+    pub favorite: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArtistRating {
+    pub artist: String,
+    pub rating: Option<i64>,
+    pub favorite: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -75,6 +299,10 @@ pub struct ScanProgress {
     pub total_files: usize,
     pub processed_files: usize,
     pub is_complete: bool,
+    /// Files processed per second so far, skipped ones included
+    pub files_per_second: f64,
+    /// Seconds the remaining files should take at that rate
+    pub eta_secs: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -93,6 +321,14 @@ pub struct ScanResult {
     pub error_files: Vec<String>,
     pub duration_secs: f64,
     pub cancelled: bool,
+    /// Tracks whose file was found at a new path and kept their id
+    pub moved: usize,
+    /// Tracks whose file was found missing by this scan
+    pub missing: usize,
+    /// Tracks removed after being missing for longer than the grace period
+    pub purged: usize,
+    /// Scan folders whose root was not mounted; their tracks were left alone
+    pub offline_folders: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -115,6 +351,148 @@ pub struct Playlist {
     pub created_at: i64,
     pub track_count: i64,
     pub total_duration: Option<i64>,
+    pub kind: PlaylistKind,
+}
+
+/// Manual playlists hold a list of tracks; smart playlists are evaluated from
+/// their rules (see `SmartPlaylistRules`) every time they are loaded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaylistKind {
+    #[default]
+    Manual,
+    Smart,
+}
+
+impl PlaylistKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PlaylistKind::Manual => "manual",
+            PlaylistKind::Smart => "smart",
+        }
+    }
+
+    pub fn from_db(value: &str) -> Self {
+        match value {
+            "smart" => PlaylistKind::Smart,
+            _ => PlaylistKind::Manual,
+        }
+    }
+}
+
+/// The definition of a smart playlist, e.g. genre is Jazz and not played in
+/// the last 90 days, 50 tracks in random order.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SmartPlaylistRules {
+    pub rule: SmartRule,
+    /// Maximum number of tracks, applied after sorting
+    pub limit: Option<i64>,
+    #[serde(default)]
+    pub sort: SmartSort,
+    #[serde(default)]
+    pub descending: bool,
+}
+
+/// A node in a smart playlist rule tree, tagged by `type` in JSON:
+/// `{"type": "text", "field": "genre", "op": "is", "value": "Jazz"}`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SmartRule {
+    /// Every child matches (an empty list matches everything)
+    All {
+        rules: Vec<SmartRule>,
+    },
+    /// At least one child matches (an empty list matches nothing)
+    Any {
+        rules: Vec<SmartRule>,
+    },
+    Not {
+        rule: Box<SmartRule>,
+    },
+    Text {
+        field: SmartTextField,
+        op: TextMatch,
+        value: String,
+    },
+    Number {
+        field: SmartNumberField,
+        op: NumberMatch,
+        value: i64,
+    },
+    /// The date field falls within the last `days` days
+    InLastDays {
+        field: SmartDateField,
+        days: i64,
+    },
+    Favorite {
+        favorite: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmartTextField {
+    Title,
+    Artist,
+    Album,
+    AlbumArtist,
+    Genre,
+    Format,
+    Path,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextMatch {
+    /// Equal, ignoring case
+    Is,
+    Contains,
+    StartsWith,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmartNumberField {
+    Year,
+    /// Seconds
+    Duration,
+    PlayCount,
+    SkipCount,
+    /// Half stars, 0–10
+    Rating,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NumberMatch {
+    Eq,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmartDateField {
+    DateAdded,
+    LastPlayed,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmartSort {
+    #[default]
+    Random,
+    Title,
+    Artist,
+    Album,
+    Year,
+    Duration,
+    DateAdded,
+    LastPlayed,
+    PlayCount,
+    Rating,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -130,14 +508,252 @@ pub struct PlayHistoryEntry {
     pub track_id: i64,
     pub played_at: i64,
     pub duration_listened: i64,
+    /// Stopped too early to count as a play
+    pub skipped: bool,
     pub track: Option<Track>,
 }
 
+/// Time range for listening statistics, in Unix time. Either end may be left open.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct StatsRange {
+    /// Inclusive
+    pub from: Option<i64>,
+    /// Exclusive
+    pub to: Option<i64>,
+}
+
+impl StatsRange {
+    /// Bounds with open ends replaced by the extremes of `i64`
+    pub fn bounds(&self) -> (i64, i64) {
+        (self.from.unwrap_or(i64::MIN), self.to.unwrap_or(i64::MAX))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StatsPeriod {
+    Day,
+    /// Weeks start on Monday
+    Week,
+    Month,
+}
+
+/// A track with its plays and listening time within a range
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackStat {
+    pub track: Track,
+    pub plays: i64,
+    pub seconds: i64,
+}
+
+/// An artist, album or genre with its plays and listening time within a range
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NameStat {
+    pub name: String,
+    /// Artist of an album; `None` for artists and genres
+    pub artist: Option<String>,
+    pub plays: i64,
+    pub seconds: i64,
+}
+
+/// Listening within one day, week or month
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ListeningTime {
+    /// First day of the period (`YYYY-MM-DD`), or `YYYY-MM` for months, in local time
+    pub period: String,
+    pub plays: i64,
+    pub seconds: i64,
+}
+
+/// Seconds listened by local weekday (Monday first) and hour
+pub type ListeningClock = [[i64; 24]; 7];
+
+/// Runs of consecutive days with at least one play
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ListeningStreaks {
+    /// Days in the run that includes today or yesterday; 0 when it has been broken
+    pub current: i64,
+    pub longest: i64,
+    /// First and last day of the longest run (`YYYY-MM-DD`)
+    pub longest_start: Option<String>,
+    pub longest_end: Option<String>,
+}
+
+/// Tracks and artists first played within a range
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Discoveries {
+    pub tracks: Vec<TrackStat>,
+    pub artists: Vec<NameStat>,
+}
+
+/// A year in review
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct YearReport {
+    pub year: i32,
+    pub plays: i64,
+    pub skips: i64,
+    pub seconds: i64,
+    pub distinct_tracks: i64,
+    pub distinct_artists: i64,
+    pub top_tracks: Vec<TrackStat>,
+    pub top_artists: Vec<NameStat>,
+    pub top_albums: Vec<NameStat>,
+    pub top_genres: Vec<NameStat>,
+    pub months: Vec<ListeningTime>,
+    pub clock: ListeningClock,
+    pub streaks: ListeningStreaks,
+    pub discoveries: Discoveries,
+}
+
+/// Audio quality across the whole library
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct QualityStats {
+    pub tracks: i64,
+    pub lossless: i64,
+    pub lossy: i64,
+    /// Lossless with more than 16 bits or 48 kHz
+    pub hi_res: i64,
+    /// Tracks whose audio headers could not be read
+    pub unknown: i64,
+    pub mono: i64,
+    /// Lossy tracks below the bitrate asked for
+    pub low_bitrate: i64,
+    /// Mean bitrate of lossy tracks in kbps
+    pub average_lossy_bitrate: Option<f64>,
+    /// Most common first
+    pub codecs: Vec<CodecStat>,
+    pub sample_rates: Vec<ValueCount>,
+    pub bit_depths: Vec<ValueCount>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CodecStat {
+    pub codec: String,
+    pub lossless: bool,
+    pub tracks: i64,
+    /// kbps
+    pub average_bitrate: Option<f64>,
+}
+
+/// Number of tracks with a sample rate, bit depth, ...
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ValueCount {
+    pub value: i64,
+    pub tracks: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportFormat {
+    Json,
+    /// A single file with inline styles and no external resources
+    Html,
+}
+
+/// A chapter marker within an audiobook or long-form file
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Chapter {
+    pub id: i64,
+    pub track_id: i64,
+    /// Zero-based position of the chapter within the track
+    pub chapter_index: i64,
+    pub title: Option<String>,
+    pub start_ms: i64,
+    pub end_ms: Option<i64>,
+}
+
+/// What moving the library from one root folder to another changes. The move
+/// is only applied when no file is missing at its new path and no new path is
+/// already in the library.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RelocationReport {
+    pub tracks: usize,
+    pub scan_folders: usize,
+    pub covers: usize,
+    /// New paths with no file, the first few of `missing_count`. Tracks
+    /// already flagged missing are not checked.
+    pub missing: Vec<String>,
+    pub missing_count: usize,
+    /// New paths that are already in the library, e.g. after the new location
+    /// was scanned; the first few of `conflict_count`
+    pub conflicts: Vec<String>,
+    pub conflict_count: usize,
+    /// False for a dry run or when the move was refused
+    pub applied: bool,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ScanSettings {
     pub scan_on_startup: bool,
     pub periodic_scan_enabled: bool,
     pub periodic_scan_interval_minutes: u64,
+    /// Days a track whose file is missing is kept before a scan removes it;
+    /// 0 keeps it until it is removed by hand
+    #[serde(default = "default_missing_grace_days")]
+    pub missing_grace_days: u64,
+    /// Rescan what changes in the scan folders as it happens. Periodic scans
+    /// are skipped while every enabled folder is watched, and otherwise scan
+    /// all of them
+    #[serde(default = "default_watch_folders_enabled")]
+    pub watch_folders_enabled: bool,
+}
+
+fn default_watch_folders_enabled() -> bool {
+    true
+}
+
+fn default_missing_grace_days() -> u64 {
+    crate::reconcile::DEFAULT_MISSING_GRACE_DAYS
+}
+
+/// When to remember the playback position of long-form content
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResumeSettings {
+    pub enabled: bool,
+    /// Tracks at least this long resume where they were left off
+    pub min_duration_minutes: u64,
+}
+
+impl Default for ResumeSettings {
+    fn default() -> Self {
+        ResumeSettings {
+            enabled: true,
+            min_duration_minutes: 20,
+        }
+    }
+}
+
+/// Saved position of a long-form track (audiobook, podcast, DJ mix)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResumePosition {
+    pub track_id: i64,
+    pub position_ms: i64,
+    /// Listened to the end; the next play starts from the beginning
+    pub finished: bool,
+    pub updated_at: i64,
+}
+
+/// The listening context restored (paused) at startup
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaybackSession {
+    pub track: Option<Track>,
+    pub position_ms: i64,
+    pub queue: Vec<Track>,
+    pub volume: f32,
+    pub speed: f32,
+    pub shuffle: bool,
+    /// `off`, `list` or `track`
+    pub repeat_mode: String,
+    pub active_playlist_id: Option<i64>,
+}
+
+/// Queue and mode state owned by the frontend, saved whenever it changes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionContext {
+    pub queue: Vec<i64>,
+    pub shuffle: bool,
+    pub repeat_mode: String,
+    pub active_playlist_id: Option<i64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
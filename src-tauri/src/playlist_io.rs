@@ -16,7 +16,7 @@ pub fn export_m3u(playlist_name: &str, tracks: &[Track], output_path: &str) -> i
         let artist = track.artist.as_deref().unwrap_or("Unknown");
 
         writeln!(file, "#EXTINF:{},{} - {}", duration, artist, title)?;
-        writeln!(file, "{}", track.file_path)?;
+        writeln!(file, "{}", track.audio_path())?;
     }
 
     Ok(())
@@ -68,7 +68,9 @@ pub fn parse_m3u(file_path: &str) -> io::Result<Vec<M3uEntry>> {
         };
 
         // Validate the file has an audio extension
-        let valid_extensions = ["mp3", "flac", "ogg", "m4a", "wav", "aac", "opus", "wma"];
+        let valid_extensions = [
+            "mp3", "flac", "ogg", "m4a", "m4b", "wav", "aac", "opus", "wma",
+        ];
         let has_audio_ext = Path::new(&resolved_path)
             .extension()
             .and_then(|e| e.to_str())
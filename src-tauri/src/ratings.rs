@@ -0,0 +1,127 @@
+/// Highest rating. Ratings are stored in half stars, 0–10 (7 is three and a
+/// half stars), and converted to the conventions of each tag format here.
+pub const MAX_RATING: i64 = 10;
+
+/// POPM byte written for each rating. Whole stars use the Windows Media Player
+/// values (1, 64, 128, 196, 255) that most players read; half stars use the
+/// in-between values MediaMonkey writes.
+const POPM_VALUES: [u8; 11] = [0, 13, 1, 54, 64, 118, 128, 186, 196, 242, 255];
+
+/// Check that a rating is within 0–10 half stars
+pub fn validate_rating(rating: Option<i64>) -> Result<Option<i64>, String> {
+    match rating {
+        Some(r) if !(0..=MAX_RATING).contains(&r) => Err(format!(
+            "Rating must be between 0 and {} half stars",
+            MAX_RATING
+        )),
+        _ => Ok(rating),
+    }
+}
+
+/// Rating for an ID3v2 POPM rating byte. 0 means unrated.
+pub fn rating_from_popm(value: u8) -> Option<i64> {
+    let rating = match value {
+        0 => return None,
+        1 => 2,
+        2..=22 => 1,
+        23..=31 => 2,
+        32..=63 => 3,
+        64..=95 => 4,
+        96..=127 => 5,
+        128..=159 => 6,
+        160..=195 => 7,
+        196..=223 => 8,
+        224..=254 => 9,
+        255 => 10,
+    };
+    Some(rating)
+}
+
+pub fn rating_to_popm(rating: i64) -> u8 {
+    POPM_VALUES[rating.clamp(0, MAX_RATING) as usize]
+}
+
+/// Rating from a POPM frame body: a null-terminated email, the rating byte,
+/// then an optional play counter
+pub fn parse_popm(data: &[u8]) -> Option<i64> {
+    let email_end = data.iter().position(|&b| b == 0)?;
+    data.get(email_end + 1).copied().and_then(rating_from_popm)
+}
+
+/// POPM frame body with no email and no play counter
+pub fn popm_frame(rating: i64) -> Vec<u8> {
+    vec![0, rating_to_popm(rating), 0, 0, 0, 0]
+}
+
+/// Rating from a Vorbis/APE comment. `FMPS_RATING` is a fraction from 0 to 1;
+/// `RATING` is written either as stars (0–5) or as a percentage (0–100).
+pub fn parse_text_rating(key: &str, value: &str) -> Option<i64> {
+    let value: f64 = value.trim().parse().ok()?;
+    if !value.is_finite() || value <= 0.0 {
+        return None;
+    }
+    let stars = if key.eq_ignore_ascii_case("FMPS_RATING") {
+        value.min(1.0) * 5.0
+    } else if key.eq_ignore_ascii_case("RATING") {
+        if value <= 5.0 {
+            value
+        } else {
+            value.min(100.0) / 20.0
+        }
+    } else {
+        return None;
+    };
+    Some(((stars * 2.0).round() as i64).clamp(1, MAX_RATING))
+}
+
+/// `RATING` comment value, as a percentage
+pub fn text_rating(rating: i64) -> String {
+    (rating.clamp(0, MAX_RATING) * 10).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_popm_round_trip() {
+        for rating in 1..=MAX_RATING {
+            assert_eq!(rating_from_popm(rating_to_popm(rating)), Some(rating));
+        }
+        assert_eq!(rating_from_popm(0), None);
+
+        // Windows Media Player whole stars
+        assert_eq!(rating_from_popm(64), Some(4));
+        assert_eq!(rating_from_popm(196), Some(8));
+
+        assert_eq!(parse_popm(b"user@example.com\0\xff\0\0\0\x07"), Some(10));
+        assert_eq!(parse_popm(&popm_frame(7)), Some(7));
+        assert_eq!(parse_popm(b"no terminator"), None);
+    }
+
+    #[test]
+    fn test_text_ratings() {
+        assert_eq!(parse_text_rating("RATING", "4"), Some(8));
+        assert_eq!(parse_text_rating("rating", "3.5"), Some(7));
+        assert_eq!(parse_text_rating("RATING", "80"), Some(8));
+        assert_eq!(parse_text_rating("FMPS_RATING", "0.7"), Some(7));
+        assert_eq!(parse_text_rating("RATING", "0"), None);
+        assert_eq!(parse_text_rating("RATING", "great"), None);
+        assert_eq!(parse_text_rating("COMMENT", "5"), None);
+
+        for rating in 1..=MAX_RATING {
+            assert_eq!(
+                parse_text_rating("RATING", &text_rating(rating)),
+                Some(rating)
+            );
+        }
+    }
+
+    #[test]
+    fn test_validate_rating() {
+        assert_eq!(validate_rating(Some(10)), Ok(Some(10)));
+        assert_eq!(validate_rating(None), Ok(None));
+        assert!(validate_rating(Some(11)).is_err());
+        assert!(validate_rating(Some(-1)).is_err());
+    }
+}
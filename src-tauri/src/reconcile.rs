@@ -0,0 +1,291 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Days a missing track is kept before a scan removes it, until the user
+/// picks their own. 0 keeps missing tracks until they are removed by hand.
+pub const DEFAULT_MISSING_GRACE_DAYS: u64 = 30;
+/// Longest grace period accepted in the scan settings, ten years
+pub const MAX_MISSING_GRACE_DAYS: u64 = 3_650;
+
+/// A library track as the reconciliation pass sees it
+pub struct StoredTrack {
+    pub id: i64,
+    /// The audio file: `source_path` for CUE sheet virtual tracks
+    pub path: String,
+    pub missing_since: Option<i64>,
+}
+
+/// Changes to make after a scan
+#[derive(Debug, Default, PartialEq)]
+pub struct Reconciliation {
+    /// Tracks whose file is gone and that are not yet flagged
+    pub missing: Vec<i64>,
+    /// Flagged tracks whose file is back
+    pub found: Vec<i64>,
+    /// Flagged tracks missing for longer than the grace period
+    pub expired: Vec<i64>,
+    /// Scan folders whose root is not mounted; their tracks are left alone
+    pub offline_folders: Vec<String>,
+}
+
+/// Tracks flagged missing before this time (seconds since the epoch) have
+/// outlived a grace period of `grace_days`; `None` when nothing expires
+pub fn expire_before(now: i64, grace_days: u64) -> Option<i64> {
+    if grace_days == 0 {
+        return None;
+    }
+    let grace_secs = i64::try_from(grace_days)
+        .unwrap_or(i64::MAX)
+        .saturating_mul(86_400);
+    Some(now.saturating_sub(grace_secs))
+}
+
+/// Whether a scan folder's root is there to scan. An unmounted volume usually
+/// leaves its mount point behind as an empty directory, so an empty root
+/// counts as offline too.
+pub fn folder_available(root: &Path) -> bool {
+    root.read_dir()
+        .map(|mut entries| entries.next().is_some())
+        .unwrap_or(false)
+}
+
+/// Whether `root`, a scan folder or a directory inside one, can be
+/// reconciled. A directory that was deleted is not offline, so it is the scan
+/// folders containing it that are checked with `available`.
+pub fn root_available(folders: &[String], root: &Path, available: impl Fn(&Path) -> bool) -> bool {
+    folders
+        .iter()
+        .filter(|folder| root.starts_with(folder))
+        .all(|folder| available(Path::new(folder)))
+}
+
+/// `path` moved from under `old_root` to the same place under `new_root`, or
+/// `None` when it is not inside `old_root`. Whole path components are
+/// compared, so `/music` does not contain `/musical`.
+pub fn replace_root(path: &str, old_root: &str, new_root: &str) -> Option<String> {
+    let rest = Path::new(path).strip_prefix(old_root).ok()?;
+    if rest.as_os_str().is_empty() {
+        return Some(new_root.to_string());
+    }
+    Some(
+        Path::new(new_root)
+            .join(rest)
+            .to_string_lossy()
+            .into_owned(),
+    )
+}
+
+/// Scan folders whose root is available, and those that are offline
+fn split_folders(
+    folders: &[String],
+    available: impl Fn(&Path) -> bool,
+) -> (Vec<&String>, Vec<&String>) {
+    folders
+        .iter()
+        .partition(|folder| available(Path::new(folder)))
+}
+
+/// Audio files in the library that are gone from disk, by content hash, so a
+/// new file with the same audio can take over their tracks.
+///
+/// Files the scan found, files that still exist and files inside offline
+/// folders (which may only be unreachable) are left out. Files outside every
+/// scanned folder are included when they no longer exist, so music moved to
+/// a new folder is followed too.
+pub fn vanished_files(
+    folders: &[String],
+    found_files: &HashSet<PathBuf>,
+    hashes: Vec<(String, String)>,
+    available: impl Fn(&Path) -> bool,
+) -> HashMap<String, Vec<String>> {
+    let (_, offline) = split_folders(folders, available);
+    let mut vanished: HashMap<String, Vec<String>> = HashMap::new();
+    for (path, hash) in hashes {
+        let file = Path::new(&path);
+        if found_files.contains(file)
+            || offline.iter().any(|root| file.starts_with(root))
+            || file.exists()
+        {
+            continue;
+        }
+        vanished.entry(hash).or_default().push(path);
+    }
+    vanished
+}
+
+/// Compare the tracks in the library with the files a complete scan of
+/// `folders` found.
+///
+/// Only tracks inside a scanned folder are considered, and tracks inside
+/// offline folders (see `folder_available`) are neither flagged nor expired.
+/// A file the scan did not find is checked on disk before it is flagged, so
+/// an unreadable directory doesn't make its tracks look deleted.
+/// `expire_before` is the Unix time before which flagged tracks expire, if any.
+pub fn reconcile(
+    folders: &[String],
+    found_files: &HashSet<PathBuf>,
+    tracks: &[StoredTrack],
+    expire_before: Option<i64>,
+    available: impl Fn(&Path) -> bool,
+) -> Reconciliation {
+    let mut result = Reconciliation::default();
+    let (online, offline) = split_folders(folders, available);
+    result.offline_folders = offline.into_iter().cloned().collect();
+
+    for track in tracks {
+        let path = Path::new(&track.path);
+        if !online.iter().any(|root| path.starts_with(root)) {
+            continue;
+        }
+        let exists = found_files.contains(path) || path.exists();
+        match (exists, track.missing_since) {
+            (true, Some(_)) => result.found.push(track.id),
+            (false, None) => result.missing.push(track.id),
+            (false, Some(since)) if expire_before.is_some_and(|before| since < before) => {
+                result.expired.push(track.id)
+            }
+            _ => {}
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(id: i64, path: &str, missing_since: Option<i64>) -> StoredTrack {
+        StoredTrack {
+            id,
+            path: path.to_string(),
+            missing_since,
+        }
+    }
+
+    #[test]
+    fn test_reconcile() {
+        let folders = vec!["/music".to_string(), "/mnt/external".to_string()];
+        let found: HashSet<PathBuf> = ["/music/kept.flac", "/music/back.flac"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        let tracks = [
+            track(1, "/music/kept.flac", None),
+            track(2, "/music/gone.flac", None),
+            track(3, "/music/back.flac", Some(100)),
+            track(4, "/music/old.flac", Some(100)),
+            track(5, "/music/recent.flac", Some(900)),
+            // Offline volume and a folder that is no longer scanned
+            track(6, "/mnt/external/a.flac", None),
+            track(7, "/mnt/external/b.flac", Some(100)),
+            track(8, "/elsewhere/c.flac", None),
+            // Shares a prefix with /music but is not inside it
+            track(9, "/musical/d.flac", None),
+        ];
+
+        let result = reconcile(&folders, &found, &tracks, Some(500), |root| {
+            root == Path::new("/music")
+        });
+        assert_eq!(
+            result,
+            Reconciliation {
+                missing: vec![2],
+                found: vec![3],
+                expired: vec![4],
+                offline_folders: vec!["/mnt/external".to_string()],
+            }
+        );
+
+        // Without a grace period nothing expires
+        let result = reconcile(&folders, &found, &tracks, None, |_| true);
+        assert!(result.expired.is_empty());
+        assert_eq!(result.missing, vec![2, 6]);
+    }
+
+    #[test]
+    fn test_reconcile_deleted_directory() {
+        let folders = vec!["/music".to_string(), "/mnt/external".to_string()];
+        let available = |folder: &Path| folder == Path::new("/music");
+        let tracks = [
+            track(1, "/music/Deleted Album/01.flac", None),
+            track(2, "/music/Kept/01.flac", None),
+            track(3, "/mnt/external/Album/01.flac", None),
+        ];
+
+        // A rescan of directories that no longer exist, as after a change
+        let roots = vec![
+            "/music/Deleted Album".to_string(),
+            "/mnt/external/Album".to_string(),
+        ];
+        let result = reconcile(&roots, &HashSet::new(), &tracks, None, |root| {
+            root_available(&folders, root, available)
+        });
+        // The deleted directory's tracks go missing, those on the offline
+        // volume are kept
+        assert_eq!(result.missing, vec![1]);
+        assert_eq!(
+            result.offline_folders,
+            vec!["/mnt/external/Album".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_expire_before() {
+        assert_eq!(expire_before(1_000_000, 0), None);
+        assert_eq!(expire_before(1_000_000, 1), Some(1_000_000 - 86_400));
+        // Absurd settings expire nothing rather than overflowing
+        assert!(expire_before(1_000_000, u64::MAX).unwrap() < 0);
+        assert!(expire_before(1_000_000, i64::MAX as u64 / 1_000).unwrap() < 0);
+        assert_eq!(expire_before(i64::MIN, 1), Some(i64::MIN));
+    }
+
+    #[test]
+    fn test_vanished_files() {
+        let folders = vec!["/music".to_string(), "/mnt/external".to_string()];
+        let found: HashSet<PathBuf> = [PathBuf::from("/music/kept.flac")].into_iter().collect();
+        let hash = |path: &str, hash: &str| (path.to_string(), hash.to_string());
+        let vanished = vanished_files(
+            &folders,
+            &found,
+            vec![
+                hash("/music/kept.flac", "a"),
+                hash("/music/old name.flac", "b"),
+                hash("/mnt/external/unplugged.flac", "c"),
+                hash("/old_nas/music/moved.flac", "d"),
+                hash("/music/copy 1.flac", "e"),
+                hash("/music/copy 2.flac", "e"),
+            ],
+            |root| root == Path::new("/music"),
+        );
+
+        let mut hashes: Vec<&str> = vanished.keys().map(String::as_str).collect();
+        hashes.sort();
+        assert_eq!(hashes, vec!["b", "d", "e"]);
+        assert_eq!(vanished["e"].len(), 2);
+    }
+
+    #[test]
+    fn test_replace_root() {
+        let moved = |path: &str| replace_root(path, "/mnt/old_nas/music/", "/media/music");
+        assert_eq!(
+            moved("/mnt/old_nas/music/A/b.flac").as_deref(),
+            Some("/media/music/A/b.flac")
+        );
+        assert_eq!(moved("/mnt/old_nas/music").as_deref(), Some("/media/music"));
+        assert_eq!(
+            moved("/mnt/old_nas/music/disc.flac#02").as_deref(),
+            Some("/media/music/disc.flac#02")
+        );
+        assert_eq!(moved("/mnt/old_nas/musical/b.flac"), None);
+        assert_eq!(moved("/elsewhere/b.flac"), None);
+    }
+
+    #[test]
+    fn test_folder_available() {
+        let dir = tempfile::TempDir::new().unwrap();
+        assert!(!folder_available(dir.path()));
+        std::fs::write(dir.path().join("a.flac"), b"").unwrap();
+        assert!(folder_available(dir.path()));
+        assert!(!folder_available(&dir.path().join("absent")));
+    }
+}
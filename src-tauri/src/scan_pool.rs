@@ -0,0 +1,223 @@
+use anyhow::Result;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+/// What became of one file of a pass
+pub enum FileRead<T> {
+    /// Left out before reading, e.g. because it did not change
+    Skipped,
+    Read(T),
+    Failed(anyhow::Error),
+}
+
+/// How many files a pass read, skipped and failed to read
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PassCounts {
+    pub scanned: usize,
+    pub skipped: usize,
+    pub errors: usize,
+    pub cancelled: bool,
+}
+
+/// Read `files` on a pool of `workers` threads.
+///
+/// On the calling thread `prepare` turns each file into a job, or `None` to
+/// skip it, and `done` takes in every file skipped or read, in the order they
+/// finish. `read` runs on the workers; one that panics counts as an error
+/// rather than a worker lost while the queue waits for it.
+///
+/// Once `cancelled` is set no more files are prepared, and those still queued
+/// are drained without being read.
+pub fn read_files<S, J, T>(
+    files: &[PathBuf],
+    workers: usize,
+    cancelled: &AtomicBool,
+    state: &mut S,
+    mut prepare: impl FnMut(&mut S, &Path) -> Option<J>,
+    read: impl Fn(&Path, J) -> Result<T> + Sync,
+    mut done: impl FnMut(&mut S, &Path, FileRead<T>),
+) -> PassCounts
+where
+    J: Send,
+    T: Send,
+{
+    let mut counts = PassCounts::default();
+    // `None` for a skipped file
+    let mut finish = |state: &mut S, path: &Path, file: Option<Result<T>>| {
+        let file = match file {
+            None => {
+                counts.skipped += 1;
+                FileRead::Skipped
+            }
+            Some(Ok(read)) => {
+                counts.scanned += 1;
+                FileRead::Read(read)
+            }
+            Some(Err(e)) => {
+                counts.errors += 1;
+                FileRead::Failed(e)
+            }
+        };
+        done(state, path, file);
+    };
+
+    // Bounded, so files are only queued as fast as workers read them
+    let (job_sender, jobs) = mpsc::sync_channel::<(PathBuf, J)>(workers * 2);
+    let jobs = Mutex::new(jobs);
+    let (result_sender, results) = mpsc::channel::<(PathBuf, Result<T>)>();
+    thread::scope(|scope| {
+        for _ in 0..workers.max(1) {
+            let jobs = &jobs;
+            let read = &read;
+            let result_sender = result_sender.clone();
+            scope.spawn(move || loop {
+                let job = match jobs.lock() {
+                    Ok(jobs) => jobs.recv(),
+                    Err(_) => break,
+                };
+                let Ok((path, job)) = job else { break };
+                // After a cancellation the queue is emptied without reading
+                if cancelled.load(Ordering::SeqCst) {
+                    continue;
+                }
+                let file = panic::catch_unwind(AssertUnwindSafe(|| read(&path, job)))
+                    .unwrap_or_else(|_| Err(anyhow::anyhow!("Reading the file crashed")));
+                if result_sender.send((path, file)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(result_sender);
+
+        for path in files {
+            if cancelled.load(Ordering::SeqCst) {
+                break;
+            }
+            let Some(job) = prepare(state, path) else {
+                finish(state, path, None);
+                continue;
+            };
+            if job_sender.send((path.clone(), job)).is_err() {
+                break;
+            }
+            for (path, file) in results.try_iter() {
+                finish(state, &path, Some(file));
+            }
+        }
+
+        // Wait for the files still being read
+        drop(job_sender);
+        for (path, file) in results.iter() {
+            finish(state, &path, Some(file));
+        }
+    });
+
+    counts.cancelled = cancelled.load(Ordering::SeqCst);
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    fn files(count: usize) -> Vec<PathBuf> {
+        (0..count)
+            .map(|i| PathBuf::from(format!("/music/{:02}.flac", i)))
+            .collect()
+    }
+
+    #[test]
+    fn test_counts_add_up_to_the_files() {
+        let files = files(50);
+        let cancelled = AtomicBool::new(false);
+        let mut seen = Vec::new();
+        let counts = read_files(
+            &files,
+            4,
+            &cancelled,
+            &mut seen,
+            // Every fifth file is unchanged
+            |_, path| (!path.to_string_lossy().ends_with("5.flac")).then_some(()),
+            |path, ()| {
+                if path.to_string_lossy().ends_with("7.flac") {
+                    anyhow::bail!("Unreadable");
+                }
+                Ok(path.to_path_buf())
+            },
+            |seen, path, _| seen.push(path.to_path_buf()),
+        );
+
+        assert_eq!(
+            counts,
+            PassCounts {
+                scanned: 40,
+                skipped: 5,
+                errors: 5,
+                cancelled: false,
+            }
+        );
+        assert_eq!(counts.scanned + counts.skipped + counts.errors, files.len());
+        seen.sort();
+        assert_eq!(seen, files);
+    }
+
+    #[test]
+    fn test_panicking_reader_is_an_error() {
+        let files = files(10);
+        let cancelled = AtomicBool::new(false);
+        let mut failures = Vec::new();
+        let counts = read_files(
+            &files,
+            2,
+            &cancelled,
+            &mut failures,
+            |_, _| Some(()),
+            |path, ()| {
+                if path.ends_with("03.flac") {
+                    panic!("bad tag");
+                }
+                Ok(())
+            },
+            |failures, _, file| {
+                if let FileRead::Failed(e) = file {
+                    failures.push(e.to_string());
+                }
+            },
+        );
+
+        assert_eq!(counts.scanned, 9);
+        assert_eq!(counts.errors, 1);
+        assert_eq!(failures, vec!["Reading the file crashed".to_string()]);
+    }
+
+    #[test]
+    fn test_cancelled_pass_drains_the_queue() {
+        let files = files(100);
+        let cancelled = AtomicBool::new(false);
+        let reads = AtomicUsize::new(0);
+        let counts = read_files(
+            &files,
+            1,
+            &cancelled,
+            &mut (),
+            |_, _| Some(()),
+            |_, ()| {
+                // Cancelled while reading the first file
+                reads.fetch_add(1, Ordering::SeqCst);
+                cancelled.store(true, Ordering::SeqCst);
+                Ok(())
+            },
+            |_, _, _| {},
+        );
+
+        assert!(counts.cancelled);
+        // The files queued behind it were dropped without being read
+        assert_eq!(reads.load(Ordering::SeqCst), 1);
+        assert_eq!(counts.scanned, 1);
+        assert!(counts.scanned + counts.skipped + counts.errors < files.len());
+    }
+}
@@ -1,20 +1,93 @@
+use crate::chapters;
+use crate::content_hash;
+use crate::cue::{self, CueSheet};
 use crate::database::Database;
-use crate::models::{ScanDiscovery, ScanError, ScanProgress, ScanResult, Track};
+use crate::models::{
+    AudioProperties, Chapter, ExtendedTags, ScanDiscovery, ScanError, ScanProgress, ScanResult,
+    Track,
+};
+use crate::ratings;
+use crate::reconcile::{self, Reconciliation};
+use crate::scan_pool::{self, FileRead};
 use anyhow::Result;
+use lofty::file::{FileType, TaggedFile};
 use lofty::prelude::{Accessor, AudioFile, TaggedFileExt};
 use lofty::read_from_path;
-use std::collections::HashMap;
+use lofty::tag::{ItemKey, ItemValue, Tag};
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::thread;
 use std::time::Instant;
 use tauri::Emitter;
 use tracing::{error, warn};
 use walkdir::WalkDir;
 
 const BATCH_SIZE: usize = 100;
-const AUDIO_EXTENSIONS: [&str; 8] = ["mp3", "flac", "ogg", "m4a", "wav", "aac", "opus", "wma"];
+const MIN_SCAN_WORKERS: usize = 4;
+const MAX_SCAN_WORKERS: usize = 16;
+const AUDIO_EXTENSIONS: [&str; 9] = [
+    "mp3", "flac", "ogg", "m4a", "m4b", "wav", "aac", "opus", "wma",
+];
+
+/// Tracks and chapters read from one audio file
+struct ScannedFile {
+    tracks: Vec<Track>,
+    /// Chapters of a plain (non-CUE) file; replaces whatever was stored for it
+    chapters: Option<(String, Vec<Chapter>)>,
+    /// Content hash of the audio, to follow moves and renames
+    content_hash: Option<String>,
+}
+
+/// Running totals of a scan's pass over the audio files
+struct FilePass {
+    total: usize,
+    processed: usize,
+    moved: usize,
+    error_files: Vec<String>,
+    written_files: Vec<String>,
+    batch: Vec<Track>,
+    chapter_batch: Vec<(String, Vec<Chapter>)>,
+    started: Instant,
+    last_progress: Instant,
+}
+
+impl FilePass {
+    fn new(total: usize) -> Self {
+        let now = Instant::now();
+        FilePass {
+            total,
+            processed: 0,
+            moved: 0,
+            error_files: Vec::new(),
+            written_files: Vec::new(),
+            batch: Vec::with_capacity(BATCH_SIZE),
+            chapter_batch: Vec::new(),
+            started: now,
+            last_progress: now,
+        }
+    }
+
+    /// Files processed per second since the pass started
+    fn files_per_second(&self) -> f64 {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            self.processed as f64 / elapsed
+        } else {
+            0.0
+        }
+    }
+}
+
+/// A finished scan, with what it changed in the library
+struct ScanOutcome {
+    result: ScanResult,
+    /// Audio files whose tracks were added, updated or moved
+    written_files: Vec<String>,
+    reconciliation: Reconciliation,
+}
 
 /// Scanner with progress reporting, incremental scanning, batching, and cancellation support
 pub struct ScannerWithProgress {
@@ -49,149 +122,476 @@ impl ScannerWithProgress {
 
     /// Main scan entry point with all optimizations
     pub fn scan_with_progress(&self, folders: Vec<String>) -> Result<ScanResult> {
+        self.scan(&folders, None).map(|outcome| outcome.result)
+    }
+
+    /// Scan `folders` like `scan_with_progress`, also returning the ids of the
+    /// tracks that were added, updated, moved, flagged missing or removed
+    pub fn scan_with_changes(&self, folders: &[String]) -> Result<(ScanResult, Vec<i64>)> {
+        let outcome = self.scan(folders, None)?;
+        self.with_changed_ids(outcome)
+    }
+
+    /// Rescan only `paths`, files or directories inside `folders` that changed
+    /// on disk. Also returns the ids of the tracks that changed, as
+    /// `scan_with_changes` does.
+    pub fn scan_changes(
+        &self,
+        folders: &[String],
+        paths: &[PathBuf],
+    ) -> Result<(ScanResult, Vec<i64>)> {
+        let outcome = self.scan(folders, Some(paths))?;
+        self.with_changed_ids(outcome)
+    }
+
+    /// The result of a scan and the ids of the tracks it changed
+    fn with_changed_ids(&self, outcome: ScanOutcome) -> Result<(ScanResult, Vec<i64>)> {
+        let mut ids = self
+            .db
+            .read()
+            .map_err(|e| anyhow::anyhow!("Database lock poisoned: {}", e))?
+            .get_track_ids_for_files(&outcome.written_files)?;
+        let reconciliation = outcome.reconciliation;
+        ids.extend(reconciliation.missing);
+        ids.extend(reconciliation.found);
+        ids.extend(reconciliation.expired);
+        ids.sort_unstable();
+        ids.dedup();
+        Ok((outcome.result, ids))
+    }
+
+    /// Scan `folders`, or only the `changed` paths inside them
+    fn scan(&self, folders: &[String], changed: Option<&[PathBuf]>) -> Result<ScanOutcome> {
         let start_time = Instant::now();
 
         // Reset cancellation flag at start of scan
         self.cancelled.store(false, Ordering::SeqCst);
 
         // 1. Collect all audio file paths first (fast pass)
-        let files = self.collect_audio_files(&folders);
+        let roots: Vec<String> = match changed {
+            Some(paths) => paths
+                .iter()
+                .map(|p| p.to_string_lossy().into_owned())
+                .collect(),
+            None => folders.to_vec(),
+        };
+        let (files, mut cue_files) = self.collect_audio_files(&roots);
+        if changed.is_some() {
+            // The sidecar CUE sheet of a changed file may not have changed itself
+            cue_files.extend(sidecar_cue_sheets(&files));
+            cue_files.sort();
+            cue_files.dedup();
+        }
         let total_files = files.len();
+        let cue_sheets = map_cue_sheets(&cue_files);
 
-        // 2. Get existing files for incremental scanning
-        let existing_files = self
-            .db
-            .lock()
-            .map_err(|e| anyhow::anyhow!("Database lock poisoned: {}", e))?
-            .get_existing_file_info()
-            .unwrap_or_default();
-
-        // 3. Process files with progress reporting and batching
-        let mut scanned_count = 0;
-        let mut skipped_count = 0;
-        let mut error_count = 0;
-        let mut error_files: Vec<String> = Vec::new();
-        let mut batch: Vec<Track> = Vec::with_capacity(BATCH_SIZE);
-
-        for (index, path) in files.iter().enumerate() {
-            // Check for cancellation
-            if self.cancelled.load(Ordering::SeqCst) {
-                // Flush any remaining batch before returning
-                if !batch.is_empty() {
-                    if let Ok(mut db) = self.db.lock() {
-                        let _ = db.insert_tracks_batch(&batch);
+        // 2. Get existing files for incremental scanning, and the content
+        // hashes of files that disappeared to follow moves and renames
+        let found_files: HashSet<PathBuf> = files.iter().cloned().collect();
+        let (existing_files, read_ratings, mut vanished) = {
+            let db = self
+                .db
+                .read()
+                .map_err(|e| anyhow::anyhow!("Database lock poisoned: {}", e))?;
+            let mut hashes = db.get_content_hashes().unwrap_or_default();
+            if changed.is_some() {
+                // A move shows up as changes to both its old and new path
+                hashes
+                    .retain(|(path, _)| roots.iter().any(|root| Path::new(path).starts_with(root)));
+            }
+            (
+                db.get_existing_file_info().unwrap_or_default(),
+                db.load_rating_tags_enabled().unwrap_or(false),
+                reconcile::vanished_files(
+                    folders,
+                    &found_files,
+                    hashes,
+                    reconcile::folder_available,
+                ),
+            )
+        };
+
+        // 3. Read tags and hash the audio on a pool of workers while this
+        // thread skips unchanged files, follows moves, inserts the results in
+        // batches and reports progress
+        let mut pass = FilePass::new(total_files);
+        let counts = scan_pool::read_files(
+            &files,
+            scan_workers(),
+            &self.cancelled,
+            &mut pass,
+            |_, path| {
+                // Check if file needs scanning (incremental)
+                let cue_path = cue_sheets.get(path).map(|p| p.as_path());
+                self.should_scan_file(path, cue_path, &existing_files)
+                    .then(|| cue_path.map(Path::to_path_buf))
+            },
+            // A file with a CUE sheet yields one track per INDEX
+            |path, cue_path| self.scan_file_for_batch(path, cue_path.as_deref(), read_ratings),
+            |pass, path, mut file| {
+                // A new path may be a file that was moved or renamed
+                if let FileRead::Read(scanned) = &mut file {
+                    if !vanished.is_empty()
+                        && self.follow_move(path, scanned, &mut vanished, &existing_files)
+                    {
+                        pass.moved += 1;
                     }
                 }
+                self.add_scanned(pass, path, file)
+            },
+        );
 
-                return Ok(ScanResult {
+        // Insert remaining batch
+        if !pass.batch.is_empty() {
+            self.flush_batch(&mut pass.batch, &mut pass.chapter_batch);
+        }
+
+        if counts.cancelled {
+            return Ok(ScanOutcome {
+                result: ScanResult {
                     total_files,
-                    scanned: scanned_count,
-                    skipped: skipped_count,
-                    errors: error_count,
-                    error_files,
+                    scanned: counts.scanned,
+                    skipped: counts.skipped,
+                    errors: counts.errors,
+                    error_files: pass.error_files,
                     duration_secs: start_time.elapsed().as_secs_f64(),
                     cancelled: true,
-                });
+                    moved: pass.moved,
+                    missing: 0,
+                    purged: 0,
+                    offline_folders: Vec::new(),
+                },
+                written_files: pass.written_files,
+                reconciliation: Reconciliation::default(),
+            });
+        }
+
+        // 4. Flag tracks whose file is gone
+        let reconciliation = self.reconcile_missing(folders, &roots, &found_files);
+
+        // 5. Fill in tags, audio properties and content hashes of tracks
+        // scanned before they were stored
+        if changed.is_none() {
+            self.backfill_tags();
+            self.backfill_audio_properties();
+            self.backfill_content_hashes();
+        }
+
+        let duration_secs = start_time.elapsed().as_secs_f64();
+
+        // Emit completion event
+        if !self.quiet {
+            let completion = ScanProgress {
+                current_file: String::new(),
+                total_files,
+                processed_files: total_files,
+                is_complete: true,
+                files_per_second: pass.files_per_second(),
+                eta_secs: Some(0),
+            };
+            let _ = self.app_handle.emit("scan-progress", &completion);
+        }
+
+        Ok(ScanOutcome {
+            result: ScanResult {
+                total_files,
+                scanned: counts.scanned,
+                skipped: counts.skipped,
+                errors: counts.errors,
+                error_files: pass.error_files,
+                duration_secs,
+                cancelled: false,
+                moved: pass.moved,
+                missing: reconciliation.missing.len(),
+                purged: reconciliation.expired.len(),
+                offline_folders: reconciliation.offline_folders.clone(),
+            },
+            written_files: pass.written_files,
+            reconciliation,
+        })
+    }
+
+    /// Take in a file the workers read or that was skipped, inserting a batch
+    /// when it is full
+    fn add_scanned(&self, pass: &mut FilePass, path: &Path, file: FileRead<ScannedFile>) {
+        match file {
+            FileRead::Skipped => {}
+            FileRead::Read(scanned) => {
+                pass.batch.extend(scanned.tracks);
+                pass.chapter_batch.extend(scanned.chapters);
+                pass.written_files.push(path.to_string_lossy().to_string());
+
+                // Batch insert when batch is full
+                if pass.batch.len() >= BATCH_SIZE {
+                    self.flush_batch(&mut pass.batch, &mut pass.chapter_batch);
+                }
             }
+            FileRead::Failed(e) => {
+                let error_msg = e.to_string();
 
-            // Emit progress event
-            if !self.quiet {
-                let progress = ScanProgress {
-                    current_file: path.display().to_string(),
-                    total_files,
-                    processed_files: index,
-                    is_complete: false,
-                };
-                let _ = self.app_handle.emit("scan-progress", &progress);
+                // Store all error files with their error messages
+                pass.error_files
+                    .push(format!("{}|{}", path.display(), error_msg));
+
+                // Emit error event
+                if !self.quiet {
+                    let scan_error = ScanError {
+                        file: path.display().to_string(),
+                        error: error_msg,
+                    };
+                    let _ = self.app_handle.emit("scan-error", &scan_error);
+                }
             }
+        }
+        self.file_done(pass, path);
+    }
 
-            // Check if file needs scanning (incremental)
-            if !self.should_scan_file(path, &existing_files) {
-                skipped_count += 1;
-                continue;
+    /// Count `path` as processed and emit a progress event, at most every 100ms
+    fn file_done(&self, pass: &mut FilePass, path: &Path) {
+        pass.processed += 1;
+        if self.quiet || pass.last_progress.elapsed().as_millis() <= 100 {
+            return;
+        }
+        let files_per_second = pass.files_per_second();
+        let progress = ScanProgress {
+            current_file: path.display().to_string(),
+            total_files: pass.total,
+            processed_files: pass.processed,
+            is_complete: false,
+            files_per_second,
+            eta_secs: (files_per_second > 0.0)
+                .then(|| ((pass.total - pass.processed) as f64 / files_per_second).ceil() as u64),
+        };
+        let _ = self.app_handle.emit("scan-progress", &progress);
+        pass.last_progress = Instant::now();
+    }
+
+    /// Insert a batch of tracks, then their chapters (which need the track ids)
+    fn flush_batch(&self, batch: &mut Vec<Track>, chapters: &mut Vec<(String, Vec<Chapter>)>) {
+        match self.db.write() {
+            Ok(mut db) => {
+                if let Err(e) = db.insert_tracks_batch(batch) {
+                    warn!("Batch insert error: {}", e);
+                } else if let Err(e) = db.replace_chapters_batch(chapters) {
+                    warn!("Chapter insert error: {}", e);
+                }
             }
+            Err(e) => {
+                error!("Database lock poisoned during batch insert: {}", e);
+            }
+        }
+        batch.clear();
+        chapters.clear();
+    }
 
-            // Process file
-            match self.scan_file_for_batch(path) {
-                Ok(track) => {
-                    batch.push(track);
-                    scanned_count += 1;
-
-                    // Batch insert when batch is full
-                    if batch.len() >= BATCH_SIZE {
-                        match self.db.lock() {
-                            Ok(mut db) => {
-                                if let Err(e) = db.insert_tracks_batch(&batch) {
-                                    warn!("Batch insert error: {}", e);
-                                }
-                            }
-                            Err(e) => {
-                                error!("Database lock poisoned during batch insert: {}", e);
-                            }
-                        }
-                        batch.clear();
-                    }
+    /// If `path` is new to the library and holds the audio of a file that
+    /// disappeared, move that file's tracks to it so they keep their ids, and
+    /// return true. When the file did not change since its tracks were stored,
+    /// the tags read from it are dropped so nothing stored is overwritten.
+    fn follow_move(
+        &self,
+        path: &Path,
+        scanned: &mut ScannedFile,
+        vanished: &mut HashMap<String, Vec<String>>,
+        existing: &HashMap<String, i64>,
+    ) -> bool {
+        let new_path = path.to_string_lossy();
+        if existing.contains_key(new_path.as_ref()) {
+            return false;
+        }
+        let Some(hash) = scanned.content_hash.as_ref() else {
+            return false;
+        };
+        let Some(candidates) = vanished.get_mut(hash) else {
+            return false;
+        };
+        let Some(old_path) = candidates.pop() else {
+            return false;
+        };
+        if candidates.is_empty() {
+            vanished.remove(hash);
+        }
+
+        let moved = match self.db.write() {
+            Ok(mut db) => db.relocate_track_file(&old_path, &new_path),
+            Err(e) => {
+                error!("Database lock poisoned: {}", e);
+                return false;
+            }
+        };
+        if let Err(e) = moved {
+            warn!("Moving tracks of {} failed: {}", old_path, e);
+            return false;
+        }
+
+        let modified = scanned.tracks.first().map_or(0, |t| t.last_modified);
+        if existing
+            .get(&old_path)
+            .is_some_and(|&stored| modified <= stored)
+        {
+            scanned.tracks.clear();
+            scanned.chapters = None;
+        }
+        true
+    }
+
+    /// Flag tracks in the scanned `roots` whose file the scan did not find,
+    /// clear the flag of those that are back, and remove the ones missing for
+    /// longer than the grace period. Roots inside a scan folder on an unmounted
+    /// volume are skipped.
+    fn reconcile_missing(
+        &self,
+        folders: &[String],
+        roots: &[String],
+        found_files: &HashSet<PathBuf>,
+    ) -> Reconciliation {
+        let (tracks, grace_days) = match self.db.read() {
+            Ok(db) => match (db.get_stored_tracks(), db.load_missing_grace_days()) {
+                (Ok(tracks), Ok(grace_days)) => (tracks, grace_days),
+                (Err(e), _) | (_, Err(e)) => {
+                    warn!("Loading tracks to reconcile failed: {}", e);
+                    return Reconciliation::default();
                 }
-                Err(e) => {
-                    error_count += 1;
-                    let error_msg = e.to_string();
-
-                    // Store all error files with their error messages
-                    error_files.push(format!("{}|{}", path.display(), error_msg));
-
-                    // Emit error event
-                    if !self.quiet {
-                        let scan_error = ScanError {
-                            file: path.display().to_string(),
-                            error: error_msg,
-                        };
-                        let _ = self.app_handle.emit("scan-error", &scan_error);
-                    }
+            },
+            Err(e) => {
+                error!("Database lock poisoned: {}", e);
+                return Reconciliation::default();
+            }
+        };
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64);
+        let expire_before = reconcile::expire_before(now, grace_days);
+        let result = reconcile::reconcile(roots, found_files, &tracks, expire_before, |root| {
+            reconcile::root_available(folders, root, reconcile::folder_available)
+        });
+        for folder in &result.offline_folders {
+            warn!("Scan folder {} is offline; its tracks were kept", folder);
+        }
+
+        match self.db.write() {
+            Ok(mut db) => {
+                if let Err(e) = db.set_tracks_missing(&result.missing, &result.found) {
+                    warn!("Flagging missing tracks failed: {}", e);
+                } else if let Err(e) = db.delete_tracks(&result.expired) {
+                    warn!("Removing missing tracks failed: {}", e);
                 }
             }
+            Err(e) => error!("Database lock poisoned: {}", e),
         }
+        result
+    }
 
-        // Insert remaining batch
-        if !batch.is_empty() {
-            match self.db.lock() {
+    /// Read the tags added since existing tracks were scanned, without
+    /// replacing what was edited in the app. Runs once after an upgrade.
+    fn backfill_tags(&self) {
+        let pending = match self.db.read() {
+            Ok(db) => db.get_tracks_with_pending_tags().unwrap_or_default(),
+            Err(e) => {
+                error!("Database lock poisoned: {}", e);
+                return;
+            }
+        };
+
+        for chunk in pending.chunks(BATCH_SIZE) {
+            if self.cancelled.load(Ordering::SeqCst) {
+                return;
+            }
+            let updates: Vec<(i64, Option<Track>)> = chunk
+                .iter()
+                .map(|(id, path)| (*id, pending_tags(Path::new(path))))
+                .collect();
+            match self.db.write() {
                 Ok(mut db) => {
-                    if let Err(e) = db.insert_tracks_batch(&batch) {
-                        warn!("Final batch insert error: {}", e);
+                    if let Err(e) = db.update_pending_tags(&updates) {
+                        warn!("Tag update error: {}", e);
                     }
                 }
-                Err(e) => {
-                    error!("Database lock poisoned during final batch insert: {}", e);
-                }
+                Err(e) => error!("Database lock poisoned: {}", e),
             }
         }
+    }
 
-        let duration_secs = start_time.elapsed().as_secs_f64();
+    /// Read the audio properties of tracks that have none, without rescanning
+    /// their tags. Virtual tracks of a CUE sheet share their file's properties.
+    fn backfill_audio_properties(&self) {
+        let pending = match self.db.read() {
+            Ok(db) => db.get_tracks_without_audio_properties().unwrap_or_default(),
+            Err(e) => {
+                error!("Database lock poisoned: {}", e);
+                return;
+            }
+        };
 
-        // Emit completion event
-        if !self.quiet {
-            let completion = ScanProgress {
-                current_file: String::new(),
-                total_files,
-                processed_files: total_files,
-                is_complete: true,
-            };
-            let _ = self.app_handle.emit("scan-progress", &completion);
+        let mut read: HashMap<String, Option<AudioProperties>> = HashMap::new();
+        for chunk in pending.chunks(BATCH_SIZE) {
+            if self.cancelled.load(Ordering::SeqCst) {
+                return;
+            }
+            let updates: Vec<(i64, Option<AudioProperties>)> = chunk
+                .iter()
+                .map(|(id, path)| {
+                    let audio = read.entry(path.clone()).or_insert_with(|| {
+                        read_from_path(path)
+                            .ok()
+                            .map(|file| audio_properties(&file))
+                    });
+                    (*id, audio.clone())
+                })
+                .collect();
+            match self.db.write() {
+                Ok(mut db) => {
+                    if let Err(e) = db.update_audio_properties(&updates) {
+                        warn!("Audio properties update error: {}", e);
+                    }
+                }
+                Err(e) => error!("Database lock poisoned: {}", e),
+            }
         }
+    }
 
-        Ok(ScanResult {
-            total_files,
-            scanned: scanned_count,
-            skipped: skipped_count,
-            errors: error_count,
-            error_files,
-            duration_secs,
-            cancelled: false,
-        })
+    /// Hash the audio of tracks scanned before content hashes were stored, so
+    /// they can be followed when their file is moved
+    fn backfill_content_hashes(&self) {
+        let pending = match self.db.read() {
+            Ok(db) => db.get_paths_without_content_hash().unwrap_or_default(),
+            Err(e) => {
+                error!("Database lock poisoned: {}", e);
+                return;
+            }
+        };
+
+        for chunk in pending.chunks(BATCH_SIZE) {
+            if self.cancelled.load(Ordering::SeqCst) {
+                return;
+            }
+            let hashes: Vec<(String, String)> = chunk
+                .iter()
+                .filter_map(|path| {
+                    content_hash::audio_hash(Path::new(path))
+                        .ok()
+                        .map(|hash| (path.clone(), hash))
+                })
+                .collect();
+            if hashes.is_empty() {
+                continue;
+            }
+            match self.db.write() {
+                Ok(mut db) => {
+                    if let Err(e) = db.update_content_hashes(&hashes) {
+                        warn!("Content hash update error: {}", e);
+                    }
+                }
+                Err(e) => error!("Database lock poisoned: {}", e),
+            }
+        }
     }
 
-    /// Collect all audio files from folders (first pass - fast) with discovery events
-    fn collect_audio_files(&self, folders: &[String]) -> Vec<PathBuf> {
+    /// Collect all audio files and CUE sheets from folders (first pass - fast) with discovery events
+    fn collect_audio_files(&self, folders: &[String]) -> (Vec<PathBuf>, Vec<PathBuf>) {
         let mut files = Vec::new();
+        let mut cue_files = Vec::new();
         let mut last_emit = Instant::now();
 
         for folder_path in folders {
@@ -218,7 +618,7 @@ impl ScannerWithProgress {
             {
                 // Check for cancellation during discovery
                 if self.cancelled.load(Ordering::SeqCst) {
-                    return files;
+                    return (files, cue_files);
                 }
 
                 let path = entry.path();
@@ -226,7 +626,9 @@ impl ScannerWithProgress {
                 if path.is_file() {
                     if let Some(ext) = path.extension() {
                         let ext_lower = ext.to_string_lossy().to_lowercase();
-                        if AUDIO_EXTENSIONS.contains(&ext_lower.as_str()) {
+                        if ext_lower == "cue" {
+                            cue_files.push(path.to_path_buf());
+                        } else if AUDIO_EXTENSIONS.contains(&ext_lower.as_str()) {
                             files.push(path.to_path_buf());
 
                             // Emit progress every 100ms to avoid flooding
@@ -259,32 +661,38 @@ impl ScannerWithProgress {
             );
         }
 
-        files
+        (files, cue_files)
     }
 
-    /// Check if a file needs scanning (for incremental scan support)
-    fn should_scan_file(&self, path: &Path, existing: &HashMap<String, i64>) -> bool {
+    /// Check if a file needs scanning (for incremental scan support).
+    /// Editing a sidecar CUE sheet also triggers a rescan of its audio file.
+    fn should_scan_file(
+        &self,
+        path: &Path,
+        cue_path: Option<&Path>,
+        existing: &HashMap<String, i64>,
+    ) -> bool {
         let path_str = path.to_string_lossy().to_string();
 
         match existing.get(&path_str) {
             None => true, // New file - needs scanning
             Some(&db_modified) => {
-                // Check if file was modified since last scan
-                let file_modified = fs::metadata(path)
-                    .and_then(|m| m.modified())
-                    .map(|t| {
-                        t.duration_since(std::time::UNIX_EPOCH)
-                            .unwrap_or(std::time::Duration::ZERO)
-                            .as_secs() as i64
-                    })
-                    .unwrap_or(0);
+                // Check if file (or its CUE sheet) was modified since last scan
+                let file_modified = modified_secs(path).max(cue_path.map_or(0, modified_secs));
                 file_modified > db_modified
             }
         }
     }
 
-    /// Scan a single file and return Track (doesn't insert into DB - that's done in batches)
-    fn scan_file_for_batch(&self, path: &Path) -> Result<Track> {
+    /// Scan a single file and return its tracks (doesn't insert into DB - that's done in batches).
+    /// Files without a CUE sheet yield exactly one track, plus any chapter markers.
+    /// `read_ratings` takes the rating from the file's tags (see `ratings.rs`).
+    fn scan_file_for_batch(
+        &self,
+        path: &Path,
+        cue_path: Option<&Path>,
+        read_ratings: bool,
+    ) -> Result<ScannedFile> {
         let file_path = path.to_string_lossy().to_string();
         let metadata = fs::metadata(path)?;
         let file_size = metadata.len() as i64;
@@ -293,6 +701,7 @@ impl ScannerWithProgress {
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap_or(std::time::Duration::ZERO)
             .as_secs() as i64;
+        let last_modified = last_modified.max(cue_path.map_or(0, modified_secs));
 
         let extension = path
             .extension()
@@ -307,6 +716,16 @@ impl ScannerWithProgress {
         let mut year = None;
         let mut genre = None;
         let mut track_number = None;
+        let mut album_artist = None;
+        let mut disc_number = None;
+        let mut disc_total = None;
+        let mut track_total = None;
+        let mut compilation = false;
+        let mut extended = ExtendedTags::default();
+        let mut audio = AudioProperties::default();
+        let mut rating = None;
+        let mut embedded_cue: Option<CueSheet> = None;
+        let mut vorbis_chapters: Vec<Chapter> = Vec::new();
         let duration: Option<i64>;
 
         // Skip files that are likely sound effects based on path
@@ -338,17 +757,59 @@ impl ScannerWithProgress {
                 }
 
                 duration = Some(duration_secs);
+                audio = audio_properties(&tagged_file);
 
                 if let Some(tag) = tagged_file
                     .primary_tag()
                     .or_else(|| tagged_file.first_tag())
                 {
                     title = tag.title().map(|s| s.to_string());
-                    artist = tag.artist().map(|s| s.to_string());
+                    artist = joined_values(tag, &ItemKey::TrackArtist)
+                        .or_else(|| tag.artist().map(|s| s.to_string()));
                     album = tag.album().map(|s| s.to_string());
                     year = tag.year().map(|y| y as i64);
-                    genre = tag.genre().map(|s| s.to_string());
+                    genre = joined_values(tag, &ItemKey::Genre)
+                        .or_else(|| tag.genre().map(|s| s.to_string()));
                     track_number = tag.track().map(|t| t as i64);
+                    track_total = tag.track_total().map(|t| t as i64);
+                    disc_number = tag.disk().map(|d| d as i64);
+                    disc_total = tag.disk_total().map(|d| d as i64);
+                    album_artist = tag.get_string(&ItemKey::AlbumArtist).map(|s| s.to_string());
+                    compilation = tag
+                        .get_string(&ItemKey::FlagCompilation)
+                        .is_some_and(is_flag_set);
+                    extended = extended_tags(tag);
+
+                    // ID3v2 POPM frames, or RATING / FMPS_RATING comments
+                    if read_ratings {
+                        rating = tag
+                            .items()
+                            .find_map(|item| match (item.key(), item.value()) {
+                                (ItemKey::Popularimeter, ItemValue::Binary(data)) => {
+                                    ratings::parse_popm(data)
+                                }
+                                (ItemKey::Unknown(key), ItemValue::Text(value)) => {
+                                    ratings::parse_text_rating(key, value)
+                                }
+                                _ => None,
+                            });
+                    }
+
+                    // FLAC/Vorbis use CUESHEET, APEv2 uses Cuesheet
+                    embedded_cue = ["CUESHEET", "Cuesheet"]
+                        .iter()
+                        .find_map(|key| tag.get_string(&ItemKey::Unknown(key.to_string())))
+                        .map(cue::parse_cue);
+
+                    // Ogg/FLAC chapters are CHAPTERxxx comments (unmapped keys in lofty)
+                    vorbis_chapters = chapters::vorbis_chapters(
+                        tag.items()
+                            .filter_map(|item| match (item.key(), item.value().text()) {
+                                (ItemKey::Unknown(key), Some(value)) => Some((key.as_str(), value)),
+                                _ => None,
+                            }),
+                        Some(duration_secs * 1000),
+                    );
                 }
             }
             Err(_) => {
@@ -356,7 +817,7 @@ impl ScannerWithProgress {
                 // Typical bitrates: MP3 ~128-320kbps, FLAC ~800-1400kbps
                 let estimated_bitrate = match extension.as_str() {
                     "mp3" => 192_000, // 192 kbps average
-                    "m4a" | "aac" => 192_000,
+                    "m4a" | "m4b" | "aac" => 192_000,
                     "ogg" | "opus" => 160_000,
                     "flac" => 900_000,  // ~900 kbps average
                     "wav" => 1_411_000, // CD quality
@@ -386,9 +847,10 @@ impl ScannerWithProgress {
             }
         }
 
-        Ok(Track {
+        let content_hash = content_hash::audio_hash(path).ok();
+        let track = Track {
             id: 0,
-            file_path,
+            file_path: file_path.clone(),
             title,
             artist,
             album,
@@ -396,12 +858,223 @@ impl ScannerWithProgress {
             year,
             genre,
             track_number,
+            album_artist,
+            disc_number,
+            disc_total,
+            track_total,
+            compilation,
+            tags: extended,
+            audio,
             file_size,
             file_format: extension,
             last_modified,
             metadata_fetched: false,
             release_mbid: None,
             created_at: 0,
+            rating,
+            content_hash: content_hash.clone(),
+            ..Default::default()
+        };
+
+        // A sidecar sheet wins over an embedded one; the embedded sheet describes this file only
+        let sheet_segments = match cue_path.and_then(|p| cue::read_cue_file(p).ok()) {
+            Some(sheet) => {
+                let file_name = path.file_name().map(|n| n.to_string_lossy().to_string());
+                let segments = sheet.segments(file_name.as_deref());
+                Some((sheet, segments))
+            }
+            None => match embedded_cue {
+                Some(sheet) => {
+                    let segments = sheet.segments(None);
+                    Some((sheet, segments))
+                }
+                None => None,
+            },
+        };
+
+        let (sheet, segments) = match sheet_segments {
+            Some((sheet, segments)) if !segments.is_empty() => (sheet, segments),
+            _ => {
+                let chapters = if vorbis_chapters.is_empty() {
+                    chapters::read_chapters(path, duration.map(|d| d * 1000)).unwrap_or_else(|e| {
+                        warn!("Failed to read chapters from {}: {}", path.display(), e);
+                        Vec::new()
+                    })
+                } else {
+                    vorbis_chapters
+                };
+                return Ok(ScannedFile {
+                    tracks: vec![track],
+                    chapters: Some((file_path, chapters)),
+                    content_hash,
+                });
+            }
+        };
+
+        Ok(ScannedFile {
+            tracks: cue::virtual_tracks(&track, &sheet, &segments),
+            chapters: None,
+            content_hash,
+        })
+    }
+}
+
+/// A field stored as several values (repeated Vorbis comments, ID3v2.4 lists),
+/// joined with the default separator so it is split again into the same values
+fn joined_values(tag: &Tag, key: &ItemKey) -> Option<String> {
+    let values: Vec<&str> = tag.get_strings(key).collect();
+    (values.len() > 1).then(|| values.join("; "))
+}
+
+/// Extended tags through lofty's generic keys, which map to the matching ID3
+/// frame, Vorbis comment, MP4 atom or APE item for each format
+fn extended_tags(tag: &Tag) -> ExtendedTags {
+    let text = |key: ItemKey| {
+        tag.get_string(&key)
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+    };
+    ExtendedTags {
+        composer: text(ItemKey::Composer),
+        conductor: text(ItemKey::Conductor),
+        performer: text(ItemKey::Performer),
+        work: text(ItemKey::Work),
+        movement: text(ItemKey::Movement),
+        label: text(ItemKey::Label),
+        catalog_number: text(ItemKey::CatalogNumber),
+        isrc: text(ItemKey::Isrc),
+        // MP4 stores an integer `tmpo`, the others text that may have decimals
+        bpm: text(ItemKey::IntegerBpm)
+            .or_else(|| text(ItemKey::Bpm))
+            .and_then(|v| v.parse::<f64>().ok())
+            .filter(|v| v.is_finite() && *v > 0.0)
+            .map(|v| v.round() as i64),
+        comment: tag.comment().map(|s| s.to_string()),
+        title_sort: text(ItemKey::TrackTitleSortOrder),
+        artist_sort: text(ItemKey::TrackArtistSortOrder),
+        album_sort: text(ItemKey::AlbumTitleSortOrder),
+    }
+}
+
+/// The tags `backfill_tags` reads again, or `None` when the file can't be read
+fn pending_tags(path: &Path) -> Option<Track> {
+    let file = read_from_path(path).ok()?;
+    let Some(tag) = file.primary_tag().or_else(|| file.first_tag()) else {
+        return Some(Track::default());
+    };
+    Some(Track {
+        album_artist: tag.get_string(&ItemKey::AlbumArtist).map(|s| s.to_string()),
+        disc_number: tag.disk().map(|d| d as i64),
+        disc_total: tag.disk_total().map(|d| d as i64),
+        track_total: tag.track_total().map(|t| t as i64),
+        compilation: tag
+            .get_string(&ItemKey::FlagCompilation)
+            .is_some_and(is_flag_set),
+        tags: extended_tags(tag),
+        ..Default::default()
+    })
+}
+
+/// Bitrate, sample rate, bit depth and channels from the audio headers, and
+/// the codec worked out from the container
+fn audio_properties(file: &TaggedFile) -> AudioProperties {
+    let properties = file.properties();
+    let bit_depth = properties.bit_depth().map(i64::from);
+    let codec = match file.file_type() {
+        FileType::Aac => Some(("AAC", false)),
+        FileType::Aiff => Some(("AIFF", true)),
+        FileType::Ape => Some(("APE", true)),
+        FileType::Flac => Some(("FLAC", true)),
+        FileType::Mpeg => Some(("MP3", false)),
+        // ALAC is the only MP4 codec lofty reports a bit depth for
+        FileType::Mp4 if bit_depth.is_some() => Some(("ALAC", true)),
+        FileType::Mp4 => Some(("AAC", false)),
+        FileType::Mpc => Some(("Musepack", false)),
+        FileType::Opus => Some(("Opus", false)),
+        FileType::Speex => Some(("Speex", false)),
+        FileType::Vorbis => Some(("Vorbis", false)),
+        FileType::Wav => Some(("PCM", true)),
+        FileType::WavPack => Some(("WavPack", true)),
+        _ => None,
+    };
+    AudioProperties {
+        bitrate: properties
+            .audio_bitrate()
+            .or_else(|| properties.overall_bitrate())
+            .filter(|&kbps| kbps > 0)
+            .map(i64::from),
+        sample_rate: properties.sample_rate().map(i64::from),
+        bit_depth,
+        channels: properties.channels().map(i64::from),
+        codec: codec.map(|(name, _)| name.to_string()),
+        lossless: codec.map(|(_, lossless)| lossless),
+    }
+}
+
+/// Tag flags are written as `1`, `true` or `yes` depending on the tagger
+fn is_flag_set(value: &str) -> bool {
+    matches!(
+        value.trim().to_ascii_lowercase().as_str(),
+        "1" | "true" | "yes"
+    )
+}
+
+/// Number of threads reading tags. Reading mostly waits on the disk or the
+/// network, so small machines get more workers than cores.
+fn scan_workers() -> usize {
+    thread::available_parallelism()
+        .map_or(MIN_SCAN_WORKERS, |n| n.get())
+        .clamp(MIN_SCAN_WORKERS, MAX_SCAN_WORKERS)
+}
+
+/// Whether `path` has the extension of an audio file the scanner reads
+pub(crate) fn is_audio_file(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| {
+        AUDIO_EXTENSIONS.contains(&ext.to_string_lossy().to_lowercase().as_str())
+    })
+}
+
+/// CUE sheets in the directories of `files`
+fn sidecar_cue_sheets(files: &[PathBuf]) -> Vec<PathBuf> {
+    let dirs: HashSet<&Path> = files.iter().filter_map(|f| f.parent()).collect();
+    dirs.into_iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.is_file()
+                && path
+                    .extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("cue"))
         })
+        .collect()
+}
+
+/// Map each audio file to the sidecar CUE sheet that references it
+fn map_cue_sheets(cue_files: &[PathBuf]) -> HashMap<PathBuf, PathBuf> {
+    let mut map = HashMap::new();
+    for cue_path in cue_files {
+        match cue::read_cue_file(cue_path) {
+            Ok(sheet) => {
+                for (_, audio_path) in sheet.resolve_files(cue_path, &AUDIO_EXTENSIONS) {
+                    map.insert(audio_path, cue_path.clone());
+                }
+            }
+            Err(e) => warn!("Failed to read CUE sheet {}: {}", cue_path.display(), e),
+        }
     }
+    map
+}
+
+/// Modification time in seconds since the epoch, or 0 if unavailable
+fn modified_secs(path: &Path) -> i64 {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .map(|t| {
+            t.duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or(std::time::Duration::ZERO)
+                .as_secs() as i64
+        })
+        .unwrap_or(0)
 }
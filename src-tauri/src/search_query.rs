@@ -0,0 +1,542 @@
+use crate::ratings;
+use std::fmt;
+
+/// Text columns matched as case-insensitive substrings (`artist:radiohead`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextField {
+    Title,
+    Artist,
+    Album,
+    AlbumArtist,
+    Genre,
+    Path,
+    Composer,
+    Conductor,
+    Performer,
+    Work,
+    Movement,
+    Label,
+    CatalogNumber,
+    Isrc,
+    Comment,
+}
+
+/// Integer columns compared against a number or range (`year:1995..2000`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumberField {
+    Year,
+    /// Seconds; `m:ss` and `h:mm:ss` are accepted as well
+    Duration,
+    /// Written in stars (`rating:>=3.5`), compared in half stars
+    Rating,
+    PlayCount,
+    SkipCount,
+    Disc,
+    Bpm,
+    /// kbps; a `k` or `kbps` suffix is accepted
+    Bitrate,
+    /// Hz; `44.1k` and `96khz` are accepted as well
+    SampleRate,
+    BitDepth,
+    Channels,
+}
+
+/// A numeric comparison. Ranges are inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Range {
+    Eq(i64),
+    Lt(i64),
+    Le(i64),
+    Gt(i64),
+    Ge(i64),
+    Between(i64, i64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    /// Free text matched against the full-text index. Bare words match as
+    /// prefixes, quoted text as an exact phrase.
+    Text {
+        text: String,
+        phrase: bool,
+    },
+    Field(TextField, String),
+    Number(NumberField, Range),
+    /// File format, compared case-insensitively (`format:flac`)
+    Format(String),
+    /// `favorite:yes` or `favorite:no`
+    Favorite(bool),
+    /// Audio codec, compared case-insensitively (`codec:alac`)
+    Codec(String),
+    /// `lossless:yes` or `lossless:no`
+    Lossless(bool),
+    /// `hires:yes`: lossless with more than 16 bits or 48 kHz
+    HiRes(bool),
+}
+
+/// A term, optionally negated with a leading `-`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clause {
+    pub negated: bool,
+    pub term: Term,
+}
+
+/// A parsed search. All clauses must match.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchQuery {
+    pub clauses: Vec<Clause>,
+}
+
+/// A syntax error. `position` is the 0-based character offset into the query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+fn error(position: usize, message: impl Into<String>) -> QueryError {
+    QueryError {
+        position,
+        message: message.into(),
+    }
+}
+
+impl SearchQuery {
+    /// Parse a query such as
+    /// `artist:radiohead year:1995..2000 genre:"trip hop" format:flac duration:>300 -live`.
+    pub fn parse(input: &str) -> Result<Self, QueryError> {
+        let mut parser = Parser {
+            chars: input.chars().collect(),
+            pos: 0,
+        };
+        let mut clauses = Vec::new();
+        loop {
+            parser.skip_whitespace();
+            if parser.peek().is_none() {
+                break;
+            }
+            clauses.push(parser.clause()?);
+        }
+        Ok(SearchQuery { clauses })
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn clause(&mut self) -> Result<Clause, QueryError> {
+        let start = self.pos;
+        let negated = self.peek() == Some('-');
+        if negated {
+            self.pos += 1;
+            if self.peek().is_none_or(char::is_whitespace) {
+                return Err(error(start, "Expected a term after '-'"));
+            }
+        }
+
+        if let Some(name) = self.field_name() {
+            let name_pos = self.pos;
+            self.pos += name.len() + 1;
+            let value_pos = self.pos;
+            let (value, _) = self.value()?;
+            if !value.trim().is_empty() {
+                if let Some(term) = field_term(&name, value.trim(), value_pos)? {
+                    return Ok(Clause { negated, term });
+                }
+            }
+            // Not a filter after all, like "Re: Stacks" or "Live: Dublin"
+            self.pos = name_pos;
+        }
+
+        let (text, phrase) = self.value()?;
+        Ok(Clause {
+            negated,
+            term: Term::Text { text, phrase },
+        })
+    }
+
+    /// The lowercased name of a `field:` prefix at the cursor
+    fn field_name(&self) -> Option<String> {
+        let name: String = self.chars[self.pos..]
+            .iter()
+            .take_while(|c| c.is_ascii_alphabetic())
+            .collect();
+        let followed_by_colon = self.chars.get(self.pos + name.len()) == Some(&':');
+        (!name.is_empty() && followed_by_colon).then(|| name.to_ascii_lowercase())
+    }
+
+    /// A quoted string, or a run of non-whitespace characters. Returns whether it was quoted.
+    fn value(&mut self) -> Result<(String, bool), QueryError> {
+        if self.peek() == Some('"') {
+            let open = self.pos;
+            self.pos += 1;
+            let mut value = String::new();
+            loop {
+                match self.peek() {
+                    Some('"') => {
+                        self.pos += 1;
+                        return Ok((value, true));
+                    }
+                    Some(c) => {
+                        value.push(c);
+                        self.pos += 1;
+                    }
+                    None => return Err(error(open, "Unterminated quote")),
+                }
+            }
+        }
+
+        let mut value = String::new();
+        while let Some(c) = self.peek().filter(|c| !c.is_whitespace()) {
+            value.push(c);
+            self.pos += 1;
+        }
+        Ok((value, false))
+    }
+}
+
+/// The filter for a known field name, or `None` for any other word
+fn field_term(name: &str, value: &str, value_pos: usize) -> Result<Option<Term>, QueryError> {
+    let text = |field| Term::Field(field, value.to_string());
+    Ok(Some(match name {
+        "title" => text(TextField::Title),
+        "artist" => text(TextField::Artist),
+        "album" => text(TextField::Album),
+        "albumartist" => text(TextField::AlbumArtist),
+        "genre" => text(TextField::Genre),
+        "path" => text(TextField::Path),
+        "composer" => text(TextField::Composer),
+        "conductor" => text(TextField::Conductor),
+        "performer" => text(TextField::Performer),
+        "work" => text(TextField::Work),
+        "movement" => text(TextField::Movement),
+        "label" => text(TextField::Label),
+        "catalog" => text(TextField::CatalogNumber),
+        "isrc" => text(TextField::Isrc),
+        "comment" => text(TextField::Comment),
+        "format" => Term::Format(value.trim_start_matches('.').to_ascii_lowercase()),
+        "year" => Term::Number(
+            NumberField::Year,
+            parse_range(value, value_pos, parse_integer)?,
+        ),
+        "duration" => Term::Number(
+            NumberField::Duration,
+            parse_range(value, value_pos, parse_duration)?,
+        ),
+        "rating" => Term::Number(
+            NumberField::Rating,
+            parse_range(value, value_pos, parse_stars)?,
+        ),
+        "plays" => Term::Number(
+            NumberField::PlayCount,
+            parse_range(value, value_pos, parse_integer)?,
+        ),
+        "skips" => Term::Number(
+            NumberField::SkipCount,
+            parse_range(value, value_pos, parse_integer)?,
+        ),
+        "disc" => Term::Number(
+            NumberField::Disc,
+            parse_range(value, value_pos, parse_integer)?,
+        ),
+        "bpm" => Term::Number(
+            NumberField::Bpm,
+            parse_range(value, value_pos, parse_integer)?,
+        ),
+        "bitrate" => Term::Number(
+            NumberField::Bitrate,
+            parse_range(value, value_pos, parse_bitrate)?,
+        ),
+        "samplerate" => Term::Number(
+            NumberField::SampleRate,
+            parse_range(value, value_pos, parse_sample_rate)?,
+        ),
+        "bitdepth" => Term::Number(
+            NumberField::BitDepth,
+            parse_range(value, value_pos, parse_integer)?,
+        ),
+        "channels" => Term::Number(
+            NumberField::Channels,
+            parse_range(value, value_pos, parse_integer)?,
+        ),
+        "codec" => Term::Codec(value.to_ascii_lowercase()),
+        "favorite" => Term::Favorite(parse_flag(value, value_pos)?),
+        "lossless" => Term::Lossless(parse_flag(value, value_pos)?),
+        "hires" => Term::HiRes(parse_flag(value, value_pos)?),
+        _ => return Ok(None),
+    }))
+}
+
+/// Parse `N`, `=N`, `>N`, `>=N`, `<N`, `<=N`, `A..B`, `A..` or `..B`
+fn parse_range(
+    value: &str,
+    position: usize,
+    parse_number: fn(&str) -> Option<i64>,
+) -> Result<Range, QueryError> {
+    let number = |text: &str, at: usize| {
+        parse_number(text).ok_or_else(|| error(at, format!("Invalid number '{}'", text)))
+    };
+
+    if let Some((low, high)) = value.split_once("..") {
+        let high_pos = position + low.chars().count() + 2;
+        let low_value = (!low.is_empty())
+            .then(|| number(low, position))
+            .transpose()?;
+        let high_value = (!high.is_empty())
+            .then(|| number(high, high_pos))
+            .transpose()?;
+        return match (low_value, high_value) {
+            (Some(a), Some(b)) if a > b => Err(error(
+                position,
+                format!("Range start {} is after its end {}", low, high),
+            )),
+            (Some(a), Some(b)) => Ok(Range::Between(a, b)),
+            (Some(a), None) => Ok(Range::Ge(a)),
+            (None, Some(b)) => Ok(Range::Le(b)),
+            (None, None) => Err(error(position, "Expected a number around '..'")),
+        };
+    }
+
+    let operators: [(&str, fn(i64) -> Range); 5] = [
+        (">=", Range::Ge),
+        ("<=", Range::Le),
+        (">", Range::Gt),
+        ("<", Range::Lt),
+        ("=", Range::Eq),
+    ];
+    for (op, range) in operators {
+        if let Some(rest) = value.strip_prefix(op) {
+            return number(rest, position + op.len()).map(range);
+        }
+    }
+    number(value, position).map(Range::Eq)
+}
+
+/// `yes`, `true` or `1`; `no`, `false` or `0`
+fn parse_flag(value: &str, position: usize) -> Result<bool, QueryError> {
+    match value.to_ascii_lowercase().as_str() {
+        "yes" | "true" | "1" => Ok(true),
+        "no" | "false" | "0" => Ok(false),
+        _ => Err(error(
+            position,
+            format!("Expected yes or no, found '{}'", value),
+        )),
+    }
+}
+
+fn parse_integer(text: &str) -> Option<i64> {
+    text.parse::<u32>().ok().map(i64::from)
+}
+
+/// kbps, optionally followed by `k` or `kbps`
+fn parse_bitrate(text: &str) -> Option<i64> {
+    let text = text.to_ascii_lowercase();
+    parse_integer(
+        text.strip_suffix("kbps")
+            .or_else(|| text.strip_suffix('k'))
+            .unwrap_or(&text),
+    )
+}
+
+/// Hz, or kHz when followed by `k` or `khz` (`44.1k`)
+fn parse_sample_rate(text: &str) -> Option<i64> {
+    let text = text.to_ascii_lowercase();
+    match text.strip_suffix("khz").or_else(|| text.strip_suffix('k')) {
+        Some(khz) => {
+            let hz = khz.parse::<f64>().ok()? * 1000.0;
+            (hz.is_finite() && (0.0..=u32::MAX as f64).contains(&hz)).then(|| hz.round() as i64)
+        }
+        None => parse_integer(text.strip_suffix("hz").unwrap_or(&text)),
+    }
+}
+
+/// Stars in whole or half steps (`4`, `3.5`), as half stars
+fn parse_stars(text: &str) -> Option<i64> {
+    let half_stars = text.parse::<f64>().ok()? * 2.0;
+    let valid =
+        half_stars.fract() == 0.0 && (0.0..=ratings::MAX_RATING as f64).contains(&half_stars);
+    valid.then_some(half_stars as i64)
+}
+
+/// Seconds, or `m:ss` / `h:mm:ss`
+fn parse_duration(text: &str) -> Option<i64> {
+    if text.split(':').count() > 3 {
+        return None;
+    }
+    text.split(':')
+        .try_fold(0i64, |total, part| Some(total * 60 + parse_integer(part)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clause(negated: bool, term: Term) -> Clause {
+        Clause { negated, term }
+    }
+
+    #[test]
+    fn test_parse_full_query() {
+        let query = SearchQuery::parse(
+            "artist:radiohead year:1995..2000 genre:\"trip hop\" format:FLAC duration:>300 -live",
+        )
+        .unwrap();
+
+        assert_eq!(
+            query.clauses,
+            vec![
+                clause(false, Term::Field(TextField::Artist, "radiohead".into())),
+                clause(
+                    false,
+                    Term::Number(NumberField::Year, Range::Between(1995, 2000))
+                ),
+                clause(false, Term::Field(TextField::Genre, "trip hop".into())),
+                clause(false, Term::Format("flac".into())),
+                clause(false, Term::Number(NumberField::Duration, Range::Gt(300))),
+                clause(
+                    true,
+                    Term::Text {
+                        text: "live".into(),
+                        phrase: false
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_ranges_and_durations() {
+        let range = |q: &str| match SearchQuery::parse(q).unwrap().clauses[0].term {
+            Term::Number(_, range) => range,
+            ref other => panic!("unexpected term {:?}", other),
+        };
+        assert_eq!(range("year:1999"), Range::Eq(1999));
+        assert_eq!(range("year:2000.."), Range::Ge(2000));
+        assert_eq!(range("year:..1980"), Range::Le(1980));
+        assert_eq!(range("year:<=1980"), Range::Le(1980));
+        assert_eq!(range("duration:<4:30"), Range::Lt(270));
+        assert_eq!(
+            range("duration:1:00:00..2:00:00"),
+            Range::Between(3600, 7200)
+        );
+        assert_eq!(range("rating:>=3.5"), Range::Ge(7));
+        assert_eq!(range("rating:4..5"), Range::Between(8, 10));
+        assert_eq!(range("bitrate:<192k"), Range::Lt(192));
+        assert_eq!(range("samplerate:>44.1k"), Range::Gt(44100));
+        assert_eq!(range("samplerate:96khz"), Range::Eq(96000));
+        assert_eq!(range("samplerate:48000"), Range::Eq(48000));
+    }
+
+    #[test]
+    fn test_parse_free_text() {
+        let query = SearchQuery::parse("  \"paranoid android\"  Björk's -\"live at\" ").unwrap();
+        assert_eq!(query.clauses.len(), 3);
+        assert_eq!(
+            query.clauses[0].term,
+            Term::Text {
+                text: "paranoid android".into(),
+                phrase: true
+            }
+        );
+        assert_eq!(
+            query.clauses[1].term,
+            Term::Text {
+                text: "Björk's".into(),
+                phrase: false
+            }
+        );
+        assert!(query.clauses[2].negated);
+
+        // Colons that don't follow a field name are plain text
+        let query = SearchQuery::parse("10:15 re-mix").unwrap();
+        assert_eq!(query.clauses.len(), 2);
+
+        // So are unknown field names and fields without a value
+        let text = |q: &str| -> Vec<String> {
+            SearchQuery::parse(q)
+                .unwrap()
+                .clauses
+                .into_iter()
+                .map(|c| match c.term {
+                    Term::Text { text, .. } => text,
+                    term => panic!("{:?} is not text", term),
+                })
+                .collect()
+        };
+        assert_eq!(text("Re: Stacks"), vec!["Re:", "Stacks"]);
+        assert_eq!(text("Live: Dublin"), vec!["Live:", "Dublin"]);
+        assert_eq!(text("Björk mood:calm"), vec!["Björk", "mood:calm"]);
+        assert_eq!(text("artist:"), vec!["artist:"]);
+        assert_eq!(
+            text("title: \"Karma Police\""),
+            vec!["title:", "Karma Police"]
+        );
+        assert_eq!(
+            SearchQuery::parse("-Re:Stacks").unwrap().clauses,
+            vec![clause(
+                true,
+                Term::Text {
+                    text: "Re:Stacks".into(),
+                    phrase: false
+                }
+            )]
+        );
+
+        assert!(SearchQuery::parse("   ").unwrap().clauses.is_empty());
+    }
+
+    #[test]
+    fn test_parse_audio_quality_fields() {
+        let query = SearchQuery::parse("codec:ALAC hires:yes -lossless:no channels:1").unwrap();
+        assert_eq!(
+            query.clauses,
+            vec![
+                clause(false, Term::Codec("alac".into())),
+                clause(false, Term::HiRes(true)),
+                clause(true, Term::Lossless(false)),
+                clause(false, Term::Number(NumberField::Channels, Range::Eq(1))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors_report_position() {
+        let err = |q: &str| SearchQuery::parse(q).unwrap_err();
+
+        assert_eq!(err("genre:\"trip hop").position, 6);
+        assert_eq!(err("rock - pop").position, 5);
+        assert_eq!(err("year:199x").position, 5);
+        assert_eq!(err("year:1990..20x0").position, 11);
+        assert_eq!(err("duration:>abc").position, 10);
+        assert_eq!(err("rating:3.2").position, 7);
+        assert_eq!(err("rating:6").position, 7);
+        assert_eq!(err("favorite:maybe").position, 9);
+        assert_eq!(err("hires:maybe").position, 6);
+        assert_eq!(err("samplerate:fast").position, 11);
+        assert_eq!(
+            err("year:2000..1990").to_string(),
+            "Range start 2000 is after its end 1990 at position 5"
+        );
+    }
+}
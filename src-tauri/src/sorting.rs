@@ -0,0 +1,229 @@
+use rusqlite::{params, Connection, OptionalExtension, Result as SqlResult};
+use std::cmp::Ordering;
+use std::iter::Peekable;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// Name of the collation used to order names and titles, registered on every
+/// connection by `register_collation`
+pub const COLLATION: &str = "UNICODE";
+
+/// Articles ignored at the start of titles and names until the user picks
+/// their own
+pub const DEFAULT_ARTICLES: [&str; 4] = ["The", "A", "Die", "Les"];
+
+/// Register the `UNICODE` collation on `conn`. Queries that order by it fail
+/// with "no such collation sequence" on connections without it.
+pub fn register_collation(conn: &Connection) -> SqlResult<()> {
+    conn.create_collation(COLLATION, compare)
+}
+
+/// Characters compared by `compare`: compatibility-decomposed, without
+/// combining marks and lowercased, so "Émile" and "emile" fold the same way
+fn folded(value: &str) -> Peekable<impl Iterator<Item = char> + '_> {
+    value
+        .nfkd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .peekable()
+}
+
+/// Take a run of ASCII digits, without leading zeros
+fn digit_run(chars: &mut Peekable<impl Iterator<Item = char>>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        if !(digits.is_empty() && c == '0') {
+            digits.push(c);
+        }
+    }
+    digits
+}
+
+/// Order two strings ignoring case and accents, with runs of digits compared
+/// by value ("Track 2" before "Track 10"). Strings that fold the same are
+/// ordered by their bytes, so only identical strings compare equal.
+pub fn compare(a: &str, b: &str) -> Ordering {
+    let mut left = folded(a);
+    let mut right = folded(b);
+    loop {
+        let ordering = match (left.peek().copied(), right.peek().copied()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(l), Some(r)) if l.is_ascii_digit() && r.is_ascii_digit() => {
+                let (l, r) = (digit_run(&mut left), digit_run(&mut right));
+                l.len().cmp(&r.len()).then_with(|| l.cmp(&r))
+            }
+            (Some(l), Some(r)) => {
+                left.next();
+                right.next();
+                l.cmp(&r)
+            }
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+/// `value` without a leading article: "The Beatles" sorts as "Beatles". An
+/// article must be followed by a space, or end in an apostrophe ("L'"), and a
+/// name that is only an article is kept.
+pub fn strip_article<'a>(value: &'a str, articles: &[String]) -> &'a str {
+    for article in articles.iter().map(|a| a.trim()).filter(|a| !a.is_empty()) {
+        let Some(prefix) = value.get(..article.len()) else {
+            continue;
+        };
+        if prefix.to_lowercase() != article.to_lowercase() {
+            continue;
+        }
+        let rest = &value[article.len()..];
+        let separated = article.ends_with('\'') || rest.starts_with(char::is_whitespace);
+        let rest = rest.trim_start();
+        if separated && !rest.is_empty() {
+            return rest;
+        }
+    }
+    value
+}
+
+/// Key a value is ordered by: its sort-name tag when it has one, else the
+/// value without a leading article
+pub fn sort_key(
+    sort_tag: Option<&str>,
+    value: Option<&str>,
+    articles: &[String],
+) -> Option<String> {
+    match sort_tag.map(str::trim).filter(|s| !s.is_empty()) {
+        Some(tag) => Some(tag.to_string()),
+        None => value.map(|v| strip_article(v.trim(), articles).to_string()),
+    }
+}
+
+/// Work out the sort keys of a track from its title, artist and album and
+/// their sort-name tags, and those of its album and credited artists.
+///
+/// An album takes the ALBUMSORT tag of any of its tracks. An artist takes the
+/// ARTISTSORT tag of a track credited to that artist alone, since the tag of
+/// a track with several artists names all of them.
+pub fn update_sort_keys(conn: &Connection, track_id: i64, articles: &[String]) -> SqlResult<()> {
+    // Each key comes from a value column and its sort tag column
+    let (title_key, artist_key, album_key, album_id) = conn
+        .prepare_cached(
+            "SELECT title, artist, album, title_sort, artist_sort, album_sort, album_id
+             FROM tracks WHERE id = ?1",
+        )?
+        .query_row(params![track_id], |row| {
+            let key = |value: usize, tag: usize| -> SqlResult<Option<String>> {
+                let value: Option<String> = row.get(value)?;
+                let tag: Option<String> = row.get(tag)?;
+                Ok(sort_key(tag.as_deref(), value.as_deref(), articles))
+            };
+            Ok((
+                key(0, 3)?,
+                key(1, 4)?,
+                key(2, 5)?,
+                row.get::<_, Option<i64>>(6)?,
+            ))
+        })?;
+    conn.prepare_cached(
+        "UPDATE tracks SET title_key = ?1, artist_key = ?2, album_key = ?3 WHERE id = ?4",
+    )?
+    .execute(params![title_key, artist_key, album_key, track_id])?;
+
+    if let Some(album_id) = album_id {
+        let tag: Option<String> = conn
+            .prepare_cached(
+                "SELECT album_sort FROM tracks
+                 WHERE album_id = ?1 AND TRIM(album_sort) != '' LIMIT 1",
+            )?
+            .query_row(params![album_id], |row| row.get(0))
+            .optional()?;
+        let name: String = conn
+            .prepare_cached("SELECT name FROM albums WHERE id = ?1")?
+            .query_row(params![album_id], |row| row.get(0))?;
+        conn.prepare_cached("UPDATE albums SET sort_key = ?1 WHERE id = ?2")?
+            .execute(params![
+                sort_key(tag.as_deref(), Some(&name), articles),
+                album_id
+            ])?;
+    }
+
+    let artists = conn
+        .prepare_cached(
+            "SELECT ar.id, ar.name FROM track_artists ta JOIN artists ar ON ar.id = ta.artist_id
+             WHERE ta.track_id = ?1",
+        )?
+        .query_map(params![track_id], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<SqlResult<Vec<_>>>()?;
+    for (artist_id, name) in artists {
+        let tag: Option<String> = conn
+            .prepare_cached(
+                "SELECT t.artist_sort FROM track_artists ta JOIN tracks t ON t.id = ta.track_id
+                 WHERE ta.artist_id = ?1 AND t.artist = ?2 AND TRIM(t.artist_sort) != ''
+                 LIMIT 1",
+            )?
+            .query_row(params![artist_id, name], |row| row.get(0))
+            .optional()?;
+        conn.prepare_cached("UPDATE artists SET sort_key = ?1 WHERE id = ?2")?
+            .execute(params![
+                sort_key(tag.as_deref(), Some(&name), articles),
+                artist_id
+            ])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn articles() -> Vec<String> {
+        DEFAULT_ARTICLES.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_compare() {
+        let mut names = vec![
+            "track 10", "Zoë", "Émile", "Track 2", "apple", "Track 02", "ABBA", "zebra", "Äpfel",
+        ];
+        names.sort_by(|a, b| compare(a, b));
+        assert_eq!(
+            names,
+            vec![
+                "ABBA", "Äpfel", "apple", "Émile", "Track 02", "Track 2", "track 10", "zebra",
+                "Zoë",
+            ]
+        );
+        assert_eq!(compare("same", "same"), Ordering::Equal);
+        assert_ne!(compare("Bjork", "Björk"), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_strip_article() {
+        let articles = articles();
+        assert_eq!(strip_article("The Beatles", &articles), "Beatles");
+        assert_eq!(strip_article("the  xx", &articles), "xx");
+        assert_eq!(strip_article("Die Ärzte", &articles), "Ärzte");
+        assert_eq!(strip_article("Theatre", &articles), "Theatre");
+        assert_eq!(strip_article("The", &articles), "The");
+        assert_eq!(strip_article("A", &articles), "A");
+        assert_eq!(
+            strip_article("L'Arc-en-Ciel", &["L'".to_string()]),
+            "Arc-en-Ciel"
+        );
+        assert_eq!(strip_article("The Beatles", &[]), "The Beatles");
+
+        assert_eq!(
+            sort_key(Some("Beatles, The"), Some("The Beatles"), &articles).as_deref(),
+            Some("Beatles, The")
+        );
+        assert_eq!(
+            sort_key(Some(" "), Some("The Who"), &articles).as_deref(),
+            Some("Who")
+        );
+        assert_eq!(sort_key(None, None, &articles), None);
+    }
+}
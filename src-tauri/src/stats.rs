@@ -0,0 +1,293 @@
+use crate::models::{ListeningStreaks, NameStat, TrackStat, YearReport};
+use std::fmt::Write;
+
+/// Streaks from the distinct days with a play, as ascending day numbers paired
+/// with their `YYYY-MM-DD` dates. `today` is the day number of the current day.
+pub fn streaks(days: &[(i64, String)], today: i64) -> ListeningStreaks {
+    let mut result = ListeningStreaks::default();
+    let mut start = 0;
+
+    for i in 0..days.len() {
+        if i > 0 && days[i].0 != days[i - 1].0 + 1 {
+            start = i;
+        }
+        let length = (i - start + 1) as i64;
+        if length > result.longest {
+            result.longest = length;
+            result.longest_start = Some(days[start].1.clone());
+            result.longest_end = Some(days[i].1.clone());
+        }
+        // The current run survives until a whole day passes without a play
+        if i == days.len() - 1 && days[i].0 >= today - 1 {
+            result.current = length;
+        }
+    }
+
+    result
+}
+
+/// `3 h 25 min`, `12 min`
+pub fn format_listening_time(seconds: i64) -> String {
+    let minutes = seconds / 60;
+    match (minutes / 60, minutes % 60) {
+        (0, m) => format!("{} min", m),
+        (h, 0) => format!("{} h", h),
+        (h, m) => format!("{} h {} min", h, m),
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+const MONTH_NAMES: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+const WEEKDAY_NAMES: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+const REPORT_STYLE: &str = "
+body { font-family: system-ui, sans-serif; background: #111; color: #eee; margin: 0 auto; max-width: 960px; padding: 2rem; }
+h1 { font-size: 2.5rem; margin-bottom: 0.25rem; }
+h2 { margin-top: 2.5rem; border-bottom: 1px solid #333; padding-bottom: 0.25rem; }
+.muted { color: #999; }
+.totals { display: flex; flex-wrap: wrap; gap: 1rem; }
+.total { background: #1d1d1d; border-radius: 8px; padding: 1rem 1.5rem; }
+.total strong { display: block; font-size: 1.75rem; }
+.columns { display: grid; grid-template-columns: repeat(auto-fit, minmax(280px, 1fr)); gap: 2rem; }
+ol { padding-left: 1.5rem; }
+li { margin: 0.35rem 0; }
+.bars { display: flex; align-items: flex-end; gap: 6px; height: 160px; }
+.bar { flex: 1; display: flex; flex-direction: column; justify-content: flex-end; align-items: center; height: 100%; font-size: 0.75rem; }
+.bar div { width: 100%; background: #1db954; border-radius: 4px 4px 0 0; }
+table.clock { border-collapse: collapse; font-size: 0.7rem; }
+table.clock td { width: 24px; height: 18px; }
+table.clock th { font-weight: normal; color: #999; padding-right: 6px; }
+";
+
+fn write_name_list(html: &mut String, title: &str, entries: &[NameStat]) {
+    let _ = write!(html, "<div><h2>{}</h2><ol>", title);
+    for entry in entries {
+        let _ = write!(html, "<li>{}", escape_html(&entry.name));
+        if let Some(artist) = &entry.artist {
+            let _ = write!(
+                html,
+                " <span class=\"muted\">— {}</span>",
+                escape_html(artist)
+            );
+        }
+        let _ = write!(
+            html,
+            " <span class=\"muted\">({} plays)</span></li>",
+            entry.plays
+        );
+    }
+    html.push_str("</ol></div>");
+}
+
+fn write_track_list(html: &mut String, title: &str, entries: &[TrackStat]) {
+    let _ = write!(html, "<div><h2>{}</h2><ol>", title);
+    for entry in entries {
+        let name = entry.track.title.as_deref().unwrap_or("Unknown");
+        let artist = entry.track.artist.as_deref().unwrap_or("Unknown Artist");
+        let _ = write!(
+            html,
+            "<li>{} <span class=\"muted\">— {} ({} plays)</span></li>",
+            escape_html(name),
+            escape_html(artist),
+            entry.plays
+        );
+    }
+    html.push_str("</ol></div>");
+}
+
+/// Render a yearly report as a standalone HTML page with inline styles and no
+/// external resources, so it can be opened or shared without a network connection
+pub fn year_report_html(report: &YearReport) -> String {
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html lang=\"en\"><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+         <title>{year} in music</title><style>{style}</style></head><body>\
+         <h1>{year} in music</h1><p class=\"muted\">Your listening year, from OSMP</p>",
+        year = report.year,
+        style = REPORT_STYLE
+    );
+
+    html.push_str("<div class=\"totals\">");
+    for (value, label) in [
+        (format_listening_time(report.seconds), "listened"),
+        (report.plays.to_string(), "plays"),
+        (report.distinct_tracks.to_string(), "tracks"),
+        (report.distinct_artists.to_string(), "artists"),
+        (report.streaks.longest.to_string(), "day longest streak"),
+    ] {
+        let _ = write!(
+            html,
+            "<div class=\"total\"><strong>{}</strong>{}</div>",
+            value, label
+        );
+    }
+    html.push_str("</div>");
+
+    html.push_str("<div class=\"columns\">");
+    write_track_list(&mut html, "Top tracks", &report.top_tracks);
+    write_name_list(&mut html, "Top artists", &report.top_artists);
+    write_name_list(&mut html, "Top albums", &report.top_albums);
+    write_name_list(&mut html, "Top genres", &report.top_genres);
+    html.push_str("</div>");
+
+    html.push_str("<h2>Listening by month</h2><div class=\"bars\">");
+    let max_month = report.months.iter().map(|m| m.seconds).max().unwrap_or(0);
+    for (i, name) in MONTH_NAMES.iter().enumerate() {
+        let key = format!("{:04}-{:02}", report.year, i + 1);
+        let seconds = report
+            .months
+            .iter()
+            .find(|m| m.period == key)
+            .map_or(0, |m| m.seconds);
+        let height = if max_month > 0 {
+            seconds * 100 / max_month
+        } else {
+            0
+        };
+        let _ = write!(
+            html,
+            "<div class=\"bar\" title=\"{}\"><div style=\"height:{}%\"></div>{}</div>",
+            format_listening_time(seconds),
+            height,
+            name
+        );
+    }
+    html.push_str("</div>");
+
+    html.push_str("<h2>Listening clock</h2><table class=\"clock\"><tr><th></th>");
+    for hour in 0..24 {
+        let _ = write!(
+            html,
+            "<th>{}</th>",
+            if hour % 6 == 0 {
+                hour.to_string()
+            } else {
+                String::new()
+            }
+        );
+    }
+    html.push_str("</tr>");
+    let max_cell = report.clock.iter().flatten().copied().max().unwrap_or(0);
+    for (day, hours) in report.clock.iter().enumerate() {
+        let _ = write!(html, "<tr><th>{}</th>", WEEKDAY_NAMES[day]);
+        for seconds in hours {
+            let opacity = if max_cell > 0 {
+                *seconds as f64 / max_cell as f64
+            } else {
+                0.0
+            };
+            let _ = write!(
+                html,
+                "<td title=\"{}\" style=\"background:rgba(29,185,84,{:.2})\"></td>",
+                format_listening_time(*seconds),
+                opacity.max(0.05)
+            );
+        }
+        html.push_str("</tr>");
+    }
+    html.push_str("</table>");
+
+    html.push_str("<div class=\"columns\">");
+    write_track_list(&mut html, "New discoveries", &report.discoveries.tracks);
+    write_name_list(&mut html, "New artists", &report.discoveries.artists);
+    html.push_str("</div>");
+
+    if let (Some(start), Some(end)) = (&report.streaks.longest_start, &report.streaks.longest_end) {
+        let _ = write!(
+            html,
+            "<p class=\"muted\">Longest streak: {} days, {} to {}</p>",
+            report.streaks.longest, start, end
+        );
+    }
+
+    html.push_str("</body></html>\n");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Discoveries, Track};
+
+    fn days(numbers: &[i64]) -> Vec<(i64, String)> {
+        numbers.iter().map(|&n| (n, format!("day{}", n))).collect()
+    }
+
+    #[test]
+    fn test_streaks() {
+        let result = streaks(&days(&[1, 2, 3, 7, 8, 10, 11]), 12);
+        assert_eq!(result.longest, 3);
+        assert_eq!(result.longest_start.as_deref(), Some("day1"));
+        assert_eq!(result.longest_end.as_deref(), Some("day3"));
+        // Last played yesterday, so the run is still going
+        assert_eq!(result.current, 2);
+
+        assert_eq!(streaks(&days(&[1, 2, 3, 7, 8, 10, 11]), 13).current, 0);
+        assert_eq!(streaks(&[], 5), ListeningStreaks::default());
+    }
+
+    #[test]
+    fn test_format_listening_time() {
+        assert_eq!(format_listening_time(59), "0 min");
+        assert_eq!(format_listening_time(12 * 60), "12 min");
+        assert_eq!(format_listening_time(2 * 3600), "2 h");
+        assert_eq!(format_listening_time(3 * 3600 + 25 * 60 + 10), "3 h 25 min");
+    }
+
+    #[test]
+    fn test_year_report_html_is_escaped_and_self_contained() {
+        let report = YearReport {
+            year: 2024,
+            plays: 1,
+            skips: 0,
+            seconds: 200,
+            distinct_tracks: 1,
+            distinct_artists: 1,
+            top_tracks: vec![TrackStat {
+                track: Track {
+                    title: Some("<script>alert(1)</script>".to_string()),
+                    artist: Some("Simon & Garfunkel".to_string()),
+                    ..Default::default()
+                },
+                plays: 1,
+                seconds: 200,
+            }],
+            top_artists: Vec::new(),
+            top_albums: Vec::new(),
+            top_genres: Vec::new(),
+            months: Vec::new(),
+            clock: [[0; 24]; 7],
+            streaks: ListeningStreaks::default(),
+            discoveries: Discoveries {
+                tracks: Vec::new(),
+                artists: Vec::new(),
+            },
+        };
+
+        let html = year_report_html(&report);
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("2024 in music"));
+        assert!(html.contains("&lt;script&gt;"));
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("Simon &amp; Garfunkel"));
+        assert!(!html.contains("http"));
+    }
+}
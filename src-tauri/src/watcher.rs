@@ -0,0 +1,309 @@
+use crate::reconcile;
+use crate::scanner::is_audio_file;
+use notify::event::{Event, EventKind};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Changes are rescanned once the folders have been quiet for this long
+const QUIET_PERIOD: Duration = Duration::from_secs(2);
+/// ...or once they have been pending this long, during a long copy
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Filesystems that don't report changes made by other machines, so watching
+/// them would miss most of what happens to the music on them
+const NETWORK_FILESYSTEMS: [&str; 8] = [
+    "nfs",
+    "nfs4",
+    "cifs",
+    "smb3",
+    "smbfs",
+    "9p",
+    "fuse.sshfs",
+    "fuse.rclone",
+];
+
+/// Collects changed paths until they have settled
+#[derive(Debug, Default)]
+pub struct Debouncer {
+    pending: HashSet<PathBuf>,
+    first: Option<Instant>,
+    last: Option<Instant>,
+}
+
+impl Debouncer {
+    pub fn add(&mut self, path: PathBuf, now: Instant) {
+        self.pending.insert(path);
+        self.first.get_or_insert(now);
+        self.last = Some(now);
+    }
+
+    /// Whether the pending changes have settled, or waited long enough
+    pub fn ready(&self, now: Instant) -> bool {
+        match (self.first, self.last) {
+            (Some(first), Some(last)) => {
+                now.duration_since(last) >= QUIET_PERIOD || now.duration_since(first) >= MAX_DELAY
+            }
+            _ => false,
+        }
+    }
+
+    /// The pending paths, leaving out those inside another pending directory
+    pub fn take(&mut self) -> Vec<PathBuf> {
+        self.first = None;
+        self.last = None;
+        let mut paths: Vec<PathBuf> = self.pending.drain().collect();
+        // A parent sorts before its children
+        paths.sort();
+        let mut roots: Vec<PathBuf> = Vec::with_capacity(paths.len());
+        for path in paths {
+            if !roots.iter().any(|root| path.starts_with(root)) {
+                roots.push(path);
+            }
+        }
+        roots
+    }
+}
+
+/// The paths an event asks to rescan: audio files, and the directories of
+/// CUE sheets, of directories that appeared and of paths that are gone
+fn changed_paths(event: &Event) -> Vec<PathBuf> {
+    if matches!(event.kind, EventKind::Access(_)) {
+        return Vec::new();
+    }
+    event
+        .paths
+        .iter()
+        .filter_map(|path| {
+            let is_cue = path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("cue"));
+            if is_cue {
+                path.parent().map(Path::to_path_buf)
+            } else if is_audio_file(path) || path.is_dir() || !path.exists() {
+                Some(path.clone())
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// The filesystem type of the mount `path` is on, from the contents of
+/// `/proc/self/mounts`
+fn mount_type<'a>(mounts: &'a str, path: &Path) -> Option<&'a str> {
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let _device = fields.next()?;
+            // Spaces in mount points are escaped as \040
+            let mount_point = fields.next()?.replace("\\040", " ");
+            let fs_type = fields.next()?;
+            path.starts_with(&mount_point)
+                .then_some((mount_point.len(), fs_type))
+        })
+        .max_by_key(|(len, _)| *len)
+        .map(|(_, fs_type)| fs_type)
+}
+
+/// Whether `path` is on a network filesystem (see `NETWORK_FILESYSTEMS`).
+/// Only known on Linux; elsewhere every folder is watched.
+fn is_network_path(path: &Path) -> bool {
+    std::fs::read_to_string("/proc/self/mounts").is_ok_and(|mounts| {
+        mount_type(&mounts, path).is_some_and(|fs_type| NETWORK_FILESYSTEMS.contains(&fs_type))
+    })
+}
+
+/// Watches scan folders and hands out the paths that changed inside them
+pub struct FolderWatcher {
+    watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    watched: Vec<String>,
+    debouncer: Debouncer,
+}
+
+impl FolderWatcher {
+    pub fn new() -> notify::Result<Self> {
+        let (sender, events) = mpsc::channel();
+        Ok(FolderWatcher {
+            watcher: notify::recommended_watcher(sender)?,
+            events,
+            watched: Vec::new(),
+            debouncer: Debouncer::default(),
+        })
+    }
+
+    /// Watch `folders` and stop watching any others. Offline folders and those
+    /// on a network filesystem are not watched. Returns whether every folder
+    /// is watched.
+    pub fn set_folders(&mut self, folders: &[String]) -> bool {
+        let wanted: Vec<&String> = folders
+            .iter()
+            .filter(|folder| {
+                let path = Path::new(folder);
+                reconcile::folder_available(path) && !is_network_path(path)
+            })
+            .collect();
+
+        let watcher = &mut self.watcher;
+        self.watched.retain(|folder| {
+            if wanted.contains(&folder) {
+                return true;
+            }
+            // Fails when the folder is gone, which ends the watch anyway
+            let _ = watcher.unwatch(Path::new(folder));
+            false
+        });
+        for folder in wanted {
+            if self.watched.contains(folder) {
+                continue;
+            }
+            match watcher.watch(Path::new(folder), RecursiveMode::Recursive) {
+                Ok(()) => self.watched.push(folder.clone()),
+                Err(e) => warn!("Watching {} failed: {}", folder, e),
+            }
+        }
+        self.watched.len() == folders.len()
+    }
+
+    /// Wait up to `timeout` for changes, and return the changed paths once
+    /// they have settled; empty while there is nothing to rescan yet
+    pub fn poll(&mut self, timeout: Duration) -> Vec<PathBuf> {
+        match self.events.recv_timeout(timeout) {
+            Ok(Ok(event)) => self.add_event(&event),
+            Ok(Err(e)) => warn!("Folder watcher error: {}", e),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => std::thread::sleep(timeout),
+        }
+        // Take in everything that has queued up meanwhile
+        while let Ok(result) = self.events.try_recv() {
+            if let Ok(event) = result {
+                self.add_event(&event);
+            }
+        }
+
+        if self.debouncer.ready(Instant::now()) {
+            self.debouncer.take()
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Put changes back to be handed out again, when they couldn't be rescanned
+    pub fn requeue(&mut self, paths: Vec<PathBuf>) {
+        let now = Instant::now();
+        for path in paths {
+            self.debouncer.add(path, now);
+        }
+    }
+
+    fn add_event(&mut self, event: &Event) {
+        let now = Instant::now();
+        // The watcher dropped events, so anything may have changed
+        let paths = if event.need_rescan() {
+            self.watched.iter().map(PathBuf::from).collect()
+        } else {
+            changed_paths(event)
+        };
+        for path in paths {
+            self.debouncer.add(path, now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{AccessKind, CreateKind, ModifyKind};
+
+    #[test]
+    fn test_debouncer() {
+        let start = Instant::now();
+        let mut debouncer = Debouncer::default();
+        assert!(!debouncer.ready(start + MAX_DELAY));
+
+        debouncer.add(PathBuf::from("/music/A/b.flac"), start);
+        debouncer.add(PathBuf::from("/music/B/c.flac"), start);
+        debouncer.add(PathBuf::from("/music/A"), start + Duration::from_secs(1));
+        assert!(!debouncer.ready(start + Duration::from_secs(2)));
+        assert!(debouncer.ready(start + Duration::from_secs(3)));
+        assert_eq!(
+            debouncer.take(),
+            vec![PathBuf::from("/music/A"), PathBuf::from("/music/B/c.flac")]
+        );
+        assert!(!debouncer.ready(start + MAX_DELAY));
+
+        // A steady stream of changes is still handed out after the max delay
+        for second in 0..MAX_DELAY.as_secs() {
+            let now = start + Duration::from_secs(second);
+            debouncer.add(PathBuf::from(format!("/music/{}.flac", second)), now);
+            assert!(!debouncer.ready(now));
+        }
+        assert!(debouncer.ready(start + MAX_DELAY));
+    }
+
+    #[test]
+    fn test_changed_paths() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let album = dir.path().join("Album");
+        std::fs::create_dir(&album).unwrap();
+        let cover = album.join("cover.jpg");
+        std::fs::write(&cover, b"").unwrap();
+        let event = |kind: EventKind, paths: &[&Path]| Event {
+            kind,
+            paths: paths.iter().map(|p| p.to_path_buf()).collect(),
+            attrs: Default::default(),
+        };
+
+        let paths = changed_paths(&event(
+            EventKind::Create(CreateKind::Any),
+            &[
+                &album,
+                &cover,
+                &album.join("01.FLAC"),
+                &album.join("disc.cue"),
+                &dir.path().join("deleted"),
+            ],
+        ));
+        assert_eq!(
+            paths,
+            vec![
+                album.clone(),
+                album.join("01.FLAC"),
+                album.clone(),
+                dir.path().join("deleted"),
+            ]
+        );
+        assert!(changed_paths(&event(
+            EventKind::Access(AccessKind::Any),
+            &[&album.join("01.flac")]
+        ))
+        .is_empty());
+        assert!(!changed_paths(&event(
+            EventKind::Modify(ModifyKind::Any),
+            &[&album.join("01.flac")]
+        ))
+        .is_empty());
+    }
+
+    #[test]
+    fn test_mount_type() {
+        let mounts = "\
+/dev/sda1 / ext4 rw,relatime 0 0
+nas:/export/music /mnt/nas nfs4 rw,relatime 0 0
+//server/share /mnt/my\\040share cifs rw 0 0
+/dev/sdb1 /mnt/nas/local ext4 rw 0 0
+";
+        let fs_type = |path: &str| mount_type(mounts, Path::new(path));
+        assert_eq!(fs_type("/home/me/Music"), Some("ext4"));
+        assert_eq!(fs_type("/mnt/nas/Albums"), Some("nfs4"));
+        assert_eq!(fs_type("/mnt/nas/local/Albums"), Some("ext4"));
+        assert_eq!(fs_type("/mnt/my share/Albums"), Some("cifs"));
+        // Whole components only
+        assert_eq!(fs_type("/mnt/nasty"), Some("ext4"));
+    }
+}
@@ -1,5 +1,6 @@
 use osmp_lib::database::DatabaseInner;
 use osmp_lib::models::Track;
+use osmp_lib::search_query::SearchQuery;
 
 fn create_test_db() -> Result<DatabaseInner, Box<dyn std::error::Error>> {
     let temp_dir = tempfile::TempDir::new()?;
@@ -35,6 +36,7 @@ fn test_database_operations() -> Result<(), Box<dyn std::error::Error>> {
         metadata_fetched: false,
         release_mbid: None,
         created_at: 1234567890,
+        ..Default::default()
     };
 
     let track_id = db.insert_track(&track)?;
@@ -45,7 +47,7 @@ fn test_database_operations() -> Result<(), Box<dyn std::error::Error>> {
     assert_eq!(retrieved.artist, track.artist);
 
     // Test search
-    let search_results = db.search_tracks("Integration")?;
+    let search_results = db.search_tracks(&SearchQuery::parse("Integration")?)?;
     assert_eq!(search_results.len(), 1);
     assert_eq!(search_results[0].id, track_id);
 
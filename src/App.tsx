@@ -46,6 +46,8 @@ function App() {
 
   const loadTracks = useStore((s) => s.loadTracks);
   const loadAlbums = useStore((s) => s.loadAlbums);
+  const restorePlaybackSession = useStore((s) => s.restorePlaybackSession);
+  const flushListen = useStore((s) => s.flushListen);
 
   // Register global keyboard shortcuts
   useKeyboardShortcuts();
@@ -196,14 +198,22 @@ function App() {
   useEffect(() => {
     const cleanupMediaControls = setupMediaControlListeners();
     loadEqSettings();
-    loadTracks();
+    // The session's shuffle order is drawn from the loaded tracks
+    loadTracks().then(restorePlaybackSession);
     loadAlbums();
     return cleanupMediaControls;
-  }, [loadEqSettings, loadTracks, loadAlbums]);
+  }, [loadEqSettings, loadTracks, loadAlbums, restorePlaybackSession]);
+
+  // A track still playing when the window closes was heard too
+  useEffect(() => {
+    window.addEventListener('beforeunload', flushListen);
+    return () => window.removeEventListener('beforeunload', flushListen);
+  }, [flushListen]);
 
   // Auto-reload library when background scan finds new tracks
   useEffect(() => {
-    const unlisten = listen<number>('library-updated', () => {
+    // Background scans and the folder watcher send the ids of the changed tracks
+    const unlisten = listen<number[]>('library-updated', () => {
       loadTracks(true);
       loadAlbums(true);
     });
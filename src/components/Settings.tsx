@@ -450,6 +450,14 @@ export default function Settings() {
               />
             </div>
 
+            {/* Throughput and time left */}
+            {progress.eta_secs !== null && (
+              <p className="text-xs text-text-tertiary mb-1">
+                {progress.files_per_second.toFixed(1)} files/s · about{' '}
+                {formatDuration(progress.eta_secs)} left
+              </p>
+            )}
+
             {/* Current file */}
             <p className="text-xs text-text-tertiary truncate" title={progress.current_file}>
               {getCurrentFileName()}
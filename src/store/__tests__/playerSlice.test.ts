@@ -1,7 +1,8 @@
 import { describe, it, expect, vi, beforeEach } from 'vitest';
 import { useStore } from '../index';
 import { invoke } from '@tauri-apps/api/core';
-import { mockTrack, mockTrack2 } from '../../test/fixtures';
+import { listen } from '@tauri-apps/api/event';
+import { mockTrack, mockTrack2, mockPlaylist } from '../../test/fixtures';
 
 vi.mock('@tauri-apps/api/core');
 vi.mock('@tauri-apps/api/event', () => ({
@@ -9,6 +10,7 @@ vi.mock('@tauri-apps/api/event', () => ({
 }));
 
 const mockInvoke = vi.mocked(invoke);
+const mockListen = vi.mocked(listen);
 
 describe('playerSlice', () => {
   beforeEach(() => {
@@ -25,6 +27,8 @@ describe('playerSlice', () => {
       currentCoverArt: null,
       playbackSpeed: 1.0,
       _positionInterval: null,
+      _listened: 0,
+      _lastHeard: null,
       tracks: [mockTrack, mockTrack2],
       queue: [],
       playlistTracks: [],
@@ -130,6 +134,14 @@ describe('playerSlice', () => {
 
       useStore.getState().cycleRepeatMode();
       expect(useStore.getState().repeatMode).toBe('off');
+    }
+    it('saves the session when shuffle or repeat change', () => {
+      useStore.getState().toggleShuffle();
+      useStore.getState().cycleRepeatMode();
+
+      expect(mockInvoke).toHaveBeenCalledWith('save_playback_session', {
+        context: { queue: [], shuffle: true, repeat_mode: 'list', active_playlist_id: null },
+      });
     });
   });
 
@@ -145,6 +157,52 @@ describe('playerSlice', () => {
       expect(useStore.getState().position).toBe(0);
     });
 
+    it('records how long the previous track was heard', async () => {
+      useStore.setState({
+        currentTrack: mockTrack2,
+        audioLoaded: true,
+        position: 200,
+        _listened: 42.7,
+      });
+      mockInvoke.mockResolvedValue(undefined);
+
+      await useStore.getState().playTrack(mockTrack.id);
+
+      expect(mockInvoke).toHaveBeenCalledWith('record_play_history', {
+        trackId: mockTrack2.id,
+        durationListened: 42,
+      });
+      expect(useStore.getState()._listened).toBe(0);
+    });
+
+    it('counts time heard rather than where the playhead is', async () => {
+      mockInvoke.mockResolvedValue(undefined);
+      await useStore.getState().playTrack(mockTrack.id);
+      const call = mockListen.mock.calls.find((c) => c[0] === 'position-update');
+      const handler = call![1] as (event: { payload: number }) => void;
+
+      // Three seconds played, a seek near the end, then one more second
+      for (const position of [1, 2, 3, 170, 171]) {
+        handler({ payload: position } as never);
+      }
+      expect(useStore.getState()._listened).toBe(4);
+
+      await useStore.getState().stopPlayback();
+      expect(mockInvoke).toHaveBeenCalledWith('record_play_history', {
+        trackId: mockTrack.id,
+        durationListened: 4,
+      });
+    });
+
+    it('does not count a restored position as heard', async () => {
+      useStore.setState({ currentTrack: mockTrack, audioLoaded: true, position: 150 });
+      mockInvoke.mockResolvedValue(undefined);
+
+      await useStore.getState().stopPlayback();
+
+      expect(mockInvoke).not.toHaveBeenCalledWith('record_play_history', expect.anything());
+    });
+
     it('fetches cover art after playing', async () => {
       mockInvoke.mockResolvedValueOnce(undefined); // play_track
       mockInvoke.mockResolvedValueOnce('cover-data'); // get_track_cover
@@ -246,12 +304,52 @@ describe('playerSlice', () => {
       expect(mockInvoke).toHaveBeenCalledWith('stop_playback');
     });
 
+    it('records the listen when the last track ends on its own', async () => {
+      useStore.setState({
+        currentTrack: mockTrack2,
+        isPlaying: true,
+        audioLoaded: true,
+        _listened: 238,
+        _lastHeard: 238.8,
+      });
+      mockInvoke.mockResolvedValue(undefined);
+
+      useStore.getState().startPositionTracking();
+      const call = mockListen.mock.calls.find((c) => c[0] === 'position-update');
+      const handler = call![1] as (event: { payload: number }) => void;
+      handler({ payload: 239.8 } as never);
+
+      expect(mockInvoke).toHaveBeenCalledWith('record_play_history', {
+        trackId: mockTrack2.id,
+        durationListened: 239,
+      });
+      expect(mockInvoke).toHaveBeenCalledWith('stop_playback');
+      await vi.waitFor(() => expect(useStore.getState().currentTrack).toBeNull());
+    });
+
     it('does nothing without current track', () => {
       useStore.getState().playNextTrack();
       expect(mockInvoke).not.toHaveBeenCalled();
     });
   });
 
+  describe('flushListen', () => {
+    it('records the track still playing', () => {
+      useStore.setState({ currentTrack: mockTrack, audioLoaded: true, _listened: 95.5 });
+      mockInvoke.mockResolvedValue(undefined);
+
+      useStore.getState().flushListen();
+
+      expect(mockInvoke).toHaveBeenCalledWith('record_play_history', {
+        trackId: mockTrack.id,
+        durationListened: 95,
+      });
+      // Not counted twice
+      useStore.getState().flushListen();
+      expect(mockInvoke).toHaveBeenCalledTimes(1);
+    });
+  });
+
   describe('playPreviousTrack', () => {
     it('restarts track if position > threshold', () => {
       useStore.setState({
@@ -336,4 +434,58 @@ describe('playerSlice', () => {
       expect(mockInvoke).not.toHaveBeenCalled();
     });
   });
+
+  describe('restorePlaybackSession', () => {
+    it('loads the saved session into the store', async () => {
+      mockInvoke.mockImplementation(async (cmd: string) => {
+        switch (cmd) {
+          case 'get_playback_session':
+            return {
+              track: mockTrack,
+              position_ms: 61500,
+              queue: [mockTrack2],
+              volume: 0.5,
+              speed: 1.25,
+              shuffle: false,
+              repeat_mode: 'list',
+              active_playlist_id: mockPlaylist.id,
+            };
+          case 'get_playlists':
+            return [mockPlaylist];
+          case 'get_playlist_tracks':
+            return [mockTrack, mockTrack2];
+          case 'get_track_cover':
+            return 'cover-data';
+          default:
+            return undefined;
+        }
+      });
+
+      await useStore.getState().restorePlaybackSession();
+
+      const state = useStore.getState();
+      expect(state.currentTrack).toEqual(mockTrack);
+      expect(state.position).toBe(61.5);
+      expect(state.isPlaying).toBe(false);
+      expect(state.audioLoaded).toBe(false);
+      expect(state.volume).toBe(0.5);
+      expect(state.playbackSpeed).toBe(1.25);
+      expect(state.repeatMode).toBe('list');
+      expect(state.queue.map((item) => item.track.id)).toEqual([mockTrack2.id]);
+      expect(state.currentPlaylist).toEqual(mockPlaylist);
+      expect(state.playlistTracks).toEqual([mockTrack, mockTrack2]);
+      expect(state.currentCoverArt).toBe('cover-data');
+      mockInvoke.mockReset();
+    });
+
+    it('keeps the store as it is without a saved session', async () => {
+      useStore.setState({ currentTrack: mockTrack, position: 12 });
+      mockInvoke.mockResolvedValueOnce(null);
+
+      await useStore.getState().restorePlaybackSession();
+
+      expect(useStore.getState().currentTrack).toEqual(mockTrack);
+      expect(useStore.getState().position).toBe(12);
+    });
+  });
 });
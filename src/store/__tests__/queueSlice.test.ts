@@ -1,5 +1,6 @@
 import { describe, it, expect, vi, beforeEach } from 'vitest';
 import { useStore } from '../index';
+import { invoke } from '@tauri-apps/api/core';
 import { mockTrack, mockTrack2 } from '../../test/fixtures';
 
 vi.mock('@tauri-apps/api/core');
@@ -7,6 +8,8 @@ vi.mock('@tauri-apps/api/event', () => ({
   listen: vi.fn(() => Promise.resolve(() => {})),
 }));
 
+const mockInvoke = vi.mocked(invoke);
+
 describe('queueSlice', () => {
   beforeEach(() => {
     vi.clearAllMocks();
@@ -130,4 +133,23 @@ describe('queueSlice', () => {
       expect(useStore.getState().isQueuePanelOpen).toBe(false);
     });
   });
+
+  describe('session', () => {
+    it('saves the queue when it changes', async () => {
+      await useStore.getState().addToQueue([mockTrack.id, mockTrack2.id]);
+      expect(mockInvoke).toHaveBeenLastCalledWith('save_playback_session', {
+        context: expect.objectContaining({ queue: [mockTrack.id, mockTrack2.id] }),
+      });
+
+      useStore.getState().reorderQueue(0, 1);
+      expect(mockInvoke).toHaveBeenLastCalledWith('save_playback_session', {
+        context: expect.objectContaining({ queue: [mockTrack2.id, mockTrack.id] }),
+      });
+
+      useStore.getState().clearQueue();
+      expect(mockInvoke).toHaveBeenLastCalledWith('save_playback_session', {
+        context: expect.objectContaining({ queue: [] }),
+      });
+    });
+  });
 });
@@ -2,6 +2,7 @@ import { StateCreator } from 'zustand';
 import { invoke } from '@tauri-apps/api/core';
 import type { Track, Album } from '../types';
 import type { AppState, LibrarySlice, QueueItem } from './types';
+import { saveSession } from './session';
 
 let nextQueueId = 1000;
 
@@ -104,6 +105,7 @@ export const createLibrarySlice: StateCreator<AppState, [], [], LibrarySlice> =
         } catch {
           set({ currentCoverArt: null });
         }
+        saveSession(get());
       }
     } catch {
       /* silently handled */
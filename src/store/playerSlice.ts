@@ -3,10 +3,29 @@ import { invoke } from '@tauri-apps/api/core';
 import { listen, type UnlistenFn } from '@tauri-apps/api/event';
 import { PREVIOUS_TRACK_THRESHOLD } from '../constants';
 import { coverCache } from '../utils/coverCache';
-import type { AppState, PlayerSlice, RepeatMode } from './types';
+import { saveSession } from './session';
+import type { PlaybackSession, Playlist } from '../types';
+import type { AppState, PlayerSlice, QueueItem, RepeatMode } from './types';
 
 const GAPLESS_PRELOAD_THRESHOLD = 5; // seconds before end to preload
-const PLAY_HISTORY_THRESHOLD = 30; // seconds before recording play history
+// Longest step between two position updates (about a second apart) that
+// counts as listening: the fastest playback speed, plus a late update.
+// Anything else is a seek.
+const MAX_LISTEN_STEP = 5;
+
+let nextQueueId = 4000;
+
+// Report how long the current track was actually heard, leaving out seeks;
+// the backend decides whether that counts as a play or a skip
+const recordListen = (get: () => AppState, set: (state: Partial<AppState>) => void) => {
+  const { currentTrack, audioLoaded, _listened } = get();
+  set({ _listened: 0, _lastHeard: null });
+  if (!currentTrack || !audioLoaded || _listened < 1) return;
+  invoke('record_play_history', {
+    trackId: currentTrack.id,
+    durationListened: Math.floor(_listened),
+  }).catch(() => {});
+};
 
 export const createPlayerSlice: StateCreator<AppState, [], [], PlayerSlice> = (set, get) => ({
   currentTrack: null,
@@ -20,6 +39,8 @@ export const createPlayerSlice: StateCreator<AppState, [], [], PlayerSlice> = (s
   currentCoverArt: null,
   playbackSpeed: 1.0,
   _positionInterval: null,
+  _listened: 0,
+  _lastHeard: null,
 
   setCurrentTrack: (track) => set({ currentTrack: track }),
   setIsPlaying: (playing) => set({ isPlaying: playing }),
@@ -42,26 +63,18 @@ export const createPlayerSlice: StateCreator<AppState, [], [], PlayerSlice> = (s
     }
 
     let preloaded = false;
-    let historyRecorded = false;
 
     // Listen for push-based position updates from Rust
     const unlistenPromise = listen<number>('position-update', (event) => {
       const position = event.payload;
-      const { currentTrack, isPlaying } = get();
+      const { currentTrack, isPlaying, _listened, _lastHeard } = get();
 
-      set({ position });
+      const step = _lastHeard === null ? 0 : position - _lastHeard;
+      const heard = step > 0 && step <= MAX_LISTEN_STEP ? step : 0;
+      set({ position, _listened: _listened + heard, _lastHeard: position });
 
       if (!currentTrack?.duration || !isPlaying) return;
 
-      // Record play history after 30s of listening
-      if (!historyRecorded && position >= PLAY_HISTORY_THRESHOLD) {
-        historyRecorded = true;
-        invoke('record_play_history', {
-          trackId: currentTrack.id,
-          durationListened: Math.floor(position),
-        }).catch(() => {});
-      }
-
       // Preload next track for gapless playback (5s before end)
       if (!preloaded && position >= currentTrack.duration - GAPLESS_PRELOAD_THRESHOLD) {
         preloaded = true;
@@ -125,6 +138,7 @@ export const createPlayerSlice: StateCreator<AppState, [], [], PlayerSlice> = (s
     if (newState) {
       get().generateShuffledQueue();
     }
+    saveSession(get());
   },
 
   cycleRepeatMode: () => {
@@ -133,6 +147,7 @@ export const createPlayerSlice: StateCreator<AppState, [], [], PlayerSlice> = (s
     const currentIndex = modes.indexOf(repeatMode);
     const nextMode = modes[(currentIndex + 1) % modes.length];
     set({ repeatMode: nextMode });
+    saveSession(get());
   },
 
   playNextTrack: () => {
@@ -157,6 +172,7 @@ export const createPlayerSlice: StateCreator<AppState, [], [], PlayerSlice> = (s
       const nextItem = queue[0];
       set({ queue: queue.slice(1) });
       get().playTrack(nextItem.track.id);
+      saveSession(get());
       return;
     }
 
@@ -264,10 +280,17 @@ export const createPlayerSlice: StateCreator<AppState, [], [], PlayerSlice> = (s
 
   playTrack: async (trackId: number) => {
     try {
+      recordListen(get, set);
       await invoke('play_track', { trackId });
       const track = get().tracks.find((t) => t.id === trackId);
       if (track) {
-        set({ currentTrack: track, isPlaying: true, audioLoaded: true, position: 0 });
+        set({
+          currentTrack: track,
+          isPlaying: true,
+          audioLoaded: true,
+          position: 0,
+          _lastHeard: 0,
+        });
         get().startPositionTracking();
 
         // Check cover cache first
@@ -320,6 +343,7 @@ export const createPlayerSlice: StateCreator<AppState, [], [], PlayerSlice> = (s
 
   stopPlayback: async () => {
     try {
+      recordListen(get, set);
       await invoke('stop_playback');
       get().stopPositionTracking();
       set({ isPlaying: false, audioLoaded: false, currentTrack: null, position: 0 });
@@ -328,6 +352,60 @@ export const createPlayerSlice: StateCreator<AppState, [], [], PlayerSlice> = (s
     }
   },
 
+  flushListen: () => recordListen(get, set),
+
+  restorePlaybackSession: async () => {
+    try {
+      const session = await invoke<PlaybackSession | null>('get_playback_session');
+      if (!session) return;
+
+      let currentPlaylist: Playlist | null = null;
+      if (session.active_playlist_id !== null) {
+        await get().loadPlaylists();
+        currentPlaylist = get().playlists.find((p) => p.id === session.active_playlist_id) ?? null;
+        if (currentPlaylist) {
+          await get().loadPlaylistTracks(currentPlaylist.id);
+        }
+      }
+
+      const queue: QueueItem[] = session.queue.map((t) => ({ queueId: nextQueueId++, track: t }));
+      const modes: RepeatMode[] = ['off', 'list', 'track'];
+      const repeatMode = modes.find((mode) => mode === session.repeat_mode) ?? 'off';
+      // The backend has the track cued, but playing goes through playTrack
+      // so position tracking starts with it
+      set({
+        currentTrack: session.track,
+        position: session.position_ms / 1000,
+        _listened: 0,
+        _lastHeard: null,
+        isPlaying: false,
+        audioLoaded: false,
+        volume: session.volume,
+        playbackSpeed: session.speed,
+        shuffleEnabled: session.shuffle,
+        repeatMode,
+        queue,
+        currentPlaylist,
+      });
+      if (session.shuffle) {
+        get().generateShuffledQueue();
+      }
+
+      if (session.track) {
+        try {
+          const cover = await invoke<string | null>('get_track_cover', {
+            trackId: session.track.id,
+          });
+          set({ currentCoverArt: cover });
+        } catch {
+          set({ currentCoverArt: null });
+        }
+      }
+    } catch {
+      // Keep the state persisted in the browser
+    }
+  },
+
   refreshCurrentTrack: async () => {
     const { currentTrack } = get();
     if (!currentTrack) return;
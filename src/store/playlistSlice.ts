@@ -2,6 +2,7 @@ import { StateCreator } from 'zustand';
 import { invoke } from '@tauri-apps/api/core';
 import type { Track, Playlist } from '../types';
 import type { AppState, PlaylistSlice, QueueItem } from './types';
+import { saveSession } from './session';
 
 let nextQueueId = 2000;
 
@@ -11,7 +12,10 @@ export const createPlaylistSlice: StateCreator<AppState, [], [], PlaylistSlice>
   playlistTracks: [],
   playlistLoading: false,
 
-  setCurrentPlaylist: (playlist) => set({ currentPlaylist: playlist }),
+  setCurrentPlaylist: (playlist) => {
+    set({ currentPlaylist: playlist });
+    saveSession(get());
+  },
 
   loadPlaylists: async () => {
     try {
@@ -33,6 +37,7 @@ export const createPlaylistSlice: StateCreator<AppState, [], [], PlaylistSlice>
     const { currentPlaylist } = get();
     if (currentPlaylist?.id === id) {
       set({ currentPlaylist: null, playlistTracks: [] });
+      saveSession(get());
     }
     await get().loadPlaylists();
   },
@@ -129,6 +134,7 @@ export const createPlaylistSlice: StateCreator<AppState, [], [], PlaylistSlice>
       if (playlist) {
         set({ currentPlaylist: playlist });
       }
+      saveSession(get());
     }
   },
 });
@@ -1,6 +1,7 @@
 import { StateCreator } from 'zustand';
 import type { Track } from '../types';
 import type { AppState, QueueSlice, QueueItem } from './types';
+import { saveSession } from './session';
 
 let nextQueueId = 3000;
 
@@ -41,11 +42,13 @@ export const createQueueSlice: StateCreator<AppState, [], [], QueueSlice> = (set
     } else {
       set({ queue: [...queue, ...newItems] });
     }
+    saveSession(get());
   },
 
   removeFromQueue: (queueId: number) => {
     const { queue } = get();
     set({ queue: queue.filter((item) => item.queueId !== queueId) });
+    saveSession(get());
   },
 
   reorderQueue: (fromIndex: number, toIndex: number) => {
@@ -63,10 +66,12 @@ export const createQueueSlice: StateCreator<AppState, [], [], QueueSlice> = (set
     const [moved] = newQueue.splice(fromIndex, 1);
     newQueue.splice(toIndex, 0, moved);
     set({ queue: newQueue });
+    saveSession(get());
   },
 
   clearQueue: () => {
     set({ queue: [] });
+    saveSession(get());
   },
 
   toggleQueuePanel: () => {
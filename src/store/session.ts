@@ -0,0 +1,20 @@
+import { invoke } from '@tauri-apps/api/core';
+import type { AppState } from './types';
+
+// Save the queue, modes and active playlist so the next launch picks up
+// where this one left off. Track, position, volume and speed are saved by
+// the backend's playback commands.
+export async function saveSession(state: AppState): Promise<void> {
+  try {
+    await invoke('save_playback_session', {
+      context: {
+        queue: state.queue.map((item) => item.track.id),
+        shuffle: state.shuffleEnabled,
+        repeat_mode: state.repeatMode,
+        active_playlist_id: state.currentPlaylist?.id ?? null,
+      },
+    });
+  } catch {
+    // Saved again on the next change, and at exit
+  }
+}
@@ -20,6 +20,10 @@ export interface PlayerSlice {
   currentCoverArt: string | null;
   playbackSpeed: number;
   _positionInterval: ReturnType<typeof setInterval> | null;
+  /** Seconds of the current track heard so far, not counting seeks */
+  _listened: number;
+  /** Position of the last position update, to tell listening from seeking */
+  _lastHeard: number | null;
 
   setCurrentTrack: (track: Track | null) => void;
   setIsPlaying: (playing: boolean) => void;
@@ -29,6 +33,8 @@ export interface PlayerSlice {
   playTrack: (trackId: number) => Promise<void>;
   pausePlayback: () => Promise<void>;
   stopPlayback: () => Promise<void>;
+  /** Record how long the current track was heard, e.g. before the window closes */
+  flushListen: () => void;
   startPositionTracking: () => void;
   stopPositionTracking: () => void;
   toggleShuffle: () => void;
@@ -38,6 +44,7 @@ export interface PlayerSlice {
   generateShuffledQueue: () => void;
   getNextTrackId: () => number | null;
   refreshCurrentTrack: () => Promise<void>;
+  restorePlaybackSession: () => Promise<void>;
 }
 
 export interface LibrarySlice {
@@ -25,6 +25,7 @@ export const mockTrack: Track = {
   metadata_fetched: true,
   release_mbid: null,
   created_at: 1700000000,
+  compilation: false,
 };
 
 export const mockTrack2: Track = {
@@ -43,6 +44,7 @@ export const mockTrack2: Track = {
   metadata_fetched: false,
   release_mbid: null,
   created_at: 1700000100,
+  compilation: false,
 };
 
 export const mockTrack3: Track = {
@@ -80,6 +82,7 @@ export const mockPlaylist2: Playlist = {
 };
 
 export const mockAlbum: Album = {
+  id: 1,
   name: 'Test Album',
   artist: 'Test Artist',
   year: 2023,
@@ -90,6 +93,7 @@ export const mockAlbum: Album = {
 };
 
 export const mockAlbum2: Album = {
+  id: 2,
   name: 'Another Album',
   artist: 'Another Artist',
   year: 2022,
@@ -25,12 +25,15 @@ export interface ScanFolder {
 export interface TrackFilters {
   artist?: string;
   album?: string;
+  artist_id?: number;
+  album_id?: number;
   genre?: string;
   year?: number;
   format?: string;
 }
 
 export interface Album {
+  id: number;
   name: string;
   artist: string | null;
   year: number | null;
@@ -38,6 +41,7 @@ export interface Album {
   track_count: number;
   total_duration: number | null;
   created_at: number;
+  compilation: boolean;
 }
 
 export interface ScanDiscovery {
@@ -51,6 +55,8 @@ export interface ScanProgress {
   total_files: number;
   processed_files: number;
   is_complete: boolean;
+  files_per_second: number;
+  eta_secs: number | null;
 }
 
 export interface ScanResult {
@@ -139,6 +145,17 @@ export interface PlayHistoryEntry {
   track: Track | null;
 }
 
+export interface PlaybackSession {
+  track: Track | null;
+  position_ms: number;
+  queue: Track[];
+  volume: number;
+  speed: number;
+  shuffle: boolean;
+  repeat_mode: string;
+  active_playlist_id: number | null;
+}
+
 export interface M3uEntry {
   path: string;
   title: string | null;